@@ -1,3 +1,78 @@
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// Build metadata the agent reports to the server, read back with `env!`
+fn build_metadata() {
+    // CI may build from a tarball without .git, it can pass the commit in instead
+    println!("cargo:rerun-if-env-changed=MSPAGENT_GIT_SHA");
+    let git_sha = std::env::var("MSPAGENT_GIT_SHA")
+        .ok()
+        .or_else(|| git(&["rev-parse", "--short=12", "HEAD"]))
+        .unwrap_or_else(|| String::from("unknown"));
+    println!("cargo:rustc-env=MSPAGENT_GIT_SHA={}", git_sha);
+
+    // Rebuilt on a new commit, not on every build
+    if let Some(git_dir) = git(&["rev-parse", "--absolute-git-dir"]) {
+        println!("cargo:rerun-if-changed={}/HEAD", git_dir);
+        if let Some(head_ref) = git(&["symbolic-ref", "-q", "HEAD"]) {
+            println!("cargo:rerun-if-changed={}/{}", git_dir, head_ref);
+        }
+    }
+
+    // Reproducible builds pin the date
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    let built_at = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or(0)
+        });
+    println!("cargo:rustc-env=MSPAGENT_BUILD_EPOCH={}", built_at);
+
+    println!(
+        "cargo:rustc-env=MSPAGENT_TARGET={}",
+        std::env::var("TARGET").unwrap_or_default()
+    );
+    println!(
+        "cargo:rustc-env=MSPAGENT_PROFILE={}",
+        std::env::var("PROFILE").unwrap_or_default()
+    );
+
+    let mut features: Vec<String> = std::env::vars()
+        .filter_map(|(name, _)| {
+            name.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=MSPAGENT_FEATURES={}", features.join(","));
+}
+
+/// The vendor release keys update manifests are checked against, read back with `option_env!`.
+/// Builds without MSPAGENT_RELEASE_KEY run but never self-update.
+fn release_keys() {
+    println!("cargo:rerun-if-env-changed=MSPAGENT_RELEASE_KEY");
+    println!("cargo:rerun-if-env-changed=MSPAGENT_RELEASE_KEY_NEXT");
+}
+
 fn main() {
+    build_metadata();
+    release_keys();
     tauri_build::build()
 }
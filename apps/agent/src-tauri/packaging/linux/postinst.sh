@@ -0,0 +1,22 @@
+#!/bin/sh
+# Runs after the deb or rpm is installed or upgraded, the service owns the machine settings
+set -e
+
+CONFIG_DIR=/etc/mspagent
+
+mkdir -p "${CONFIG_DIR}/logs"
+chown root:root "${CONFIG_DIR}"
+chmod 755 "${CONFIG_DIR}"
+# Only the service logs here, each account's tray logs under its own home
+chown root:root "${CONFIG_DIR}/logs"
+chmod 755 "${CONFIG_DIR}/logs"
+if [ -f "${CONFIG_DIR}/settings.json" ]; then
+    chown root:root "${CONFIG_DIR}/settings.json"
+    chmod 600 "${CONFIG_DIR}/settings.json"
+fi
+
+if [ -d /run/systemd/system ]; then
+    systemctl daemon-reload
+    systemctl enable mspagent.service
+    systemctl restart mspagent.service
+fi
@@ -0,0 +1,11 @@
+#!/bin/sh
+# Runs before the deb or rpm is removed, dpkg passes "remove" and rpm passes 0 when nothing replaces it
+set -e
+
+case "$1" in
+    remove|0)
+        if [ -d /run/systemd/system ]; then
+            systemctl disable --now mspagent.service || true
+        fi
+        ;;
+esac
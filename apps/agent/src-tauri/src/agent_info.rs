@@ -0,0 +1,284 @@
+use serde::{Deserialize, Serialize};
+
+#[cfg(target_os = "macos")]
+use std::process::Command;
+
+/// Features the server may rely on on this platform, one entry per thing it can send or ask for.
+/// Entries are only ever added here, a change in behaviour gets a new versioned name.
+pub fn capabilities() -> Vec<&'static str> {
+    let mut capabilities = vec![
+        "heartbeat-jobs",
+        "signed-jobs",
+        "signing-key-rotation",
+        "job-cancel",
+        "on-demand-jobs",
+        "file-transfer",
+        "maintenance-window",
+        "self-update",
+        "staged-rollout",
+        "alert-rules",
+        "service-monitor",
+        "ticket-status",
+        "tray-menu",
+        "branding",
+        "screenshots-multi",
+        "screen-recording",
+        "scripts",
+        "scripts-powershell",
+        "scripts-python",
+    ];
+
+    // Shell and bash scripts are rejected on Windows
+    #[cfg(unix)]
+    capabilities.push("scripts-shell");
+
+    // macOS can't tell whether a reboot is pending
+    #[cfg(not(target_os = "macos"))]
+    capabilities.push("reboot");
+
+    // CPU, disk I/O and network are only sampled on Linux
+    #[cfg(target_os = "linux")]
+    capabilities.push("metrics");
+
+    capabilities
+}
+
+/// How this binary was built
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BuildInfo {
+    pub version: String,
+    pub git_sha: String,
+    pub built_at: Option<String>,
+    /// Rust target triple, e.g. "x86_64-pc-windows-msvc"
+    pub target: String,
+    pub profile: String,
+    pub features: Vec<String>,
+}
+
+/// The OS release, from /etc/os-release on Linux and the closest equivalent elsewhere
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct OsRelease {
+    pub id: String,
+    pub id_like: Option<String>,
+    pub name: Option<String>,
+    pub version_id: Option<String>,
+    pub version_codename: Option<String>,
+    pub pretty_name: Option<String>,
+    pub build: Option<String>,
+}
+
+/// What the agent can do and what it runs on, sent at registration and with every heartbeat
+/// since a self-update changes it without registering again
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AgentInfo {
+    pub capabilities: Vec<String>,
+    pub build: BuildInfo,
+    pub os_release: Option<OsRelease>,
+}
+
+pub fn build_info() -> BuildInfo {
+    let built_at = env!("MSPAGENT_BUILD_EPOCH")
+        .parse::<i64>()
+        .ok()
+        .and_then(|epoch| chrono::DateTime::from_timestamp(epoch, 0))
+        .map(|at| at.to_rfc3339());
+
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_sha: env!("MSPAGENT_GIT_SHA").to_string(),
+        built_at,
+        target: env!("MSPAGENT_TARGET").to_string(),
+        profile: env!("MSPAGENT_PROFILE").to_string(),
+        features: env!("MSPAGENT_FEATURES")
+            .split(',')
+            .filter(|feature| !feature.is_empty())
+            .map(String::from)
+            .collect(),
+    }
+}
+
+/// Reads os-release's KEY=value lines, values may be quoted with shell-style escapes
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_os_release(content: &str) -> OsRelease {
+    let mut release = OsRelease::default();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some(pair) => pair,
+            None => continue,
+        };
+
+        let value = value.trim();
+        let value = if value.len() >= 2
+            && ((value.starts_with('"') && value.ends_with('"'))
+                || (value.starts_with('\'') && value.ends_with('\'')))
+        {
+            let mut unescaped = String::new();
+            let mut chars = value[1..value.len() - 1].chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => unescaped.extend(chars.next()),
+                    c => unescaped.push(c),
+                }
+            }
+            unescaped
+        } else {
+            value.to_string()
+        };
+        let value = Some(value).filter(|value| !value.is_empty());
+
+        match key.trim() {
+            "ID" => release.id = value.unwrap_or_default(),
+            "ID_LIKE" => release.id_like = value,
+            "NAME" => release.name = value,
+            "VERSION_ID" => release.version_id = value,
+            "VERSION_CODENAME" => release.version_codename = value,
+            "PRETTY_NAME" => release.pretty_name = value,
+            "BUILD_ID" => release.build = value,
+            _ => {}
+        }
+    }
+
+    // os-release(5) says a missing ID means "linux"
+    if release.id.is_empty() {
+        release.id = String::from("linux");
+    }
+    release
+}
+
+/// The OS release, None when it can't be read
+pub fn os_release() -> Option<OsRelease> {
+    #[cfg(target_os = "linux")]
+    {
+        ["/etc/os-release", "/usr/lib/os-release"]
+            .iter()
+            .find_map(|path| std::fs::read_to_string(path).ok())
+            .map(|content| parse_os_release(&content))
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        use winreg::enums::*;
+        use winreg::RegKey;
+
+        let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
+        let key = hklm
+            .open_subkey("SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion")
+            .ok()?;
+        let value = |name: &str| {
+            key.get_value::<String, _>(name)
+                .ok()
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+
+        let name = value("ProductName");
+        let version_id = value("DisplayVersion").or_else(|| value("ReleaseId"));
+        let build = value("CurrentBuild").map(|build| match key.get_value::<u32, _>("UBR") {
+            Ok(ubr) => format!("{}.{}", build, ubr),
+            Err(_) => build,
+        });
+        Some(OsRelease {
+            id: String::from("windows"),
+            pretty_name: name
+                .as_ref()
+                .map(|name| match &version_id {
+                    Some(version) => format!("{} {}", name, version),
+                    None => name.clone(),
+                }),
+            name,
+            version_id,
+            build,
+            ..OsRelease::default()
+        })
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        let sw_vers = |flag: &str| {
+            let output = Command::new("sw_vers").arg(flag).output().ok()?;
+            let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            Some(value).filter(|value| output.status.success() && !value.is_empty())
+        };
+
+        let name = sw_vers("-productName");
+        let version_id = sw_vers("-productVersion");
+        Some(OsRelease {
+            id: String::from("macos"),
+            pretty_name: match (&name, &version_id) {
+                (Some(name), Some(version)) => Some(format!("{} {}", name, version)),
+                _ => None,
+            },
+            name,
+            version_id,
+            build: sw_vers("-buildVersion"),
+            ..OsRelease::default()
+        })
+    }
+}
+
+pub fn agent_info() -> AgentInfo {
+    AgentInfo {
+        capabilities: capabilities().into_iter().map(String::from).collect(),
+        build: build_info(),
+        os_release: os_release(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_platform_runs_scripts_and_only_unix_runs_shell_scripts() {
+        let capabilities = capabilities();
+        for capability in ["scripts", "scripts-powershell", "scripts-python"] {
+            assert!(capabilities.contains(&capability), "missing {}", capability);
+        }
+        assert_eq!(capabilities.contains(&"scripts-shell"), cfg!(unix));
+    }
+
+    #[test]
+    fn os_release_values_are_unquoted_and_unescaped() {
+        let release = parse_os_release(concat!(
+            "# Comment lines and blank lines are skipped\n",
+            "\n",
+            "NAME=\"Ubuntu\"\n",
+            "ID=ubuntu\n",
+            "ID_LIKE=debian\n",
+            "PRETTY_NAME=\"Ubuntu 24.04.1 LTS \\\"Noble\\\" \\\\ \\$HOME\"\n",
+            "VERSION_ID='24.04'\n",
+            "VERSION_CODENAME=noble\n",
+            "BUILD_ID=\"\"\n",
+            "not a key value line\n",
+        ));
+
+        assert_eq!(release.id, "ubuntu");
+        assert_eq!(release.id_like.as_deref(), Some("debian"));
+        assert_eq!(release.name.as_deref(), Some("Ubuntu"));
+        assert_eq!(release.pretty_name.as_deref(), Some("Ubuntu 24.04.1 LTS \"Noble\" \\ $HOME"));
+        assert_eq!(release.version_id.as_deref(), Some("24.04"));
+        assert_eq!(release.version_codename.as_deref(), Some("noble"));
+        assert_eq!(release.build, None);
+    }
+
+    #[test]
+    fn os_release_without_an_id_is_linux() {
+        let release = parse_os_release("NAME=\"Some Distro\"\nUNQUOTED=a=b\n");
+
+        assert_eq!(release.id, "linux");
+        assert_eq!(release.name.as_deref(), Some("Some Distro"));
+    }
+
+    #[test]
+    fn mismatched_quotes_are_kept_as_written() {
+        let release = parse_os_release("ID=\"arch\nNAME='Arch Linux\"\nPRETTY_NAME=\"\n");
+
+        assert_eq!(release.id, "\"arch");
+        assert_eq!(release.name.as_deref(), Some("'Arch Linux\""));
+        assert_eq!(release.pretty_name.as_deref(), Some("\""));
+    }
+}
@@ -0,0 +1,336 @@
+use crate::alerts::{AlertEvent, AlertSeverity, AlertStatus};
+use crate::reboot::ScheduledReboot;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+// Heartbeats run every 10 minutes, one miss is a blip but two in a row means we're cut off
+const HEARTBEAT_FAILURES_BEFORE_ERROR: u32 = 2;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentHealth {
+    Healthy,
+    Attention,
+    Error,
+}
+
+/// An alert raised by a local rule that hasn't resolved yet
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ActiveAlert {
+    pub fingerprint: String,
+    pub severity: AlertSeverity,
+    pub message: String,
+    pub notify_user: bool,
+    pub raised_at: String,
+}
+
+/// What the agent knows about itself, published by the modules doing the work
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AgentState {
+    pub registered: bool,
+    pub last_heartbeat_at: Option<String>,
+    pub heartbeat_failures: u32,
+    pub last_heartbeat_error: Option<String>,
+    pub pending_tickets: usize,
+    pub update_available: Option<String>,
+    #[serde(default)]
+    pub active_alerts: Vec<ActiveAlert>,
+    #[serde(default)]
+    pub scheduled_reboot: Option<ScheduledReboot>,
+    /// None until something publishes, so readers can tell a fresh process from a broken one
+    pub updated_at: Option<String>,
+}
+
+static AGENT_STATE: Mutex<AgentState> = Mutex::new(AgentState {
+    registered: false,
+    last_heartbeat_at: None,
+    heartbeat_failures: 0,
+    last_heartbeat_error: None,
+    pending_tickets: 0,
+    update_available: None,
+    active_alerts: Vec::new(),
+    scheduled_reboot: None,
+    updated_at: None,
+});
+
+/// Snapshot of the agent state in this process
+pub fn current_state() -> AgentState {
+    match AGENT_STATE.lock() {
+        Ok(state) => state.clone(),
+        Err(poisoned) => poisoned.into_inner().clone(),
+    }
+}
+
+fn publish(update: impl FnOnce(&mut AgentState)) {
+    let mut state = match AGENT_STATE.lock() {
+        Ok(state) => state,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    update(&mut state);
+    state.updated_at = Some(chrono::Utc::now().to_rfc3339());
+}
+
+pub fn set_registered(registered: bool) {
+    publish(|state| state.registered = registered);
+}
+
+pub fn record_heartbeat_result(result: Result<(), String>) {
+    publish(|state| match result {
+        Ok(()) => {
+            state.registered = true;
+            state.last_heartbeat_at = Some(chrono::Utc::now().to_rfc3339());
+            state.heartbeat_failures = 0;
+            state.last_heartbeat_error = None;
+        }
+        Err(e) => {
+            state.heartbeat_failures += 1;
+            state.last_heartbeat_error = Some(e);
+        }
+    });
+}
+
+pub fn set_pending_tickets(count: usize) {
+    publish(|state| state.pending_tickets = count);
+}
+
+pub fn set_update_available(version: Option<String>) {
+    publish(|state| state.update_available = version);
+}
+
+pub fn set_scheduled_reboot(reboot: Option<ScheduledReboot>) {
+    publish(|state| state.scheduled_reboot = reboot);
+}
+
+/// Tracks an alert raised or resolved by the alert engine
+pub fn record_alert(event: &AlertEvent) {
+    publish(|state| {
+        state
+            .active_alerts
+            .retain(|alert| alert.fingerprint != event.fingerprint);
+        if event.status == AlertStatus::Active {
+            state.active_alerts.push(ActiveAlert {
+                fingerprint: event.fingerprint.clone(),
+                severity: event.severity,
+                message: event.message.clone(),
+                notify_user: event.notify_user,
+                raised_at: event.detected_at.clone(),
+            });
+        }
+    });
+}
+
+impl AgentState {
+    pub fn health(&self) -> AgentHealth {
+        if self.updated_at.is_none()
+            || !self.registered
+            || self.heartbeat_failures >= HEARTBEAT_FAILURES_BEFORE_ERROR
+        {
+            AgentHealth::Error
+        } else if self.heartbeat_failures > 0
+            || self.pending_tickets > 0
+            || self.update_available.is_some()
+            || !self.active_alerts.is_empty()
+            || self.scheduled_reboot.is_some()
+        {
+            AgentHealth::Attention
+        } else {
+            AgentHealth::Healthy
+        }
+    }
+
+    /// Short multi-line description for the tray tooltip, headed by the agent's name
+    pub fn summary(&self, name: &str) -> String {
+        let mut lines = vec![name.to_string()];
+
+        if self.updated_at.is_none() {
+            lines.push(String::from("Agent service is not running"));
+            return lines.join("\n");
+        }
+
+        if !self.registered {
+            lines.push(String::from("Not registered"));
+        } else if self.heartbeat_failures > 0 {
+            lines.push(String::from("Can't reach the server"));
+        } else if self.last_heartbeat_at.is_some() {
+            lines.push(String::from("Connected"));
+        } else {
+            lines.push(String::from("Registered"));
+        }
+
+        if let Some(at) = self
+            .last_heartbeat_at
+            .as_ref()
+            .and_then(|at| chrono::DateTime::parse_from_rfc3339(at).ok())
+        {
+            lines.push(format!(
+                "Last check-in {}",
+                at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M")
+            ));
+        }
+
+        match self.pending_tickets {
+            0 => {}
+            1 => lines.push(String::from("1 ticket waiting to send")),
+            count => lines.push(format!("{} tickets waiting to send", count)),
+        }
+
+        match self.active_alerts.len() {
+            0 => {}
+            1 => lines.push(String::from("1 active alert")),
+            count => lines.push(format!("{} active alerts", count)),
+        }
+
+        if let Some(at) = self
+            .scheduled_reboot
+            .as_ref()
+            .and_then(|reboot| chrono::DateTime::parse_from_rfc3339(&reboot.reboot_at).ok())
+        {
+            lines.push(format!(
+                "Restart scheduled for {}",
+                at.with_timezone(&chrono::Local).format("%H:%M")
+            ));
+        }
+
+        if let Some(version) = &self.update_available {
+            lines.push(format!("Update available: {}", version));
+        }
+
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEARTBEAT_AT: &str = "2026-10-16T09:30:00Z";
+
+    fn connected() -> AgentState {
+        AgentState {
+            registered: true,
+            last_heartbeat_at: Some(String::from(HEARTBEAT_AT)),
+            heartbeat_failures: 0,
+            last_heartbeat_error: None,
+            pending_tickets: 0,
+            update_available: None,
+            active_alerts: Vec::new(),
+            scheduled_reboot: None,
+            updated_at: Some(String::from(HEARTBEAT_AT)),
+        }
+    }
+
+    fn local(at: &str, format: &str) -> String {
+        chrono::DateTime::parse_from_rfc3339(at)
+            .unwrap()
+            .with_timezone(&chrono::Local)
+            .format(format)
+            .to_string()
+    }
+
+    fn alert(fingerprint: &str) -> ActiveAlert {
+        ActiveAlert {
+            fingerprint: fingerprint.to_string(),
+            severity: AlertSeverity::High,
+            message: String::from("Disk almost full"),
+            notify_user: false,
+            raised_at: String::from(HEARTBEAT_AT),
+        }
+    }
+
+    #[test]
+    fn being_unregistered_or_cut_off_outranks_everything_else() {
+        let mut busy = connected();
+        busy.pending_tickets = 2;
+        busy.update_available = Some(String::from("1.3.0"));
+        assert_eq!(busy.health(), AgentHealth::Attention);
+
+        let mut unregistered = busy.clone();
+        unregistered.registered = false;
+        assert_eq!(unregistered.health(), AgentHealth::Error);
+
+        let mut cut_off = busy.clone();
+        cut_off.heartbeat_failures = HEARTBEAT_FAILURES_BEFORE_ERROR;
+        assert_eq!(cut_off.health(), AgentHealth::Error);
+
+        let mut not_running = connected();
+        not_running.updated_at = None;
+        assert_eq!(not_running.health(), AgentHealth::Error);
+    }
+
+    #[test]
+    fn anything_waiting_needs_attention() {
+        assert_eq!(connected().health(), AgentHealth::Healthy);
+
+        let mut one_miss = connected();
+        one_miss.heartbeat_failures = 1;
+        let mut pending = connected();
+        pending.pending_tickets = 1;
+        let mut update = connected();
+        update.update_available = Some(String::from("1.3.0"));
+        let mut alerting = connected();
+        alerting.active_alerts.push(alert("disk:/"));
+        let mut rebooting = connected();
+        rebooting.scheduled_reboot = Some(ScheduledReboot {
+            run_id: String::from("run-1"),
+            reboot_at: String::from("2026-10-16T10:00:00Z"),
+            deferrals_left: 0,
+            message: None,
+        });
+
+        for state in [one_miss, pending, update, alerting, rebooting] {
+            assert_eq!(state.health(), AgentHealth::Attention, "{:?}", state);
+        }
+    }
+
+    #[test]
+    fn tooltip_leads_with_the_connection_then_what_is_waiting() {
+        let mut state = connected();
+        state.pending_tickets = 2;
+        state.active_alerts = vec![alert("disk:/"), alert("cpu")];
+        state.scheduled_reboot = Some(ScheduledReboot {
+            run_id: String::from("run-1"),
+            reboot_at: String::from("2026-10-16T10:00:00Z"),
+            deferrals_left: 1,
+            message: None,
+        });
+        state.update_available = Some(String::from("1.3.0"));
+
+        let expected = [
+            String::from("Acme Support"),
+            String::from("Connected"),
+            format!("Last check-in {}", local(HEARTBEAT_AT, "%Y-%m-%d %H:%M")),
+            String::from("2 tickets waiting to send"),
+            String::from("2 active alerts"),
+            format!("Restart scheduled for {}", local("2026-10-16T10:00:00Z", "%H:%M")),
+            String::from("Update available: 1.3.0"),
+        ];
+        assert_eq!(state.summary("Acme Support"), expected.join("\n"));
+
+        state.pending_tickets = 1;
+        state.active_alerts.truncate(1);
+        let summary = state.summary("Acme Support");
+        assert!(summary.contains("\n1 ticket waiting to send\n1 active alert\n"));
+    }
+
+    #[test]
+    fn tooltip_status_line_follows_the_same_precedence() {
+        let status = |state: &AgentState| state.summary("Agent").lines().nth(1).unwrap().to_string();
+
+        let mut state = connected();
+        state.updated_at = None;
+        assert_eq!(state.summary("Agent"), "Agent\nAgent service is not running");
+
+        let mut state = connected();
+        state.registered = false;
+        state.heartbeat_failures = 3;
+        assert_eq!(status(&state), "Not registered");
+
+        let mut state = connected();
+        state.heartbeat_failures = 1;
+        assert_eq!(status(&state), "Can't reach the server");
+
+        let mut state = connected();
+        state.last_heartbeat_at = None;
+        assert_eq!(state.summary("Agent"), "Agent\nRegistered");
+    }
+}
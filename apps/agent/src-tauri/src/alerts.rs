@@ -0,0 +1,577 @@
+use crate::agent_state::record_alert;
+use crate::device_manager::get_settings;
+use crate::logger::log_to_file;
+use crate::metrics::MetricSample;
+use crate::outbox::enqueue;
+use crate::service_monitor::{latest_service_states, ServiceState, ServiceStatus};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+
+// A rule without a cooldown could page on every wobble around its threshold
+pub const DEFAULT_ALERT_COOLDOWN_SECS: u64 = 30 * 60;
+
+const ALERTS_PATH: &str = "/v1.0/alerts";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertSeverity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Comparison {
+    #[default]
+    Above,
+    Below,
+}
+
+/// What a rule watches, disk metrics are checked per mount and services per name
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AlertMetric {
+    CpuPercent,
+    MemoryPercent,
+    SwapPercent,
+    Load1,
+    /// Every disk when no mount is given, e.g. "C:" or "/"
+    DiskFreePercent {
+        #[serde(default)]
+        mount: Option<String>,
+    },
+    DiskUsedPercent {
+        #[serde(default)]
+        mount: Option<String>,
+    },
+    /// A monitored service that is stopped, failed or missing, every monitored one when no name is given
+    ServiceDown {
+        #[serde(default)]
+        name: Option<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AlertRule {
+    pub id: String,
+    pub metric: AlertMetric,
+    /// Comparison and thresholds don't apply to service_down
+    #[serde(default)]
+    pub comparison: Comparison,
+    #[serde(default)]
+    pub threshold: f64,
+    /// How long the threshold must stay crossed before the alert is raised
+    #[serde(default)]
+    pub duration_secs: u64,
+    /// Where the value must get back to before the alert resolves, defaults to the threshold
+    #[serde(default)]
+    pub clear_threshold: Option<f64>,
+    /// Minimum time between two raises of the same alert
+    #[serde(default)]
+    pub cooldown_secs: Option<u64>,
+    pub severity: AlertSeverity,
+    /// Also show the alert to the signed in user
+    #[serde(default)]
+    pub notify_user: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertStatus {
+    Active,
+    Resolved,
+}
+
+/// An alert as the server stores them in `entity_alerts`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertEvent {
+    pub guid: String,
+    pub alert_type: String,
+    pub severity: AlertSeverity,
+    pub message: String,
+    pub fingerprint: String,
+    pub status: AlertStatus,
+    pub metadata: serde_json::Value,
+    pub detected_at: String,
+    #[serde(skip)]
+    pub notify_user: bool,
+}
+
+#[derive(Debug, Clone)]
+struct RuleState {
+    /// The rule as last evaluated, so an alert still resolves after its rule is removed
+    rule: AlertRule,
+    breached_since: Option<i64>,
+    active: bool,
+    last_raised_at: Option<i64>,
+}
+
+impl RuleState {
+    fn new(rule: &AlertRule) -> Self {
+        RuleState {
+            rule: rule.clone(),
+            breached_since: None,
+            active: false,
+            last_raised_at: None,
+        }
+    }
+}
+
+impl AlertMetric {
+    fn name(&self) -> &'static str {
+        match self {
+            AlertMetric::CpuPercent => "cpu_percent",
+            AlertMetric::MemoryPercent => "memory_percent",
+            AlertMetric::SwapPercent => "swap_percent",
+            AlertMetric::Load1 => "load_1",
+            AlertMetric::DiskFreePercent { .. } => "disk_free_percent",
+            AlertMetric::DiskUsedPercent { .. } => "disk_used_percent",
+            AlertMetric::ServiceDown { .. } => "service_down",
+        }
+    }
+
+    fn describe(&self, subject: &str) -> String {
+        match self {
+            AlertMetric::CpuPercent => String::from("CPU usage"),
+            AlertMetric::MemoryPercent => String::from("Memory usage"),
+            AlertMetric::SwapPercent => String::from("Swap usage"),
+            AlertMetric::Load1 => String::from("Load average"),
+            AlertMetric::DiskFreePercent { .. } => format!("Disk {} free space", subject),
+            AlertMetric::DiskUsedPercent { .. } => format!("Disk {} usage", subject),
+            AlertMetric::ServiceDown { .. } => format!("Service {}", subject),
+        }
+    }
+
+    fn is_percent(&self) -> bool {
+        !matches!(self, AlertMetric::Load1)
+    }
+
+    /// The values this metric has in a sample, keyed by what they were measured on. Services
+    /// read 1 when down and 0 otherwise, None is a subject that is still there but wasn't measured.
+    fn readings(&self, sample: &MetricSample, services: &[ServiceState]) -> Vec<(String, Option<f64>)> {
+        let single = |subject: &str, value: Option<f64>| {
+            value
+                .map(|value| vec![(subject.to_string(), Some(value))])
+                .unwrap_or_default()
+        };
+        let disks = |mount: &Option<String>, value: fn(f64) -> f64| {
+            sample
+                .disks
+                .iter()
+                .filter(|disk| match mount {
+                    Some(mount) => disk.mount.eq_ignore_ascii_case(mount),
+                    None => true,
+                })
+                .map(|disk| (disk.mount.clone(), Some(value(disk.used_percent))))
+                .collect()
+        };
+
+        match self {
+            AlertMetric::CpuPercent => single("cpu", sample.cpu_percent),
+            AlertMetric::MemoryPercent => single("memory", sample.memory_percent),
+            AlertMetric::SwapPercent => single("swap", sample.swap_percent),
+            AlertMetric::Load1 => single("load", sample.load_1),
+            AlertMetric::DiskFreePercent { mount } => disks(mount, |used| 100.0 - used),
+            AlertMetric::DiskUsedPercent { mount } => disks(mount, |used| used),
+            AlertMetric::ServiceDown { name } => services
+                .iter()
+                .filter(|service| name.as_ref().is_none_or(|name| &service.name == name))
+                .map(|service| {
+                    // A service we couldn't ask about is neither up nor down
+                    let down = match service.status {
+                        ServiceStatus::Unknown => None,
+                        status if status.is_down() => Some(1.0),
+                        _ => Some(0.0),
+                    };
+                    (service.name.clone(), down)
+                })
+                .collect(),
+        }
+    }
+}
+
+impl AlertRule {
+    fn is_breached(&self, value: f64) -> bool {
+        if let AlertMetric::ServiceDown { .. } = self.metric {
+            return value > 0.0;
+        }
+        match self.comparison {
+            Comparison::Above => value > self.threshold,
+            Comparison::Below => value < self.threshold,
+        }
+    }
+
+    // Hysteresis, a value hovering right at the threshold shouldn't raise and resolve over and over
+    fn is_cleared(&self, value: f64) -> bool {
+        if let AlertMetric::ServiceDown { .. } = self.metric {
+            return value == 0.0;
+        }
+        let clear = self.clear_threshold.unwrap_or(self.threshold);
+        match self.comparison {
+            Comparison::Above => value <= clear,
+            Comparison::Below => value >= clear,
+        }
+    }
+
+    fn format_value(&self, value: f64) -> String {
+        if self.metric.is_percent() {
+            format!("{:.1}%", value)
+        } else {
+            format!("{:.2}", value)
+        }
+    }
+}
+
+/// Checks a rule definition, returning why it can't be used
+pub fn validate_rule(rule: &AlertRule) -> Result<(), String> {
+    if rule.id.trim().is_empty() {
+        return Err(String::from("Rule id is empty"));
+    }
+    if !rule.threshold.is_finite() || !rule.clear_threshold.unwrap_or(0.0).is_finite() {
+        return Err(format!("Rule {} has an invalid threshold", rule.id));
+    }
+    Ok(())
+}
+
+/// Evaluates rules against samples as they come in, remembering what is breached and raised
+#[derive(Default)]
+pub struct AlertEngine {
+    /// Keyed by rule id and subject
+    states: HashMap<(String, String), RuleState>,
+}
+
+impl AlertEngine {
+    /// Returns the alerts raised or resolved by this sample
+    pub fn evaluate(
+        &mut self,
+        guid: &str,
+        rules: &[AlertRule],
+        sample: &MetricSample,
+        services: &[ServiceState],
+    ) -> Vec<AlertEvent> {
+        let now = sample.at;
+        let mut events = Vec::new();
+        let mut measured = HashSet::new();
+
+        for rule in rules {
+            for (subject, value) in rule.metric.readings(sample, services) {
+                let key = (rule.id.clone(), subject.clone());
+                measured.insert(key.clone());
+                let state = self.states.entry(key).or_insert_with(|| RuleState::new(rule));
+                state.rule = rule.clone();
+                let value = match value {
+                    Some(value) => value,
+                    None => continue,
+                };
+
+                if state.active {
+                    if rule.is_cleared(value) {
+                        state.active = false;
+                        state.breached_since = None;
+                        events.push(build_event(guid, rule, &subject, value, AlertStatus::Resolved, now));
+                    }
+                    continue;
+                }
+
+                if !rule.is_breached(value) {
+                    state.breached_since = None;
+                    continue;
+                }
+
+                let since = *state.breached_since.get_or_insert(now);
+                if now - since < rule.duration_secs as i64 {
+                    continue;
+                }
+
+                let cooldown = rule.cooldown_secs.unwrap_or(DEFAULT_ALERT_COOLDOWN_SECS) as i64;
+                if state.last_raised_at.is_some_and(|raised| now - raised < cooldown) {
+                    continue;
+                }
+
+                state.active = true;
+                state.last_raised_at = Some(now);
+                events.push(build_event(guid, rule, &subject, value, AlertStatus::Active, now));
+            }
+        }
+
+        // A removed rule, an unmounted disk or a service no longer monitored can't clear on its
+        // own, resolve what it raised. Rules still configured keep their state through the
+        // cooldown, so a subject that comes straight back can't raise again at once.
+        self.states.retain(|key, state| {
+            if measured.contains(key) {
+                return true;
+            }
+            let (rule_id, subject) = key;
+            if state.active {
+                let mut event = build_event(guid, &state.rule, subject, 0.0, AlertStatus::Resolved, now);
+                event.message = format!("{} is no longer monitored", state.rule.metric.describe(subject));
+                event.metadata["value"] = serde_json::Value::Null;
+                events.push(event);
+                state.active = false;
+            }
+            state.breached_since = None;
+
+            let cooldown = state.rule.cooldown_secs.unwrap_or(DEFAULT_ALERT_COOLDOWN_SECS) as i64;
+            rules.iter().any(|rule| &rule.id == rule_id)
+                && state.last_raised_at.is_some_and(|raised| now - raised < cooldown)
+        });
+
+        events
+    }
+}
+
+fn build_event(
+    guid: &str,
+    rule: &AlertRule,
+    subject: &str,
+    value: f64,
+    status: AlertStatus,
+    at: i64,
+) -> AlertEvent {
+    let what = rule.metric.describe(subject);
+    let duration = match rule.duration_secs {
+        0 => String::new(),
+        secs if secs < 60 => format!(" for {} sec", secs),
+        secs => format!(" for {} min", secs / 60),
+    };
+    let message = match (&rule.metric, status) {
+        (AlertMetric::ServiceDown { .. }, AlertStatus::Active) => format!("{} is down{}", what, duration),
+        (AlertMetric::ServiceDown { .. }, AlertStatus::Resolved) => format!("{} is running again", what),
+        (_, AlertStatus::Active) => format!(
+            "{} is {}, {} {}{}",
+            what,
+            rule.format_value(value),
+            match rule.comparison {
+                Comparison::Above => "above",
+                Comparison::Below => "below",
+            },
+            rule.format_value(rule.threshold),
+            duration
+        ),
+        (_, AlertStatus::Resolved) => format!("{} is back to {}", what, rule.format_value(value)),
+    };
+
+    AlertEvent {
+        guid: guid.to_string(),
+        alert_type: format!("agent_{}", rule.metric.name()),
+        severity: rule.severity,
+        message,
+        // Stable per device, rule and subject so the server can dedupe raises and match resolves
+        fingerprint: format!("agent:{}:{}:{}", guid, rule.id, subject),
+        status,
+        metadata: json!({
+            "rule_id": rule.id,
+            "metric": rule.metric.name(),
+            "subject": subject,
+            "value": value,
+            "threshold": rule.threshold,
+            "duration_secs": rule.duration_secs,
+        }),
+        detected_at: chrono::DateTime::from_timestamp(at, 0)
+            .unwrap_or_default()
+            .to_rfc3339(),
+        notify_user: rule.notify_user,
+    }
+}
+
+/// Runs the configured rules against a new sample and queues whatever they raise or resolve
+pub async fn process_sample(engine: &mut AlertEngine, sample: &MetricSample) -> Result<(), Box<dyn std::error::Error>> {
+    let settings = get_settings().await?;
+
+    // Alerts are keyed to the device, there's no one to send them to before registration
+    let guid = match settings.guid {
+        Some(guid) => guid,
+        None => return Ok(()),
+    };
+    let rules: Vec<AlertRule> = settings
+        .alert_rules
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|rule| validate_rule(rule).is_ok())
+        .collect();
+
+    for event in engine.evaluate(&guid, &rules, sample, &latest_service_states()) {
+        log_to_file(
+            "INFO".to_string(),
+            format!("Alert {:?}: {}", event.status, event.message),
+        );
+        record_alert(&event);
+        enqueue("alert", ALERTS_PATH, serde_json::to_value(&event)?).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::DiskUsage;
+    use crate::service_monitor::MonitorKind;
+
+    fn cpu_rule() -> AlertRule {
+        AlertRule {
+            id: String::from("cpu-high"),
+            metric: AlertMetric::CpuPercent,
+            comparison: Comparison::Above,
+            threshold: 90.0,
+            duration_secs: 0,
+            clear_threshold: None,
+            cooldown_secs: Some(0),
+            severity: AlertSeverity::High,
+            notify_user: false,
+        }
+    }
+
+    fn cpu_at(at: i64, cpu_percent: f64) -> MetricSample {
+        MetricSample {
+            at,
+            cpu_percent: Some(cpu_percent),
+            ..MetricSample::default()
+        }
+    }
+
+    fn disks_at(at: i64, disks: &[(&str, f64)]) -> MetricSample {
+        MetricSample {
+            at,
+            disks: disks
+                .iter()
+                .map(|(mount, used_percent)| DiskUsage {
+                    mount: mount.to_string(),
+                    used_percent: *used_percent,
+                })
+                .collect(),
+            ..MetricSample::default()
+        }
+    }
+
+    fn service(status: ServiceStatus) -> ServiceState {
+        ServiceState {
+            name: String::from("nginx"),
+            kind: MonitorKind::Service,
+            status,
+            detail: None,
+            pid: None,
+            previous_status: None,
+            changed_at: 0,
+            restarts: 0,
+        }
+    }
+
+    fn statuses(events: &[AlertEvent]) -> Vec<AlertStatus> {
+        events.iter().map(|event| event.status).collect()
+    }
+
+    #[test]
+    fn an_alert_is_raised_once_the_threshold_stays_crossed_for_the_duration() {
+        let rules = [AlertRule { duration_secs: 120, ..cpu_rule() }];
+        let mut engine = AlertEngine::default();
+
+        assert!(engine.evaluate("guid", &rules, &cpu_at(0, 95.0), &[]).is_empty());
+        assert!(engine.evaluate("guid", &rules, &cpu_at(60, 95.0), &[]).is_empty());
+        let events = engine.evaluate("guid", &rules, &cpu_at(120, 97.0), &[]);
+
+        assert_eq!(statuses(&events), vec![AlertStatus::Active]);
+        assert_eq!(events[0].message, "CPU usage is 97.0%, above 90.0% for 2 min");
+        assert_eq!(events[0].fingerprint, "agent:guid:cpu-high:cpu");
+    }
+
+    #[test]
+    fn dipping_below_the_threshold_restarts_the_duration() {
+        let rules = [AlertRule { duration_secs: 120, ..cpu_rule() }];
+        let mut engine = AlertEngine::default();
+
+        engine.evaluate("guid", &rules, &cpu_at(0, 95.0), &[]);
+        engine.evaluate("guid", &rules, &cpu_at(60, 50.0), &[]);
+        assert!(engine.evaluate("guid", &rules, &cpu_at(120, 95.0), &[]).is_empty());
+        assert!(engine.evaluate("guid", &rules, &cpu_at(180, 95.0), &[]).is_empty());
+        let events = engine.evaluate("guid", &rules, &cpu_at(240, 95.0), &[]);
+
+        assert_eq!(statuses(&events), vec![AlertStatus::Active]);
+    }
+
+    #[test]
+    fn an_alert_only_resolves_past_the_clear_threshold() {
+        let rules = [AlertRule { clear_threshold: Some(80.0), ..cpu_rule() }];
+        let mut engine = AlertEngine::default();
+
+        assert_eq!(statuses(&engine.evaluate("guid", &rules, &cpu_at(0, 95.0), &[])), vec![AlertStatus::Active]);
+        assert!(engine.evaluate("guid", &rules, &cpu_at(60, 85.0), &[]).is_empty());
+        assert!(engine.evaluate("guid", &rules, &cpu_at(120, 80.5), &[]).is_empty());
+        let events = engine.evaluate("guid", &rules, &cpu_at(180, 80.0), &[]);
+
+        assert_eq!(statuses(&events), vec![AlertStatus::Resolved]);
+        assert_eq!(events[0].message, "CPU usage is back to 80.0%");
+    }
+
+    #[test]
+    fn a_resolved_alert_is_not_raised_again_within_the_cooldown() {
+        let rules = [AlertRule { cooldown_secs: Some(600), ..cpu_rule() }];
+        let mut engine = AlertEngine::default();
+
+        assert_eq!(statuses(&engine.evaluate("guid", &rules, &cpu_at(0, 95.0), &[])), vec![AlertStatus::Active]);
+        assert_eq!(statuses(&engine.evaluate("guid", &rules, &cpu_at(60, 50.0), &[])), vec![AlertStatus::Resolved]);
+        assert!(engine.evaluate("guid", &rules, &cpu_at(120, 95.0), &[]).is_empty());
+        assert!(engine.evaluate("guid", &rules, &cpu_at(599, 95.0), &[]).is_empty());
+
+        assert_eq!(statuses(&engine.evaluate("guid", &rules, &cpu_at(600, 95.0), &[])), vec![AlertStatus::Active]);
+    }
+
+    #[test]
+    fn removing_a_rule_resolves_its_alert_and_forgets_it() {
+        let mut engine = AlertEngine::default();
+        engine.evaluate("guid", &[cpu_rule()], &cpu_at(0, 95.0), &[]);
+
+        let events = engine.evaluate("guid", &[], &cpu_at(60, 95.0), &[]);
+
+        assert_eq!(statuses(&events), vec![AlertStatus::Resolved]);
+        assert_eq!(events[0].message, "CPU usage is no longer monitored");
+        assert_eq!(events[0].fingerprint, "agent:guid:cpu-high:cpu");
+        assert!(engine.states.is_empty());
+        assert!(engine.evaluate("guid", &[], &cpu_at(120, 95.0), &[]).is_empty());
+    }
+
+    #[test]
+    fn a_disk_that_disappears_resolves_only_its_own_alert() {
+        let rules = [AlertRule {
+            id: String::from("disk-full"),
+            metric: AlertMetric::DiskUsedPercent { mount: None },
+            ..cpu_rule()
+        }];
+        let mut engine = AlertEngine::default();
+
+        let events = engine.evaluate("guid", &rules, &disks_at(0, &[("/", 95.0), ("/data", 99.0)]), &[]);
+        assert_eq!(statuses(&events), vec![AlertStatus::Active, AlertStatus::Active]);
+
+        let events = engine.evaluate("guid", &rules, &disks_at(60, &[("/", 95.0)]), &[]);
+
+        assert_eq!(statuses(&events), vec![AlertStatus::Resolved]);
+        assert_eq!(events[0].fingerprint, "agent:guid:disk-full:/data");
+        assert_eq!(events[0].metadata["value"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn a_service_that_cannot_be_checked_keeps_its_alert() {
+        let rules = [AlertRule {
+            id: String::from("nginx-down"),
+            metric: AlertMetric::ServiceDown { name: None },
+            ..cpu_rule()
+        }];
+        let mut engine = AlertEngine::default();
+
+        let events = engine.evaluate("guid", &rules, &cpu_at(0, 0.0), &[service(ServiceStatus::Failed)]);
+        assert_eq!(statuses(&events), vec![AlertStatus::Active]);
+        assert_eq!(events[0].message, "Service nginx is down");
+
+        assert!(engine
+            .evaluate("guid", &rules, &cpu_at(60, 0.0), &[service(ServiceStatus::Unknown)])
+            .is_empty());
+
+        let events = engine.evaluate("guid", &rules, &cpu_at(120, 0.0), &[service(ServiceStatus::Running)]);
+        assert_eq!(statuses(&events), vec![AlertStatus::Resolved]);
+        assert_eq!(events[0].message, "Service nginx is running again");
+    }
+}
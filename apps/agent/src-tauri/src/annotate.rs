@@ -0,0 +1,657 @@
+use crate::screenshot::{get_screenshot_mime_type, get_screenshot_path, new_screenshot_file};
+use image::codecs::png::{CompressionType, FilterType as PngFilter, PngEncoder};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ExtendedColorType, ImageEncoder, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+pub const DEFAULT_MAX_DIMENSION: u32 = 1920;
+pub const DEFAULT_MAX_BYTES: usize = 4 * 1024 * 1024;
+
+// Downscaling stops here, an image still over the byte budget is rejected
+const MIN_DIMENSION: u32 = 320;
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+
+// Thicker strokes would only hide what the arrow points at
+const MAX_THICKNESS: u32 = 64;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Point {
+    pub x: i64,
+    pub y: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnnotationOp {
+    Blur {
+        rect: Rect,
+    },
+    Redact {
+        rect: Rect,
+        color: Option<String>,
+    },
+    Highlight {
+        rect: Rect,
+        color: Option<String>,
+        thickness: Option<u32>,
+    },
+    Arrow {
+        from: Point,
+        to: Point,
+        color: Option<String>,
+        thickness: Option<u32>,
+    },
+    Text {
+        at: Point,
+        text: String,
+        color: Option<String>,
+        background: Option<String>,
+        scale: Option<u32>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UploadOptions {
+    pub max_dimension: Option<u32>,
+    pub max_bytes: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AnnotatedImage {
+    pub id: String,
+    pub width: u32,
+    pub height: u32,
+    pub size_bytes: usize,
+}
+
+/// Parses `#rrggbb` or `#rrggbbaa` into a pixel
+pub fn parse_color(color: &str) -> Result<Rgba<u8>, Box<dyn std::error::Error>> {
+    let hex = color.trim_start_matches('#');
+    // Checked before slicing by byte offset, which would panic inside a multi-byte character
+    if (hex.len() != 6 && hex.len() != 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid color: {}", color).into());
+    }
+
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16);
+    let alpha = if hex.len() == 8 { channel(6)? } else { 255 };
+    Ok(Rgba([channel(0)?, channel(2)?, channel(4)?, alpha]))
+}
+
+fn color_or(color: &Option<String>, default: Rgba<u8>) -> Result<Rgba<u8>, Box<dyn std::error::Error>> {
+    match color {
+        Some(color) => parse_color(color),
+        None => Ok(default),
+    }
+}
+
+fn blend_pixel(image: &mut RgbaImage, x: i64, y: i64, color: Rgba<u8>) {
+    if x < 0 || y < 0 || x >= image.width() as i64 || y >= image.height() as i64 {
+        return;
+    }
+
+    let pixel = image.get_pixel_mut(x as u32, y as u32);
+    let alpha = color[3] as u32;
+    for i in 0..3 {
+        pixel[i] = ((color[i] as u32 * alpha + pixel[i] as u32 * (255 - alpha)) / 255) as u8;
+    }
+    pixel[3] = 255;
+}
+
+fn fill_rect(image: &mut RgbaImage, x: i64, y: i64, width: i64, height: i64, color: Rgba<u8>) {
+    // Clipped first so a huge rectangle costs no more than the image it covers
+    let (x0, y0) = (x.max(0), y.max(0));
+    let x1 = x.saturating_add(width).min(image.width() as i64);
+    let y1 = y.saturating_add(height).min(image.height() as i64);
+    for py in y0..y1 {
+        for px in x0..x1 {
+            blend_pixel(image, px, py, color);
+        }
+    }
+}
+
+/// Clamps a rectangle to the image, returning None if nothing is left
+fn clip_rect(image: &RgbaImage, rect: &Rect) -> Option<Rect> {
+    if rect.x >= image.width() || rect.y >= image.height() {
+        return None;
+    }
+
+    let width = rect.width.min(image.width() - rect.x);
+    let height = rect.height.min(image.height() - rect.y);
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    Some(Rect {
+        x: rect.x,
+        y: rect.y,
+        width,
+        height,
+    })
+}
+
+/// Clips a segment to the image grown by `margin` on every side (Liang-Barsky), so walking it
+/// never takes more steps than the image is wide
+fn clip_segment(image: &RgbaImage, from: Point, to: Point, margin: i64) -> Option<(Point, Point)> {
+    let (min_x, min_y) = (-margin as f64, -margin as f64);
+    let max_x = (image.width() as i64 - 1 + margin) as f64;
+    let max_y = (image.height() as i64 - 1 + margin) as f64;
+
+    let (x0, y0) = (from.x as f64, from.y as f64);
+    let (dx, dy) = (to.x as f64 - x0, to.y as f64 - y0);
+    let (mut t0, mut t1) = (0.0f64, 1.0f64);
+    for (p, q) in [(-dx, x0 - min_x), (dx, max_x - x0), (-dy, y0 - min_y), (dy, max_y - y0)] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+            continue;
+        }
+        let t = q / p;
+        if p < 0.0 {
+            t0 = t0.max(t);
+        } else {
+            t1 = t1.min(t);
+        }
+        if t0 > t1 {
+            return None;
+        }
+    }
+
+    let at = |t: f64| Point {
+        x: (x0 + t * dx).round() as i64,
+        y: (y0 + t * dy).round() as i64,
+    };
+    Some((at(t0), at(t1)))
+}
+
+fn draw_line(image: &mut RgbaImage, from: Point, to: Point, thickness: u32, color: Rgba<u8>) {
+    let thickness = thickness.clamp(1, MAX_THICKNESS);
+    let (from, to) = match clip_segment(image, from, to, thickness as i64) {
+        Some(segment) => segment,
+        None => return,
+    };
+
+    let (mut x, mut y) = (from.x, from.y);
+    let dx = (to.x - from.x).abs();
+    let dy = -(to.y - from.y).abs();
+    let sx = if from.x < to.x { 1 } else { -1 };
+    let sy = if from.y < to.y { 1 } else { -1 };
+    let mut err = dx + dy;
+
+    let half = thickness as i64 / 2;
+    loop {
+        for py in y - half..y - half + thickness as i64 {
+            for px in x - half..x - half + thickness as i64 {
+                if px >= 0 && py >= 0 && px < image.width() as i64 && py < image.height() as i64 {
+                    image.put_pixel(px as u32, py as u32, color);
+                }
+            }
+        }
+
+        if x == to.x && y == to.y {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+}
+
+fn draw_arrow(image: &mut RgbaImage, from: Point, to: Point, thickness: u32, color: Rgba<u8>) {
+    draw_line(image, from, to, thickness, color);
+
+    let angle = (to.y as f64 - from.y as f64).atan2(to.x as f64 - from.x as f64);
+    let head_length = (thickness.clamp(1, MAX_THICKNESS) * 5).max(12) as f64;
+    for side in [-1.0, 1.0] {
+        let head_angle = angle + std::f64::consts::PI - side * std::f64::consts::FRAC_PI_6;
+        let head = Point {
+            x: to.x.saturating_add((head_length * head_angle.cos()).round() as i64),
+            y: to.y.saturating_add((head_length * head_angle.sin()).round() as i64),
+        };
+        draw_line(image, to, head, thickness, color);
+    }
+}
+
+// Pixelates before blurring so small text can't be recovered from a light blur
+fn blur_rect(image: &mut RgbaImage, rect: &Rect) {
+    let rect = match clip_rect(image, rect) {
+        Some(rect) => rect,
+        None => return,
+    };
+
+    let region = imageops::crop_imm(image, rect.x, rect.y, rect.width, rect.height).to_image();
+    let block = (rect.width.min(rect.height) / 6).clamp(4, 24);
+    let small = imageops::resize(
+        &region,
+        (rect.width / block).max(1),
+        (rect.height / block).max(1),
+        FilterType::Triangle,
+    );
+    let pixelated = imageops::resize(&small, rect.width, rect.height, FilterType::Nearest);
+    let blurred = imageops::blur(&pixelated, block as f32 / 2.0);
+
+    imageops::replace(image, &blurred, rect.x as i64, rect.y as i64);
+}
+
+/// 5x7 bitmap glyphs, one byte per row with the leftmost pixel in bit 4
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        'A' => [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        'B' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110],
+        'C' => [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110],
+        'D' => [0b11110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b11110],
+        'E' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111],
+        'F' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000],
+        'G' => [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111],
+        'H' => [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        'I' => [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+        'J' => [0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100],
+        'K' => [0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001],
+        'L' => [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111],
+        'M' => [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001],
+        'N' => [0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001],
+        'O' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'P' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000],
+        'Q' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101],
+        'R' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001],
+        'S' => [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110],
+        'T' => [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100],
+        'U' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'V' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100],
+        'W' => [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010],
+        'X' => [0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001],
+        'Y' => [0b10001, 0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100],
+        'Z' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111],
+        '0' => [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110],
+        '1' => [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+        '2' => [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111],
+        '3' => [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110],
+        '4' => [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010],
+        '5' => [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110],
+        '6' => [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110],
+        '7' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000],
+        '8' => [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110],
+        '9' => [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100],
+        ' ' => [0; 7],
+        '.' => [0, 0, 0, 0, 0, 0b01100, 0b01100],
+        ',' => [0, 0, 0, 0, 0b01100, 0b00100, 0b01000],
+        ':' => [0, 0b01100, 0b01100, 0, 0b01100, 0b01100, 0],
+        ';' => [0, 0b01100, 0b01100, 0, 0b01100, 0b00100, 0b01000],
+        '!' => [0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0, 0b00100],
+        '-' => [0, 0, 0, 0b11111, 0, 0, 0],
+        '_' => [0, 0, 0, 0, 0, 0, 0b11111],
+        '/' => [0, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0],
+        '\\' => [0, 0b10000, 0b01000, 0b00100, 0b00010, 0b00001, 0],
+        '(' => [0b00010, 0b00100, 0b01000, 0b01000, 0b01000, 0b00100, 0b00010],
+        ')' => [0b01000, 0b00100, 0b00010, 0b00010, 0b00010, 0b00100, 0b01000],
+        '\'' => [0b00100, 0b00100, 0b01000, 0, 0, 0, 0],
+        '"' => [0b01010, 0b01010, 0, 0, 0, 0, 0],
+        '#' => [0b01010, 0b01010, 0b11111, 0b01010, 0b11111, 0b01010, 0b01010],
+        '+' => [0, 0b00100, 0b00100, 0b11111, 0b00100, 0b00100, 0],
+        '=' => [0, 0, 0b11111, 0, 0b11111, 0, 0],
+        '<' => [0b00010, 0b00100, 0b01000, 0b10000, 0b01000, 0b00100, 0b00010],
+        '>' => [0b01000, 0b00100, 0b00010, 0b00001, 0b00010, 0b00100, 0b01000],
+        '@' => [0b01110, 0b10001, 0b00001, 0b01101, 0b10101, 0b10101, 0b01110],
+        '%' => [0b11000, 0b11001, 0b00010, 0b00100, 0b01000, 0b10011, 0b00011],
+        '&' => [0b01100, 0b10010, 0b10100, 0b01000, 0b10101, 0b10010, 0b01101],
+        _ => [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0, 0b00100],
+    }
+}
+
+fn draw_text(
+    image: &mut RgbaImage,
+    at: Point,
+    text: &str,
+    scale: u32,
+    color: Rgba<u8>,
+    background: Option<Rgba<u8>>,
+) {
+    let scale = scale.max(1) as i64;
+    let advance = (GLYPH_WIDTH as i64 + 1) * scale;
+    let padding = scale * 2;
+
+    if let Some(background) = background {
+        let width = advance.saturating_mul(text.chars().count() as i64) - scale;
+        fill_rect(
+            image,
+            at.x.saturating_sub(padding),
+            at.y.saturating_sub(padding),
+            width.saturating_add(padding * 2),
+            GLYPH_HEIGHT as i64 * scale + padding * 2,
+            background,
+        );
+    }
+
+    for (i, c) in text.chars().enumerate() {
+        let origin_x = at.x.saturating_add((i as i64).saturating_mul(advance));
+        if origin_x >= image.width() as i64 {
+            break;
+        }
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - col)) != 0 {
+                    fill_rect(
+                        image,
+                        origin_x.saturating_add(col as i64 * scale),
+                        at.y.saturating_add(row as i64 * scale),
+                        scale,
+                        scale,
+                        color,
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Renders every operation onto the image in order
+pub fn render_annotations(
+    image: &mut RgbaImage,
+    operations: &[AnnotationOp],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let red = Rgba([220, 38, 38, 255]);
+    let black = Rgba([0, 0, 0, 255]);
+
+    for op in operations {
+        match op {
+            AnnotationOp::Blur { rect } => blur_rect(image, rect),
+            AnnotationOp::Redact { rect, color } => {
+                let mut color = color_or(color, black)?;
+                // A redaction that lets the content show through isn't one
+                color[3] = 255;
+                if let Some(rect) = clip_rect(image, rect) {
+                    fill_rect(
+                        image,
+                        rect.x as i64,
+                        rect.y as i64,
+                        rect.width as i64,
+                        rect.height as i64,
+                        color,
+                    );
+                }
+            }
+            AnnotationOp::Highlight {
+                rect,
+                color,
+                thickness,
+            } => {
+                let color = color_or(color, red)?;
+                let thickness = thickness.unwrap_or(4) as i64;
+                let (x, y) = (rect.x as i64, rect.y as i64);
+                let (w, h) = (rect.width as i64, rect.height as i64);
+
+                fill_rect(image, x, y, w, h, Rgba([color[0], color[1], color[2], 40]));
+                fill_rect(image, x, y, w, thickness, color);
+                fill_rect(image, x, y + h - thickness, w, thickness, color);
+                fill_rect(image, x, y, thickness, h, color);
+                fill_rect(image, x + w - thickness, y, thickness, h, color);
+            }
+            AnnotationOp::Arrow {
+                from,
+                to,
+                color,
+                thickness,
+            } => draw_arrow(image, *from, *to, thickness.unwrap_or(4), color_or(color, red)?),
+            AnnotationOp::Text {
+                at,
+                text,
+                color,
+                background,
+                scale,
+            } => {
+                let background = match background {
+                    Some(background) => Some(parse_color(background)?),
+                    None => None,
+                };
+                draw_text(
+                    image,
+                    *at,
+                    text,
+                    scale.unwrap_or(3),
+                    color_or(color, red)?,
+                    background,
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn encode_png(image: &RgbaImage) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    // Screenshots are opaque, dropping alpha saves a quarter of the raw size
+    let rgb = DynamicImage::ImageRgba8(image.clone()).into_rgb8();
+
+    let mut bytes = Vec::new();
+    let encoder = PngEncoder::new_with_quality(&mut bytes, CompressionType::Best, PngFilter::Adaptive);
+    encoder.write_image(rgb.as_raw(), rgb.width(), rgb.height(), ExtendedColorType::Rgb8)?;
+    Ok(bytes)
+}
+
+/// Downscales and re-encodes an image until it fits the upload limits, failing when it
+/// still doesn't at the smallest size. The output is a fresh PNG, so no metadata from the
+/// source file survives.
+pub fn encode_for_upload(
+    image: &RgbaImage,
+    options: &UploadOptions,
+) -> Result<(Vec<u8>, u32, u32), Box<dyn std::error::Error>> {
+    let max_dimension = options.max_dimension.unwrap_or(DEFAULT_MAX_DIMENSION).max(MIN_DIMENSION);
+    let max_bytes = options.max_bytes.unwrap_or(DEFAULT_MAX_BYTES);
+
+    let longest = image.width().max(image.height());
+    let mut scale = if longest > max_dimension {
+        max_dimension as f64 / longest as f64
+    } else {
+        1.0
+    };
+
+    loop {
+        let width = ((image.width() as f64 * scale).round() as u32).max(1);
+        let height = ((image.height() as f64 * scale).round() as u32).max(1);
+        let resized = if scale < 1.0 {
+            imageops::resize(image, width, height, FilterType::Lanczos3)
+        } else {
+            image.clone()
+        };
+
+        let bytes = encode_png(&resized)?;
+        if bytes.len() <= max_bytes {
+            return Ok((bytes, width, height));
+        }
+        if width.max(height) <= MIN_DIMENSION {
+            return Err(format!(
+                "Image is {} bytes, over the {} byte limit even at {}x{}",
+                bytes.len(),
+                max_bytes,
+                width,
+                height
+            )
+            .into());
+        }
+        scale *= 0.75;
+    }
+}
+
+/// Applies operations to the image at `source` and returns the upload-ready PNG with its size
+pub fn annotate_image(
+    source: &Path,
+    operations: &[AnnotationOp],
+    options: &UploadOptions,
+) -> Result<(Vec<u8>, u32, u32), Box<dyn std::error::Error>> {
+    let mut image = image::open(source)?.into_rgba8();
+    render_annotations(&mut image, operations)?;
+    encode_for_upload(&image, options)
+}
+
+/// Annotates a stored screenshot, saving the result as a new screenshot so the original can be re-edited
+pub fn annotate_screenshot(
+    id: &str,
+    operations: &[AnnotationOp],
+    options: &UploadOptions,
+) -> Result<AnnotatedImage, Box<dyn std::error::Error>> {
+    let source = get_screenshot_path(id)?;
+    if get_screenshot_mime_type(&source) != "image/png" {
+        return Err("Only still screenshots can be annotated".into());
+    }
+    let (bytes, width, height) = annotate_image(&source, operations, options)?;
+
+    let (annotated_id, output) = new_screenshot_file("png")?;
+    std::fs::write(output, &bytes)?;
+
+    Ok(AnnotatedImage {
+        id: annotated_id,
+        width,
+        height,
+        size_bytes: bytes.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    fn fixture() -> RgbaImage {
+        RgbaImage::from_pixel(64, 48, WHITE)
+    }
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> Rect {
+        Rect { x, y, width, height }
+    }
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!(parse_color("#ff8000").unwrap(), Rgba([255, 128, 0, 255]));
+        assert_eq!(parse_color("00ff0080").unwrap(), Rgba([0, 255, 0, 128]));
+        assert!(parse_color("#ff80").is_err());
+        assert!(parse_color("#gg0000").is_err());
+        // Six bytes but not six characters, slicing these used to panic
+        assert!(parse_color("#ééé").is_err());
+        assert!(parse_color("#+1+2+3").is_err());
+    }
+
+    #[test]
+    fn redact_covers_only_the_clipped_rect() {
+        let mut image = fixture();
+        let ops = [AnnotationOp::Redact { rect: rect(60, 40, 100, 100), color: Some(String::from("#ff000010")) }];
+        render_annotations(&mut image, &ops).unwrap();
+
+        assert_eq!(*image.get_pixel(63, 47), Rgba([255, 0, 0, 255]));
+        assert_eq!(*image.get_pixel(59, 47), WHITE);
+    }
+
+    #[test]
+    fn highlight_draws_the_border() {
+        let mut image = fixture();
+        let ops = [AnnotationOp::Highlight { rect: rect(10, 10, 20, 20), color: Some(String::from("#0000ff")), thickness: Some(2) }];
+        render_annotations(&mut image, &ops).unwrap();
+
+        assert_eq!(*image.get_pixel(10, 10), Rgba([0, 0, 255, 255]));
+        assert_eq!(*image.get_pixel(29, 29), Rgba([0, 0, 255, 255]));
+        // Tinted inside, untouched outside
+        assert_ne!(*image.get_pixel(20, 20), WHITE);
+        assert_eq!(*image.get_pixel(31, 31), WHITE);
+    }
+
+    #[test]
+    fn arrow_and_text_draw_on_the_image() {
+        let mut image = fixture();
+        let ops = [
+            AnnotationOp::Arrow { from: Point { x: 2, y: 2 }, to: Point { x: 40, y: 2 }, color: None, thickness: Some(1) },
+            AnnotationOp::Text { at: Point { x: 4, y: 30 }, text: String::from("HI"), color: Some(String::from("#000000")), background: None, scale: Some(1) },
+        ];
+        render_annotations(&mut image, &ops).unwrap();
+
+        assert_eq!(*image.get_pixel(20, 2), Rgba([220, 38, 38, 255]));
+        // Top-left pixel of the H
+        assert_eq!(*image.get_pixel(4, 30), Rgba([0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn huge_coordinates_finish_quickly() {
+        let mut image = fixture();
+        let ops = [
+            AnnotationOp::Highlight { rect: rect(0, 0, u32::MAX, u32::MAX), color: None, thickness: Some(u32::MAX) },
+            AnnotationOp::Arrow { from: Point { x: i64::MIN, y: i64::MIN }, to: Point { x: i64::MAX, y: i64::MAX }, color: None, thickness: Some(u32::MAX) },
+            AnnotationOp::Arrow { from: Point { x: -1_000_000_000, y: 5 }, to: Point { x: 1_000_000_000, y: 5 }, color: None, thickness: None },
+            AnnotationOp::Text { at: Point { x: i64::MAX - 1, y: i64::MAX - 1 }, text: String::from("far away"), color: None, background: Some(String::from("#000000")), scale: Some(u32::MAX) },
+            AnnotationOp::Text { at: Point { x: -10, y: -10 }, text: String::from("big"), color: None, background: Some(String::from("#000000")), scale: Some(u32::MAX) },
+        ];
+
+        let started = Instant::now();
+        render_annotations(&mut image, &ops).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5), "took {:?}", started.elapsed());
+        // The oversized text's first glyph covers everything
+        assert_eq!(*image.get_pixel(32, 5), Rgba([220, 38, 38, 255]));
+    }
+
+    // Random pixels barely compress, so the PNG stays large however it is scaled
+    fn noise(width: u32, height: u32) -> RgbaImage {
+        let mut seed: u32 = 0x2545_f491;
+        RgbaImage::from_fn(width, height, |_, _| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let [r, g, b, _] = seed.to_le_bytes();
+            Rgba([r, g, b, 255])
+        })
+    }
+
+    #[test]
+    fn large_images_are_scaled_down_to_fit() {
+        let image = noise(640, 320);
+        let options = UploadOptions { max_dimension: None, max_bytes: Some(300 * 1024) };
+        let (bytes, width, height) = encode_for_upload(&image, &options).unwrap();
+
+        assert!(bytes.len() <= 300 * 1024, "{} bytes", bytes.len());
+        assert!((MIN_DIMENSION..640).contains(&width), "{}x{}", width, height);
+        assert_eq!(width, height * 2);
+    }
+
+    #[test]
+    fn images_over_the_limit_at_the_smallest_size_are_rejected() {
+        let options = UploadOptions { max_dimension: None, max_bytes: Some(1024) };
+
+        let error = encode_for_upload(&noise(400, 200), &options).unwrap_err();
+        assert!(error.to_string().contains("over the 1024 byte limit"), "{}", error);
+        // Already below the smallest size, there's nothing left to shrink
+        assert!(encode_for_upload(&noise(100, 100), &options).is_err());
+    }
+
+    #[test]
+    fn annotates_a_fixture_file_for_upload() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("fixture.png");
+        RgbaImage::from_pixel(400, 200, WHITE).save(&source).unwrap();
+
+        let ops = [AnnotationOp::Redact { rect: rect(0, 0, 200, 200), color: None }];
+        let options = UploadOptions { max_dimension: Some(320), max_bytes: None };
+        let (bytes, width, height) = annotate_image(&source, &ops, &options).unwrap();
+
+        assert_eq!((width, height), (320, 160));
+        let output = image::load_from_memory(&bytes).unwrap().into_rgba8();
+        assert_eq!(output.dimensions(), (320, 160));
+        assert_eq!(*output.get_pixel(10, 80), Rgba([0, 0, 0, 255]));
+        assert_eq!(*output.get_pixel(310, 80), WHITE);
+    }
+}
+
@@ -0,0 +1,257 @@
+use crate::annotate::parse_color;
+use crate::device_manager::{get_config_dir, get_settings_path, Settings};
+use image::imageops::{self, FilterType};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::time::Duration;
+
+pub const DEFAULT_BRAND_NAME: &str = "MSPAgent";
+
+// Logos only ever show as icons, anything bigger is a mistake or abuse
+const MAX_LOGO_BYTES: usize = 1024 * 1024;
+const LOGO_SIZE: u32 = 256;
+
+/// Tenant branding, every field falls back to the agent's own look when not set
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Branding {
+    pub name: Option<String>,
+    pub logo_url: Option<String>,
+    pub accent_color: Option<String>,
+    pub support_phone: Option<String>,
+    pub support_email: Option<String>,
+}
+
+impl Branding {
+    /// Fields set here win over the ones in `base`
+    fn over(self, base: Branding) -> Branding {
+        Branding {
+            name: self.name.or(base.name),
+            logo_url: self.logo_url.or(base.logo_url),
+            accent_color: self.accent_color.or(base.accent_color),
+            support_phone: self.support_phone.or(base.support_phone),
+            support_email: self.support_email.or(base.support_email),
+        }
+    }
+
+    pub fn display_name(&self) -> &str {
+        self.name
+            .as_deref()
+            .filter(|name| !name.trim().is_empty())
+            .unwrap_or(DEFAULT_BRAND_NAME)
+    }
+}
+
+pub fn get_branding_dir() -> PathBuf {
+    get_config_dir().join("branding")
+}
+
+fn get_server_branding_path() -> PathBuf {
+    get_branding_dir().join("branding.json")
+}
+
+pub fn get_logo_path() -> PathBuf {
+    get_branding_dir().join("logo.png")
+}
+
+// The URL the cached logo was downloaded from, so it's only fetched again when it changes
+fn get_logo_source_path() -> PathBuf {
+    get_branding_dir().join("logo.url")
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &PathBuf) -> Option<T> {
+    let content = std::fs::read(path).ok()?;
+    serde_json::from_slice(&content).ok()
+}
+
+/// Branding from settings laid over the last branding the server sent.
+/// Reads synchronously so windows can be titled as they're created.
+pub fn load_branding() -> Branding {
+    let server: Branding = read_json(&get_server_branding_path()).unwrap_or_default();
+    let local = read_json::<Settings>(&get_settings_path())
+        .and_then(|settings| settings.branding)
+        .unwrap_or_default();
+    local.over(server)
+}
+
+/// The cached logo, if there is one
+pub fn get_logo() -> Option<PathBuf> {
+    let path = get_logo_path();
+    path.exists().then_some(path)
+}
+
+/// Caches the branding sent by the server, returning whether it changed
+pub async fn save_server_branding(branding: &Branding) -> Result<bool, Box<dyn std::error::Error>> {
+    let path = get_server_branding_path();
+    let current: Option<Branding> = read_json(&path);
+    if current.as_ref() == Some(branding) {
+        return Ok(false);
+    }
+
+    tokio::fs::create_dir_all(get_branding_dir()).await?;
+    tokio::fs::write(&path, serde_json::to_vec_pretty(branding)?).await?;
+    Ok(true)
+}
+
+async fn download_logo(url: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    if !url.to_lowercase().starts_with("https://") {
+        return Err(format!("Logo URL must use https: {}", url).into());
+    }
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(15))
+        .build()?;
+    let mut response = client.get(url).send().await?.error_for_status()?;
+    if response.content_length().unwrap_or(0) as usize > MAX_LOGO_BYTES {
+        return Err(format!("Logo is over {} bytes", MAX_LOGO_BYTES).into());
+    }
+
+    // The length header can be missing or wrong, stop reading as soon as the limit is passed
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if bytes.len() + chunk.len() > MAX_LOGO_BYTES {
+            return Err(format!("Logo is over {} bytes", MAX_LOGO_BYTES).into());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+// Formats the image crate is built to decode, checked up front for a clearer error
+const LOGO_FORMATS: &[image::ImageFormat] = &[
+    image::ImageFormat::Png,
+    image::ImageFormat::Jpeg,
+    image::ImageFormat::Gif,
+    image::ImageFormat::WebP,
+    image::ImageFormat::Ico,
+];
+
+/// Re-encodes whatever was served into a square PNG we know is well formed
+fn render_logo(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let format = image::guess_format(bytes)
+        .ok()
+        .filter(|format| LOGO_FORMATS.contains(format))
+        .ok_or("Logo must be a PNG, JPEG, GIF, WebP or ICO image")?;
+    let image = image::load_from_memory_with_format(bytes, format).map_err(|e| e.to_string())?;
+    let image = image.resize(LOGO_SIZE, LOGO_SIZE, FilterType::Triangle).into_rgba8();
+
+    // Center on a square canvas, tray icons are square
+    let mut canvas = image::RgbaImage::new(LOGO_SIZE, LOGO_SIZE);
+    imageops::overlay(
+        &mut canvas,
+        &image,
+        ((LOGO_SIZE - image.width()) / 2) as i64,
+        ((LOGO_SIZE - image.height()) / 2) as i64,
+    );
+
+    let mut png = Vec::new();
+    canvas
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .map_err(|e| e.to_string())?;
+    Ok(png)
+}
+
+/// Brings the cached logo in line with the current branding, downloading it when the URL changed
+pub async fn sync_logo() -> Result<(), Box<dyn std::error::Error>> {
+    let logo_path = get_logo_path();
+    let source_path = get_logo_source_path();
+
+    let url = match load_branding().logo_url.filter(|url| !url.trim().is_empty()) {
+        Some(url) => url,
+        None => {
+            let _ = tokio::fs::remove_file(&logo_path).await;
+            let _ = tokio::fs::remove_file(&source_path).await;
+            return Ok(());
+        }
+    };
+
+    let cached_source = tokio::fs::read_to_string(&source_path).await.unwrap_or_default();
+    if cached_source == url && logo_path.exists() {
+        return Ok(());
+    }
+
+    let bytes = download_logo(&url).await?;
+
+    let logo = tauri::async_runtime::spawn_blocking(move || render_logo(&bytes))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|r| r)?;
+
+    tokio::fs::create_dir_all(get_branding_dir()).await?;
+    tokio::fs::write(&logo_path, logo).await?;
+    tokio::fs::write(&source_path, &url).await?;
+    Ok(())
+}
+
+/// Branding as the webview needs it, with the logo inlined
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BrandingInfo {
+    pub name: String,
+    pub accent_color: Option<String>,
+    pub support_phone: Option<String>,
+    pub support_email: Option<String>,
+    pub logo: Option<String>,
+}
+
+pub fn get_branding_info() -> BrandingInfo {
+    use base64::Engine;
+
+    let branding = load_branding();
+    let logo = get_logo()
+        .and_then(|path| std::fs::read(path).ok())
+        .map(|bytes| {
+            format!(
+                "data:image/png;base64,{}",
+                base64::engine::general_purpose::STANDARD.encode(bytes)
+            )
+        });
+
+    BrandingInfo {
+        name: branding.display_name().to_string(),
+        // The webview drops this straight into CSS, only pass on real colors
+        accent_color: branding
+            .accent_color
+            .filter(|color| color.starts_with('#') && parse_color(color).is_ok()),
+        support_phone: branding.support_phone,
+        support_email: branding.support_email,
+        logo,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(format: image::ImageFormat) -> Vec<u8> {
+        let image = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            64,
+            32,
+            image::Rgba([200, 40, 40, 255]),
+        ));
+        // JPEG has no alpha channel to encode
+        let image = match format {
+            image::ImageFormat::Jpeg => image::DynamicImage::ImageRgb8(image.to_rgb8()),
+            _ => image,
+        };
+        let mut bytes = Vec::new();
+        image
+            .write_to(&mut std::io::Cursor::new(&mut bytes), format)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn logos_in_common_formats_render_as_square_pngs() {
+        for &format in LOGO_FORMATS {
+            let png = render_logo(&encode(format)).unwrap_or_else(|e| panic!("{:?}: {}", format, e));
+            let logo = image::load_from_memory_with_format(&png, image::ImageFormat::Png).unwrap();
+            assert_eq!((logo.width(), logo.height()), (LOGO_SIZE, LOGO_SIZE), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn unsupported_logos_are_rejected() {
+        let err = render_logo(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>").unwrap_err();
+        assert!(err.contains("PNG, JPEG"), "{}", err);
+        assert!(render_logo(b"BM\0\0\0\0").is_err());
+    }
+}
@@ -0,0 +1,739 @@
+use crate::device_manager::{
+    get_config_dir, get_machine_id, get_settings, get_settings_path, reset_identity,
+};
+use crate::device_registration::register_device_to_site;
+use crate::heartbeat::{gather_system_info, post_heartbeat, HeartbeatResponse};
+use crate::ipc::{request, IpcCommand, IpcError};
+use crate::job_signing::{
+    generate_keypair, open_envelope, sign_job, sign_key_rotation, verify_envelope, JobEnvelope,
+    PinnedKey, SignedJob,
+};
+use crate::jobs::Job;
+use crate::logger::{get_log_path, log_message, LogLevel};
+use crate::service::run_service;
+use crate::updater::guard_update;
+#[cfg(target_os = "windows")]
+use crate::updater::watch_applied_update;
+use serde::Serialize;
+use std::future::Future;
+use tokio::time::Duration;
+
+// Exit codes are part of the contract with RMM scripts, do not renumber
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_NOT_REGISTERED: i32 = 3;
+pub const EXIT_NO_SETTINGS: i32 = 4;
+pub const EXIT_NETWORK: i32 = 5;
+pub const EXIT_CONFLICT: i32 = 6;
+
+const COMMANDS: &[&str] = &[
+    "status",
+    "register",
+    "heartbeat",
+    "info",
+    "logs",
+    "diagnose",
+    "reset-identity",
+    "service",
+    "update-guard",
+    "job-keygen",
+    "job-sign",
+    "job-verify",
+    "job-rotate",
+    "help",
+    "--help",
+    "-h",
+    "--version",
+    "-V",
+];
+
+const DEFAULT_TAIL_LINES: usize = 50;
+
+const USAGE: &str = "Usage: MSPAgent <command> [options]
+
+Commands:
+  status [--json]                 Show registration, device id and last heartbeat
+  register --site <id> [--force]  Register this device with the server
+  heartbeat --once                Send a single heartbeat through the service and report the result
+  info                            Print the current system info as JSON
+  logs [--tail <lines>]           Print the agent log (default: last 50 lines)
+  diagnose                        Run connectivity and configuration checks
+  reset-identity                  Clear device id, guid and registration time
+  service                         Run the privileged agent service (used by the installer)
+  update-guard [--watch]          Roll back an update that keeps failing to start, run from the
+                                  backup before each service start (--watch on Windows)
+
+Job signing (for testing jobs without the server):
+  job-keygen                      Print a new Ed25519 keypair as base64 JSON
+  job-sign --key <private> --file <job.json> [--expires-in <secs>] [--guid <guid>]
+                                  Sign a job and print the envelope the server would send
+  job-verify --file <envelope.json> [--key <public>]
+                                  Check an envelope against the pinned keys, or the given key
+  job-rotate --key <private> --new <public>
+                                  Print a key rotation signed by the current key
+
+Exit codes:
+  0 success, 1 failure, 2 usage error, 3 not registered,
+  4 settings missing, 5 network error, 6 conflicting registration";
+
+struct CliArgs {
+    command: String,
+    rest: Vec<String>,
+}
+
+impl CliArgs {
+    fn parse(args: &[String]) -> Self {
+        CliArgs {
+            command: args.first().cloned().unwrap_or_default(),
+            rest: args.iter().skip(1).cloned().collect(),
+        }
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.rest.iter().any(|a| a == name)
+    }
+
+    fn value(&self, name: &str) -> Option<&str> {
+        let prefix = format!("{}=", name);
+        for (i, arg) in self.rest.iter().enumerate() {
+            if arg == name {
+                return self.rest.get(i + 1).map(|v| v.as_str());
+            }
+            if let Some(value) = arg.strip_prefix(&prefix) {
+                return Some(value);
+            }
+        }
+        None
+    }
+}
+
+#[derive(Serialize)]
+struct StatusOutput {
+    registered: bool,
+    site_id: String,
+    device_id: Option<String>,
+    guid: Option<String>,
+    hostname: Option<String>,
+    api_host: String,
+    registered_at: Option<String>,
+    last_heartbeat_at: Option<String>,
+    version: String,
+}
+
+/// Returns true when the arguments ask for a CLI subcommand instead of the app
+pub fn is_cli_invocation(args: &[String]) -> bool {
+    args.first()
+        .map(|cmd| COMMANDS.contains(&cmd.as_str()))
+        .unwrap_or(false)
+}
+
+/// Runs a CLI subcommand and returns the process exit code
+pub fn run(args: Vec<String>) -> i32 {
+    attach_parent_console();
+
+    let args = CliArgs::parse(&args);
+    match args.command.as_str() {
+        "status" => tauri::async_runtime::block_on(status(&args)),
+        "register" => tauri::async_runtime::block_on(register(&args)),
+        "heartbeat" => tauri::async_runtime::block_on(heartbeat(&args)),
+        "info" => tauri::async_runtime::block_on(info()),
+        "logs" => logs(&args),
+        "diagnose" => tauri::async_runtime::block_on(diagnose()),
+        "reset-identity" => tauri::async_runtime::block_on(reset()),
+        "service" => tauri::async_runtime::block_on(service()),
+        "update-guard" => tauri::async_runtime::block_on(update_guard(&args)),
+        "job-keygen" => job_keygen(),
+        "job-sign" => job_sign(&args),
+        "job-verify" => tauri::async_runtime::block_on(job_verify(&args)),
+        "job-rotate" => job_rotate(&args),
+        "--version" | "-V" => {
+            println!("MSPAgent {}", env!("CARGO_PKG_VERSION"));
+            EXIT_OK
+        }
+        _ => {
+            println!("{}", USAGE);
+            EXIT_OK
+        }
+    }
+}
+
+fn usage_error(message: &str) -> i32 {
+    eprintln!("{}\n\n{}", message, USAGE);
+    EXIT_USAGE
+}
+
+// Logging is best effort here, the CLI may run without write access to the logs dir
+fn log(level: &str, message: String) {
+    let _ = log_message(LogLevel::from(level.to_string()), &message);
+}
+
+async fn status(args: &CliArgs) -> i32 {
+    let settings = match get_settings().await {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Failed to load settings: {}", e);
+            return EXIT_NO_SETTINGS;
+        }
+    };
+
+    let output = StatusOutput {
+        registered: settings.registered_at.is_some(),
+        site_id: settings.site_id,
+        device_id: settings.device_id,
+        guid: settings.guid,
+        hostname: settings.hostname,
+        api_host: settings.api_host,
+        registered_at: settings.registered_at,
+        last_heartbeat_at: settings.last_heartbeat_at,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    };
+
+    if args.flag("--json") {
+        match serde_json::to_string_pretty(&output) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Failed to serialize status: {}", e);
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        let none = || "N/A".to_string();
+        println!("Version:        {}", output.version);
+        println!("Registered:     {}", if output.registered { "yes" } else { "no" });
+        println!("Site ID:        {}", output.site_id);
+        println!("Device ID:      {}", output.device_id.clone().unwrap_or_else(none));
+        println!("GUID:           {}", output.guid.clone().unwrap_or_else(none));
+        println!("Hostname:       {}", output.hostname.clone().unwrap_or_else(none));
+        println!("API host:       {}", output.api_host);
+        println!("Registered at:  {}", output.registered_at.clone().unwrap_or_else(none));
+        println!("Last heartbeat: {}", output.last_heartbeat_at.clone().unwrap_or_else(none));
+    }
+
+    if output.registered {
+        EXIT_OK
+    } else {
+        EXIT_NOT_REGISTERED
+    }
+}
+
+async fn register(args: &CliArgs) -> i32 {
+    let site_id = match args.value("--site") {
+        Some(site) if !site.trim().is_empty() => site.trim().to_string(),
+        _ => return usage_error("register requires --site <id>"),
+    };
+    let force = args.flag("--force");
+
+    let settings = match get_settings().await {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Failed to load settings: {}", e);
+            return EXIT_NO_SETTINGS;
+        }
+    };
+
+    if settings.registered_at.is_some() && !force {
+        if settings.site_id == site_id {
+            println!(
+                "Device already registered to site {} (device id: {})",
+                site_id,
+                settings.device_id.unwrap_or_else(|| "N/A".to_string())
+            );
+            return EXIT_OK;
+        }
+        eprintln!(
+            "Device is registered to site {}, use --force to re-register to {}",
+            settings.site_id, site_id
+        );
+        return EXIT_CONFLICT;
+    }
+
+    log("INFO", format!("CLI registration requested for site {}", site_id));
+    // The current identity stays until the server accepts the new registration
+    match register_device_to_site(Some(&site_id)).await {
+        Ok(response) => {
+            log(
+                "INFO",
+                format!("Device registered via CLI, device id {}", response.data.device_id),
+            );
+            println!("Device registered successfully");
+            println!("Device ID: {}", response.data.device_id);
+            println!("GUID:      {}", response.data.guid);
+            EXIT_OK
+        }
+        Err(e) => {
+            log("ERROR", format!("CLI registration failed: {}", e));
+            eprintln!("Failed to register device: {}", e);
+            EXIT_NETWORK
+        }
+    }
+}
+
+async fn heartbeat(args: &CliArgs) -> i32 {
+    if !args.flag("--once") {
+        return usage_error("heartbeat requires --once");
+    }
+
+    match get_settings().await {
+        Ok(settings) if settings.device_id.is_none() => {
+            eprintln!("Device not registered, cannot send heartbeat");
+            return EXIT_NOT_REGISTERED;
+        }
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("Failed to load settings: {}", e);
+            return EXIT_NO_SETTINGS;
+        }
+    }
+
+    heartbeat_once(request::<serde_json::Value>(IpcCommand::SendHeartbeat), post_heartbeat).await
+}
+
+/// Sends the heartbeat through the service, or straight to the server when the service can't
+/// be reached. Once the service got the command it may have sent one, so there's no second try.
+async fn heartbeat_once<D, Fut>(
+    via_service: impl Future<Output = Result<serde_json::Value, IpcError>>,
+    direct: D,
+) -> i32
+where
+    D: FnOnce() -> Fut,
+    Fut: Future<Output = Result<HeartbeatResponse, Box<dyn std::error::Error>>>,
+{
+    // The service runs any jobs in the reply and keeps the settings, this process would exit
+    // before a job finished and the server won't deliver it twice
+    match via_service.await {
+        Ok(data) => {
+            let guid = data.get("guid").and_then(|g| g.as_str()).unwrap_or("N/A");
+            println!("Heartbeat sent by the agent service (guid: {})", guid);
+            return EXIT_OK;
+        }
+        Err(IpcError::Unavailable(e)) => {
+            log("WARN", format!("Agent service unavailable for CLI heartbeat: {}", e));
+        }
+        Err(e) => {
+            eprintln!("Failed to send heartbeat: {}", e);
+            return EXIT_NETWORK;
+        }
+    }
+
+    match direct().await {
+        Ok(response) => {
+            println!("Heartbeat sent successfully (guid: {})", response.data.guid);
+            if !response.data.jobs.is_empty() || !response.data.cancel_jobs.is_empty() {
+                println!("The agent service is not running, jobs in the reply were left for it");
+            }
+            EXIT_OK
+        }
+        Err(e) => {
+            eprintln!("Failed to send heartbeat: {}", e);
+            EXIT_NETWORK
+        }
+    }
+}
+
+async fn info() -> i32 {
+    let info = match gather_system_info().await {
+        Ok(info) => info,
+        Err(e) => {
+            eprintln!("Failed to gather system info: {}", e);
+            return EXIT_NO_SETTINGS;
+        }
+    };
+
+    match serde_json::to_string_pretty(&info) {
+        Ok(json) => {
+            println!("{}", json);
+            EXIT_OK
+        }
+        Err(e) => {
+            eprintln!("Failed to serialize system info: {}", e);
+            EXIT_FAILURE
+        }
+    }
+}
+
+fn logs(args: &CliArgs) -> i32 {
+    let lines = match args.value("--tail") {
+        Some(value) => match value.parse::<usize>() {
+            Ok(lines) => lines,
+            Err(_) => return usage_error("--tail expects a number of lines"),
+        },
+        None => DEFAULT_TAIL_LINES,
+    };
+
+    let log_path = get_log_path();
+    let content = match std::fs::read_to_string(&log_path) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("Failed to read log file {}: {}", log_path.display(), e);
+            return EXIT_FAILURE;
+        }
+    };
+
+    let all_lines: Vec<&str> = content.lines().collect();
+    let start = all_lines.len().saturating_sub(lines);
+    for line in &all_lines[start..] {
+        println!("{}", line);
+    }
+
+    EXIT_OK
+}
+
+async fn diagnose() -> i32 {
+    let mut failures = 0;
+    let mut report = |ok: bool, check: &str, detail: String| {
+        println!("[{}] {}: {}", if ok { " OK " } else { "FAIL" }, check, detail);
+        if !ok {
+            failures += 1;
+        }
+    };
+
+    let config_dir = get_config_dir();
+    report(
+        config_dir.exists(),
+        "Config directory",
+        config_dir.display().to_string(),
+    );
+
+    let settings = get_settings().await;
+    match &settings {
+        Ok(_) => report(true, "Settings", get_settings_path().display().to_string()),
+        Err(e) => report(false, "Settings", e.to_string()),
+    }
+
+    match get_machine_id() {
+        Ok(id) => report(true, "Machine ID", id),
+        Err(e) => report(false, "Machine ID", e.to_string()),
+    }
+
+    match log_message(LogLevel::Info, "Diagnostics run from CLI") {
+        Ok(_) => report(true, "Log file", get_log_path().display().to_string()),
+        Err(e) => report(false, "Log file", e.to_string()),
+    }
+
+    if let Ok(settings) = &settings {
+        report(
+            settings.registered_at.is_some(),
+            "Registration",
+            settings
+                .device_id
+                .clone()
+                .unwrap_or_else(|| "not registered".to_string()),
+        );
+        report(
+            true,
+            "Last heartbeat",
+            settings
+                .last_heartbeat_at
+                .clone()
+                .unwrap_or_else(|| "never".to_string()),
+        );
+
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build();
+        match client {
+            Ok(client) => match client.get(&settings.api_host).send().await {
+                // Any HTTP response means the host is reachable
+                Ok(response) => report(
+                    true,
+                    "API reachable",
+                    format!("{} ({})", settings.api_host, response.status()),
+                ),
+                Err(e) => report(false, "API reachable", format!("{}: {}", settings.api_host, e)),
+            },
+            Err(e) => report(false, "API reachable", e.to_string()),
+        }
+    }
+
+    if failures == 0 {
+        println!("All checks passed");
+        EXIT_OK
+    } else {
+        println!("{} check(s) failed", failures);
+        EXIT_FAILURE
+    }
+}
+
+async fn reset() -> i32 {
+    match reset_identity().await {
+        Ok(settings) => {
+            log(
+                "WARN",
+                format!("Device identity reset via CLI for site {}", settings.site_id),
+            );
+            println!("Device identity cleared, the agent will register again on next launch");
+            EXIT_OK
+        }
+        Err(e) => {
+            eprintln!("Failed to reset identity: {}", e);
+            EXIT_NO_SETTINGS
+        }
+    }
+}
+
+async fn service() -> i32 {
+    match run_service().await {
+        Ok(_) => EXIT_OK,
+        Err(e) => {
+            log("ERROR", format!("Agent service stopped: {}", e));
+            eprintln!("Agent service stopped: {}", e);
+            EXIT_FAILURE
+        }
+    }
+}
+
+async fn update_guard(args: &CliArgs) -> i32 {
+    if args.flag("--watch") {
+        #[cfg(target_os = "windows")]
+        {
+            watch_applied_update().await;
+            return EXIT_OK;
+        }
+        #[cfg(not(target_os = "windows"))]
+        return usage_error("--watch is only for Windows, elsewhere the service manager runs the guard on each start");
+    }
+
+    match guard_update().await {
+        Ok(_) => EXIT_OK,
+        Err(e) => {
+            log("ERROR", format!("Update guard failed: {}", e));
+            eprintln!("Update guard failed: {}", e);
+            EXIT_FAILURE
+        }
+    }
+}
+
+fn print_json<T: Serialize>(value: &T) -> i32 {
+    match serde_json::to_string_pretty(value) {
+        Ok(json) => {
+            println!("{}", json);
+            EXIT_OK
+        }
+        Err(e) => {
+            eprintln!("Failed to serialize output: {}", e);
+            EXIT_FAILURE
+        }
+    }
+}
+
+fn read_json_arg<T: serde::de::DeserializeOwned>(args: &CliArgs) -> Result<T, i32> {
+    let path = match args.value("--file") {
+        Some(path) => path,
+        None => return Err(usage_error("--file <path> is required")),
+    };
+    let content = std::fs::read(path).map_err(|e| {
+        eprintln!("Failed to read {}: {}", path, e);
+        EXIT_FAILURE
+    })?;
+    serde_json::from_slice(&content).map_err(|e| {
+        eprintln!("Failed to parse {}: {}", path, e);
+        EXIT_FAILURE
+    })
+}
+
+fn job_keygen() -> i32 {
+    let (private_key, public_key) = generate_keypair();
+    print_json(&serde_json::json!({
+        "private_key": private_key,
+        "public_key": public_key,
+    }))
+}
+
+fn job_sign(args: &CliArgs) -> i32 {
+    let private_key = match args.value("--key") {
+        Some(key) => key,
+        None => return usage_error("job-sign requires --key <private>"),
+    };
+    let expires_in = match args.value("--expires-in").map(|v| v.parse::<i64>()) {
+        Some(Ok(secs)) => secs,
+        Some(Err(_)) => return usage_error("--expires-in expects a number of seconds"),
+        None => 3600,
+    };
+    let job: Job = match read_json_arg(args) {
+        Ok(job) => job,
+        Err(code) => return code,
+    };
+
+    let signed = SignedJob {
+        job,
+        expires_at: (chrono::Utc::now() + chrono::Duration::seconds(expires_in)).to_rfc3339(),
+        guid: args.value("--guid").map(String::from),
+    };
+    match sign_job(private_key, &signed) {
+        Ok(envelope) => print_json(&envelope),
+        Err(e) => {
+            eprintln!("Failed to sign job: {}", e);
+            EXIT_FAILURE
+        }
+    }
+}
+
+async fn job_verify(args: &CliArgs) -> i32 {
+    let envelope: JobEnvelope = match read_json_arg(args) {
+        Ok(envelope) => envelope,
+        Err(code) => return code,
+    };
+
+    let verified = match args.value("--key") {
+        Some(key) => {
+            let pinned = PinnedKey {
+                key: key.to_string(),
+                pinned_at: chrono::Utc::now().to_rfc3339(),
+                retires_at: None,
+            };
+            let guid = get_settings().await.ok().and_then(|settings| settings.guid);
+            verify_envelope(&envelope, &[pinned], guid.as_deref(), chrono::Utc::now())
+        }
+        None => open_envelope(&envelope).await,
+    };
+
+    match verified {
+        Ok(job) => {
+            println!("Job {} is valid", job.id);
+            print_json(&job)
+        }
+        Err(rejection) => {
+            eprintln!("Job rejected: {}", rejection.reason);
+            EXIT_FAILURE
+        }
+    }
+}
+
+fn job_rotate(args: &CliArgs) -> i32 {
+    let (private_key, new_key) = match (args.value("--key"), args.value("--new")) {
+        (Some(private_key), Some(new_key)) => (private_key, new_key),
+        _ => return usage_error("job-rotate requires --key <private> and --new <public>"),
+    };
+    match sign_key_rotation(private_key, new_key) {
+        Ok(rotation) => print_json(&rotation),
+        Err(e) => {
+            eprintln!("Failed to sign key rotation: {}", e);
+            EXIT_FAILURE
+        }
+    }
+}
+
+// Release builds use the windows subsystem, so stdout has to be reattached to
+// the calling console for output to show up in cmd/PowerShell
+#[cfg(target_os = "windows")]
+fn attach_parent_console() {
+    const ATTACH_PARENT_PROCESS: u32 = 0xFFFFFFFF;
+    extern "system" {
+        fn AttachConsole(dw_process_id: u32) -> i32;
+    }
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn attach_parent_console() {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_manager::test_support::use_settings;
+    use serde_json::json;
+    use std::cell::Cell;
+
+    fn args(args: &[&str]) -> CliArgs {
+        CliArgs::parse(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    fn reply(guid: &str) -> HeartbeatResponse {
+        serde_json::from_value(json!({ "data": { "guid": guid } })).unwrap()
+    }
+
+    #[test]
+    fn exit_codes_keep_their_numbers() {
+        let codes = [
+            EXIT_OK,
+            EXIT_FAILURE,
+            EXIT_USAGE,
+            EXIT_NOT_REGISTERED,
+            EXIT_NO_SETTINGS,
+            EXIT_NETWORK,
+            EXIT_CONFLICT,
+        ];
+        assert_eq!(codes, [0, 1, 2, 3, 4, 5, 6]);
+        assert!(USAGE.contains(
+            "0 success, 1 failure, 2 usage error, 3 not registered,\n  4 settings missing, 5 network error, 6 conflicting registration"
+        ));
+    }
+
+    #[test]
+    fn only_known_commands_run_the_cli() {
+        let invocation = |list: &[&str]| is_cli_invocation(&list.iter().map(|arg| arg.to_string()).collect::<Vec<_>>());
+        assert!(invocation(&["status", "--json"]));
+        assert!(invocation(&["--version"]));
+        assert!(invocation(&["job-sign"]));
+        assert!(!invocation(&[]));
+        assert!(!invocation(&["--minimized"]));
+        assert!(!invocation(&["Status"]));
+    }
+
+    #[test]
+    fn options_take_separate_or_inline_values() {
+        let parsed = args(&["register", "--site", "site-a", "--force", "--tail=20"]);
+        assert_eq!(parsed.command, "register");
+        assert!(parsed.flag("--force"));
+        assert!(!parsed.flag("--json"));
+        assert_eq!(parsed.value("--site"), Some("site-a"));
+        assert_eq!(parsed.value("--tail"), Some("20"));
+        assert_eq!(args(&["register", "--site"]).value("--site"), None);
+        assert_eq!(args(&[]).command, "");
+    }
+
+    #[tokio::test]
+    async fn bad_arguments_are_usage_errors() {
+        assert_eq!(heartbeat(&args(&["heartbeat"])).await, EXIT_USAGE);
+        assert_eq!(register(&args(&["register"])).await, EXIT_USAGE);
+        assert_eq!(register(&args(&["register", "--site", " "])).await, EXIT_USAGE);
+        assert_eq!(logs(&args(&["logs", "--tail", "lots"])), EXIT_USAGE);
+        assert_eq!(job_sign(&args(&["job-sign", "--key", "k", "--expires-in", "soon"])), EXIT_USAGE);
+        assert_eq!(job_rotate(&args(&["job-rotate", "--key", "k"])), EXIT_USAGE);
+    }
+
+    #[tokio::test]
+    async fn registration_state_maps_to_exit_codes() {
+        let settings = use_settings(json!({})).await;
+        assert_eq!(status(&args(&["status"])).await, EXIT_OK);
+        // Already registered to the site asked for, nothing to send
+        assert_eq!(register(&args(&["register", "--site", "site-test"])).await, EXIT_OK);
+        assert_eq!(register(&args(&["register", "--site", "site-other"])).await, EXIT_CONFLICT);
+        drop(settings);
+
+        let _settings = use_settings(json!({ "device_id": null, "registered_at": null })).await;
+        assert_eq!(status(&args(&["status", "--json"])).await, EXIT_NOT_REGISTERED);
+        assert_eq!(heartbeat(&args(&["heartbeat", "--once"])).await, EXIT_NOT_REGISTERED);
+    }
+
+    #[tokio::test]
+    async fn heartbeat_goes_direct_only_when_the_service_is_unreachable() {
+        let direct_posts = Cell::new(0);
+        let direct = |result: Result<HeartbeatResponse, String>| {
+            let direct_posts = &direct_posts;
+            move || async move {
+                direct_posts.set(direct_posts.get() + 1);
+                result.map_err(|e| e.into())
+            }
+        };
+
+        let sent = async { Ok(json!({ "guid": "guid-test" })) };
+        assert_eq!(heartbeat_once(sent, direct(Ok(reply("guid-test")))).await, EXIT_OK);
+        assert_eq!(direct_posts.get(), 0);
+
+        // The service may have sent it before going quiet, a second one would run its jobs twice
+        let no_answer = async { Err(IpcError::NoResponse(String::from("Timed out waiting for service"))) };
+        assert_eq!(heartbeat_once(no_answer, direct(Ok(reply("guid-test")))).await, EXIT_NETWORK);
+        let refused = async { Err(IpcError::Remote(String::from("Permission denied"))) };
+        assert_eq!(heartbeat_once(refused, direct(Ok(reply("guid-test")))).await, EXIT_NETWORK);
+        assert_eq!(direct_posts.get(), 0);
+
+        let unavailable = || async { Err(IpcError::Unavailable(String::from("No such file or directory"))) };
+        assert_eq!(heartbeat_once(unavailable(), direct(Ok(reply("guid-test")))).await, EXIT_OK);
+        assert_eq!(heartbeat_once(unavailable(), direct(Err(String::from("Connection refused")))).await, EXIT_NETWORK);
+        assert_eq!(direct_posts.get(), 2);
+    }
+}
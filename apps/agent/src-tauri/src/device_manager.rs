@@ -1,5 +1,13 @@
+use crate::alerts::AlertRule;
+use crate::branding::Branding;
+use crate::job_signing::{pin_registration_key, PinnedKey};
+use crate::reboot::MaintenanceWindow;
+use crate::service_monitor::MonitoredService;
+use crate::tray::TrayMenuEntry;
+use crate::updater::UpdateChannel;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use whoami;
 use std::process::Command;
 
@@ -16,8 +24,48 @@ pub struct Settings {
     pub installed_at: String,
     pub registered_at: Option<String>,
     pub show_tray: Option<bool>, // Show system tray icon - defaults to false if not set
+    pub last_heartbeat_at: Option<String>,
+    pub local_api_action_uids: Option<Vec<u32>>, // Non-root users allowed to run local API actions
+    pub screenshot_ttl_minutes: Option<u64>, // Unsent screenshots are deleted after this long - defaults to 60
+    pub tray_menu: Option<Vec<TrayMenuEntry>>, // Tray menu sent by the server - the built-in menu is used if not set
+    pub branding: Option<Branding>, // Local branding, fields set here win over the server's
+    pub metrics_interval_secs: Option<u64>, // How often performance metrics are sampled - defaults to 60
+    pub metrics_retention_hours: Option<u64>, // How long sampled metrics are kept on disk - defaults to 24
+    pub support_hotkey: Option<String>, // Global shortcut that opens the support window with a screenshot, e.g. "CommandOrControl+Shift+H"
+    pub alert_rules: Option<Vec<AlertRule>>, // Threshold rules checked against every metrics sample - no alerts if not set
+    pub monitored_services: Option<Vec<MonitoredService>>, // Services and processes to watch, and optionally restart
+    pub job_signing_keys: Option<Vec<PinnedKey>>, // Keys jobs must be signed with, pinned at registration - no jobs run if not set
+    pub maintenance_window: Option<MaintenanceWindow>, // When scheduled reboots that ask for it may happen - any time if not set
+    pub file_access_paths: Option<Vec<String>>, // Directories file reads and transfers may touch - defaults to the agent logs, screenshots and system log directories
+    pub update_channel: Option<UpdateChannel>, // Release channel checked for agent updates - defaults to stable
+    pub pinned_update_version: Option<String>, // Agent version to move to and stay on, ignoring rollouts - follows the channel if not set
 }
 
+/// The part of the settings the tray needs, without the device's credentials
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct UiSettings {
+    pub hostname: Option<String>,
+    pub show_tray: Option<bool>,
+    pub screenshot_ttl_minutes: Option<u64>,
+    pub tray_menu: Option<Vec<TrayMenuEntry>>,
+    pub branding: Option<Branding>,
+    pub support_hotkey: Option<String>,
+}
+
+impl From<&Settings> for UiSettings {
+    fn from(settings: &Settings) -> Self {
+        UiSettings {
+            hostname: settings.hostname.clone(),
+            show_tray: settings.show_tray,
+            screenshot_ttl_minutes: settings.screenshot_ttl_minutes,
+            tray_menu: settings.tray_menu.clone(),
+            branding: settings.branding.clone(),
+            support_hotkey: settings.support_hotkey.clone(),
+        }
+    }
+}
+
+#[cfg(not(test))]
 pub fn get_config_dir() -> PathBuf {
     #[cfg(target_os = "windows")]
     {
@@ -33,10 +81,28 @@ pub fn get_config_dir() -> PathBuf {
     }
 }
 
+// Tests never touch the machine's config, each test binary gets its own scratch directory
+#[cfg(test)]
+pub fn get_config_dir() -> PathBuf {
+    static TEST_CONFIG_DIR: std::sync::OnceLock<tempfile::TempDir> = std::sync::OnceLock::new();
+    TEST_CONFIG_DIR
+        .get_or_init(|| tempfile::tempdir().expect("Failed to create test config dir"))
+        .path()
+        .to_path_buf()
+}
+
 pub fn get_settings_path() -> PathBuf {
     get_config_dir().join("settings.json")
 }
 
+// Serializes read-modify-write of the settings file within this process, the lock file
+// below does the same between processes
+static SETTINGS_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+fn get_settings_lock_path() -> PathBuf {
+    get_config_dir().join("settings.lock")
+}
+
 pub async fn get_settings() -> Result<Settings, Box<dyn std::error::Error>> {
     let settings_path = get_settings_path();
 
@@ -50,52 +116,301 @@ pub async fn get_settings() -> Result<Settings, Box<dyn std::error::Error>> {
     Ok(settings)
 }
 
-pub async fn save_settings(settings: &Settings) -> Result<(), Box<dyn std::error::Error>> {
+// Written aside and renamed into place so readers in other processes never see half a file
+async fn write_settings(settings: &Settings) -> Result<(), Box<dyn std::error::Error>> {
     let settings_path = get_settings_path();
+    let content = serde_json::to_string_pretty(settings)?;
+    tauri::async_runtime::spawn_blocking(move || replace_file(&settings_path, content.as_bytes())).await??;
+    Ok(())
+}
 
-    // Ensure directory exists
-    if let Some(parent) = settings_path.parent() {
-        tokio::fs::create_dir_all(parent).await?;
+/// Replaces `path` with `content` through a fresh file in the same directory, keeping the
+/// permissions of the file it replaces
+fn replace_file(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    std::fs::create_dir_all(dir)?;
+
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".{:08x}.tmp", rand::random::<u32>()));
+    let tmp_path = dir.join(name);
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    // The settings carry the device's credentials, a file we create is the owner's alone
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let written = options
+        .open(&tmp_path)
+        .and_then(|mut file| file.write_all(content).and_then(|_| file.sync_all()))
+        .and_then(|_| move_into_place(&tmp_path, path));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
     }
+    written
+}
 
-    let content = serde_json::to_string_pretty(settings)?;
-    tokio::fs::write(&settings_path, content).await?;
+#[cfg(unix)]
+fn move_into_place(tmp_path: &Path, path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
 
+    match std::fs::metadata(path) {
+        Ok(metadata) => {
+            std::fs::set_permissions(tmp_path, metadata.permissions())?;
+            // Only root can give a file away, anyone else already owns what they write
+            let _ = std::os::unix::fs::chown(tmp_path, Some(metadata.uid()), Some(metadata.gid()));
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    std::fs::rename(tmp_path, path)
+}
+
+// A rename would leave the new file with the directory's inherited ACL, ReplaceFileW moves the
+// replaced file's ACL and attributes over to it
+#[cfg(windows)]
+fn move_into_place(tmp_path: &Path, path: &Path) -> std::io::Result<()> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::ReplaceFileW;
+
+    if !path.exists() {
+        return std::fs::rename(tmp_path, path);
+    }
+
+    let wide = |path: &Path| path.as_os_str().encode_wide().chain(Some(0)).collect::<Vec<u16>>();
+    let (replaced, replacement) = (wide(path), wide(tmp_path));
+    let replaced = unsafe {
+        ReplaceFileW(
+            replaced.as_ptr(),
+            replacement.as_ptr(),
+            std::ptr::null(),
+            0,
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    if replaced == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
     Ok(())
 }
 
-pub async fn complete_settings() -> Result<Settings, Box<dyn std::error::Error>> {
+/// An exclusive advisory lock on the settings lock file, released when dropped
+struct SettingsFileLock {
+    file: std::fs::File,
+}
+
+impl SettingsFileLock {
+    /// Blocks until no other process is updating the settings
+    fn acquire() -> std::io::Result<Self> {
+        let path = get_settings_lock_path();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        lock_exclusive(&file)?;
+        Ok(SettingsFileLock { file })
+    }
+}
+
+#[cfg(unix)]
+fn lock_exclusive(file: &std::fs::File) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+            return Ok(());
+        }
+        let error = std::io::Error::last_os_error();
+        if error.kind() != std::io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+}
+
+#[cfg(windows)]
+fn lock_exclusive(file: &std::fs::File) -> std::io::Result<()> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Storage::FileSystem::{LockFileEx, LOCKFILE_EXCLUSIVE_LOCK};
+    use windows_sys::Win32::System::IO::OVERLAPPED;
+
+    let mut overlapped: OVERLAPPED = unsafe { std::mem::zeroed() };
+    let locked = unsafe {
+        LockFileEx(
+            file.as_raw_handle(),
+            LOCKFILE_EXCLUSIVE_LOCK,
+            0,
+            u32::MAX,
+            u32::MAX,
+            &mut overlapped,
+        )
+    };
+    if locked == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Closing the file would release it too, on Windows only eventually
+impl Drop for SettingsFileLock {
+    fn drop(&mut self) {
+        unlock(&self.file);
+    }
+}
+
+#[cfg(unix)]
+fn unlock(file: &std::fs::File) {
+    use std::os::unix::io::AsRawFd;
+
+    unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_UN) };
+}
+
+#[cfg(windows)]
+fn unlock(file: &std::fs::File) {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Storage::FileSystem::UnlockFileEx;
+    use windows_sys::Win32::System::IO::OVERLAPPED;
+
+    let mut overlapped: OVERLAPPED = unsafe { std::mem::zeroed() };
+    unsafe { UnlockFileEx(file.as_raw_handle(), 0, u32::MAX, u32::MAX, &mut overlapped) };
+}
+
+/// Reads, changes and saves the settings under the settings lock, so concurrent updates in this
+/// or another agent process don't lose each other's changes. Nothing is written when `update` fails.
+pub async fn update_settings<T>(
+    update: impl FnOnce(&mut Settings) -> Result<T, Box<dyn std::error::Error>>,
+) -> Result<T, Box<dyn std::error::Error>> {
+    let _lock = SETTINGS_LOCK.lock().await;
+    let _file_lock = tauri::async_runtime::spawn_blocking(SettingsFileLock::acquire).await??;
     let mut settings = get_settings().await?;
+    let value = update(&mut settings)?;
+    write_settings(&settings).await?;
+    Ok(value)
+}
+
+pub async fn complete_settings() -> Result<Settings, Box<dyn std::error::Error>> {
+    let settings = get_settings().await?;
 
     // If already complete, return as-is
     if settings.guid.is_some() && settings.hostname.is_some() {
         return Ok(settings);
     }
 
-    // Complete missing fields
-    if settings.guid.is_none() {
-        settings.guid = Some(get_machine_id()?);
-    }
-
-    if settings.hostname.is_none() {
-        settings.hostname = Some(hostname::get()?.to_string_lossy().to_string());
-    }
+    update_settings(|settings| {
+        // Complete missing fields
+        if settings.guid.is_none() {
+            settings.guid = Some(get_machine_id()?);
+        }
 
-    save_settings(&settings).await?;
+        if settings.hostname.is_none() {
+            settings.hostname = Some(hostname::get()?.to_string_lossy().to_string());
+        }
 
-    Ok(settings)
+        Ok(settings.clone())
+    })
+    .await
 }
 
 pub async fn update_from_registration(
-    settings: &mut Settings,
+    site_id: &str,
     device_id: String,
     guid: String,
-) -> Result<(), Box<dyn std::error::Error>> {
-    settings.device_id = Some(device_id);
-    settings.guid = Some(guid);
-    settings.registered_at = Some(chrono::Utc::now().to_rfc3339());
-    save_settings(settings).await?;
-    Ok(())
+    job_signing_key: Option<&str>,
+) -> Result<Settings, Box<dyn std::error::Error>> {
+    update_settings(|settings| {
+        settings.site_id = site_id.to_string();
+        settings.device_id = Some(device_id);
+        settings.guid = Some(guid);
+        // A new registration is a new trust root, the old keys belong to whoever registered us before
+        settings.job_signing_keys = job_signing_key.map(pin_registration_key).transpose()?;
+        settings.registered_at = Some(chrono::Utc::now().to_rfc3339());
+        Ok(settings.clone())
+    })
+    .await
+}
+
+pub async fn record_heartbeat() -> Result<(), Box<dyn std::error::Error>> {
+    update_settings(|settings| {
+        settings.last_heartbeat_at = Some(chrono::Utc::now().to_rfc3339());
+        Ok(())
+    })
+    .await
+}
+
+/// Stores the tray menu sent by the server, an empty menu restores the built-in one.
+/// Returns whether anything changed.
+pub async fn save_tray_menu(menu: &[TrayMenuEntry]) -> Result<bool, Box<dyn std::error::Error>> {
+    let menu = if menu.is_empty() { None } else { Some(menu.to_vec()) };
+    if get_settings().await?.tray_menu == menu {
+        return Ok(false);
+    }
+
+    update_settings(|settings| {
+        settings.tray_menu = menu;
+        Ok(true)
+    })
+    .await
+}
+
+/// Stores the alert rules sent by the server, an empty list turns local alerting off.
+/// Returns whether anything changed.
+pub async fn save_alert_rules(rules: &[AlertRule]) -> Result<bool, Box<dyn std::error::Error>> {
+    let rules = if rules.is_empty() { None } else { Some(rules.to_vec()) };
+    if get_settings().await?.alert_rules == rules {
+        return Ok(false);
+    }
+
+    update_settings(|settings| {
+        settings.alert_rules = rules;
+        Ok(true)
+    })
+    .await
+}
+
+/// Stores the monitored services sent by the server, an empty list stops monitoring.
+/// Returns whether anything changed.
+pub async fn save_monitored_services(services: &[MonitoredService]) -> Result<bool, Box<dyn std::error::Error>> {
+    let services = if services.is_empty() { None } else { Some(services.to_vec()) };
+    if get_settings().await?.monitored_services == services {
+        return Ok(false);
+    }
+
+    update_settings(|settings| {
+        settings.monitored_services = services;
+        Ok(true)
+    })
+    .await
+}
+
+/// Stores the maintenance window sent by the server, returning whether it changed
+pub async fn save_maintenance_window(window: &MaintenanceWindow) -> Result<bool, Box<dyn std::error::Error>> {
+    if get_settings().await?.maintenance_window.as_ref() == Some(window) {
+        return Ok(false);
+    }
+
+    update_settings(|settings| {
+        settings.maintenance_window = Some(window.clone());
+        Ok(true)
+    })
+    .await
+}
+
+/// Clears the server-assigned identity so the next launch registers again
+pub async fn reset_identity() -> Result<Settings, Box<dyn std::error::Error>> {
+    update_settings(|settings| {
+        settings.device_id = None;
+        settings.guid = None;
+        settings.registered_at = None;
+        settings.last_heartbeat_at = None;
+        Ok(settings.clone())
+    })
+    .await
 }
 
 pub async fn is_device_registered() -> bool {
@@ -351,3 +666,127 @@ pub async fn get_api_endpoint(path: &str) -> Result<String, Box<dyn std::error::
 pub async fn get_username() -> Option<String> {
     Some(whoami::username())
 }
+
+#[cfg(test)]
+pub(crate) mod test_support {
+    use super::*;
+
+    // Tests that write settings share one file, so they take turns
+    static TEST_SETTINGS_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    /// Replaces the test settings with `overrides` on top of a minimal registered device, and
+    /// holds other settings tests off until the guard drops
+    pub async fn use_settings(overrides: serde_json::Value) -> tokio::sync::MutexGuard<'static, ()> {
+        let guard = TEST_SETTINGS_LOCK.lock().await;
+        let mut settings = serde_json::json!({
+            "site_id": "site-test",
+            "device_id": "device-test",
+            "guid": "guid-test",
+            "api_host": "http://127.0.0.1:9",
+            "installed_at": "2026-01-01T00:00:00Z",
+            "registered_at": "2026-01-01T00:00:00Z",
+        });
+        if let (Some(settings), Some(overrides)) = (settings.as_object_mut(), overrides.as_object()) {
+            settings.extend(overrides.clone());
+        }
+        let settings: Settings = serde_json::from_value(settings).expect("Invalid test settings");
+        write_settings(&settings).await.expect("Failed to write test settings");
+        guard
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn replacing_a_file_keeps_its_mode_and_leaves_nothing_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("settings.json");
+        std::fs::write(&path, "old").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+
+        replace_file(&path, b"new").unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+        let names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, vec![std::ffi::OsString::from("settings.json")]);
+    }
+
+    #[test]
+    fn a_new_settings_file_is_private_to_its_owner() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("settings.json");
+
+        replace_file(&path, b"{}").unwrap();
+
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    #[test]
+    fn the_settings_file_lock_is_held_until_dropped() {
+        let held = SettingsFileLock::acquire().unwrap();
+
+        let (acquired, waiting) = mpsc::channel();
+        let other = std::thread::spawn(move || {
+            let lock = SettingsFileLock::acquire().unwrap();
+            acquired.send(()).unwrap();
+            drop(lock);
+        });
+
+        assert!(waiting.recv_timeout(Duration::from_millis(200)).is_err());
+        drop(held);
+        assert!(waiting.recv_timeout(Duration::from_secs(5)).is_ok());
+        other.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn registering_without_a_key_forgets_the_previous_keys() {
+        let (_, old_key) = crate::job_signing::generate_keypair();
+        let _settings = test_support::use_settings(serde_json::json!({
+            "job_signing_keys": [{ "key": old_key, "pinned_at": "2026-01-01T00:00:00Z", "retires_at": null }],
+        }))
+        .await;
+
+        let (_, new_key) = crate::job_signing::generate_keypair();
+        let settings = update_from_registration("site-b", String::from("device-b"), String::from("guid-b"), Some(&new_key))
+            .await
+            .unwrap();
+        let keys: Vec<String> = settings.job_signing_keys.unwrap().into_iter().map(|pinned| pinned.key).collect();
+        assert_eq!(keys, vec![new_key]);
+
+        let settings = update_from_registration("site-c", String::from("device-c"), String::from("guid-c"), None)
+            .await
+            .unwrap();
+        assert!(settings.job_signing_keys.is_none());
+        assert!(get_settings().await.unwrap().job_signing_keys.is_none());
+    }
+
+    #[tokio::test]
+    async fn concurrent_updates_all_land() {
+        let _settings = test_support::use_settings(serde_json::json!({})).await;
+
+        let updates: Vec<_> = (0..16u32)
+            .map(|uid| {
+                tokio::spawn(update_settings(move |settings| {
+                    settings.local_api_action_uids.get_or_insert_with(Vec::new).push(uid);
+                    Ok(())
+                }))
+            })
+            .collect();
+        for update in updates {
+            update.await.unwrap().unwrap();
+        }
+
+        let mut uids = get_settings().await.unwrap().local_api_action_uids.unwrap();
+        uids.sort();
+        assert_eq!(uids, (0..16).collect::<Vec<_>>());
+    }
+}
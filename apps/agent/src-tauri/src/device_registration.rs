@@ -1,3 +1,4 @@
+use crate::agent_info::{agent_info, BuildInfo, OsRelease};
 use crate::device_manager::{
     complete_settings, get_api_endpoint, get_machine_id, get_primary_mac, get_serial_number,
     get_username, update_from_registration,
@@ -17,6 +18,9 @@ pub struct RegistrationRequest {
     pub ip_address: Option<String>,
     pub ext_address: Option<String>,
     pub username: Option<String>,
+    pub capabilities: Vec<String>, // Features the server may use with this agent
+    pub build: BuildInfo,
+    pub os_release: Option<OsRelease>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -28,12 +32,23 @@ pub struct RegistrationResponse {
 pub struct RegistrationData {
     pub device_id: String,
     pub guid: String,
+    #[serde(default)]
+    pub job_signing_key: Option<String>, // Base64 Ed25519 public key jobs for this tenant are signed with
 }
 
 pub async fn register_device_with_server(
+) -> Result<RegistrationResponse, Box<dyn std::error::Error>> {
+    register_device_to_site(None).await
+}
+
+/// Registers with the server, to another site when `site_id` is given. Settings only change
+/// once the server accepts, a failed attempt leaves the current identity in place.
+pub async fn register_device_to_site(
+    site_id: Option<&str>,
 ) -> Result<RegistrationResponse, Box<dyn std::error::Error>> {
     // Complete settings with local machine info
-    let mut settings = complete_settings().await?;
+    let settings = complete_settings().await?;
+    let site_id = site_id.unwrap_or(&settings.site_id).to_string();
     let api_url = get_api_endpoint("/v1.0/register").await?;
 
     // Try to get machine GUID, but allow None if not available
@@ -45,10 +60,11 @@ pub async fn register_device_with_server(
     let ip_address = get_local_ip();
     let ext_address = get_external_ip().await.ok();
     let username = get_username().await;
+    let agent = tauri::async_runtime::spawn_blocking(agent_info).await?;
 
     let request = RegistrationRequest {
         guid: guid.clone(),
-        site_id: settings.site_id.clone(),
+        site_id: site_id.clone(),
         hostname: settings
             .hostname
             .clone()
@@ -60,6 +76,9 @@ pub async fn register_device_with_server(
         ip_address,
         ext_address,
         username,
+        capabilities: agent.capabilities,
+        build: agent.build,
+        os_release: agent.os_release,
     };
 
     let client = reqwest::Client::new();
@@ -79,9 +98,10 @@ pub async fn register_device_with_server(
 
         // Update settings with server-provided device_id and guid
         update_from_registration(
-            &mut settings,
+            &site_id,
             result.data.device_id.clone(),
             result.data.guid.clone(),
+            result.data.job_signing_key.as_deref(),
         )
         .await?;
 
@@ -0,0 +1,265 @@
+use crate::device_manager::{get_config_dir, get_settings};
+use crate::logger::get_logs_dir;
+use std::path::{Path, PathBuf};
+
+/// System log directories, the files techs most often need off a machine
+fn system_log_dirs() -> Vec<PathBuf> {
+    #[cfg(target_os = "windows")]
+    {
+        vec![PathBuf::from("C:\\Windows\\Logs")]
+    }
+    #[cfg(target_os = "macos")]
+    {
+        vec![PathBuf::from("/Library/Logs"), PathBuf::from("/var/log")]
+    }
+    #[cfg(target_os = "linux")]
+    {
+        vec![PathBuf::from("/var/log")]
+    }
+}
+
+/// Where files may be read and written when settings don't say otherwise
+pub fn default_allowed_dirs() -> Vec<PathBuf> {
+    // Not the temp dir, anyone can plant files and links there. Nor the screenshot dir, for
+    // the service that is a predictable path in it.
+    let mut dirs = vec![get_logs_dir()];
+    dirs.extend(system_log_dirs());
+    dirs
+}
+
+/// The directories from settings, or the defaults when none are configured
+pub async fn allowed_dirs() -> Vec<PathBuf> {
+    match get_settings().await.ok().and_then(|settings| settings.file_access_paths) {
+        Some(paths) => paths.into_iter().map(PathBuf::from).collect(),
+        None => default_allowed_dirs(),
+    }
+}
+
+// Settings, pinned signing keys and jobs live in the config dir, handing those out or
+// overwriting them would undo every other check. Only the logs in it are fair game.
+fn is_protected(path: &Path) -> bool {
+    let config_dir = match get_config_dir().canonicalize() {
+        Ok(dir) => dir,
+        Err(_) => return false,
+    };
+    let in_logs = get_logs_dir()
+        .canonicalize()
+        .map(|logs| path.starts_with(logs))
+        .unwrap_or(false);
+    path.starts_with(config_dir) && !in_logs
+}
+
+/// Checks a resolved path against the allowed directories
+fn check_allowed(path: &Path, allowed: &[PathBuf]) -> Result<(), String> {
+    if is_protected(path) {
+        return Err(format!("Access to {} is not allowed", path.display()));
+    }
+    // Missing directories resolve to nothing and so allow nothing
+    let inside = allowed
+        .iter()
+        .filter_map(|dir| dir.canonicalize().ok())
+        .any(|dir| path.starts_with(dir));
+    if !inside {
+        return Err(format!("{} is outside the allowed directories", path.display()));
+    }
+    Ok(())
+}
+
+/// Resolves an existing file for reading, following links and `..` before checking it
+pub fn check_read_path(path: &str, allowed: &[PathBuf]) -> Result<PathBuf, String> {
+    let resolved = Path::new(path)
+        .canonicalize()
+        .map_err(|e| format!("Failed to resolve {}: {}", path, e))?;
+    check_allowed(&resolved, allowed)?;
+    if !resolved.is_file() {
+        return Err(format!("{} is not a file", path));
+    }
+    Ok(resolved)
+}
+
+/// Resolves a file about to be written, its directory must exist and the file can't be a link
+pub fn check_write_path(path: &str, allowed: &[PathBuf]) -> Result<PathBuf, String> {
+    let path = Path::new(path);
+    let (parent, name) = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => (parent, name),
+        _ => return Err(format!("{} is not a file path", path.display())),
+    };
+    let resolved = parent
+        .canonicalize()
+        .map_err(|e| format!("Failed to resolve {}: {}", parent.display(), e))?
+        .join(name);
+    check_allowed(&resolved, allowed)?;
+
+    // A link could point the write anywhere, even one inside the allowed directories
+    if let Ok(metadata) = std::fs::symlink_metadata(&resolved) {
+        if !metadata.is_file() {
+            return Err(format!("{} is not a regular file", resolved.display()));
+        }
+    }
+    Ok(resolved)
+}
+
+/// Opens a checked path without following a link in its place, then makes sure the file
+/// opened is the one the path still resolves to, so nothing swapped in between check and open
+pub fn open_checked(path: &Path, options: &mut std::fs::OpenOptions) -> Result<std::fs::File, String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.custom_flags(libc::O_NOFOLLOW);
+    }
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        // Opens a link itself rather than its target, it then fails the regular file check below
+        const FILE_FLAG_OPEN_REPARSE_POINT: u32 = 0x00200000;
+        options.custom_flags(FILE_FLAG_OPEN_REPARSE_POINT);
+    }
+
+    let file = options
+        .open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let opened = file
+        .metadata()
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    if !opened.is_file() {
+        return Err(format!("{} is not a regular file", path.display()));
+    }
+
+    let changed = || format!("{} changed while it was being opened", path.display());
+    if path.canonicalize().map_err(|_| changed())? != path {
+        return Err(changed());
+    }
+    let current = std::fs::symlink_metadata(path).map_err(|_| changed())?;
+    if !same_file(&opened, &current) {
+        return Err(changed());
+    }
+    Ok(file)
+}
+
+#[cfg(unix)]
+fn same_file(a: &std::fs::Metadata, b: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+// File ids aren't exposed on stable Windows, a freshly swapped file differs in some of these
+#[cfg(target_os = "windows")]
+fn same_file(a: &std::fs::Metadata, b: &std::fs::Metadata) -> bool {
+    b.is_file() && a.len() == b.len() && a.created().ok() == b.created().ok() && a.modified().ok() == b.modified().ok()
+}
+
+// Full control for SYSTEM and Administrators only, inherited by everything inside and
+// protected so the broader access list of the directory above doesn't flow in
+#[cfg(target_os = "windows")]
+const SYSTEM_ONLY_SDDL: &str = "D:P(A;OICI;FA;;;SY)(A;OICI;FA;;;BA)";
+
+/// Replaces a directory's access list so only SYSTEM and Administrators can open it or what it holds
+#[cfg(target_os = "windows")]
+pub fn restrict_to_system(path: &Path) -> std::io::Result<()> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Foundation::LocalFree;
+    use windows_sys::Win32::Security::Authorization::{
+        ConvertStringSecurityDescriptorToSecurityDescriptorW, SetNamedSecurityInfoW, SDDL_REVISION_1,
+        SE_FILE_OBJECT,
+    };
+    use windows_sys::Win32::Security::{
+        GetSecurityDescriptorDacl, DACL_SECURITY_INFORMATION, PROTECTED_DACL_SECURITY_INFORMATION,
+    };
+
+    let sddl: Vec<u16> = SYSTEM_ONLY_SDDL
+        .encode_utf16()
+        .chain(std::iter::once(0))
+        .collect();
+    let mut descriptor = std::ptr::null_mut();
+    let converted = unsafe {
+        ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            sddl.as_ptr(),
+            SDDL_REVISION_1,
+            &mut descriptor,
+            std::ptr::null_mut(),
+        )
+    };
+    if converted == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let mut present = 0;
+    let mut defaulted = 0;
+    let mut dacl = std::ptr::null_mut();
+    let found = unsafe { GetSecurityDescriptorDacl(descriptor, &mut present, &mut dacl, &mut defaulted) };
+    let result = if found == 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        let name: Vec<u16> = path.as_os_str().encode_wide().chain(std::iter::once(0)).collect();
+        // Also rewrites what the directory's existing files inherited
+        let status = unsafe {
+            SetNamedSecurityInfoW(
+                name.as_ptr(),
+                SE_FILE_OBJECT,
+                DACL_SECURITY_INFORMATION | PROTECTED_DACL_SECURITY_INFORMATION,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                dacl,
+                std::ptr::null(),
+            )
+        };
+        match status {
+            0 => Ok(()),
+            code => Err(std::io::Error::from_raw_os_error(code as i32)),
+        }
+    };
+
+    unsafe {
+        LocalFree(descriptor as _);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temp_dir_is_not_allowed_by_default() {
+        let temp = std::env::temp_dir();
+        assert!(!default_allowed_dirs().iter().any(|dir| temp.starts_with(dir)));
+        let screenshots = crate::screenshot::get_screenshot_dir();
+        assert!(!default_allowed_dirs().iter().any(|dir| screenshots.starts_with(dir)));
+    }
+
+    #[test]
+    fn open_checked_refuses_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().canonicalize().unwrap();
+        let real = dir.join("real.txt");
+        std::fs::write(&real, b"data").unwrap();
+
+        assert!(open_checked(&real, std::fs::OpenOptions::new().read(true)).is_ok());
+
+        #[cfg(unix)]
+        {
+            let link = dir.join("link.txt");
+            std::os::unix::fs::symlink(&real, &link).unwrap();
+            assert!(open_checked(&link, std::fs::OpenOptions::new().read(true)).is_err());
+
+            // A new file can't be created through a link either
+            let dangling = dir.join("dangling.txt");
+            std::os::unix::fs::symlink(dir.join("elsewhere.txt"), &dangling).unwrap();
+            let created = open_checked(&dangling, std::fs::OpenOptions::new().write(true).create_new(true));
+            assert!(created.is_err());
+            assert!(!dir.join("elsewhere.txt").exists());
+        }
+    }
+
+    #[test]
+    fn open_checked_refuses_paths_that_resolve_elsewhere() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().canonicalize().unwrap();
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("file.txt"), b"data").unwrap();
+
+        // Same file, but not the path that was checked
+        let indirect = dir.join("sub").join("..").join("file.txt");
+        assert!(open_checked(&indirect, std::fs::OpenOptions::new().read(true)).is_err());
+    }
+}
@@ -0,0 +1,566 @@
+use crate::device_manager::{get_config_dir, get_settings};
+use crate::file_access::{allowed_dirs, check_read_path, check_write_path, open_checked};
+use crate::logger::log_to_file;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::time::Duration;
+
+pub const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+const MAX_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+const MIN_CHUNK_SIZE: usize = 64 * 1024;
+
+pub const DEFAULT_MAX_UPLOAD_BYTES: u64 = 100 * 1024 * 1024;
+const MAX_TRANSFER_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+
+// A chunk that fails this many times in a row fails the transfer, the next run resumes it
+const MAX_CHUNK_ATTEMPTS: u32 = 8;
+const MAX_RETRY_DELAY_SECS: u64 = 60;
+const CHUNK_TIMEOUT_SECS: u64 = 120;
+
+/// Sends a file from the device to the server
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileUploadJob {
+    pub path: String,
+    #[serde(default)]
+    pub chunk_size: Option<usize>,
+    /// Larger files are refused, defaults to 100 MiB
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+}
+
+/// Fetches a file from the server onto the device
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileDownloadJob {
+    /// Where the file is written
+    pub path: String,
+    /// Size and hash of the file on the server, covered by the job signature like the rest
+    pub size: u64,
+    pub sha256: String,
+    /// Replace the file if it already exists
+    #[serde(default)]
+    pub overwrite: bool,
+    #[serde(default)]
+    pub chunk_size: Option<usize>,
+}
+
+/// The file a transfer moved, reported with the job result
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransferSummary {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// How much of an upload the server holds
+#[derive(Deserialize)]
+struct UploadOffset {
+    offset: u64,
+}
+
+enum ChunkError {
+    /// Network trouble or a busy server, worth another go
+    Retry(String),
+    Fatal(String),
+}
+
+fn should_retry(status: reqwest::StatusCode) -> bool {
+    status.is_server_error()
+        || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        || status == reqwest::StatusCode::REQUEST_TIMEOUT
+}
+
+async fn status_error(response: reqwest::Response) -> ChunkError {
+    let status = response.status();
+    let message = format!("{}: {}", status, response.text().await.unwrap_or_default());
+    if should_retry(status) {
+        ChunkError::Retry(message)
+    } else {
+        ChunkError::Fatal(message)
+    }
+}
+
+/// Runs one chunk request until it succeeds, backing off between attempts
+async fn with_retries<T, F, Fut>(what: &str, mut request: F) -> Result<T, String>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, ChunkError>>,
+{
+    let mut attempt = 0;
+    loop {
+        match request().await {
+            Ok(value) => return Ok(value),
+            Err(ChunkError::Fatal(e)) => return Err(format!("Failed to {}: {}", what, e)),
+            Err(ChunkError::Retry(e)) => {
+                attempt += 1;
+                if attempt >= MAX_CHUNK_ATTEMPTS {
+                    return Err(format!("Failed to {} after {} attempts: {}", what, attempt, e));
+                }
+                let delay = 2u64.saturating_pow(attempt).min(MAX_RETRY_DELAY_SECS);
+                log_to_file(
+                    "WARN".to_string(),
+                    format!("Failed to {}, retrying in {}s: {}", what, delay, e),
+                );
+                tokio::time::sleep(Duration::from_secs(delay)).await;
+            }
+        }
+    }
+}
+
+fn chunk_size(requested: Option<usize>) -> usize {
+    requested
+        .unwrap_or(DEFAULT_CHUNK_SIZE)
+        .clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE)
+}
+
+/// Hex SHA-256 of a file, read in pieces so large files don't sit in memory
+pub async fn hash_file(path: &Path) -> Result<String, String> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    hash_open_file(&mut file, path).await
+}
+
+/// Hex SHA-256 of an open file from its current position, `path` is only for errors
+async fn hash_open_file(file: &mut tokio::fs::File, path: &Path) -> Result<String, String> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// The server side of one job's transfer
+struct TransferClient {
+    client: reqwest::Client,
+    url: String,
+    device_id: String,
+    site_id: String,
+    run_id: String,
+}
+
+impl TransferClient {
+    async fn new(job_id: &str, run_id: &str, endpoint: &str) -> Result<Self, String> {
+        let settings = get_settings()
+            .await
+            .map_err(|e| format!("Failed to get settings: {}", e))?;
+        let device_id = settings
+            .device_id
+            .ok_or_else(|| String::from("Device is not registered"))?;
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(CHUNK_TIMEOUT_SECS))
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(TransferClient {
+            client,
+            url: format!("{}/v1.0/jobs/{}/{}", settings.api_host, job_id, endpoint),
+            device_id,
+            site_id: settings.site_id,
+            run_id: run_id.to_string(),
+        })
+    }
+
+    fn request(&self, method: reqwest::Method) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, &self.url)
+            .header("x-device-id", &self.device_id)
+            .header("x-site-id", &self.site_id)
+            .header("x-run-id", &self.run_id)
+    }
+
+    /// Where to carry on uploading, nothing is stored yet when the server doesn't know the upload
+    async fn upload_offset(&self) -> Result<u64, ChunkError> {
+        let response = self
+            .request(reqwest::Method::GET)
+            .send()
+            .await
+            .map_err(|e| ChunkError::Retry(e.to_string()))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(0);
+        }
+        if !response.status().is_success() {
+            return Err(status_error(response).await);
+        }
+        response
+            .json::<UploadOffset>()
+            .await
+            .map(|progress| progress.offset)
+            .map_err(|e| ChunkError::Retry(format!("Unreadable upload offset: {}", e)))
+    }
+
+    /// Sends one chunk, returning the offset the server wants next
+    async fn put_chunk(&self, offset: u64, chunk: &[u8], size: u64, sha256: &str) -> Result<u64, ChunkError> {
+        let response = self
+            .request(reqwest::Method::PUT)
+            .header("Content-Type", "application/octet-stream")
+            .header("x-upload-offset", offset)
+            .header("x-upload-length", size)
+            .header("x-upload-sha256", sha256)
+            .body(chunk.to_vec())
+            .send()
+            .await
+            .map_err(|e| ChunkError::Retry(e.to_string()))?;
+
+        // A conflict means the server holds a different amount than we assumed, it says where to go on from
+        let status = response.status();
+        if !status.is_success() && status != reqwest::StatusCode::CONFLICT {
+            return Err(status_error(response).await);
+        }
+        let next = response
+            .json::<UploadOffset>()
+            .await
+            .map_err(|e| ChunkError::Retry(format!("Unreadable upload offset: {}", e)))?
+            .offset;
+        if next > size || next == offset || (status.is_success() && next < offset) {
+            return Err(ChunkError::Retry(format!(
+                "Server did not take the chunk at offset {} (now at {})",
+                offset, next
+            )));
+        }
+        Ok(next)
+    }
+
+    /// Fetches the bytes from `start` up to and including `end`
+    async fn get_chunk(&self, start: u64, end: u64) -> Result<Vec<u8>, ChunkError> {
+        let response = self
+            .request(reqwest::Method::GET)
+            .header("Range", format!("bytes={}-{}", start, end))
+            .send()
+            .await
+            .map_err(|e| ChunkError::Retry(e.to_string()))?;
+
+        let expected = end - start + 1;
+        match response.status() {
+            reqwest::StatusCode::PARTIAL_CONTENT => {}
+            // Fine for a file that fits in one chunk, anything else needs ranges to resume
+            reqwest::StatusCode::OK if start == 0 => {}
+            reqwest::StatusCode::OK => {
+                return Err(ChunkError::Fatal(String::from("Server does not support ranged downloads")))
+            }
+            _ => return Err(status_error(response).await),
+        }
+        if response.content_length().is_some_and(|length| length > expected) {
+            return Err(ChunkError::Fatal(format!(
+                "Server sent more than the {} bytes asked for",
+                expected
+            )));
+        }
+
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| ChunkError::Retry(e.to_string()))?;
+        if bytes.len() as u64 != expected {
+            return Err(ChunkError::Retry(format!(
+                "Got {} bytes at offset {}, expected {}",
+                bytes.len(),
+                start,
+                expected
+            )));
+        }
+        Ok(bytes.to_vec())
+    }
+}
+
+/// Uploads an allowed file in chunks, carrying on from whatever the server already has
+pub async fn upload_file(job_id: &str, run_id: &str, job: &FileUploadJob) -> Result<TransferSummary, String> {
+    let path = check_read_path(&job.path, &allowed_dirs().await)?;
+    let limit = job
+        .max_bytes
+        .unwrap_or(DEFAULT_MAX_UPLOAD_BYTES)
+        .min(MAX_TRANSFER_BYTES);
+
+    // Everything below goes through this handle, the path could point elsewhere by now
+    let mut file = tokio::fs::File::from_std(open_checked(&path, std::fs::OpenOptions::new().read(true))?);
+    let before = file
+        .metadata()
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let size = before.len();
+    if size > limit {
+        return Err(format!(
+            "{} is {} bytes, over the {} byte limit",
+            path.display(),
+            size,
+            limit
+        ));
+    }
+
+    // Hashed up front so the server can check the whole file, however many runs it took to send
+    let sha256 = hash_open_file(&mut file, &path).await?;
+    let client = TransferClient::new(job_id, run_id, "upload").await?;
+    let mut offset = with_retries("get upload progress", || client.upload_offset()).await?;
+
+    let mut buffer = vec![0u8; chunk_size(job.chunk_size)];
+    while offset < size {
+        let length = (size - offset).min(buffer.len() as u64) as usize;
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        file.read_exact(&mut buffer[..length])
+            .await
+            .map_err(|_| format!("{} changed while it was being uploaded", path.display()))?;
+
+        let chunk = &buffer[..length];
+        let what = format!("upload {} at offset {}", path.display(), offset);
+        offset = with_retries(&what, || client.put_chunk(offset, chunk, size, &sha256)).await?;
+    }
+
+    let after = file
+        .metadata()
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    if after.len() != size || after.modified().ok() != before.modified().ok() {
+        return Err(format!("{} changed while it was being uploaded", path.display()));
+    }
+
+    Ok(TransferSummary {
+        path: path.display().to_string(),
+        size,
+        sha256,
+    })
+}
+
+/// Partial downloads are kept where only the agent can reach them, nobody can swap them
+/// for a link or slip other content in before the hash check
+fn get_staging_dir() -> PathBuf {
+    get_config_dir().join("downloads")
+}
+
+async fn create_staging_dir() -> Result<(), String> {
+    let dir = get_staging_dir();
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700))
+            .await
+            .map_err(|e| format!("Failed to secure {}: {}", dir.display(), e))?;
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        let secured = dir.clone();
+        tauri::async_runtime::spawn_blocking(move || crate::file_access::restrict_to_system(&secured))
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| format!("Failed to secure {}: {}", dir.display(), e))?;
+    }
+
+    Ok(())
+}
+
+/// One staging file per target and content, so a retried job resumes and a different file starts over
+fn partial_path(target: &Path, sha256: &str) -> PathBuf {
+    let mut hasher = Sha256::new();
+    hasher.update(target.to_string_lossy().as_bytes());
+    hasher.update(b"\n");
+    hasher.update(sha256.as_bytes());
+    get_staging_dir().join(format!("{:x}.part", hasher.finalize()))
+}
+
+/// Copies a verified download to its target, never through a link and never over a file
+/// that appeared unless the job allows overwriting
+fn place_download(staged: &Path, target: &Path, overwrite: bool) -> Result<(), String> {
+    // Overwrites go through a fresh file next to the target, renaming replaces a link rather than following it
+    let destination = if overwrite {
+        let mut name = std::ffi::OsString::from(".");
+        name.push(target.file_name().unwrap_or_default());
+        name.push(format!(".{:08x}.tmp", rand::random::<u32>()));
+        target.with_file_name(name)
+    } else {
+        target.to_path_buf()
+    };
+
+    let mut output = open_checked(&destination, std::fs::OpenOptions::new().write(true).create_new(true))
+        .map_err(|e| match std::fs::symlink_metadata(&destination) {
+            Ok(_) => format!("{} already exists", destination.display()),
+            Err(_) => e,
+        })?;
+    let copied = std::fs::File::open(staged)
+        .and_then(|mut input| std::io::copy(&mut input, &mut output))
+        .and_then(|_| output.sync_all());
+    drop(output);
+
+    let placed = copied
+        .map_err(|e| format!("Failed to write {}: {}", destination.display(), e))
+        .and_then(|_| {
+            if overwrite {
+                std::fs::rename(&destination, target)
+                    .map_err(|e| format!("Failed to move download to {}: {}", target.display(), e))
+            } else {
+                Ok(())
+            }
+        });
+    if placed.is_err() {
+        let _ = std::fs::remove_file(&destination);
+    }
+    placed
+}
+
+/// Downloads a file to an allowed path in chunks, picking up a partial download where it stopped
+pub async fn download_file(job_id: &str, run_id: &str, job: &FileDownloadJob) -> Result<TransferSummary, String> {
+    if job.size > MAX_TRANSFER_BYTES {
+        return Err(format!(
+            "File is {} bytes, over the {} byte limit",
+            job.size, MAX_TRANSFER_BYTES
+        ));
+    }
+    let expected = job.sha256.trim().to_ascii_lowercase();
+    if expected.len() != 64 || !expected.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(String::from("Job has an invalid SHA-256"));
+    }
+
+    let target = check_write_path(&job.path, &allowed_dirs().await)?;
+    if target.exists() && !job.overwrite {
+        return Err(format!("{} already exists", target.display()));
+    }
+    create_staging_dir().await?;
+    let partial = partial_path(&target, &expected);
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&partial)
+        .await
+        .map_err(|e| format!("Failed to open {}: {}", partial.display(), e))?;
+    let mut offset = file
+        .metadata()
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", partial.display(), e))?
+        .len();
+    if offset > job.size {
+        offset = 0;
+    }
+    file.set_len(offset)
+        .await
+        .map_err(|e| format!("Failed to write {}: {}", partial.display(), e))?;
+    file.seek(SeekFrom::Start(offset))
+        .await
+        .map_err(|e| format!("Failed to write {}: {}", partial.display(), e))?;
+    if offset > 0 {
+        log_to_file(
+            "INFO".to_string(),
+            format!("Resuming download of {} at {} bytes", target.display(), offset),
+        );
+    }
+
+    let client = TransferClient::new(job_id, run_id, "download").await?;
+    let chunk = chunk_size(job.chunk_size) as u64;
+    while offset < job.size {
+        let end = (offset + chunk).min(job.size) - 1;
+        let what = format!("download {} at offset {}", target.display(), offset);
+        let bytes = with_retries(&what, || client.get_chunk(offset, end)).await?;
+        file.write_all(&bytes)
+            .await
+            .map_err(|e| format!("Failed to write {}: {}", partial.display(), e))?;
+        offset += bytes.len() as u64;
+    }
+    file.sync_all()
+        .await
+        .map_err(|e| format!("Failed to write {}: {}", partial.display(), e))?;
+    drop(file);
+
+    let actual = hash_file(&partial).await?;
+    if actual != expected {
+        let _ = tokio::fs::remove_file(&partial).await;
+        return Err(format!(
+            "Downloaded file has SHA-256 {}, expected {}",
+            actual, expected
+        ));
+    }
+    let (staged, placed_at) = (partial.clone(), target.clone());
+    let overwrite = job.overwrite;
+    tauri::async_runtime::spawn_blocking(move || place_download(&staged, &placed_at, overwrite))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|r| r)?;
+    let _ = tokio::fs::remove_file(&partial).await;
+
+    Ok(TransferSummary {
+        path: target.display().to_string(),
+        size: job.size,
+        sha256: actual,
+    })
+}
+
+/// Removes what a cancelled download left behind
+pub async fn discard_partial_download(job: &FileDownloadJob) {
+    if let Ok(target) = check_write_path(&job.path, &allowed_dirs().await) {
+        let expected = job.sha256.trim().to_ascii_lowercase();
+        let _ = tokio::fs::remove_file(partial_path(&target, &expected)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn staged(dir: &Path) -> PathBuf {
+        let staged = dir.join("staged.part");
+        std::fs::write(&staged, b"new").unwrap();
+        staged
+    }
+
+    #[test]
+    fn downloads_never_replace_files_unless_asked() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().canonicalize().unwrap();
+        let target = dir.join("target.txt");
+        std::fs::write(&target, b"old").unwrap();
+
+        assert!(place_download(&staged(&dir), &target, false).unwrap_err().contains("already exists"));
+        assert_eq!(std::fs::read(&target).unwrap(), b"old");
+
+        place_download(&staged(&dir), &target, true).unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), b"new");
+
+        // Nothing but the staged file and the target is left behind
+        let mut names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["staged.part", "target.txt"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn downloads_never_write_through_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().canonicalize().unwrap();
+        let outside = dir.join("outside.txt");
+        std::fs::write(&outside, b"precious").unwrap();
+
+        let target = dir.join("target.txt");
+        std::os::unix::fs::symlink(&outside, &target).unwrap();
+        assert!(place_download(&staged(&dir), &target, false).is_err());
+        assert_eq!(std::fs::read(&outside).unwrap(), b"precious");
+
+        // Overwriting replaces the link itself
+        place_download(&staged(&dir), &target, true).unwrap();
+        assert!(!std::fs::symlink_metadata(&target).unwrap().file_type().is_symlink());
+        assert_eq!(std::fs::read(&target).unwrap(), b"new");
+        assert_eq!(std::fs::read(&outside).unwrap(), b"precious");
+
+        let dangling = dir.join("dangling.txt");
+        std::os::unix::fs::symlink(dir.join("planted.txt"), &dangling).unwrap();
+        assert!(place_download(&staged(&dir), &dangling, false).is_err());
+        assert!(!dir.join("planted.txt").exists());
+    }
+}
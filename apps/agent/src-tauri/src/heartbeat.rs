@@ -1,11 +1,31 @@
-use crate::device_manager::{get_api_endpoint, get_primary_mac, get_settings, get_username};
+use crate::agent_info::{agent_info, AgentInfo};
+use crate::agent_state::{record_heartbeat_result, set_registered};
+use crate::alerts::{validate_rule, AlertRule};
+use crate::branding::{save_server_branding, sync_logo, Branding};
+use crate::device_manager::{
+    get_api_endpoint, get_primary_mac, get_settings, get_username, is_device_registered,
+    record_heartbeat, save_alert_rules, save_maintenance_window, save_monitored_services,
+    save_tray_menu,
+};
+use crate::device_registration::register_device_with_server;
+use crate::job_signing::{rotate_signing_key, JobEnvelope, KeyRotation};
+use crate::jobs::{cancel_job, dispatch_jobs, save_on_demand_jobs};
 use crate::logger::log_to_file;
+use crate::metrics::{summarize_recent, Aggregate, MetricsSummary};
+use crate::outbox::{enqueue_latest, is_network_error};
+use crate::reboot::{reboot_status, validate_window, MaintenanceWindow, RebootStatus};
+use crate::service_monitor::{latest_service_states, MonitoredService, ServiceState};
+use crate::ticket_store::{apply_ticket_updates, open_ticket_ids, TicketStatusUpdate};
+use crate::tray::TrayMenuEntry;
+use crate::updater::{update_status, UpdateStatus};
 use serde::{Deserialize, Serialize};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokio::time::{interval, Duration};
 
-#[derive(Serialize, Debug)]
+pub const HEARTBEAT_INTERVAL_SECS: u64 = 60 * 10;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct HeartbeatRequest {
     pub hostname: String,
     pub ip_address: Option<String>,
@@ -14,6 +34,18 @@ pub struct HeartbeatRequest {
     pub mac_address: Option<String>,
     pub guid: Option<String>,
     pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub open_ticket_ids: Option<Vec<String>>, // Tickets the server should report status for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<MetricsSummary>, // Aggregates of the samples since the last heartbeat
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub services: Option<Vec<ServiceState>>, // Last known state of each monitored service
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reboot: Option<RebootStatus>, // Whether the OS wants a reboot and any reboot counting down
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub update: Option<UpdateStatus>, // Update channel, rollout bucket and any staged release
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<AgentInfo>, // Capabilities, build and OS release, re-sent since a self-update changes them
 }
 
 #[derive(Deserialize, Debug)]
@@ -24,6 +56,26 @@ pub struct HeartbeatResponse {
 #[derive(Deserialize, Debug)]
 pub struct HeartbeatData {
     pub guid: String,
+    #[serde(default)]
+    pub tickets: Vec<TicketStatusUpdate>,
+    #[serde(default)]
+    pub tray_menu: Option<Vec<TrayMenuEntry>>, // Left alone when the server doesn't send one
+    #[serde(default)]
+    pub branding: Option<Branding>,
+    #[serde(default)]
+    pub alert_rules: Option<Vec<AlertRule>>, // Left alone when the server doesn't send any
+    #[serde(default)]
+    pub monitored_services: Option<Vec<MonitoredService>>, // Left alone when the server doesn't send any
+    #[serde(default)]
+    pub maintenance_window: Option<MaintenanceWindow>, // Left alone when the server doesn't send one
+    #[serde(default)]
+    pub jobs: Vec<JobEnvelope>, // Run as soon as they arrive
+    #[serde(default)]
+    pub cancel_jobs: Vec<String>, // Run ids of jobs to stop
+    #[serde(default)]
+    pub on_demand_jobs: Option<Vec<JobEnvelope>>, // Jobs users may start from the tray, left alone when not sent
+    #[serde(default)]
+    pub signing_key_rotation: Option<KeyRotation>,
 }
 
 /// Gathers current system information for heartbeat
@@ -46,6 +98,12 @@ pub async fn gather_system_info() -> Result<HeartbeatRequest, Box<dyn std::error
         mac_address,
         guid: settings.guid,
         username,
+        open_ticket_ids: None,
+        metrics: None,
+        services: None,
+        reboot: None,
+        update: None,
+        agent: None,
     })
 }
 
@@ -73,8 +131,8 @@ pub async fn get_external_ip() -> Result<String, Box<dyn std::error::Error>> {
     Ok(response.text().await?)
 }
 
-/// Sends a heartbeat to the server
-pub async fn send_heartbeat() -> Result<HeartbeatResponse, Box<dyn std::error::Error>> {
+/// Posts a heartbeat to the server without acting on the reply, for callers outside the service
+pub async fn post_heartbeat() -> Result<HeartbeatResponse, Box<dyn std::error::Error>> {
     let settings = get_settings().await?;
 
     // Check if device is registered
@@ -86,27 +144,49 @@ pub async fn send_heartbeat() -> Result<HeartbeatResponse, Box<dyn std::error::E
     let site_id = &settings.site_id;
 
     // Gather system info
-    let request = gather_system_info().await?;
+    let mut request = gather_system_info().await?;
+    request.open_ticket_ids = open_ticket_ids().await.ok().filter(|ids| !ids.is_empty());
+    request.metrics = summarize_recent(HEARTBEAT_INTERVAL_SECS).await.ok().flatten();
+    request.services = Some(latest_service_states()).filter(|states| !states.is_empty());
+    // The checks shell out on Windows
+    request.reboot = tauri::async_runtime::spawn_blocking(reboot_status).await.ok();
+    request.update = update_status().await;
+    request.agent = tauri::async_runtime::spawn_blocking(agent_info).await.ok();
 
     let api_url = get_api_endpoint("/v1.0/heartbeat").await?;
 
     let client = reqwest::Client::new();
-    let response = client
+    let response = match client
         .post(&api_url)
         .header("Content-Type", "application/json")
         .header("x-device-id", device_id)
         .header("x-site-id", site_id)
         .json(&request)
         .send()
-        .await?;
+        .await
+    {
+        Ok(response) => response,
+        Err(e) => {
+            // Only the latest heartbeat is worth delivering once the network is back
+            if is_network_error(&e) {
+                if let Err(queue_err) =
+                    enqueue_latest("heartbeat", "/v1.0/heartbeat", serde_json::to_value(&request)?).await
+                {
+                    log_to_file(
+                        "WARN".to_string(),
+                        format!("Failed to queue heartbeat: {}", queue_err),
+                    );
+                }
+            }
+            return Err(e.into());
+        }
+    };
 
     let status = response.status();
 
     if status.is_success() {
         let response_text = response.text().await?;
-        let result: HeartbeatResponse = serde_json::from_str(&response_text)?;
-
-        Ok(result)
+        Ok(serde_json::from_str(&response_text)?)
     } else {
         let error_text = response
             .text()
@@ -116,6 +196,179 @@ pub async fn send_heartbeat() -> Result<HeartbeatResponse, Box<dyn std::error::E
     }
 }
 
+/// Sends a heartbeat and applies the reply. Only the service may call this, it runs the
+/// delivered jobs and writes the machine settings.
+pub async fn send_heartbeat() -> Result<HeartbeatResponse, Box<dyn std::error::Error>> {
+    let result = post_heartbeat().await?;
+    apply_heartbeat(&result.data).await;
+    Ok(result)
+}
+
+/// Applies the reply to a heartbeat the outbox delivered once the network came back
+pub async fn apply_delivered_heartbeat(response: &str) {
+    match serde_json::from_str::<HeartbeatResponse>(response) {
+        Ok(result) => {
+            apply_heartbeat(&result.data).await;
+            record_heartbeat_result(Ok(()));
+        }
+        Err(e) => log_to_file(
+            "WARN".to_string(),
+            format!("Queued heartbeat delivered with unreadable response: {}", e),
+        ),
+    }
+}
+
+/// Acts on a heartbeat reply: config updates, ticket statuses and jobs
+async fn apply_heartbeat(data: &HeartbeatData) {
+    if let Err(e) = record_heartbeat().await {
+        log_to_file(
+            "WARN".to_string(),
+            format!("Failed to record heartbeat time: {}", e),
+        );
+    }
+
+    if !data.tickets.is_empty() {
+        match apply_ticket_updates(&data.tickets).await {
+            Ok(0) => {}
+            Ok(changed) => log_to_file(
+                "INFO".to_string(),
+                format!("Heartbeat updated {} tracked ticket(s)", changed),
+            ),
+            Err(e) => log_to_file(
+                "WARN".to_string(),
+                format!("Failed to apply ticket updates: {}", e),
+            ),
+        }
+    }
+
+    if let Some(menu) = &data.tray_menu {
+        match save_tray_menu(menu).await {
+            Ok(true) => log_to_file(
+                "INFO".to_string(),
+                "Heartbeat updated the tray menu".to_string(),
+            ),
+            Ok(false) => {}
+            Err(e) => log_to_file(
+                "WARN".to_string(),
+                format!("Failed to save tray menu: {}", e),
+            ),
+        }
+    }
+
+    if let Some(rules) = &data.alert_rules {
+        // A bad rule is dropped on its own rather than losing the whole policy
+        let rules: Vec<AlertRule> = rules
+            .iter()
+            .filter(|rule| match validate_rule(rule) {
+                Ok(()) => true,
+                Err(e) => {
+                    log_to_file("WARN".to_string(), format!("Skipping alert rule: {}", e));
+                    false
+                }
+            })
+            .cloned()
+            .collect();
+        match save_alert_rules(&rules).await {
+            Ok(true) => log_to_file(
+                "INFO".to_string(),
+                "Heartbeat updated the alert rules".to_string(),
+            ),
+            Ok(false) => {}
+            Err(e) => log_to_file(
+                "WARN".to_string(),
+                format!("Failed to save alert rules: {}", e),
+            ),
+        }
+    }
+
+    if let Some(services) = &data.monitored_services {
+        match save_monitored_services(services).await {
+            Ok(true) => log_to_file(
+                "INFO".to_string(),
+                "Heartbeat updated the monitored services".to_string(),
+            ),
+            Ok(false) => {}
+            Err(e) => log_to_file(
+                "WARN".to_string(),
+                format!("Failed to save monitored services: {}", e),
+            ),
+        }
+    }
+
+    if let Some(window) = &data.maintenance_window {
+        match validate_window(window) {
+            Ok(()) => match save_maintenance_window(window).await {
+                Ok(true) => log_to_file(
+                    "INFO".to_string(),
+                    "Heartbeat updated the maintenance window".to_string(),
+                ),
+                Ok(false) => {}
+                Err(e) => log_to_file(
+                    "WARN".to_string(),
+                    format!("Failed to save maintenance window: {}", e),
+                ),
+            },
+            Err(e) => log_to_file(
+                "WARN".to_string(),
+                format!("Ignoring maintenance window: {}", e),
+            ),
+        }
+    }
+
+    // Before the jobs, they may already be signed with the new key
+    if let Some(rotation) = &data.signing_key_rotation {
+        match rotate_signing_key(rotation).await {
+            Ok(true) => log_to_file(
+                "INFO".to_string(),
+                "Heartbeat rotated the job signing key".to_string(),
+            ),
+            Ok(false) => {}
+            Err(e) => log_to_file(
+                "WARN".to_string(),
+                format!("Rejected job signing key rotation: {}", e),
+            ),
+        }
+    }
+
+    for run_id in &data.cancel_jobs {
+        if cancel_job(run_id) {
+            log_to_file("INFO".to_string(), format!("Cancelling job run {}", run_id));
+        }
+    }
+    dispatch_jobs(&data.jobs).await;
+
+    if let Some(jobs) = &data.on_demand_jobs {
+        if let Err(e) = save_on_demand_jobs(jobs).await.map_err(|e| e.to_string()) {
+            log_to_file(
+                "WARN".to_string(),
+                format!("Failed to save on-demand jobs: {}", e),
+            );
+        }
+    }
+
+    if let Some(branding) = &data.branding {
+        match save_server_branding(branding).await.map_err(|e| e.to_string()) {
+            Ok(true) => {
+                log_to_file(
+                    "INFO".to_string(),
+                    "Heartbeat updated the branding".to_string(),
+                );
+                if let Err(e) = sync_logo().await.map_err(|e| e.to_string()) {
+                    log_to_file(
+                        "WARN".to_string(),
+                        format!("Failed to update branding logo: {}", e),
+                    );
+                }
+            }
+            Ok(false) => {}
+            Err(e) => log_to_file(
+                "WARN".to_string(),
+                format!("Failed to save branding: {}", e),
+            ),
+        }
+    }
+}
+
 /// Starts the heartbeat background task that runs every 60 seconds
 pub fn start_heartbeat_task(running: Arc<AtomicBool>) {
     tauri::async_runtime::spawn(async move {
@@ -127,7 +380,7 @@ pub fn start_heartbeat_task(running: Arc<AtomicBool>) {
         // Wait 5 seconds before first heartbeat to allow app to fully initialize
         tokio::time::sleep(Duration::from_secs(5)).await;
 
-        let mut heartbeat_interval = interval(Duration::from_secs(60 * 10));
+        let mut heartbeat_interval = interval(Duration::from_secs(HEARTBEAT_INTERVAL_SECS));
         let mut health_check_interval = interval(Duration::from_secs(86400)); // 24 hours
 
         // Skip first tick for health check to align with actual 24hr intervals
@@ -136,22 +389,45 @@ pub fn start_heartbeat_task(running: Arc<AtomicBool>) {
         while running.load(Ordering::Relaxed) {
             tokio::select! {
                 _ = heartbeat_interval.tick() => {
+                    // Registration failed at boot (server down, no network yet), keep trying until it sticks
+                    if !is_device_registered().await {
+                        match register_device_with_server().await.map_err(|e| e.to_string()) {
+                            Ok(response) => {
+                                log_to_file(
+                                    "INFO".to_string(),
+                                    format!("Device registered as {}", response.data.device_id),
+                                );
+                                set_registered(true);
+                            }
+                            Err(e) => {
+                                log_to_file(
+                                    "WARN".to_string(),
+                                    format!("Failed to register device: {}", e),
+                                );
+                                record_heartbeat_result(Err(e));
+                                continue;
+                            }
+                        }
+                    }
+
                     // Send heartbeat silently (no logging unless error)
-                    match send_heartbeat().await {
+                    match send_heartbeat().await.map_err(|e| e.to_string()) {
                         Ok(_response) => {
                             // Success - no logging
+                            record_heartbeat_result(Ok(()));
                         }
                         Err(e) => {
                             log_to_file(
                                 "WARN".to_string(),
                                 format!("Failed to send heartbeat: {}", e),
                             );
+                            record_heartbeat_result(Err(e));
                         }
                     }
                 }
                 _ = health_check_interval.tick() => {
                     // Daily health check log
-                    match gather_system_info().await {
+                    match gather_system_info().await.map_err(|e| e.to_string()) {
                         Ok(info) => {
                             log_to_file(
                                 "INFO".to_string(),
@@ -163,6 +439,22 @@ pub fn start_heartbeat_task(running: Arc<AtomicBool>) {
                                     info.mac_address.unwrap_or_else(|| "N/A".to_string())
                                 ),
                             );
+
+                            if let Ok(Some(summary)) = summarize_recent(86400).await.map_err(|e| e.to_string()) {
+                                let describe = |aggregate: Option<Aggregate>| match aggregate {
+                                    Some(a) => format!("avg {:.1}% / p95 {:.1}% / max {:.1}%", a.avg, a.p95, a.max),
+                                    None => "N/A".to_string(),
+                                };
+                                log_to_file(
+                                    "INFO".to_string(),
+                                    format!(
+                                        "Daily health check - CPU: {}, Memory: {} over {} samples",
+                                        describe(summary.cpu_percent),
+                                        describe(summary.memory_percent),
+                                        summary.samples
+                                    ),
+                                );
+                            }
                         }
                         Err(e) => {
                             log_to_file(
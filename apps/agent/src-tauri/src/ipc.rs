@@ -0,0 +1,796 @@
+use crate::device_manager::get_config_dir;
+use crate::logger::log_to_file;
+use crate::ticket::TicketSubmission;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::sync::Semaphore;
+use tokio::time::{timeout, Duration};
+
+/// Bump when a request or response shape changes incompatibly
+pub const IPC_PROTOCOL_VERSION: u32 = 2;
+
+const MAX_FRAME_BYTES: usize = 32 * 1024 * 1024; // 32MB, tickets carry screenshots inline
+// Peers that may only send the open commands never need more than this
+const MAX_OPEN_FRAME_BYTES: usize = 64 * 1024;
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+// The service answers once the ticket and its screenshot are uploaded, which can take a while
+const SUBMIT_TICKET_TIMEOUT: Duration = Duration::from_secs(180);
+// Gathering system info and posting the heartbeat, then acting on the reply
+const SEND_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Every local account can connect, so none may hold the service's memory or sockets for long.
+// Clients send their request as soon as they connect and hang up after the answer.
+pub const FRAME_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+pub const MAX_CONNECTIONS: usize = 16;
+// Errors like EMFILE clear up once other connections close
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+#[cfg(target_os = "windows")]
+pub const IPC_PIPE_NAME: &str = r"\\.\pipe\MSPAgent";
+
+// The default pipe DACL only grants read access to regular users, so the
+// service (running as SYSTEM) has to allow authenticated users to write
+#[cfg(target_os = "windows")]
+const IPC_PIPE_SDDL: &str = "D:(A;;GA;;;SY)(A;;GA;;;BA)(A;;GRGW;;;AU)";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", content = "params", rename_all = "snake_case")]
+pub enum IpcCommand {
+    Ping,
+    GetSettings,
+    CheckRegistration,
+    GetSystemInfo,
+    GetRmmId,
+    GetOutbox,
+    GetOutboxReceipt { id: String },
+    GetAgentState,
+    GetTickets,
+    RunJob { job_id: String },
+    DeferReboot,
+    SendHeartbeat,
+    SubmitTicket(Box<TicketSubmission>),
+}
+
+impl IpcCommand {
+    /// Commands any local account may send, the rest need a person's account or an admin
+    pub fn is_open(&self) -> bool {
+        matches!(
+            self,
+            IpcCommand::Ping | IpcCommand::CheckRegistration | IpcCommand::GetAgentState
+        )
+    }
+
+    /// How long the client waits for the service to answer once the command is sent
+    fn response_timeout(&self) -> Duration {
+        match self {
+            IpcCommand::SubmitTicket(_) => SUBMIT_TICKET_TIMEOUT,
+            IpcCommand::SendHeartbeat => SEND_HEARTBEAT_TIMEOUT,
+            _ => CLIENT_TIMEOUT,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IpcRequest<C> {
+    pub version: u32,
+    pub id: u64,
+    pub command: C,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IpcResponse {
+    pub version: u32,
+    pub id: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl IpcResponse {
+    pub fn ok(id: u64, data: Value) -> Self {
+        IpcResponse {
+            version: IPC_PROTOCOL_VERSION,
+            id,
+            data: Some(data),
+            error: None,
+        }
+    }
+
+    pub fn err(id: u64, message: String) -> Self {
+        IpcResponse {
+            version: IPC_PROTOCOL_VERSION,
+            id,
+            data: None,
+            error: Some(message),
+        }
+    }
+
+    /// Stamps another protocol's version, for the local API which shares this framing
+    pub fn with_version(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+}
+
+/// Identity of the process on the other end of the socket, when the OS exposes it
+#[derive(Debug, Clone, Default)]
+pub struct PeerInfo {
+    pub uid: Option<u32>,
+    pub pid: Option<i32>,
+    /// Account SID on Windows
+    pub sid: Option<String>,
+    /// Root, SYSTEM or an elevated administrator
+    pub admin: bool,
+    /// A person's login account rather than a service account
+    pub interactive: bool,
+}
+
+impl PeerInfo {
+    /// The account things this peer creates belong to, a uid on unix and a SID on Windows
+    pub fn owner(&self) -> Option<String> {
+        self.sid
+            .clone()
+            .or_else(|| self.uid.map(|uid| uid.to_string()))
+    }
+
+    /// Whether the peer may send commands beyond the open ones
+    pub fn is_trusted(&self) -> bool {
+        self.admin || self.interactive
+    }
+}
+
+#[derive(Debug)]
+pub enum IpcError {
+    /// The service could not be reached, callers may fall back to local handling
+    Unavailable(String),
+    /// The service got the command but never answered, it may still have acted on it
+    NoResponse(String),
+    /// The service answered with an error
+    Remote(String),
+}
+
+impl fmt::Display for IpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpcError::Unavailable(e) => write!(f, "Agent service unavailable: {}", e),
+            IpcError::NoResponse(e) => write!(f, "Agent service did not answer: {}", e),
+            IpcError::Remote(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for IpcError {}
+
+#[cfg(unix)]
+pub fn get_ipc_socket_path() -> PathBuf {
+    #[cfg(target_os = "macos")]
+    {
+        PathBuf::from("/var/run/mspagent.sock")
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        PathBuf::from("/run/mspagent/agent.sock")
+    }
+}
+
+// Older services left a world-readable token here, connections are now authorized by peer identity
+fn get_legacy_token_path() -> PathBuf {
+    get_config_dir().join("ipc.token")
+}
+
+/// Reads one newline-delimited JSON frame, returning None on a clean EOF
+pub async fn read_frame<R, T>(reader: &mut BufReader<R>) -> Result<Option<T>, Box<dyn std::error::Error + Send + Sync>>
+where
+    R: AsyncRead + Unpin,
+    T: DeserializeOwned,
+{
+    read_frame_limited(reader, MAX_FRAME_BYTES).await
+}
+
+/// Like `read_frame`, refusing frames over `max_bytes`
+async fn read_frame_limited<R, T>(
+    reader: &mut BufReader<R>,
+    max_bytes: usize,
+) -> Result<Option<T>, Box<dyn std::error::Error + Send + Sync>>
+where
+    R: AsyncRead + Unpin,
+    T: DeserializeOwned,
+{
+    let mut line = String::new();
+    let read = (&mut *reader)
+        .take(max_bytes as u64 + 1)
+        .read_line(&mut line)
+        .await?;
+
+    if read == 0 {
+        return Ok(None);
+    }
+    if read > max_bytes {
+        return Err(format!("Frame exceeds {} bytes", max_bytes).into());
+    }
+
+    Ok(Some(serde_json::from_str(line.trim_end())?))
+}
+
+/// Writes a value as a single newline-delimited JSON frame
+pub async fn write_frame<W, T>(writer: &mut W, value: &T) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    let mut payload = serde_json::to_vec(value)?;
+    payload.push(b'\n');
+    writer.write_all(&payload).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Serves requests on one connection until the client disconnects
+pub async fn handle_connection<S, C, F, Fut>(stream: S, peer: PeerInfo, handler: F)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    C: DeserializeOwned,
+    F: Fn(C, PeerInfo) -> Fut,
+    Fut: Future<Output = Result<Value, String>>,
+{
+    let (read_half, mut write_half) = tokio::io::split(stream);
+    let mut reader = BufReader::new(read_half);
+    let max_bytes = if peer.is_trusted() {
+        MAX_FRAME_BYTES
+    } else {
+        MAX_OPEN_FRAME_BYTES
+    };
+
+    loop {
+        let request: IpcRequest<Value> = match timeout(
+            FRAME_IDLE_TIMEOUT,
+            read_frame_limited(&mut reader, max_bytes),
+        )
+        .await
+        {
+            Ok(Ok(Some(request))) => request,
+            Ok(Ok(None)) | Err(_) => break,
+            Ok(Err(e)) => {
+                log_to_file(
+                    String::from("WARN"),
+                    format!("Dropping IPC connection after malformed frame: {}", e),
+                );
+                let _ = write_frame(&mut write_half, &IpcResponse::err(0, e.to_string())).await;
+                break;
+            }
+        };
+
+        let response = if request.version != IPC_PROTOCOL_VERSION {
+            IpcResponse::err(
+                request.id,
+                format!(
+                    "Unsupported protocol version {} (service speaks {})",
+                    request.version, IPC_PROTOCOL_VERSION
+                ),
+            )
+        } else {
+            match serde_json::from_value::<C>(request.command) {
+                Ok(command) => match handler(command, peer.clone()).await {
+                    Ok(data) => IpcResponse::ok(request.id, data),
+                    Err(e) => IpcResponse::err(request.id, e),
+                },
+                Err(e) => IpcResponse::err(request.id, format!("Unknown command: {}", e)),
+            }
+        };
+
+        if let Err(e) = write_frame(&mut write_half, &response).await {
+            log_to_file(
+                String::from("WARN"),
+                format!("Failed to write IPC response: {}", e),
+            );
+            break;
+        }
+    }
+}
+
+/// Listens on the agent socket and dispatches each command to the handler
+#[cfg(unix)]
+pub async fn serve<F, Fut>(handler: F) -> Result<(), Box<dyn std::error::Error>>
+where
+    F: Fn(IpcCommand, PeerInfo) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Result<Value, String>> + Send + 'static,
+{
+    let _ = tokio::fs::remove_file(get_legacy_token_path()).await;
+    let socket_path = get_ipc_socket_path();
+    let listener = bind_unix_socket(&socket_path).await?;
+
+    log_to_file(
+        String::from("INFO"),
+        format!("IPC server listening on {}", socket_path.display()),
+    );
+
+    let limit = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+    loop {
+        // Held until the connection closes, later clients wait in the listen backlog
+        let permit = limit.clone().acquire_owned().await?;
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                accept_failed("IPC server", e.to_string()).await;
+                continue;
+            }
+        };
+        let peer = unix_peer_info(&stream);
+
+        let handler = handler.clone();
+        tauri::async_runtime::spawn(async move {
+            handle_connection(stream, peer, handler).await;
+            drop(permit);
+        });
+    }
+}
+
+/// Logs a failed accept and backs off, one bad connection must not stop the server
+pub async fn accept_failed(server: &str, error: String) {
+    log_to_file(
+        String::from("WARN"),
+        format!("{} failed to accept a connection: {}", server, error),
+    );
+    tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+}
+
+/// Binds a world-connectable socket, replacing any stale one from a previous run
+#[cfg(unix)]
+pub async fn bind_unix_socket(
+    socket_path: &std::path::Path,
+) -> Result<tokio::net::UnixListener, Box<dyn std::error::Error>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Some(parent) = socket_path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let _ = tokio::fs::remove_file(socket_path).await;
+
+    let listener = tokio::net::UnixListener::bind(socket_path)?;
+    tokio::fs::set_permissions(socket_path, std::fs::Permissions::from_mode(0o666)).await?;
+
+    Ok(listener)
+}
+
+#[cfg(unix)]
+const NOBODY_UID: u32 = 65534;
+
+/// Lowest uid handed to people rather than system accounts
+#[cfg(unix)]
+fn login_uid_min() -> u32 {
+    #[cfg(target_os = "macos")]
+    {
+        501
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        std::fs::read_to_string("/etc/login.defs")
+            .ok()
+            .and_then(|defs| {
+                defs.lines().find_map(|line| {
+                    let mut fields = line.split_whitespace();
+                    match (fields.next(), fields.next()) {
+                        (Some("UID_MIN"), Some(value)) => value.parse().ok(),
+                        _ => None,
+                    }
+                })
+            })
+            .unwrap_or(1000)
+    }
+}
+
+#[cfg(unix)]
+pub fn unix_peer_info(stream: &tokio::net::UnixStream) -> PeerInfo {
+    // The kernel vouches for these, unlike anything the client sends
+    match stream.peer_cred() {
+        Ok(cred) => PeerInfo {
+            uid: Some(cred.uid()),
+            pid: cred.pid(),
+            sid: None,
+            admin: cred.uid() == 0,
+            interactive: cred.uid() >= login_uid_min() && cred.uid() != NOBODY_UID,
+        },
+        Err(_) => PeerInfo::default(),
+    }
+}
+
+/// Listens on the agent named pipe and dispatches each command to the handler
+#[cfg(target_os = "windows")]
+pub async fn serve<F, Fut>(handler: F) -> Result<(), Box<dyn std::error::Error>>
+where
+    F: Fn(IpcCommand, PeerInfo) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Result<Value, String>> + Send + 'static,
+{
+    let _ = tokio::fs::remove_file(get_legacy_token_path()).await;
+    let mut server = create_pipe_instance(IPC_PIPE_NAME, IPC_PIPE_SDDL, true)?;
+
+    log_to_file(
+        String::from("INFO"),
+        format!("IPC server listening on {}", IPC_PIPE_NAME),
+    );
+
+    let limit = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+    loop {
+        let permit = limit.clone().acquire_owned().await?;
+        let connected = server.connect().await.map_err(|e| e.to_string());
+        if let Err(e) = connected {
+            accept_failed("IPC server", e).await;
+            // The instance may be unusable after a failed connect
+            server = next_pipe_instance(IPC_PIPE_NAME, IPC_PIPE_SDDL).await;
+            continue;
+        }
+        let connected = server;
+        server = next_pipe_instance(IPC_PIPE_NAME, IPC_PIPE_SDDL).await;
+
+        let peer = pipe_peer_info(&connected);
+
+        let handler = handler.clone();
+        tauri::async_runtime::spawn(async move {
+            handle_connection(connected, peer, handler).await;
+            drop(permit);
+        });
+    }
+}
+
+/// Creates the instance the next client connects to, retrying until the OS allows another
+#[cfg(target_os = "windows")]
+pub async fn next_pipe_instance(
+    name: &str,
+    sddl: &str,
+) -> tokio::net::windows::named_pipe::NamedPipeServer {
+    loop {
+        let created = create_pipe_instance(name, sddl, false).map_err(|e| e.to_string());
+        match created {
+            Ok(server) => return server,
+            Err(e) => accept_failed(name, e).await,
+        }
+    }
+}
+
+/// Creates a local-only pipe instance guarded by the given SDDL access list
+#[cfg(target_os = "windows")]
+pub fn create_pipe_instance(
+    name: &str,
+    sddl: &str,
+    first: bool,
+) -> Result<tokio::net::windows::named_pipe::NamedPipeServer, Box<dyn std::error::Error>> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+    use windows_sys::Win32::Foundation::LocalFree;
+    use windows_sys::Win32::Security::Authorization::{
+        ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+    };
+    use windows_sys::Win32::Security::SECURITY_ATTRIBUTES;
+
+    let sddl: Vec<u16> = sddl
+        .encode_utf16()
+        .chain(std::iter::once(0))
+        .collect();
+    let mut descriptor = std::ptr::null_mut();
+
+    let converted = unsafe {
+        ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            sddl.as_ptr(),
+            SDDL_REVISION_1,
+            &mut descriptor,
+            std::ptr::null_mut(),
+        )
+    };
+    if converted == 0 {
+        return Err("Failed to build named pipe security descriptor".into());
+    }
+
+    let mut attributes = SECURITY_ATTRIBUTES {
+        nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+        lpSecurityDescriptor: descriptor,
+        bInheritHandle: 0,
+    };
+
+    let server = unsafe {
+        ServerOptions::new()
+            .first_pipe_instance(first)
+            .reject_remote_clients(true)
+            .create_with_security_attributes_raw(
+                name,
+                &mut attributes as *mut SECURITY_ATTRIBUTES as *mut std::ffi::c_void,
+            )
+    };
+
+    unsafe {
+        LocalFree(descriptor as _);
+    }
+
+    Ok(server?)
+}
+
+/// Account and groups of the process on the other end of a pipe, from its access token
+#[cfg(target_os = "windows")]
+pub fn pipe_peer_info(pipe: &tokio::net::windows::named_pipe::NamedPipeServer) -> PeerInfo {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Foundation::HANDLE;
+    use windows_sys::Win32::System::Pipes::GetNamedPipeClientProcessId;
+
+    let mut pid = 0u32;
+    if unsafe { GetNamedPipeClientProcessId(pipe.as_raw_handle() as HANDLE, &mut pid) } == 0 {
+        return PeerInfo::default();
+    }
+    process_peer_info(pid)
+}
+
+/// Identity of this process, for when the UI has to act without the service
+#[cfg(unix)]
+pub fn current_peer_info() -> PeerInfo {
+    let uid = unsafe { libc::getuid() };
+    PeerInfo {
+        uid: Some(uid),
+        pid: Some(std::process::id() as i32),
+        sid: None,
+        admin: uid == 0,
+        interactive: uid >= login_uid_min() && uid != NOBODY_UID,
+    }
+}
+
+/// Identity of this process, for when the UI has to act without the service
+#[cfg(target_os = "windows")]
+pub fn current_peer_info() -> PeerInfo {
+    process_peer_info(std::process::id())
+}
+
+#[cfg(target_os = "windows")]
+fn process_peer_info(pid: u32) -> PeerInfo {
+    use windows_sys::Win32::Foundation::{CloseHandle, LocalFree, HANDLE};
+    use windows_sys::Win32::Security::Authorization::ConvertSidToStringSidW;
+    use windows_sys::Win32::Security::{
+        GetTokenInformation, TokenGroups, TokenUser, PSID, TOKEN_GROUPS, TOKEN_INFORMATION_CLASS,
+        TOKEN_QUERY, TOKEN_USER,
+    };
+    use windows_sys::Win32::System::Threading::{
+        OpenProcess, OpenProcessToken, PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+
+    const SE_GROUP_ENABLED: u32 = 0x4;
+    const SYSTEM_SID: &str = "S-1-5-18";
+    const INTERACTIVE_SID: &str = "S-1-5-4";
+    const ADMINISTRATORS_SID: &str = "S-1-5-32-544";
+
+    unsafe fn sid_string(sid: PSID) -> Option<String> {
+        let mut raw = std::ptr::null_mut();
+        if ConvertSidToStringSidW(sid, &mut raw) == 0 {
+            return None;
+        }
+        let len = (0..).take_while(|&i| *raw.add(i) != 0).count();
+        let value = String::from_utf16_lossy(std::slice::from_raw_parts(raw, len));
+        LocalFree(raw as _);
+        Some(value)
+    }
+
+    unsafe fn token_info(token: HANDLE, class: TOKEN_INFORMATION_CLASS) -> Option<Vec<u64>> {
+        let mut needed = 0u32;
+        GetTokenInformation(token, class, std::ptr::null_mut(), 0, &mut needed);
+        if needed == 0 {
+            return None;
+        }
+        // u64 backing keeps the pointer-bearing structs aligned
+        let mut buffer = vec![0u64; (needed as usize).div_ceil(8)];
+        if GetTokenInformation(token, class, buffer.as_mut_ptr() as _, needed, &mut needed) == 0 {
+            return None;
+        }
+        Some(buffer)
+    }
+
+    let mut peer = PeerInfo {
+        pid: Some(pid as i32),
+        ..PeerInfo::default()
+    };
+    unsafe {
+
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if process.is_null() {
+            return peer;
+        }
+        let mut token: HANDLE = std::ptr::null_mut();
+        let opened = OpenProcessToken(process, TOKEN_QUERY, &mut token);
+        CloseHandle(process);
+        if opened == 0 {
+            return peer;
+        }
+
+        if let Some(buffer) = token_info(token, TokenUser) {
+            let user = &*(buffer.as_ptr() as *const TOKEN_USER);
+            peer.sid = sid_string(user.User.Sid);
+        }
+        if let Some(buffer) = token_info(token, TokenGroups) {
+            let groups = &*(buffer.as_ptr() as *const TOKEN_GROUPS);
+            let entries = std::slice::from_raw_parts(groups.Groups.as_ptr(), groups.GroupCount as usize);
+            for group in entries {
+                if group.Attributes & SE_GROUP_ENABLED == 0 {
+                    continue;
+                }
+                match sid_string(group.Sid).as_deref() {
+                    // Only enabled in an elevated token, a filtered admin token counts as a user
+                    Some(ADMINISTRATORS_SID) => peer.admin = true,
+                    Some(INTERACTIVE_SID) => peer.interactive = true,
+                    _ => {}
+                }
+            }
+        }
+        CloseHandle(token);
+    }
+
+    if peer.sid.as_deref() == Some(SYSTEM_SID) {
+        peer.admin = true;
+    }
+    peer
+}
+
+#[cfg(unix)]
+async fn connect() -> Result<tokio::net::UnixStream, IpcError> {
+    tokio::net::UnixStream::connect(get_ipc_socket_path())
+        .await
+        .map_err(|e| IpcError::Unavailable(e.to_string()))
+}
+
+#[cfg(target_os = "windows")]
+async fn connect() -> Result<tokio::net::windows::named_pipe::NamedPipeClient, IpcError> {
+    use tokio::net::windows::named_pipe::ClientOptions;
+
+    const ERROR_PIPE_BUSY: i32 = 231;
+
+    // All pipe instances can be briefly busy while the server creates the next one
+    for _ in 0..5 {
+        match ClientOptions::new().open(IPC_PIPE_NAME) {
+            Ok(client) => return Ok(client),
+            Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY) => {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            Err(e) => return Err(IpcError::Unavailable(e.to_string())),
+        }
+    }
+
+    Err(IpcError::Unavailable(String::from("Named pipe busy")))
+}
+
+/// Sends a single command to the agent service and decodes its response. Only failures before
+/// the command reached the service are `Unavailable`, anything later may have had an effect.
+pub async fn request<T: DeserializeOwned>(command: IpcCommand) -> Result<T, IpcError> {
+    let response_timeout = command.response_timeout();
+
+    let stream = timeout(CLIENT_TIMEOUT, connect())
+        .await
+        .map_err(|_| IpcError::Unavailable(String::from("Timed out connecting to service")))??;
+    let (read_half, mut write_half) = tokio::io::split(stream);
+    let mut reader = BufReader::new(read_half);
+
+    let request = IpcRequest {
+        version: IPC_PROTOCOL_VERSION,
+        id: rand::random::<u32>() as u64,
+        command,
+    };
+    // The service only acts on a complete frame, so a failed write never reached it
+    timeout(CLIENT_TIMEOUT, write_frame(&mut write_half, &request))
+        .await
+        .map_err(|_| IpcError::Unavailable(String::from("Timed out sending to service")))?
+        .map_err(|e| IpcError::Unavailable(e.to_string()))?;
+
+    let response: IpcResponse = timeout(response_timeout, read_frame(&mut reader))
+        .await
+        .map_err(|_| IpcError::NoResponse(String::from("Timed out waiting for service")))?
+        .map_err(|e| IpcError::NoResponse(e.to_string()))?
+        .ok_or_else(|| IpcError::NoResponse(String::from("Connection closed by service")))?;
+
+    if response.id != request.id {
+        return Err(IpcError::Remote(String::from("Mismatched IPC response id")));
+    }
+    if let Some(error) = response.error {
+        return Err(IpcError::Remote(error));
+    }
+
+    serde_json::from_value(response.data.unwrap_or(Value::Null))
+        .map_err(|e| IpcError::Remote(format!("Invalid IPC response: {}", e)))
+}
+
+/// Returns true when the agent service answers a ping
+pub async fn is_service_available() -> bool {
+    request::<Value>(IpcCommand::Ping).await.is_ok()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::net::UnixStream;
+
+    fn peer(trusted: bool) -> PeerInfo {
+        PeerInfo {
+            uid: Some(if trusted { 1000 } else { 999 }),
+            pid: Some(4242),
+            sid: None,
+            admin: false,
+            interactive: trusted,
+        }
+    }
+
+    async fn echo(command: IpcCommand, _peer: PeerInfo) -> Result<Value, String> {
+        serde_json::to_value(command).map_err(|e| e.to_string())
+    }
+
+    /// Serves one connection from `peer` in a temp dir, as `serve` does at the real path
+    async fn connect_as(dir: &tempfile::TempDir, peer: PeerInfo) -> BufReader<UnixStream> {
+        let socket_path = dir.path().join("agent.sock");
+        let listener = bind_unix_socket(&socket_path).await.unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            handle_connection(stream, peer, echo).await;
+        });
+        BufReader::new(UnixStream::connect(&socket_path).await.unwrap())
+    }
+
+    async fn send(stream: &mut BufReader<UnixStream>, frame: Value) -> Option<IpcResponse> {
+        write_frame(stream.get_mut(), &frame).await.unwrap();
+        read_frame(stream).await.unwrap()
+    }
+
+    fn ping(version: u32, id: u64) -> Value {
+        json!({ "version": version, "id": id, "command": { "type": "ping" } })
+    }
+
+    #[tokio::test]
+    async fn untrusted_peers_may_only_send_small_frames() {
+        let mut large = ping(IPC_PROTOCOL_VERSION, 7);
+        large["padding"] = json!("x".repeat(MAX_OPEN_FRAME_BYTES));
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut stream = connect_as(&dir, peer(false)).await;
+        let response = send(&mut stream, large.clone()).await.unwrap();
+        assert_eq!(response.id, 0);
+        assert_eq!(response.error.unwrap(), format!("Frame exceeds {} bytes", MAX_OPEN_FRAME_BYTES));
+        // The connection is dropped after a bad frame
+        assert!(read_frame::<_, IpcResponse>(&mut stream).await.unwrap().is_none());
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut stream = connect_as(&dir, peer(true)).await;
+        let response = send(&mut stream, large).await.unwrap();
+        assert_eq!(response.id, 7);
+        assert_eq!(response.data.unwrap(), json!({ "type": "ping" }));
+    }
+
+    #[tokio::test]
+    async fn other_protocol_versions_and_unknown_commands_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut stream = connect_as(&dir, peer(true)).await;
+
+        let response = send(&mut stream, ping(IPC_PROTOCOL_VERSION + 1, 1)).await.unwrap();
+        assert_eq!(response.id, 1);
+        assert_eq!(response.version, IPC_PROTOCOL_VERSION);
+        assert!(response.data.is_none());
+        assert!(response.error.unwrap().starts_with("Unsupported protocol version"));
+
+        let unknown = json!({ "version": IPC_PROTOCOL_VERSION, "id": 2, "command": { "type": "format_disk" } });
+        let response = send(&mut stream, unknown).await.unwrap();
+        assert!(response.error.unwrap().starts_with("Unknown command"));
+
+        // Neither closes the connection
+        let response = send(&mut stream, ping(IPC_PROTOCOL_VERSION, 3)).await.unwrap();
+        assert_eq!((response.id, response.error), (3, None));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_connections_are_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut stream = connect_as(&dir, peer(true)).await;
+        let connected = tokio::time::Instant::now();
+
+        let closed = timeout(FRAME_IDLE_TIMEOUT * 2, read_frame::<_, IpcResponse>(&mut stream)).await;
+
+        assert!(closed.unwrap().unwrap().is_none());
+        assert!(connected.elapsed() >= FRAME_IDLE_TIMEOUT);
+    }
+}
@@ -0,0 +1,423 @@
+use crate::device_manager::{get_settings, update_settings};
+use crate::jobs::Job;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+// Jobs signed just before a rotation are still in flight, the old key keeps working this long
+const ROTATION_GRACE_HOURS: i64 = 24;
+
+// Replays are caught by remembering job ids, which only works for jobs that expire before they're forgotten
+pub const MAX_JOB_LIFETIME_DAYS: i64 = 7;
+
+// Rotation messages are signed over this prefix so a job payload can never pass as one
+const KEY_ROTATION_CONTEXT: &[u8] = b"mspagent-signing-key:";
+
+/// A tenant signing key the agent trusts
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PinnedKey {
+    /// Base64 Ed25519 public key
+    pub key: String,
+    pub pinned_at: String,
+    /// Set once a newer key replaced this one
+    #[serde(default)]
+    pub retires_at: Option<String>,
+}
+
+/// A job as delivered, the signature covers the exact payload bytes so nothing needs re-encoding
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct JobEnvelope {
+    /// Base64 JSON of a `SignedJob`
+    pub payload: String,
+    /// Base64 Ed25519 signature of the decoded payload
+    #[serde(default)]
+    pub signature: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignedJob {
+    #[serde(flatten)]
+    pub job: Job,
+    pub expires_at: String,
+    /// Only this device may run the job when set
+    #[serde(default)]
+    pub guid: Option<String>,
+}
+
+/// A new signing key, signed by the key it replaces
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeyRotation {
+    pub key: String,
+    pub signature: String,
+}
+
+/// Why a job wasn't run, with its id when the payload was readable
+#[derive(Debug, Clone)]
+pub struct JobRejection {
+    pub job_id: Option<String>,
+    pub reason: String,
+}
+
+fn decode_key(key: &str) -> Result<VerifyingKey, String> {
+    let bytes: [u8; 32] = STANDARD
+        .decode(key.trim())
+        .map_err(|_| String::from("Signing key is not valid base64"))?
+        .try_into()
+        .map_err(|_| String::from("Signing key must be 32 bytes"))?;
+    VerifyingKey::from_bytes(&bytes).map_err(|e| format!("Invalid signing key: {}", e))
+}
+
+fn decode_signature(signature: &str) -> Result<Signature, String> {
+    let bytes = STANDARD
+        .decode(signature.trim())
+        .map_err(|_| String::from("Signature is not valid base64"))?;
+    Signature::from_slice(&bytes).map_err(|_| String::from("Signature must be 64 bytes"))
+}
+
+/// Retired keys stop counting once their grace period ends
+fn is_active(pinned: &PinnedKey, now: chrono::DateTime<chrono::Utc>) -> bool {
+    match &pinned.retires_at {
+        Some(at) => chrono::DateTime::parse_from_rfc3339(at)
+            .map(|at| at > now)
+            .unwrap_or(false),
+        None => true,
+    }
+}
+
+/// Keys that may sign right now
+fn active_keys(keys: &[PinnedKey], now: chrono::DateTime<chrono::Utc>) -> Vec<VerifyingKey> {
+    keys.iter()
+        .filter(|pinned| is_active(pinned, now))
+        .filter_map(|pinned| decode_key(&pinned.key).ok())
+        .collect()
+}
+
+fn verify_with_any(keys: &[VerifyingKey], message: &[u8], signature: &Signature) -> bool {
+    keys.iter()
+        .any(|key| key.verify_strict(message, signature).is_ok())
+}
+
+/// Checks a job's signature, expiry and target, returning the job only if it may run
+pub fn verify_envelope(
+    envelope: &JobEnvelope,
+    keys: &[PinnedKey],
+    guid: Option<&str>,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<Job, JobRejection> {
+    let reject = |job_id: Option<&str>, reason: String| JobRejection {
+        job_id: job_id.map(String::from),
+        reason,
+    };
+
+    let payload = STANDARD
+        .decode(envelope.payload.trim())
+        .map_err(|_| reject(None, String::from("Payload is not valid base64")))?;
+    let signed: SignedJob = serde_json::from_slice(&payload)
+        .map_err(|e| reject(None, format!("Payload is not a job: {}", e)))?;
+    let job_id = Some(signed.job.id.as_str());
+
+    let signature = match &envelope.signature {
+        Some(signature) => decode_signature(signature).map_err(|e| reject(job_id, e))?,
+        None => return Err(reject(job_id, String::from("Job is not signed"))),
+    };
+
+    let keys = active_keys(keys, now);
+    if keys.is_empty() {
+        return Err(reject(job_id, String::from("No signing key is pinned on this device")));
+    }
+    if !verify_with_any(&keys, &payload, &signature) {
+        return Err(reject(job_id, String::from("Signature does not match a pinned key")));
+    }
+
+    let expires_at = chrono::DateTime::parse_from_rfc3339(&signed.expires_at)
+        .map_err(|_| reject(job_id, String::from("Job has an invalid expiry")))?;
+    if expires_at <= now {
+        return Err(reject(job_id, format!("Job expired at {}", signed.expires_at)));
+    }
+    if expires_at > now + chrono::Duration::days(MAX_JOB_LIFETIME_DAYS) {
+        return Err(reject(
+            job_id,
+            format!("Job expires more than {} days from now", MAX_JOB_LIFETIME_DAYS),
+        ));
+    }
+
+    if let Some(target) = &signed.guid {
+        if Some(target.as_str()) != guid {
+            return Err(reject(job_id, String::from("Job is meant for another device")));
+        }
+    }
+
+    Ok(signed.job)
+}
+
+/// Checks a signature over `context` followed by the payload, for signed documents that aren't jobs
+pub fn verify_payload(
+    context: &[u8],
+    payload: &[u8],
+    signature: &str,
+    keys: &[PinnedKey],
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<(), String> {
+    let signature = decode_signature(signature)?;
+    let keys = active_keys(keys, now);
+    if keys.is_empty() {
+        return Err(String::from("No signing key is pinned on this device"));
+    }
+    let mut message = context.to_vec();
+    message.extend_from_slice(payload);
+    if !verify_with_any(&keys, &message, &signature) {
+        return Err(String::from("Signature does not match a pinned key"));
+    }
+    Ok(())
+}
+
+/// Verifies a job against the keys pinned in settings
+pub async fn open_envelope(envelope: &JobEnvelope) -> Result<Job, JobRejection> {
+    let settings = get_settings().await.map_err(|e| JobRejection {
+        job_id: None,
+        reason: format!("Failed to get settings: {}", e),
+    })?;
+    verify_envelope(
+        envelope,
+        &settings.job_signing_keys.unwrap_or_default(),
+        settings.guid.as_deref(),
+        chrono::Utc::now(),
+    )
+}
+
+/// The key the server handed out at registration, replacing whatever was trusted before
+pub fn pin_registration_key(key: &str) -> Result<Vec<PinnedKey>, String> {
+    decode_key(key)?;
+    Ok(vec![PinnedKey {
+        key: key.trim().to_string(),
+        pinned_at: chrono::Utc::now().to_rfc3339(),
+        retires_at: None,
+    }])
+}
+
+/// Applies a key rotation signed by a currently pinned key, returning whether the key changed
+pub async fn rotate_signing_key(rotation: &KeyRotation) -> Result<bool, Box<dyn std::error::Error>> {
+    update_settings(|settings| {
+        let mut keys = settings.job_signing_keys.clone().unwrap_or_default();
+        let now = chrono::Utc::now();
+
+        if keys
+            .iter()
+            .any(|pinned| pinned.key == rotation.key.trim() && pinned.retires_at.is_none())
+        {
+            return Ok(false);
+        }
+
+        let new_key = decode_key(&rotation.key)?;
+        let signature = decode_signature(&rotation.signature)?;
+        let mut message = KEY_ROTATION_CONTEXT.to_vec();
+        message.extend_from_slice(new_key.as_bytes());
+
+        // Keys in their grace period still verify jobs but can't hand trust on, they may be why we rotate
+        let current: Vec<VerifyingKey> = keys
+            .iter()
+            .filter(|pinned| pinned.retires_at.is_none())
+            .filter_map(|pinned| decode_key(&pinned.key).ok())
+            .collect();
+        if !verify_with_any(&current, &message, &signature) {
+            return Err("Key rotation is not signed by a pinned key".into());
+        }
+
+        let retires_at = (now + chrono::Duration::hours(ROTATION_GRACE_HOURS)).to_rfc3339();
+        keys.retain(|pinned| is_active(pinned, now));
+        for pinned in keys.iter_mut().filter(|pinned| pinned.retires_at.is_none()) {
+            pinned.retires_at = Some(retires_at.clone());
+        }
+        keys.push(PinnedKey {
+            key: rotation.key.trim().to_string(),
+            pinned_at: now.to_rfc3339(),
+            retires_at: None,
+        });
+
+        settings.job_signing_keys = Some(keys);
+        Ok(true)
+    })
+    .await
+}
+
+/// A fresh keypair as base64 (private, public), for signing test jobs locally
+pub fn generate_keypair() -> (String, String) {
+    let signing_key = SigningKey::from_bytes(&rand::random::<[u8; 32]>());
+    (
+        STANDARD.encode(signing_key.to_bytes()),
+        STANDARD.encode(signing_key.verifying_key().to_bytes()),
+    )
+}
+
+fn decode_signing_key(private_key: &str) -> Result<SigningKey, String> {
+    let bytes: [u8; 32] = STANDARD
+        .decode(private_key.trim())
+        .map_err(|_| String::from("Private key is not valid base64"))?
+        .try_into()
+        .map_err(|_| String::from("Private key must be 32 bytes"))?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+/// Signs a job the way the server does, so jobs can be tested without it
+pub fn sign_job(private_key: &str, signed: &SignedJob) -> Result<JobEnvelope, String> {
+    let signing_key = decode_signing_key(private_key)?;
+    let payload = serde_json::to_vec(signed).map_err(|e| e.to_string())?;
+    Ok(JobEnvelope {
+        signature: Some(STANDARD.encode(signing_key.sign(&payload).to_bytes())),
+        payload: STANDARD.encode(payload),
+    })
+}
+
+/// Signs a new public key with the current private key, for testing rotation
+pub fn sign_key_rotation(private_key: &str, new_key: &str) -> Result<KeyRotation, String> {
+    let signing_key = decode_signing_key(private_key)?;
+    let mut message = KEY_ROTATION_CONTEXT.to_vec();
+    message.extend_from_slice(decode_key(new_key)?.as_bytes());
+    Ok(KeyRotation {
+        key: new_key.trim().to_string(),
+        signature: STANDARD.encode(signing_key.sign(&message).to_bytes()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_manager::test_support::use_settings;
+    use chrono::{Duration, Utc};
+
+    fn job(id: &str) -> Job {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "type": "script",
+            "interpreter": "shell",
+            "script": "true",
+        }))
+        .unwrap()
+    }
+
+    fn signed(id: &str, expires_in: Duration, guid: Option<&str>) -> SignedJob {
+        SignedJob {
+            job: job(id),
+            expires_at: (Utc::now() + expires_in).to_rfc3339(),
+            guid: guid.map(String::from),
+        }
+    }
+
+    fn pinned(public_key: &str, retires_at: Option<chrono::DateTime<Utc>>) -> PinnedKey {
+        PinnedKey {
+            key: public_key.to_string(),
+            pinned_at: Utc::now().to_rfc3339(),
+            retires_at: retires_at.map(|at| at.to_rfc3339()),
+        }
+    }
+
+    fn reason(result: Result<Job, JobRejection>) -> String {
+        result.expect_err("job should have been rejected").reason
+    }
+
+    #[test]
+    fn only_jobs_signed_by_a_pinned_key_verify() {
+        let (private_key, public_key) = generate_keypair();
+        let (other_private_key, _) = generate_keypair();
+        let keys = [pinned(&public_key, None)];
+        let now = Utc::now();
+
+        let envelope = sign_job(&private_key, &signed("job-1", Duration::hours(1), None)).unwrap();
+        assert_eq!(verify_envelope(&envelope, &keys, Some("guid-test"), now).unwrap().id, "job-1");
+
+        // Same signature over a different payload
+        let tampered = JobEnvelope {
+            payload: STANDARD.encode(serde_json::to_vec(&signed("job-2", Duration::hours(1), None)).unwrap()),
+            signature: envelope.signature.clone(),
+        };
+        assert!(reason(verify_envelope(&tampered, &keys, None, now)).contains("does not match"));
+
+        let unsigned = JobEnvelope {
+            signature: None,
+            ..envelope.clone()
+        };
+        assert!(reason(verify_envelope(&unsigned, &keys, None, now)).contains("not signed"));
+
+        let foreign = sign_job(&other_private_key, &signed("job-3", Duration::hours(1), None)).unwrap();
+        assert!(reason(verify_envelope(&foreign, &keys, None, now)).contains("does not match"));
+        assert!(reason(verify_envelope(&envelope, &[], None, now)).contains("No signing key"));
+    }
+
+    #[test]
+    fn jobs_must_expire_within_the_replay_window() {
+        let (private_key, public_key) = generate_keypair();
+        let keys = [pinned(&public_key, None)];
+        let now = Utc::now();
+
+        let expired = sign_job(&private_key, &signed("job-1", Duration::seconds(-1), None)).unwrap();
+        assert!(reason(verify_envelope(&expired, &keys, None, now)).contains("expired"));
+
+        // Seen ids are forgotten after a while, a job that outlives that could run twice
+        let lasting = Duration::days(MAX_JOB_LIFETIME_DAYS) + Duration::hours(1);
+        let long_lived = sign_job(&private_key, &signed("job-2", lasting, None)).unwrap();
+        assert!(reason(verify_envelope(&long_lived, &keys, None, now)).contains("more than"));
+
+        let lasting = Duration::days(MAX_JOB_LIFETIME_DAYS) - Duration::hours(1);
+        let within = sign_job(&private_key, &signed("job-3", lasting, None)).unwrap();
+        assert!(verify_envelope(&within, &keys, None, now).is_ok());
+    }
+
+    #[test]
+    fn targeted_jobs_only_run_on_their_device() {
+        let (private_key, public_key) = generate_keypair();
+        let keys = [pinned(&public_key, None)];
+        let now = Utc::now();
+
+        let targeted = sign_job(&private_key, &signed("job-1", Duration::hours(1), Some("guid-test"))).unwrap();
+        assert!(verify_envelope(&targeted, &keys, Some("guid-test"), now).is_ok());
+        assert!(reason(verify_envelope(&targeted, &keys, Some("guid-other"), now)).contains("another device"));
+        assert!(reason(verify_envelope(&targeted, &keys, None, now)).contains("another device"));
+    }
+
+    #[test]
+    fn retired_keys_stop_verifying_after_their_grace_period() {
+        let (private_key, public_key) = generate_keypair();
+        let now = Utc::now();
+        let envelope = sign_job(&private_key, &signed("job-1", Duration::hours(1), None)).unwrap();
+
+        let retiring = [pinned(&public_key, Some(now + Duration::hours(1)))];
+        assert!(verify_envelope(&envelope, &retiring, None, now).is_ok());
+
+        let retired = [pinned(&public_key, Some(now - Duration::seconds(1)))];
+        assert!(reason(verify_envelope(&envelope, &retired, None, now)).contains("No signing key"));
+    }
+
+    #[tokio::test]
+    async fn rotation_must_be_signed_by_the_current_key() {
+        let (old_private_key, old_public_key) = generate_keypair();
+        let (new_private_key, new_public_key) = generate_keypair();
+        let (_, third_public_key) = generate_keypair();
+        let _settings = use_settings(serde_json::json!({
+            "job_signing_keys": [pinned(&old_public_key, None)],
+        }))
+        .await;
+
+        let forged = sign_key_rotation(&new_private_key, &new_public_key).unwrap();
+        assert!(rotate_signing_key(&forged).await.is_err());
+
+        let rotation = sign_key_rotation(&old_private_key, &new_public_key).unwrap();
+        assert!(rotate_signing_key(&rotation).await.unwrap());
+        assert!(!rotate_signing_key(&rotation).await.unwrap());
+
+        let keys = get_settings().await.unwrap().job_signing_keys.unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].key, old_public_key);
+        assert!(keys[0].retires_at.is_some());
+        assert_eq!(keys[1].key, new_public_key);
+        assert!(keys[1].retires_at.is_none());
+
+        // Jobs signed by the old key keep working through the grace period
+        let envelope = sign_job(&old_private_key, &signed("job-1", Duration::hours(1), None)).unwrap();
+        assert!(verify_envelope(&envelope, &keys, None, Utc::now()).is_ok());
+
+        // A retiring key can't hand trust on
+        let late = sign_key_rotation(&old_private_key, &third_public_key).unwrap();
+        assert!(rotate_signing_key(&late).await.is_err());
+    }
+}
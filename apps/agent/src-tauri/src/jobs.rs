@@ -0,0 +1,875 @@
+use crate::device_manager::{get_config_dir, get_settings};
+use crate::file_transfer::{
+    discard_partial_download, download_file, upload_file, FileDownloadJob, FileUploadJob, TransferSummary,
+};
+use crate::job_signing::{open_envelope, JobEnvelope, JobRejection, MAX_JOB_LIFETIME_DAYS};
+use crate::logger::log_to_file;
+use crate::outbox::enqueue;
+use crate::reboot::{run_reboot, RebootJob, SystemReboot};
+use crate::system_context::get_uptime_secs;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::sync::Notify;
+use tokio::time::{timeout_at, Duration, Instant};
+
+pub const DEFAULT_JOB_TIMEOUT_SECS: u64 = 5 * 60;
+const MAX_JOB_TIMEOUT_SECS: u64 = 4 * 60 * 60;
+
+pub const DEFAULT_MAX_OUTPUT_BYTES: usize = 64 * 1024;
+const MAX_OUTPUT_BYTES: usize = 128 * 1024;
+
+// Both streams together as JSON, escaping can grow output several times over. Keeps a result
+// well under the server's 1 MiB request and document limits.
+const MAX_RESULT_OUTPUT_BYTES: usize = 256 * 1024;
+
+// How long to wait for the output pipes to close once the script exited
+const OUTPUT_DRAIN_SECS: u64 = 5;
+
+// Job ids are remembered this long so a job the server sends again isn't run twice
+const SEEN_JOB_RETENTION_DAYS: i64 = 30;
+
+// A job still valid after its id was forgotten could be replayed
+const _: () = assert!(SEEN_JOB_RETENTION_DAYS > MAX_JOB_LIFETIME_DAYS);
+
+#[cfg(target_os = "windows")]
+const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+static JOBS_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+// Cancellation handles of the jobs running in this process, by run id
+static RUNNING_JOBS: Mutex<Vec<(String, Arc<Notify>)>> = Mutex::new(Vec::new());
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpreter {
+    Shell,
+    Bash,
+    Powershell,
+    Python,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ScriptJob {
+    pub interpreter: Interpreter,
+    pub script: String,
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub working_dir: Option<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Local account to run as, unix only
+    #[serde(default)]
+    pub run_as: Option<String>,
+    /// Cap on each of stdout and stderr, the rest is dropped
+    #[serde(default)]
+    pub max_output_bytes: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobTask {
+    Script(ScriptJob),
+    FileUpload(FileUploadJob),
+    FileDownload(FileDownloadJob),
+    Reboot(RebootJob),
+}
+
+/// Work sent by the server, either run on arrival or kept for the user to start from the tray
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Job {
+    pub id: String,
+    #[serde(flatten)]
+    pub task: JobTask,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    /// Ran to the end, whatever its exit code
+    Completed,
+    /// Couldn't be started
+    Failed,
+    TimedOut,
+    Cancelled,
+    /// Failed signature, expiry or target checks and was never run
+    Rejected,
+}
+
+/// What happened to one run of a job, sent back to the server
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JobResult {
+    pub guid: Option<String>,
+    pub job_id: String,
+    pub run_id: String,
+    pub status: JobStatus,
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub stdout_truncated: bool,
+    pub stderr_truncated: bool,
+    pub error: Option<String>,
+    /// The file moved, for file transfer jobs that completed
+    pub transfer: Option<TransferSummary>,
+    pub started_at: String,
+    pub finished_at: String,
+}
+
+/// A transfer or reboot that was started and not finished, resumed when the service restarts
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ResumableJob {
+    run_id: String,
+    job: Job,
+    started_at: String,
+}
+
+struct JobOutcome {
+    status: JobStatus,
+    exit_code: Option<i32>,
+    stdout: (Vec<u8>, bool),
+    stderr: (Vec<u8>, bool),
+    error: Option<String>,
+    transfer: Option<TransferSummary>,
+}
+
+impl JobOutcome {
+    fn failed(error: String) -> Self {
+        JobOutcome {
+            status: JobStatus::Failed,
+            exit_code: None,
+            stdout: (Vec::new(), false),
+            stderr: (Vec::new(), false),
+            error: Some(error),
+            transfer: None,
+        }
+    }
+
+    fn completed(stdout: String, transfer: Option<TransferSummary>) -> Self {
+        JobOutcome {
+            status: JobStatus::Completed,
+            exit_code: None,
+            stdout: (stdout.into_bytes(), false),
+            stderr: (Vec::new(), false),
+            error: None,
+            transfer,
+        }
+    }
+}
+
+fn get_jobs_dir() -> PathBuf {
+    get_config_dir().join("jobs")
+}
+
+fn get_seen_jobs_path() -> PathBuf {
+    get_jobs_dir().join("seen.json")
+}
+
+fn get_on_demand_jobs_path() -> PathBuf {
+    get_jobs_dir().join("on_demand.json")
+}
+
+fn get_resumable_jobs_path() -> PathBuf {
+    get_jobs_dir().join("resumable.json")
+}
+
+impl Interpreter {
+    /// Program and arguments that run a script fed through stdin, so it never touches the disk
+    fn command(&self) -> Result<(&'static str, Vec<&'static str>), String> {
+        #[cfg(unix)]
+        {
+            Ok(match self {
+                Interpreter::Shell => ("/bin/sh", vec!["-s"]),
+                Interpreter::Bash => ("bash", vec!["-s"]),
+                Interpreter::Powershell => ("pwsh", vec!["-NoProfile", "-NonInteractive", "-Command", "-"]),
+                Interpreter::Python => ("python3", vec!["-"]),
+            })
+        }
+
+        #[cfg(target_os = "windows")]
+        {
+            match self {
+                Interpreter::Powershell => Ok((
+                    "powershell",
+                    vec!["-NoProfile", "-NonInteractive", "-ExecutionPolicy", "Bypass", "-Command", "-"],
+                )),
+                Interpreter::Python => Ok(("python", vec!["-"])),
+                Interpreter::Shell | Interpreter::Bash => {
+                    Err(format!("{:?} scripts are not supported on Windows", self))
+                }
+            }
+        }
+    }
+}
+
+/// Output kept so far and whether anything was dropped, shared so a stuck reader still leaves what it got
+type CapturedOutput = Arc<Mutex<(Vec<u8>, bool)>>;
+
+/// Reads a stream to the end, keeping at most `cap` bytes
+async fn read_capped(mut reader: impl AsyncRead + Unpin, cap: usize, output: CapturedOutput) {
+    let mut buffer = [0u8; 8192];
+
+    // Keep reading past the cap, a full pipe would stall the script
+    while let Ok(read) = reader.read(&mut buffer).await {
+        if read == 0 {
+            break;
+        }
+        if let Ok(mut output) = output.lock() {
+            let room = cap.saturating_sub(output.0.len());
+            output.0.extend_from_slice(&buffer[..read.min(room)]);
+            output.1 |= read > room;
+        }
+    }
+}
+
+fn take_output(output: &CapturedOutput) -> (Vec<u8>, bool) {
+    match output.lock() {
+        Ok(mut output) => std::mem::take(&mut *output),
+        Err(_) => (Vec::new(), false),
+    }
+}
+
+/// Decodes captured output, cut short where its JSON encoding would pass `budget` bytes
+fn encode_output((bytes, truncated): (Vec<u8>, bool), budget: usize) -> (String, bool) {
+    let text = String::from_utf8_lossy(&bytes);
+    let mut encoded = 0;
+    for (index, c) in text.char_indices() {
+        encoded += match c {
+            '"' | '\\' | '\n' | '\r' | '\t' | '\u{8}' | '\u{c}' => 2,
+            c if (c as u32) < 0x20 => 6,
+            c => c.len_utf8(),
+        };
+        if encoded > budget {
+            return (text[..index].to_string(), true);
+        }
+    }
+    (text.into_owned(), truncated)
+}
+
+#[cfg(unix)]
+fn lookup_user(name: &str) -> Result<(u32, u32), String> {
+    let id = |flag: &str| -> Result<u32, String> {
+        let output = std::process::Command::new("id")
+            .args([flag, name])
+            .output()
+            .map_err(|e| format!("Failed to look up user {}: {}", name, e))?;
+        if !output.status.success() {
+            return Err(format!("No such user: {}", name));
+        }
+        String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .parse()
+            .map_err(|_| format!("Failed to look up user {}", name))
+    };
+    Ok((id("-u")?, id("-g")?))
+}
+
+/// Kills the script along with anything it started
+fn kill_tree(pid: u32) {
+    #[cfg(unix)]
+    let _ = std::process::Command::new("kill")
+        .args(["-KILL", "--", &format!("-{}", pid)])
+        .status();
+
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        let _ = std::process::Command::new("taskkill")
+            .args(["/T", "/F", "/PID", &pid.to_string()])
+            .creation_flags(CREATE_NO_WINDOW)
+            .status();
+    }
+}
+
+async fn run_script(job: &ScriptJob, cancel: Arc<Notify>) -> JobOutcome {
+    let (program, args) = match job.interpreter.command() {
+        Ok(command) => command,
+        Err(e) => return JobOutcome::failed(e),
+    };
+
+    let working_dir = job
+        .working_dir
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir);
+    if !working_dir.is_dir() {
+        return JobOutcome::failed(format!(
+            "Working directory does not exist: {}",
+            working_dir.display()
+        ));
+    }
+
+    let mut command = tokio::process::Command::new(program);
+    command
+        .args(args)
+        .current_dir(&working_dir)
+        .envs(&job.env)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    #[cfg(unix)]
+    {
+        // Its own process group, so a timeout or cancel takes down whatever the script started
+        command.process_group(0);
+
+        if let Some(user) = &job.run_as {
+            let (uid, gid) = match lookup_user(user) {
+                Ok(ids) => ids,
+                Err(e) => return JobOutcome::failed(e),
+            };
+            command.uid(uid).gid(gid).env("USER", user).env("LOGNAME", user);
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        if job.run_as.is_some() {
+            return JobOutcome::failed(String::from("run_as is not supported on Windows"));
+        }
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => return JobOutcome::failed(format!("Failed to start {}: {}", program, e)),
+    };
+
+    let cap = job
+        .max_output_bytes
+        .unwrap_or(DEFAULT_MAX_OUTPUT_BYTES)
+        .min(MAX_OUTPUT_BYTES);
+    let stdout = CapturedOutput::default();
+    let stderr = CapturedOutput::default();
+    let mut readers = Vec::new();
+    if let Some(out) = child.stdout.take() {
+        readers.push(tauri::async_runtime::spawn(read_capped(out, cap, stdout.clone())));
+    }
+    if let Some(err) = child.stderr.take() {
+        readers.push(tauri::async_runtime::spawn(read_capped(err, cap, stderr.clone())));
+    }
+
+    if let Some(mut stdin) = child.stdin.take() {
+        let script = job.script.clone();
+        tauri::async_runtime::spawn(async move {
+            let _ = stdin.write_all(script.as_bytes()).await;
+            // Dropping stdin closes it, which is how the interpreter knows the script ended
+        });
+    }
+
+    let limit = Duration::from_secs(
+        job.timeout_secs
+            .unwrap_or(DEFAULT_JOB_TIMEOUT_SECS)
+            .clamp(1, MAX_JOB_TIMEOUT_SECS),
+    );
+    let (status, exit_code) = tokio::select! {
+        result = child.wait() => match result {
+            Ok(exit) => (JobStatus::Completed, exit.code()),
+            Err(e) => return JobOutcome::failed(format!("Failed to wait for script: {}", e)),
+        },
+        _ = tokio::time::sleep(limit) => (JobStatus::TimedOut, None),
+        _ = cancel.notified() => (JobStatus::Cancelled, None),
+    };
+
+    if status != JobStatus::Completed {
+        if let Some(pid) = child.id() {
+            kill_tree(pid);
+        }
+        let _ = child.kill().await;
+    }
+
+    // Anything the script left running in the background may hold the pipes open indefinitely
+    let drain_until = Instant::now() + Duration::from_secs(OUTPUT_DRAIN_SECS);
+    for reader in readers {
+        let _ = timeout_at(drain_until, reader).await;
+    }
+
+    JobOutcome {
+        status,
+        exit_code,
+        stdout: take_output(&stdout),
+        stderr: take_output(&stderr),
+        error: match status {
+            JobStatus::TimedOut => Some(format!("Timed out after {} seconds", limit.as_secs())),
+            JobStatus::Cancelled => Some(String::from("Cancelled by the server")),
+            _ => None,
+        },
+        transfer: None,
+    }
+}
+
+/// Runs a job that isn't a script until it ends or the server cancels it
+async fn run_cancellable(task: impl Future<Output = JobOutcome>, cancel: Arc<Notify>) -> JobOutcome {
+    tokio::select! {
+        outcome = task => outcome,
+        _ = cancel.notified() => JobOutcome {
+            status: JobStatus::Cancelled,
+            exit_code: None,
+            stdout: (Vec::new(), false),
+            stderr: (Vec::new(), false),
+            error: Some(String::from("Cancelled by the server")),
+            transfer: None,
+        },
+    }
+}
+
+async fn read_json_file<T: serde::de::DeserializeOwned + Default>(path: &PathBuf) -> T {
+    match tokio::fs::read(path).await {
+        Ok(content) => serde_json::from_slice(&content).unwrap_or_default(),
+        Err(_) => T::default(),
+    }
+}
+
+async fn write_json_file<T: Serialize>(path: &PathBuf, value: &T) -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_path = path.with_extension("tmp");
+    tokio::fs::create_dir_all(get_jobs_dir()).await?;
+    tokio::fs::write(&tmp_path, serde_json::to_vec_pretty(value)?).await?;
+    tokio::fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
+
+/// Records a job as taken, returning false if it was already run
+async fn claim_job(id: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    let _lock = JOBS_LOCK.lock().await;
+    let path = get_seen_jobs_path();
+    let mut seen: HashMap<String, i64> = read_json_file(&path).await;
+
+    let now = chrono::Utc::now().timestamp();
+    seen.retain(|_, at| now - *at < SEEN_JOB_RETENTION_DAYS * 86_400);
+    if seen.contains_key(id) {
+        return Ok(false);
+    }
+
+    seen.insert(id.to_string(), now);
+    write_json_file(&path, &seen).await?;
+    Ok(true)
+}
+
+/// Adds or removes a job from the ones to resume after a restart
+async fn track_resumable(run_id: &str, job: Option<&Job>) -> Result<(), Box<dyn std::error::Error>> {
+    let _lock = JOBS_LOCK.lock().await;
+    let path = get_resumable_jobs_path();
+    let mut pending: Vec<ResumableJob> = read_json_file(&path).await;
+    pending.retain(|resumable| resumable.run_id != run_id);
+    if let Some(job) = job {
+        pending.push(ResumableJob {
+            run_id: run_id.to_string(),
+            job: job.clone(),
+            started_at: chrono::Utc::now().to_rfc3339(),
+        });
+    }
+    write_json_file(&path, &pending).await
+}
+
+fn register_running(run_id: &str) -> Arc<Notify> {
+    let cancel = Arc::new(Notify::new());
+    if let Ok(mut running) = RUNNING_JOBS.lock() {
+        running.push((run_id.to_string(), cancel.clone()));
+    }
+    cancel
+}
+
+fn unregister_running(run_id: &str) {
+    if let Ok(mut running) = RUNNING_JOBS.lock() {
+        running.retain(|(id, _)| id != run_id);
+    }
+}
+
+/// How many jobs are running in this process, including reboots still waiting
+pub fn running_job_count() -> usize {
+    RUNNING_JOBS.lock().map(|running| running.len()).unwrap_or(0)
+}
+
+/// Asks a running job to stop, returning whether it was running here
+pub fn cancel_job(run_id: &str) -> bool {
+    let running = match RUNNING_JOBS.lock() {
+        Ok(running) => running,
+        Err(_) => return false,
+    };
+    match running.iter().find(|(id, _)| id == run_id) {
+        Some((_, cancel)) => {
+            // notify_one keeps the wakeup if the job isn't waiting yet
+            cancel.notify_one();
+            true
+        }
+        None => false,
+    }
+}
+
+async fn queue_result(result: &JobResult) {
+    let path = format!("/v1.0/jobs/{}/result", result.job_id);
+    let queued = match serde_json::to_value(result) {
+        Ok(body) => enqueue("job_result", &path, body).await.map(|_| ()).map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    if let Err(e) = queued {
+        log_to_file(
+            "ERROR".to_string(),
+            format!("Failed to queue result of job {}: {}", result.job_id, e),
+        );
+    }
+}
+
+/// Runs a job in the background and queues its result for the server
+fn start_job(job: Job, run_id: String) {
+    tauri::async_runtime::spawn(async move {
+        let cancel = register_running(&run_id);
+        let started_at = chrono::Utc::now().to_rfc3339();
+        log_to_file("INFO".to_string(), format!("Starting job {} (run {})", job.id, run_id));
+
+        // A script half run can't be picked up again, transfers and reboots can
+        let resumable = !matches!(job.task, JobTask::Script(_));
+        if resumable {
+            if let Err(e) = track_resumable(&run_id, Some(&job)).await.map_err(|e| e.to_string()) {
+                log_to_file(
+                    "WARN".to_string(),
+                    format!("Failed to record job run {}, it won't resume after a restart: {}", run_id, e),
+                );
+            }
+        }
+
+        let outcome = match &job.task {
+            JobTask::Script(script) => run_script(script, cancel).await,
+            JobTask::FileUpload(upload) => {
+                let upload = async {
+                    match upload_file(&job.id, &run_id, upload).await {
+                        Ok(summary) => JobOutcome::completed(String::new(), Some(summary)),
+                        Err(e) => JobOutcome::failed(e),
+                    }
+                };
+                run_cancellable(upload, cancel).await
+            }
+            JobTask::FileDownload(download) => {
+                let transfer = async {
+                    match download_file(&job.id, &run_id, download).await {
+                        Ok(summary) => JobOutcome::completed(String::new(), Some(summary)),
+                        Err(e) => JobOutcome::failed(e),
+                    }
+                };
+                let outcome = run_cancellable(transfer, cancel).await;
+                if outcome.status == JobStatus::Cancelled {
+                    discard_partial_download(download).await;
+                }
+                outcome
+            }
+            JobTask::Reboot(reboot) => {
+                let reboot = async {
+                    match run_reboot(&run_id, reboot, SystemReboot).await {
+                        Ok(report) => JobOutcome::completed(report, None),
+                        Err(e) => JobOutcome::failed(e),
+                    }
+                };
+                run_cancellable(reboot, cancel).await
+            }
+        };
+        unregister_running(&run_id);
+        // Before the result is queued, a started reboot must not come back as a job to resume
+        if resumable {
+            let _ = track_resumable(&run_id, None).await;
+        }
+
+        finish_job(&job.id, &run_id, outcome, started_at).await;
+    });
+}
+
+/// Queues the result of a job run for the server
+async fn finish_job(job_id: &str, run_id: &str, outcome: JobOutcome, started_at: String) {
+    let (stdout, stdout_truncated) = encode_output(outcome.stdout, MAX_RESULT_OUTPUT_BYTES / 2);
+    let (stderr, stderr_truncated) = encode_output(outcome.stderr, MAX_RESULT_OUTPUT_BYTES / 2);
+    let result = JobResult {
+        guid: get_settings().await.ok().and_then(|settings| settings.guid),
+        job_id: job_id.to_string(),
+        run_id: run_id.to_string(),
+        status: outcome.status,
+        exit_code: outcome.exit_code,
+        stdout,
+        stderr,
+        stdout_truncated,
+        stderr_truncated,
+        error: outcome.error,
+        transfer: outcome.transfer,
+        started_at,
+        finished_at: chrono::Utc::now().to_rfc3339(),
+    };
+    log_to_file(
+        "INFO".to_string(),
+        format!(
+            "Job {} (run {}) finished: {:?}, exit code {:?}",
+            job_id, run_id, result.status, result.exit_code
+        ),
+    );
+    queue_result(&result).await;
+}
+
+/// Logs a job that failed verification and tells the server when it's clear which job it was
+async fn reject_job(rejection: JobRejection) {
+    log_to_file(
+        "WARN".to_string(),
+        format!(
+            "Rejected job {}: {}",
+            rejection.job_id.as_deref().unwrap_or("(unreadable)"),
+            rejection.reason
+        ),
+    );
+
+    if let Some(job_id) = rejection.job_id {
+        let now = chrono::Utc::now().to_rfc3339();
+        queue_result(&JobResult {
+            guid: get_settings().await.ok().and_then(|settings| settings.guid),
+            run_id: job_id.clone(),
+            job_id,
+            status: JobStatus::Rejected,
+            exit_code: None,
+            stdout: String::new(),
+            stderr: String::new(),
+            stdout_truncated: false,
+            stderr_truncated: false,
+            error: Some(rejection.reason),
+            transfer: None,
+            started_at: now.clone(),
+            finished_at: now,
+        })
+        .await;
+    }
+}
+
+/// Verifies and starts the jobs sent with a heartbeat, skipping any that already ran
+pub async fn dispatch_jobs(envelopes: &[JobEnvelope]) {
+    for envelope in envelopes {
+        let job = match open_envelope(envelope).await {
+            Ok(job) => job,
+            Err(rejection) => {
+                reject_job(rejection).await;
+                continue;
+            }
+        };
+
+        match claim_job(&job.id).await.map_err(|e| e.to_string()) {
+            Ok(true) => start_job(job.clone(), job.id.clone()),
+            Ok(false) => log_to_file(
+                "INFO".to_string(),
+                format!("Skipping job {}, it already ran", job.id),
+            ),
+            Err(e) => log_to_file(
+                "ERROR".to_string(),
+                format!("Failed to record job {}, not running it: {}", job.id, e),
+            ),
+        }
+    }
+}
+
+/// Stores the jobs users may start from the tray, replacing the previous set.
+/// They stay signed on disk and are verified each time one is started.
+pub async fn save_on_demand_jobs(envelopes: &[JobEnvelope]) -> Result<(), Box<dyn std::error::Error>> {
+    let _lock = JOBS_LOCK.lock().await;
+    write_json_file(&get_on_demand_jobs_path(), &envelopes).await
+}
+
+/// Verifies and starts a stored on-demand job, returning the id of this run
+pub async fn run_on_demand_job(job_id: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let envelopes: Vec<JobEnvelope> = {
+        let _lock = JOBS_LOCK.lock().await;
+        read_json_file(&get_on_demand_jobs_path()).await
+    };
+
+    let mut rejected = None;
+    for envelope in &envelopes {
+        match open_envelope(envelope).await {
+            Ok(job) if job.id == job_id => {
+                // On-demand jobs run as often as they're asked for, each run gets its own id
+                let run_id = format!("{}-{}", job.id, chrono::Utc::now().timestamp_millis());
+                start_job(job, run_id.clone());
+                return Ok(run_id);
+            }
+            Err(rejection) if rejection.job_id.as_deref() == Some(job_id) => rejected = Some(rejection),
+            _ => {}
+        }
+    }
+
+    match rejected {
+        Some(rejection) => {
+            let reason = rejection.reason.clone();
+            reject_job(rejection).await;
+            Err(reason.into())
+        }
+        None => Err(format!("Job {} has not been sent to this device", job_id).into()),
+    }
+}
+
+async fn restarted_since(at: &str) -> bool {
+    let at = match chrono::DateTime::parse_from_rfc3339(at) {
+        Ok(at) => at,
+        Err(_) => return false,
+    };
+    match tauri::async_runtime::spawn_blocking(get_uptime_secs).await.ok().flatten() {
+        Some(uptime) => chrono::Utc::now() - chrono::Duration::seconds(uptime as i64) > at,
+        None => false,
+    }
+}
+
+/// Restarts the transfers and reboots that were cut off when the service last stopped
+pub async fn resume_jobs() {
+    let pending: Vec<ResumableJob> = {
+        let _lock = JOBS_LOCK.lock().await;
+        read_json_file(&get_resumable_jobs_path()).await
+    };
+    for resumable in pending {
+        // Any restart does what a reboot job asked for, a job that outlived one is done
+        if let JobTask::Reboot(_) = resumable.job.task {
+            if restarted_since(&resumable.started_at).await {
+                let _ = track_resumable(&resumable.run_id, None).await;
+                let outcome = JobOutcome::completed(String::from("The device restarted"), None);
+                finish_job(&resumable.job.id, &resumable.run_id, outcome, resumable.started_at).await;
+                continue;
+            }
+        }
+
+        log_to_file(
+            "INFO".to_string(),
+            format!("Resuming job {} (run {})", resumable.job.id, resumable.run_id),
+        );
+        start_job(resumable.job, resumable.run_id);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::device_manager::test_support::use_settings;
+    use crate::outbox::{get_outbox_dir, list_outbox};
+    use serde_json::json;
+
+    fn script(script: &str) -> ScriptJob {
+        ScriptJob {
+            interpreter: Interpreter::Shell,
+            script: script.to_string(),
+            timeout_secs: None,
+            working_dir: None,
+            env: HashMap::new(),
+            run_as: None,
+            max_output_bytes: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn a_script_reports_its_exit_code_and_both_streams() {
+        let outcome = run_script(&script("echo out; echo err >&2; exit 3"), Arc::new(Notify::new())).await;
+
+        assert_eq!(outcome.status, JobStatus::Completed);
+        assert_eq!(outcome.exit_code, Some(3));
+        assert_eq!(outcome.stdout, (b"out\n".to_vec(), false));
+        assert_eq!(outcome.stderr, (b"err\n".to_vec(), false));
+        assert_eq!(outcome.error, None);
+    }
+
+    #[tokio::test]
+    async fn a_script_past_its_timeout_is_killed_with_what_it_started() {
+        let mut job = script("echo started; sleep 30 & sleep 30");
+        job.timeout_secs = Some(1);
+        let started = Instant::now();
+
+        let outcome = run_script(&job, Arc::new(Notify::new())).await;
+
+        assert_eq!(outcome.status, JobStatus::TimedOut);
+        assert_eq!(outcome.error.as_deref(), Some("Timed out after 1 seconds"));
+        assert_eq!(outcome.stdout.0, b"started\n");
+        // The background sleep held the pipes open, it must have been killed rather than drained
+        assert!(started.elapsed() < Duration::from_secs(OUTPUT_DRAIN_SECS));
+    }
+
+    #[tokio::test]
+    async fn a_cancelled_job_stops_even_when_cancelled_before_it_waits() {
+        assert!(!cancel_job("run-not-here"));
+
+        let cancel = register_running("run-cancel-early");
+        assert!(cancel_job("run-cancel-early"));
+        let outcome = run_script(&script("sleep 30"), cancel).await;
+        unregister_running("run-cancel-early");
+        assert_eq!(outcome.status, JobStatus::Cancelled);
+
+        let cancel = register_running("run-cancel-late");
+        let running = tokio::spawn(async move { run_script(&script("sleep 30"), cancel).await });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(cancel_job("run-cancel-late"));
+        let outcome = running.await.unwrap();
+        unregister_running("run-cancel-late");
+        assert_eq!(outcome.status, JobStatus::Cancelled);
+        assert_eq!(outcome.error.as_deref(), Some("Cancelled by the server"));
+    }
+
+    #[tokio::test]
+    async fn output_past_the_cap_is_dropped_and_still_read_to_the_end() {
+        let output = CapturedOutput::default();
+        read_capped(&[b'x'; 100][..], 100, output.clone()).await;
+        assert_eq!(take_output(&output), (vec![b'x'; 100], false));
+
+        read_capped(&[b'x'; 20_000][..], 100, output.clone()).await;
+        assert_eq!(take_output(&output), (vec![b'x'; 100], true));
+
+        // More than a pipe buffer, the script only exits if the rest is read
+        let mut job = script("head -c 300000 /dev/zero; exit 0");
+        job.max_output_bytes = Some(usize::MAX);
+        let outcome = run_script(&job, Arc::new(Notify::new())).await;
+        assert_eq!(outcome.status, JobStatus::Completed);
+        assert_eq!(outcome.stdout.0.len(), MAX_OUTPUT_BYTES);
+        assert!(outcome.stdout.1);
+    }
+
+    #[test]
+    fn encoded_output_stays_within_its_budget() {
+        let output = "ok\u{0}\"\n\u{e9}\\";
+        let encoded = serde_json::to_string(output).unwrap().len() - 2;
+        assert_eq!(encode_output((output.as_bytes().to_vec(), false), encoded), (output.to_string(), false));
+
+        for budget in 0..encoded {
+            let (text, truncated) = encode_output((output.as_bytes().to_vec(), false), budget);
+            assert!(truncated);
+            assert!(serde_json::to_string(&text).unwrap().len() - 2 <= budget);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_job_id_is_only_claimed_once_until_it_is_forgotten() {
+        assert!(claim_job("job-claimed-once").await.unwrap());
+        assert!(!claim_job("job-claimed-once").await.unwrap());
+
+        {
+            let _lock = JOBS_LOCK.lock().await;
+            let mut seen: HashMap<String, i64> = read_json_file(&get_seen_jobs_path()).await;
+            let forgotten = chrono::Utc::now().timestamp() - SEEN_JOB_RETENTION_DAYS * 86_400;
+            seen.insert(String::from("job-long-ago"), forgotten);
+            write_json_file(&get_seen_jobs_path(), &seen).await.unwrap();
+        }
+        assert!(claim_job("job-long-ago").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn resuming_a_reboot_the_device_already_did_reports_it_done() {
+        let _settings = use_settings(json!({})).await;
+        let _ = tokio::fs::remove_dir_all(get_outbox_dir()).await;
+        // Far off, so nothing would reboot even if it were started again
+        let job: Job = serde_json::from_value(json!({ "id": "job-rebooted", "type": "reboot", "at": "2999-01-01T00:00:00Z" })).unwrap();
+        {
+            let _lock = JOBS_LOCK.lock().await;
+            let pending = vec![ResumableJob {
+                run_id: String::from("run-rebooted"),
+                job,
+                started_at: String::from("2000-01-01T00:00:00Z"),
+            }];
+            write_json_file(&get_resumable_jobs_path(), &pending).await.unwrap();
+        }
+
+        resume_jobs().await;
+
+        let pending: Vec<ResumableJob> = read_json_file(&get_resumable_jobs_path()).await;
+        assert!(pending.is_empty());
+        let queued = list_outbox().await.unwrap();
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].path, "/v1.0/jobs/job-rebooted/result");
+    }
+}
@@ -1,24 +1,74 @@
+mod agent_info;
+mod agent_state;
+mod alerts;
+mod annotate;
+mod branding;
+pub mod cli;
 mod device_manager;
 mod device_registration;
+mod file_access;
+mod file_transfer;
 mod heartbeat;
+mod ipc;
+mod job_signing;
+mod jobs;
+mod local_api;
 mod logger;
+mod metrics;
+mod outbox;
+mod reboot;
+mod recording;
+mod screenshot;
+mod service;
+mod service_monitor;
+mod system_context;
+mod ticket;
+mod ticket_store;
+mod tray;
+mod updater;
 
+use agent_state::{current_state, AgentState};
+use annotate::{AnnotatedImage, AnnotationOp, UploadOptions};
+use branding::{get_branding_info, get_logo, load_branding, Branding, BrandingInfo};
 use base64::engine::general_purpose;
 use base64::Engine;
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 use tauri::{
     AppHandle, Emitter, EventTarget, Manager, WebviewUrl, WebviewWindowBuilder,
     tray::TrayIconBuilder,
-    menu::{Menu, MenuItem}
 };
-use tauri_plugin_screenshots::{get_monitor_screenshot, get_screenshotable_monitors};
+use tauri_plugin_dialog::DialogExt;
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut, ShortcutState};
+use tauri_plugin_notification::NotificationExt;
+use tauri_plugin_opener::OpenerExt;
 
-use device_manager::{get_settings, is_device_registered, get_rmm_device_id};
-use device_registration::register_device_with_server;
-use heartbeat::{start_heartbeat_task, gather_system_info, HeartbeatRequest};
-use logger::log_to_file;
+use device_manager::{get_settings, is_device_registered, get_rmm_device_id, UiSettings};
+use file_access::{allowed_dirs, check_read_path};
+use heartbeat::{gather_system_info, HeartbeatRequest};
+use ipc::{current_peer_info, is_service_available, request, IpcCommand, IpcError};
+use logger::{log_to_file, use_user_logs_dir};
+use outbox::{get_outbox_receipt, list_outbox, start_outbox_task, OutboxEntry, OutboxReceipt};
+use reboot::{defer_reboot, ScheduledReboot};
+use recording::{
+    record_screen, replay_buffer_running, save_replay_buffer, start_replay_buffer, stop_replay_buffer,
+    RecordingOptions, ScreenRecording,
+};
+use screenshot::{
+    capture_screens, clear_screenshots, get_screenshot_dir, get_screenshot_mime_type,
+    get_screenshot_path, import_screenshot, list_monitors, read_screenshot_data_url,
+    sweep_screenshots, MonitorInfo,
+    ScreenCapture, ScreenshotResult, DEFAULT_SCREENSHOT_TTL_MINUTES,
+};
+use service::{ensure_registered, on_outbox_delivered, start_logo_sync};
+use ticket::{parse_ticket_id, TicketAttachment, TicketOutcome, TicketRequest, TicketSubmission};
+use ticket_store::{list_owned_tickets, TicketChange, TrackedTicket};
+use tray::{apply_tray_health, apply_tray_menu, build_tray_menu, default_tray_menu, tray_base_icon, TrayAction, TrayActions, TrayMenuEntry, TrayWindow, TRAY_ID};
+
+// Last nudge before a scheduled restart, on top of the prompt when it's announced
+const REBOOT_REMINDER_MINUTES: i64 = 5;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
@@ -29,8 +79,13 @@ pub fn run() {
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_store::Builder::new().build())
         .plugin(tauri_plugin_opener::init())
-        .plugin(tauri_plugin_screenshots::init())
+        .plugin(tauri_plugin_global_shortcut::Builder::new().build())
         .setup(|app| {
+            // The tray runs as the signed-in user, each account keeps its own log
+            if let Ok(dir) = app.path().app_log_dir() {
+                use_user_logs_dir(dir);
+            }
+
             // Create atomic flags for background task control
             // let heartbeat_running = Arc::new(AtomicBool::new(true));
             // let heartbeat_flag = heartbeat_running.clone();
@@ -38,55 +93,36 @@ pub fn run() {
             // Store the flags in app state for cleanup
             // app.manage(heartbeat_running);
 
-            // Check and register device on first launch
+            // Registration and heartbeat belong to the agent service, only fall
+            // back to doing them in-process when no service is installed
             tauri::async_runtime::spawn(async move {
-                if !is_device_registered().await {
+                if is_service_available().await {
                     log_to_file(
-                        "INFO".to_string(),
-                        "First launch detected, registering device...".to_string(),
+                        String::from("INFO"),
+                        String::from("Agent service detected, skipping in-process registration"),
                     );
-
-                    match register_device_with_server().await {
-                        Ok(response) => {
-                            log_to_file(
-                                String::from("INFO"),
-                                String::from("Device registered successfully"),
-                            );
-                            log_to_file(
-                                String::from("INFO"),
-                                format!("Device ID: {}", response.data.device_id),
-                            );
-                            log_to_file(
-                                String::from("INFO"),
-                                format!("GUID: {}", response.data.guid),
-                            );
-                        }
-                        Err(e) => {
-                            log_to_file(
-                                String::from("ERROR"),
-                                format!("Failed to regiter device: {}", e),
-                            );
-                            log_to_file(
-                                String::from("ERROR"),
-                                String::from("Will retry on next launch"),
-                            );
-                        }
-                    }
                 } else {
                     log_to_file(
-                        String::from("INFO"),
-                        "Device already registered".to_string(),
+                        String::from("WARN"),
+                        String::from("Agent service not reachable, registering in-process"),
                     );
+                    ensure_registered().await;
+                    start_logo_sync();
+                    start_outbox_task(Arc::new(AtomicBool::new(true)), on_outbox_delivered);
                 }
-
-                // Start background tasks after registration check
-                // start_heartbeat_task(heartbeat_flag.clone());
             });
 
+            watch_queued_tickets(app.app_handle().clone());
+            watch_ticket_updates(app.app_handle().clone());
+            start_screenshot_cleanup(app.app_handle().clone());
+            watch_support_hotkey(app.app_handle().clone());
+            watch_user_alerts(app.app_handle().clone());
+            watch_scheduled_reboot(app.app_handle().clone());
+
             // Conditionally create system tray based on settings
             let app_handle = app.app_handle().clone();
             tauri::async_runtime::spawn(async move {
-                match get_settings().await {
+                match load_settings().await {
                     Ok(settings) => {
                         // Only create tray if show_tray is explicitly set to true
                         if settings.show_tray.unwrap_or(false) {
@@ -94,11 +130,16 @@ pub fn run() {
                                 String::from("INFO"),
                                 String::from("show_tray is enabled, creating tray icon"),
                             );
-                            if let Err(e) = create_tray_icon(&app_handle) {
-                                log_to_file(
+                            let menu = settings.tray_menu.clone().unwrap_or_else(default_tray_menu);
+                            match create_tray_icon(&app_handle, &menu) {
+                                Ok(()) => {
+                                    watch_tray_menu(app_handle.clone(), menu);
+                                    watch_agent_state(app_handle.clone());
+                                }
+                                Err(e) => log_to_file(
                                     String::from("ERROR"),
                                     format!("Failed to create tray icon: {}", e),
-                                );
+                                ),
                             }
                         } else {
                             log_to_file(
@@ -138,58 +179,49 @@ pub fn run() {
             hide_window,
             show_window,
             take_screenshot,
+            get_monitors,
+            record_screen_clip,
+            start_replay_recording,
+            save_replay_recording,
+            stop_replay_recording,
+            get_replay_recording_status,
+            annotate_screenshot,
+            import_screenshot_file,
+            read_screenshot,
+            discard_screenshots,
             read_file_text,
             read_file_base64,
             read_file_binary,
             read_registry_value,
             log_to_file,
-            get_os_info
+            get_os_info,
+            get_outbox,
+            submit_ticket,
+            get_my_tickets,
+            get_branding,
+            get_scheduled_reboot,
+            postpone_reboot
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
 
-fn create_tray_icon(app: &AppHandle) -> Result<(), Box<dyn std::error::Error>> {
+fn create_tray_icon(app: &AppHandle, entries: &[TrayMenuEntry]) -> Result<(), Box<dyn std::error::Error>> {
     log_to_file(String::from("INFO"), String::from("Creating system tray icon"));
 
-    let request_support_sc_i = MenuItem::with_id(
-        app,
-        "request_support_sc",
-        "Take Screenshot and Request Support",
-        true,
-        None::<&str>,
-    )?;
-    let request_support_i = MenuItem::with_id(
-        app,
-        "request_support",
-        "Request Support",
-        true,
-        None::<&str>,
-    )?;
-    let about_i = MenuItem::with_id(app, "about", "About", true, None::<&str>)?;
-    // let quit_i = MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?;
-
-    // Create menu with items
-    let menu = Menu::with_items(app, &[&request_support_sc_i, &request_support_i, &about_i])?;
+    // Create menu from its definition, keeping the action behind each item id
+    let (menu, actions) = build_tray_menu(app, entries)?;
+    app.manage(TrayActions::new(actions));
 
     // Build tray icon with menu
-    let _tray = TrayIconBuilder::new()
-        .icon(app.default_window_icon().unwrap().clone())
+    let _tray = TrayIconBuilder::with_id(TRAY_ID)
+        .icon(tray_base_icon(app).ok_or("No icon available for the tray")?)
         .menu(&menu)
-        .on_menu_event(|app, event| match event.id.as_ref() {
-            "request_support_sc" => {
-                handle_support_window(app, true);
+        .on_menu_event(|app, event| {
+            let action = app.state::<TrayActions>().get(event.id.as_ref());
+            if let Some(action) = action {
+                handle_tray_action(app, action);
             }
-            "request_support" => {
-                handle_support_window(app, false);
-            }
-            "about" => {
-                handle_about_window(app);               
-            }
-            // "quit" => {
-            //     app.exit(0);
-            // }
-            _ => {}
         })
         .menu_on_left_click(false)
         .build(app)?;
@@ -198,6 +230,272 @@ fn create_tray_icon(app: &AppHandle) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+fn handle_tray_action(app: &AppHandle, action: TrayAction) {
+    match action {
+        TrayAction::OpenWindow { window } => match window {
+            TrayWindow::SupportScreenshot => handle_support_window(app, true),
+            TrayWindow::Support => handle_support_window(app, false),
+            TrayWindow::About => handle_about_window(app),
+        },
+        TrayAction::OpenUrl { url } => {
+            if let Err(e) = app.opener().open_url(url.clone(), None::<&str>) {
+                log_to_file(String::from("ERROR"), format!("Failed to open {}: {}", url, e));
+            }
+        }
+        TrayAction::ShowInfo { title, message } => {
+            app.dialog().message(message).title(title).show(|_| {});
+        }
+        TrayAction::RunJob { job_id } => {
+            let app_handle = app.clone();
+            tauri::async_runtime::spawn(async move {
+                log_to_file(String::from("INFO"), format!("Requesting job {} from the tray", job_id));
+                match request::<serde_json::Value>(IpcCommand::RunJob { job_id: job_id.clone() }).await {
+                    Ok(_) => notify(&app_handle, "Job started", &format!("Job {} has been started", job_id)),
+                    Err(e) => {
+                        log_to_file(String::from("ERROR"), format!("Failed to run job {}: {}", job_id, e));
+                        notify(&app_handle, "Job could not be started", &e.to_string());
+                    }
+                }
+            });
+        }
+    }
+}
+
+// Swaps the support hotkey for the one in settings, returning what ended up registered
+fn apply_support_hotkey(app: &AppHandle, previous: Option<Shortcut>, wanted: Option<&str>) -> Option<Shortcut> {
+    let shortcuts = app.global_shortcut();
+
+    if let Some(previous) = previous {
+        match shortcuts.unregister(previous) {
+            Ok(()) => log_to_file(String::from("INFO"), String::from("Support hotkey unregistered")),
+            Err(e) => log_to_file(
+                String::from("WARN"),
+                format!("Failed to unregister support hotkey: {}", e),
+            ),
+        }
+    }
+
+    let wanted = wanted?;
+    let shortcut: Shortcut = match wanted.parse() {
+        Ok(shortcut) => shortcut,
+        Err(e) => {
+            log_to_file(
+                String::from("WARN"),
+                format!("Invalid support hotkey '{}': {}", wanted, e),
+            );
+            return None;
+        }
+    };
+
+    if shortcuts.is_registered(shortcut) {
+        log_to_file(
+            String::from("WARN"),
+            format!("Support hotkey {} conflicts with a shortcut the agent already uses", wanted),
+        );
+        return None;
+    }
+
+    // Same path as the tray's screenshot item
+    let result = shortcuts.on_shortcut(shortcut, |app, _shortcut, event| {
+        if event.state == ShortcutState::Pressed {
+            handle_support_window(app, true);
+        }
+    });
+    match result {
+        Ok(()) => {
+            log_to_file(String::from("INFO"), format!("Support hotkey {} registered", wanted));
+            Some(shortcut)
+        }
+        Err(e) => {
+            // The OS refuses shortcuts another application has already claimed
+            log_to_file(
+                String::from("WARN"),
+                format!("Support hotkey {} could not be registered, it may be in use by another application: {}", wanted, e),
+            );
+            None
+        }
+    }
+}
+
+// The hotkey is optional, follow settings so it can be turned on, changed or off without a restart
+fn watch_support_hotkey(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut configured: Option<String> = None;
+        let mut registered: Option<Shortcut> = None;
+        let mut poll_interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
+
+        loop {
+            poll_interval.tick().await;
+
+            let wanted = match load_settings().await {
+                Ok(settings) => settings.support_hotkey.filter(|hotkey| !hotkey.trim().is_empty()),
+                Err(_) => continue,
+            };
+            if wanted == configured {
+                continue;
+            }
+
+            registered = apply_support_hotkey(&app, registered, wanted.as_deref());
+            configured = wanted;
+        }
+    });
+}
+
+async fn get_agent_state() -> AgentState {
+    match request(IpcCommand::GetAgentState).await {
+        Ok(state) => state,
+        // Without a service the in-process tasks publish here, if they never ran the state says so
+        Err(_) => current_state(),
+    }
+}
+
+// Registration, heartbeat and the outbox publish their state in whichever
+// process runs them, reflect it and the branding on the tray icon whenever they change
+fn watch_agent_state(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut shown: Option<(AgentState, Branding, bool)> = None;
+        let mut poll_interval = tokio::time::interval(tokio::time::Duration::from_secs(15));
+
+        loop {
+            poll_interval.tick().await;
+
+            // Branding changes swap the icon and tooltip name too, and a replay recording
+            // that stopped itself takes its badge off
+            let current = (get_agent_state().await, load_branding(), replay_buffer_running());
+            if shown.as_ref() == Some(&current) {
+                continue;
+            }
+
+            match apply_tray_health(&app, &current.0) {
+                Ok(()) => shown = Some(current),
+                Err(e) => log_to_file(
+                    String::from("ERROR"),
+                    format!("Failed to update tray status: {}", e),
+                ),
+            }
+        }
+    });
+}
+
+// The service evaluates alert rules but can't reach the desktop, so show the
+// user any newly raised alert whose rule asks for it
+fn watch_user_alerts(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut seen: Option<Vec<String>> = None;
+        let mut poll_interval = tokio::time::interval(tokio::time::Duration::from_secs(30));
+
+        loop {
+            poll_interval.tick().await;
+
+            let alerts = get_agent_state().await.active_alerts;
+
+            // Alerts already active when the app starts were shown by the previous instance
+            if let Some(previous) = &seen {
+                for alert in alerts.iter().filter(|alert| alert.notify_user) {
+                    if !previous.contains(&alert.fingerprint) {
+                        notify(&app, load_branding().display_name(), &alert.message);
+                    }
+                }
+            }
+
+            seen = Some(alerts.into_iter().map(|alert| alert.fingerprint).collect());
+        }
+    });
+}
+
+/// Shows the restart countdown, the window keeps itself up to date and offers Postpone while it still may
+fn handle_reboot_window(app: &AppHandle) {
+    if let Some(window) = app.get_webview_window("reboot") {
+        let _ = window.show();
+        let _ = window.set_focus();
+        return;
+    }
+
+    if let Err(e) = WebviewWindowBuilder::new(app, "reboot", WebviewUrl::App("reboot.html".into()))
+        .title(format!("Restart scheduled - {}", load_branding().display_name()))
+        .inner_size(420.0, 260.0)
+        .resizable(false)
+        .always_on_top(true)
+        .center()
+        .focused(true)
+        .build()
+    {
+        log_to_file(String::from("ERROR"), format!("Failed to open restart countdown: {}", e));
+    }
+}
+
+// Reboots are scheduled by the service, which can't reach the desktop. Ask the
+// user once per announced time, and remind them shortly before it happens.
+fn watch_scheduled_reboot(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut prompted: Option<String> = None;
+        let mut reminded: Option<String> = None;
+        let mut poll_interval = tokio::time::interval(tokio::time::Duration::from_secs(15));
+
+        loop {
+            poll_interval.tick().await;
+
+            let reboot = match get_agent_state().await.scheduled_reboot {
+                Some(reboot) => reboot,
+                None => continue,
+            };
+            let key = format!("{}@{}", reboot.run_id, reboot.reboot_at);
+            let minutes_left = chrono::DateTime::parse_from_rfc3339(&reboot.reboot_at)
+                .map(|at| (at.with_timezone(&chrono::Utc) - chrono::Utc::now()).num_minutes())
+                .unwrap_or(i64::MAX);
+
+            if prompted.as_ref() != Some(&key) {
+                handle_reboot_window(&app);
+                prompted = Some(key.clone());
+                // A short countdown is already close, the prompt is reminder enough
+                if minutes_left < REBOOT_REMINDER_MINUTES {
+                    reminded = Some(key.clone());
+                }
+            }
+
+            if minutes_left < REBOOT_REMINDER_MINUTES && reminded.as_ref() != Some(&key) {
+                notify(
+                    &app,
+                    load_branding().display_name(),
+                    "This computer will restart in a few minutes. Save your work now.",
+                );
+                reminded = Some(key);
+            }
+        }
+    });
+}
+
+// The service stores the menu the server sends with each heartbeat, rebuild
+// the tray whenever it differs from what is shown
+fn watch_tray_menu(app: AppHandle, mut current: Vec<TrayMenuEntry>) {
+    tauri::async_runtime::spawn(async move {
+        let mut poll_interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
+
+        loop {
+            poll_interval.tick().await;
+
+            let menu = match load_settings().await {
+                Ok(settings) => settings.tray_menu.unwrap_or_else(default_tray_menu),
+                Err(_) => continue,
+            };
+            if menu == current {
+                continue;
+            }
+
+            match apply_tray_menu(&app, &menu) {
+                Ok(()) => {
+                    log_to_file(String::from("INFO"), String::from("Tray menu updated"));
+                    current = menu;
+                }
+                Err(e) => log_to_file(
+                    String::from("ERROR"),
+                    format!("Failed to update tray menu: {}", e),
+                ),
+            }
+        }
+    });
+}
+
 fn handle_about_window(app: &AppHandle) {
     let app_handle = app.clone();
 
@@ -206,7 +504,7 @@ fn handle_about_window(app: &AppHandle) {
         let _ = window.set_focus();
     } else {
         WebviewWindowBuilder::new(app, "about", WebviewUrl::App("about.html".into()))
-            .title("About")
+            .title(format!("About {}", load_branding().display_name()))
             .inner_size(300.0, 300.0)
             .build()
             .expect("Failed to create about window");
@@ -215,7 +513,7 @@ fn handle_about_window(app: &AppHandle) {
 
 fn create_support_window(app: &AppHandle) {
     WebviewWindowBuilder::new(app, "support", WebviewUrl::App("support.html".into()))
-        .title("Support Request")
+        .title(format!("Support Request - {}", load_branding().display_name()))
         .inner_size(1000.0, 800.0)
         .build()
         .expect("Failed to create support window");
@@ -226,12 +524,12 @@ fn handle_support_window(app: &AppHandle, screenshot: bool) {
 
     log_to_file(String::from("INFO"), format!("Opening support window with screenshot set to {}", screenshot));
     tauri::async_runtime::spawn(async move {
-        let mut screenshot_path: Option<PathBuf> = None;
+        let mut screenshot_result: Option<ScreenshotResult> = None;
 
         // Step 1: Take screenshot first (if requested)
         if screenshot {
-            if let Ok(path) = take_screenshot_internal(app_handle.clone()).await {
-                screenshot_path = Some(path);
+            if let Ok(result) = take_screenshot_internal(app_handle.clone(), None, true).await {
+                screenshot_result = Some(result);
             }
         }
 
@@ -248,50 +546,298 @@ fn handle_support_window(app: &AppHandle, screenshot: bool) {
         };
 
         // Step 3: If screenshot was taken, notify window
-        if let Some(path) = screenshot_path {
-            let _ = window.emit_to(EventTarget::Any, "use_screenshot", path);
+        if let Some(result) = screenshot_result {
+            let _ = window.emit_to(EventTarget::Any, "use_screenshot", result);
         }
     });
 }
 
-async fn take_screenshot_internal(app: AppHandle) -> Result<PathBuf, String> {
-    log_to_file(String::from("INFO"), String::from("Starting screenshot capture"));
+// Screenshots from a previous run can't belong to an open support form, so drop
+// them on startup and then expire unsent ones after the configured TTL
+fn start_screenshot_cleanup(app: AppHandle) {
+    let legacy_dirs: Vec<PathBuf> = app
+        .path()
+        .app_data_dir()
+        .map(|dir| vec![dir.join("tauri-plugin-screenshots"), dir.join("screenshots")])
+        .unwrap_or_default();
+
+    match clear_screenshots(&legacy_dirs) {
+        Ok(removed) if removed > 0 => log_to_file(
+            String::from("INFO"),
+            format!("Removed {} orphaned screenshot(s) on startup", removed),
+        ),
+        Ok(_) => {}
+        Err(e) => log_to_file(
+            String::from("WARN"),
+            format!("Failed to sweep orphaned screenshots: {}", e),
+        ),
+    }
+
+    tauri::async_runtime::spawn(async move {
+        let mut sweep_interval = tokio::time::interval(tokio::time::Duration::from_secs(60 * 5));
+
+        loop {
+            sweep_interval.tick().await;
+
+            let ttl_minutes = load_settings()
+                .await
+                .ok()
+                .and_then(|settings| settings.screenshot_ttl_minutes)
+                .unwrap_or(DEFAULT_SCREENSHOT_TTL_MINUTES);
+            match sweep_screenshots(std::time::Duration::from_secs(ttl_minutes * 60)) {
+                Ok(removed) if removed > 0 => log_to_file(
+                    String::from("INFO"),
+                    format!("Removed {} expired screenshot(s)", removed),
+                ),
+                Ok(_) => {}
+                Err(e) => log_to_file(
+                    String::from("WARN"),
+                    format!("Failed to sweep expired screenshots: {}", e),
+                ),
+            }
+        }
+    });
+}
 
+async fn hide_support_window_for_capture(app: &AppHandle) {
     // Hide window if it exists
     if let Some(window) = app.get_webview_window("support") {
-        log_to_file(String::from("INFO"), String::from("Hiding support window before screenshot"));
+        log_to_file(String::from("INFO"), String::from("Hiding support window before capture"));
         let _ = window.hide();
         // Give time for window to hide
         tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
     } else {
         log_to_file(String::from("INFO"), String::from("No support window to hide"));
     }
+}
 
-    // Get first available monitor
-    log_to_file(String::from("INFO"), String::from("Getting screenshotable monitors"));
-    let monitors = get_screenshotable_monitors().await
-        .map_err(|e| {
-            let err_msg = format!("Failed to get monitors: {}", e);
-            log_to_file(String::from("ERROR"), err_msg.clone());
-            err_msg
-        })?;
+async fn take_screenshot_internal(
+    app: AppHandle,
+    monitor_id: Option<u32>,
+    stitch: bool,
+) -> Result<ScreenshotResult, String> {
+    log_to_file(String::from("INFO"), String::from("Starting screenshot capture"));
+    hide_support_window_for_capture(&app).await;
 
-    if monitors.is_empty() {
-        let err_msg = String::from("No screenshotable monitors found");
+    log_to_file(
+        String::from("INFO"),
+        format!("Capturing monitor {:?}, stitch={}", monitor_id, stitch),
+    );
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        capture_screens(monitor_id, stitch).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|result| result)
+    .map_err(|e| {
+        let err_msg = format!("Failed to capture screenshot: {}", e);
         log_to_file(String::from("ERROR"), err_msg.clone());
-        return Err(err_msg);
+        err_msg
+    })?;
+
+    log_to_file(
+        String::from("INFO"),
+        format!(
+            "Captured {} monitor(s), stitched={}",
+            result.captures.len(),
+            result.stitched.is_some()
+        ),
+    );
+    Ok(result)
+}
+
+async fn list_outbox_entries() -> Result<Vec<OutboxEntry>, String> {
+    match request(IpcCommand::GetOutbox).await {
+        Err(IpcError::Unavailable(_)) => {}
+        result => return result.map_err(|e| e.to_string()),
     }
+    list_outbox().await.map_err(|e| e.to_string())
+}
 
-    log_to_file(String::from("INFO"), format!("Found {} monitor(s), capturing from first monitor", monitors.len()));
-    let path = get_monitor_screenshot(app, monitors[0].id).await
-        .map_err(|e| {
-            let err_msg = format!("Failed to capture screenshot: {}", e);
-            log_to_file(String::from("ERROR"), err_msg.clone());
-            err_msg
-        })?;
+async fn get_receipt(id: String) -> Result<Option<OutboxReceipt>, String> {
+    match request(IpcCommand::GetOutboxReceipt { id: id.clone() }).await {
+        Err(IpcError::Unavailable(_)) => {}
+        result => return result.map_err(|e| e.to_string()),
+    }
+    get_outbox_receipt(&id).await.map_err(|e| e.to_string())
+}
+
+fn notify(app: &AppHandle, title: &str, body: &str) {
+    let mut builder = app.notification().builder().title(title).body(body);
+    if let Some(logo) = get_logo() {
+        builder = builder.icon(logo.to_string_lossy());
+    }
+    if let Err(e) = builder.show() {
+        log_to_file(String::from("WARN"), format!("Failed to show notification: {}", e));
+    }
+}
+
+// Puts the recording badge on or takes it off straight away instead of at the next state poll
+async fn refresh_tray_status(app: &AppHandle) {
+    if app.tray_by_id(TRAY_ID).is_none() {
+        return;
+    }
+    if let Err(e) = apply_tray_health(app, &get_agent_state().await) {
+        log_to_file(String::from("ERROR"), format!("Failed to update tray status: {}", e));
+    }
+}
 
-    log_to_file(String::from("INFO"), format!("Screenshot saved to: {}", path.display()));
-    Ok(path)
+// Tickets queued while offline are delivered by the outbox, possibly in another
+// process, so watch for them leaving the queue and tell the user how it went
+fn watch_queued_tickets(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut queued: Vec<String> = Vec::new();
+        let mut poll_interval = tokio::time::interval(tokio::time::Duration::from_secs(30));
+
+        loop {
+            poll_interval.tick().await;
+
+            let entries = match list_outbox_entries().await {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            let current: Vec<String> = entries
+                .into_iter()
+                .filter(|entry| entry.kind == "ticket")
+                .map(|entry| entry.id)
+                .collect();
+
+            for id in queued.iter().filter(|id| !current.contains(id)) {
+                match get_receipt(id.clone()).await {
+                    Ok(Some(receipt)) if receipt.delivered => {
+                        let ticket_id = receipt
+                            .response
+                            .as_deref()
+                            .and_then(|response| parse_ticket_id(response).ok())
+                            .unwrap_or_else(|| String::from("N/A"));
+                        log_to_file(
+                            String::from("INFO"),
+                            format!("Queued ticket {} delivered as ticket {}", id, ticket_id),
+                        );
+                        notify(
+                            &app,
+                            "Support request sent",
+                            &format!("Your support request was submitted. Ticket ID: {}", ticket_id),
+                        );
+                    }
+                    Ok(Some(receipt)) => {
+                        log_to_file(
+                            String::from("ERROR"),
+                            format!("Queued ticket {} was rejected: {:?}", id, receipt.error),
+                        );
+                        notify(
+                            &app,
+                            "Support request failed",
+                            "Your support request could not be submitted. Please try again or contact support.",
+                        );
+                    }
+                    Ok(None) => {}
+                    Err(e) => log_to_file(
+                        String::from("WARN"),
+                        format!("Failed to look up receipt for queued ticket {}: {}", id, e),
+                    ),
+                }
+            }
+
+            queued = current;
+        }
+    });
+}
+
+async fn list_my_tickets() -> Result<Vec<TrackedTicket>, String> {
+    match request(IpcCommand::GetTickets).await {
+        Err(IpcError::Unavailable(_)) => {}
+        result => return result.map_err(|e| e.to_string()),
+    }
+    let owner = current_peer_info()
+        .owner()
+        .ok_or("Unable to identify the current user")?;
+    list_owned_tickets(&owner).await.map_err(|e| e.to_string())
+}
+
+// The ticket store belongs to the service, so which changes this user has
+// already been told about is kept per user next to the app data
+fn get_seen_tickets_path(app: &AppHandle) -> Option<PathBuf> {
+    app.path()
+        .app_data_dir()
+        .ok()
+        .map(|dir| dir.join("seen_tickets.json"))
+}
+
+fn ticket_change_message(ticket: &TrackedTicket) -> Option<(&'static str, String)> {
+    match ticket.last_change.as_ref()? {
+        TicketChange::Replied => Some((
+            "New reply on your ticket",
+            match &ticket.last_reply {
+                Some(reply) => format!("Ticket {} - {}: {}", ticket.id, ticket.summary, reply),
+                None => format!("A technician replied to ticket {} - {}", ticket.id, ticket.summary),
+            },
+        )),
+        TicketChange::Closed => Some((
+            "Ticket closed",
+            format!("Ticket {} - {} has been closed", ticket.id, ticket.summary),
+        )),
+        TicketChange::StatusChanged => Some((
+            "Ticket updated",
+            format!(
+                "Ticket {} - {} is now {}",
+                ticket.id,
+                ticket.summary,
+                ticket.status.as_deref().unwrap_or("updated")
+            ),
+        )),
+    }
+}
+
+// Status changes arrive with the service's heartbeat, notify the user about
+// any ticket whose revision moved since we last looked
+fn watch_ticket_updates(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let seen_path = get_seen_tickets_path(&app);
+        let mut seen: Option<HashMap<String, u32>> = match &seen_path {
+            Some(path) => tokio::fs::read(path)
+                .await
+                .ok()
+                .and_then(|content| serde_json::from_slice(&content).ok()),
+            None => None,
+        };
+        let mut poll_interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
+
+        loop {
+            poll_interval.tick().await;
+
+            let tickets = match list_my_tickets().await {
+                Ok(tickets) => tickets,
+                Err(_) => continue,
+            };
+            let current: HashMap<String, u32> = tickets
+                .iter()
+                .map(|ticket| (ticket.id.clone(), ticket.revision))
+                .collect();
+
+            // Without a record of what was shown, start from now instead of replaying history
+            if let Some(previous) = &seen {
+                for ticket in &tickets {
+                    let last_seen = previous.get(&ticket.id).copied().unwrap_or(0);
+                    if ticket.revision > last_seen {
+                        if let Some((title, body)) = ticket_change_message(ticket) {
+                            notify(&app, title, &body);
+                        }
+                    }
+                }
+            }
+
+            if seen.as_ref() != Some(&current) {
+                if let (Some(path), Ok(content)) = (&seen_path, serde_json::to_vec(&current)) {
+                    if let Some(parent) = path.parent() {
+                        let _ = tokio::fs::create_dir_all(parent).await;
+                    }
+                    let _ = tokio::fs::write(path, content).await;
+                }
+                seen = Some(current);
+            }
+        }
+    });
 }
 
 #[tauri::command]
@@ -350,19 +896,238 @@ fn show_window(app: tauri::AppHandle, label: String) -> Result<(), String> {
 }
 
 #[tauri::command]
-async fn take_screenshot(app: tauri::AppHandle) -> Result<String, String> {
+async fn take_screenshot(
+    app: tauri::AppHandle,
+    monitor_id: Option<u32>,
+    stitch: Option<bool>,
+) -> Result<ScreenshotResult, String> {
     log_to_file(String::from("INFO"), String::from("take_screenshot command invoked"));
-    let path = take_screenshot_internal(app).await?;
-    let path_str = path.to_string_lossy().to_string();
-    log_to_file(String::from("INFO"), format!("Returning screenshot path: {}", path_str));
-    Ok(path_str)
+    take_screenshot_internal(app, monitor_id, stitch.unwrap_or(true)).await
+}
+
+#[tauri::command]
+async fn record_screen_clip(
+    app: tauri::AppHandle,
+    options: Option<RecordingOptions>,
+) -> Result<ScreenRecording, String> {
+    let options = options.unwrap_or_default();
+    log_to_file(
+        String::from("INFO"),
+        format!("record_screen_clip command invoked: {:?}", options),
+    );
+    hide_support_window_for_capture(&app).await;
+
+    let recording = tauri::async_runtime::spawn_blocking(move || {
+        record_screen(&options).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|result| result)
+    .map_err(|e| {
+        let err_msg = format!("Failed to record screen: {}", e);
+        log_to_file(String::from("ERROR"), err_msg.clone());
+        err_msg
+    })?;
+
+    log_to_file(
+        String::from("INFO"),
+        format!(
+            "Recorded {} frame(s) over {}ms as {} ({} bytes)",
+            recording.frames, recording.duration_ms, recording.id, recording.size_bytes
+        ),
+    );
+    Ok(recording)
+}
+
+/// Starts keeping the last few seconds of the screen, so a problem can be saved after it happened
+#[tauri::command]
+async fn start_replay_recording(
+    app: tauri::AppHandle,
+    options: Option<RecordingOptions>,
+) -> Result<(), String> {
+    let options = options.unwrap_or_default();
+    log_to_file(
+        String::from("INFO"),
+        format!("start_replay_recording command invoked: {:?}", options),
+    );
+    hide_support_window_for_capture(&app).await;
+
+    start_replay_buffer(&options).map_err(|e| {
+        let err_msg = format!("Failed to start replay recording: {}", e);
+        log_to_file(String::from("ERROR"), err_msg.clone());
+        err_msg
+    })?;
+
+    // The support window is hidden while the problem is reproduced, the user still has to see
+    // that the screen is being captured
+    refresh_tray_status(&app).await;
+    notify(
+        &app,
+        "Screen recording started",
+        "Your screen is being recorded until you save or discard the replay in the support window.",
+    );
+    Ok(())
+}
+
+/// Whether a replay recording is running, it stops itself when left unsaved for too long
+#[tauri::command]
+fn get_replay_recording_status() -> bool {
+    replay_buffer_running()
+}
+
+#[tauri::command]
+async fn save_replay_recording(app: tauri::AppHandle) -> Result<ScreenRecording, String> {
+    log_to_file(String::from("INFO"), String::from("save_replay_recording command invoked"));
+
+    let recording = tauri::async_runtime::spawn_blocking(|| save_replay_buffer().map_err(|e| e.to_string()))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|result| result)
+        .map_err(|e| {
+            let err_msg = format!("Failed to save replay recording: {}", e);
+            log_to_file(String::from("ERROR"), err_msg.clone());
+            err_msg
+        });
+    refresh_tray_status(&app).await;
+    let recording = recording?;
+
+    log_to_file(
+        String::from("INFO"),
+        format!(
+            "Saved replay of {} frame(s) over {}ms as {} ({} bytes)",
+            recording.frames, recording.duration_ms, recording.id, recording.size_bytes
+        ),
+    );
+    Ok(recording)
+}
+
+/// Stops the replay recording without saving, returning whether one was running
+#[tauri::command]
+async fn stop_replay_recording(app: tauri::AppHandle) -> Result<bool, String> {
+    log_to_file(String::from("INFO"), String::from("stop_replay_recording command invoked"));
+    if !replay_buffer_running() {
+        return Ok(false);
+    }
+    let stopped = tauri::async_runtime::spawn_blocking(stop_replay_buffer)
+        .await
+        .map_err(|e| e.to_string());
+    refresh_tray_status(&app).await;
+    stopped
+}
+
+#[tauri::command]
+async fn get_monitors() -> Result<Vec<MonitorInfo>, String> {
+    log_to_file(String::from("INFO"), String::from("get_monitors command invoked"));
+    tauri::async_runtime::spawn_blocking(|| list_monitors().map_err(|e| e.to_string()))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|result| result)
+        .map_err(|e| {
+            let err_msg = format!("Failed to get monitors: {}", e);
+            log_to_file(String::from("ERROR"), err_msg.clone());
+            err_msg
+        })
 }
 
 #[tauri::command]
-async fn get_settings_info() -> Result<device_manager::Settings, String> {
+async fn annotate_screenshot(
+    id: String,
+    operations: Vec<AnnotationOp>,
+    options: Option<UploadOptions>,
+) -> Result<AnnotatedImage, String> {
+    log_to_file(
+        String::from("INFO"),
+        format!("annotate_screenshot command invoked: {} operation(s) on {}", operations.len(), id),
+    );
+
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        annotate::annotate_screenshot(&id, &operations, &options.unwrap_or_default())
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|result| result)
+    .map_err(|e| {
+        let err_msg = format!("Failed to annotate screenshot: {}", e);
+        log_to_file(String::from("ERROR"), err_msg.clone());
+        err_msg
+    })?;
+
+    log_to_file(
+        String::from("INFO"),
+        format!("Annotated screenshot saved as {} ({} bytes)", result.id, result.size_bytes),
+    );
+    Ok(result)
+}
+
+#[tauri::command]
+async fn import_screenshot_file(path: String) -> Result<ScreenCapture, String> {
+    log_to_file(String::from("INFO"), format!("import_screenshot_file command invoked: {}", path));
+    tauri::async_runtime::spawn_blocking(move || {
+        import_screenshot(&PathBuf::from(path)).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|result| result)
+    .map_err(|e| {
+        let err_msg = format!("Failed to import screenshot: {}", e);
+        log_to_file(String::from("ERROR"), err_msg.clone());
+        err_msg
+    })
+}
+
+#[tauri::command]
+fn read_screenshot(id: String) -> Result<String, String> {
+    read_screenshot_data_url(&id).map_err(|e| {
+        let err_msg = format!("Failed to read screenshot {}: {}", id, e);
+        log_to_file(String::from("ERROR"), err_msg.clone());
+        err_msg
+    })
+}
+
+#[tauri::command]
+fn discard_screenshots() -> Result<(), String> {
+    log_to_file(String::from("INFO"), String::from("discard_screenshots command invoked"));
+    clear_screenshots(&[]).map(|_| ()).map_err(|e| {
+        let err_msg = format!("Failed to discard screenshots: {}", e);
+        log_to_file(String::from("ERROR"), err_msg.clone());
+        err_msg
+    })
+}
+
+#[tauri::command]
+fn get_branding() -> BrandingInfo {
+    get_branding_info()
+}
+
+#[tauri::command]
+async fn get_my_tickets() -> Result<Vec<TrackedTicket>, String> {
+    log_to_file(String::from("INFO"), String::from("get_my_tickets command invoked"));
+    list_my_tickets().await.map_err(|e| {
+        let err_msg = format!("Failed to get tickets: {}", e);
+        log_to_file(String::from("ERROR"), err_msg.clone());
+        err_msg
+    })
+}
+
+// The machine settings belong to the service, the UI only reads the file itself
+// when no service is running (a development build or a broken install)
+async fn load_settings() -> Result<UiSettings, String> {
+    match request(IpcCommand::GetSettings).await {
+        Err(IpcError::Unavailable(_)) => {}
+        result => return result.map_err(|e| e.to_string()),
+    }
+
+    get_settings()
+        .await
+        .map(|settings| UiSettings::from(&settings))
+        .map_err(|e| format!("Failed to get settings: {}", e))
+}
+
+#[tauri::command]
+async fn get_settings_info() -> Result<UiSettings, String> {
     log_to_file(String::from("INFO"), String::from("get_settings_info command invoked"));
-    get_settings().await.map_err(|e| {
-        let err_msg = format!("Failed to get settings: {}", e);
+    load_settings().await.map_err(|err_msg| {
         log_to_file(String::from("ERROR"), err_msg.clone());
         err_msg
     })
@@ -371,15 +1136,30 @@ async fn get_settings_info() -> Result<device_manager::Settings, String> {
 #[tauri::command]
 async fn check_registration_status() -> Result<bool, String> {
     log_to_file(String::from("INFO"), String::from("check_registration_status command invoked"));
-    let is_registered = is_device_registered().await;
+    let is_registered = match request(IpcCommand::CheckRegistration).await {
+        Ok(is_registered) => is_registered,
+        Err(_) => is_device_registered().await,
+    };
     log_to_file(String::from("INFO"), format!("Device registration status: {}", is_registered));
     Ok(is_registered)
 }
 
+/// Resolves a path the UI asked to read, refusing anything outside the allowed directories
+/// and this user's own screenshots
+async fn check_ui_read_path(command: &str, path: &str) -> Result<PathBuf, String> {
+    log_to_file(String::from("INFO"), format!("{} command invoked for: {}", command, path));
+    let mut allowed = allowed_dirs().await;
+    allowed.push(get_screenshot_dir());
+    check_read_path(path, &allowed).map_err(|e| {
+        log_to_file(String::from("WARN"), format!("{} refused: {}", command, e));
+        e
+    })
+}
+
 #[tauri::command]
-fn read_file_text(path: String) -> Result<String, String> {
-    log_to_file(String::from("INFO"), format!("read_file_text command invoked for: {}", path));
-    std::fs::read_to_string(&path).map_err(|e| {
+async fn read_file_text(path: String) -> Result<String, String> {
+    let resolved = check_ui_read_path("read_file_text", &path).await?;
+    std::fs::read_to_string(&resolved).map_err(|e| {
         let err_msg = format!("Failed to read file {}: {}", path, e);
         log_to_file(String::from("ERROR"), err_msg.clone());
         err_msg
@@ -387,9 +1167,9 @@ fn read_file_text(path: String) -> Result<String, String> {
 }
 
 #[tauri::command]
-fn read_file_base64(path: String) -> Result<String, String> {
-    log_to_file(String::from("INFO"), format!("read_file_base64 command invoked for: {}", path));
-    std::fs::read(&path)
+async fn read_file_base64(path: String) -> Result<String, String> {
+    let resolved = check_ui_read_path("read_file_base64", &path).await?;
+    std::fs::read(&resolved)
         .map_err(|e| {
             let err_msg = format!("Failed to read file {}: {}", path, e);
             log_to_file(String::from("ERROR"), err_msg.clone());
@@ -402,9 +1182,9 @@ fn read_file_base64(path: String) -> Result<String, String> {
 }
 
 #[tauri::command]
-fn read_file_binary(path: String) -> Result<Vec<u8>, String> {
-    log_to_file(String::from("INFO"), format!("read_file_binary command invoked for: {}", path));
-    std::fs::read(&path)
+async fn read_file_binary(path: String) -> Result<Vec<u8>, String> {
+    let resolved = check_ui_read_path("read_file_binary", &path).await?;
+    std::fs::read(&resolved)
         .map_err(|e| {
             let err_msg = format!("Failed to read file {}: {}", path, e);
             log_to_file(String::from("ERROR"), err_msg.clone());
@@ -450,6 +1230,11 @@ fn read_registry_value(_path: &str, _key: &str) -> Result<String, String> {
 
 #[tauri::command]
 async fn get_os_info() -> Result<HeartbeatRequest, String> {
+    match request(IpcCommand::GetSystemInfo).await {
+        Err(IpcError::Unavailable(_)) => {}
+        result => return result.map_err(|e| e.to_string()),
+    }
+
     match gather_system_info().await {
         Ok(info) => {
             return Ok(info);
@@ -462,8 +1247,123 @@ async fn get_os_info() -> Result<HeartbeatRequest, String> {
 
 #[tauri::command]
 async fn get_rmm_id() -> Result<String, String> {
+    match request(IpcCommand::GetRmmId).await {
+        Err(IpcError::Unavailable(_)) => {}
+        result => return result.map_err(|e| e.to_string()),
+    }
+
     match get_rmm_device_id() {
         Some(id) => Ok(id),
         None => Err("Failed to get key".into()),
     }
 }
+
+#[tauri::command]
+async fn get_outbox() -> Result<Vec<OutboxEntry>, String> {
+    log_to_file(String::from("INFO"), String::from("get_outbox command invoked"));
+    list_outbox_entries().await.map_err(|e| {
+        let err_msg = format!("Failed to read outbox: {}", e);
+        log_to_file(String::from("ERROR"), err_msg.clone());
+        err_msg
+    })
+}
+
+#[tauri::command]
+async fn submit_ticket(
+    app: tauri::AppHandle,
+    ticket: TicketRequest,
+    screenshot_id: Option<String>,
+) -> Result<TicketOutcome, String> {
+    log_to_file(
+        String::from("INFO"),
+        format!(
+            "submit_ticket command invoked: summary=\"{}\", urgency={}, impact={}, has_screenshot={}",
+            ticket.summary,
+            ticket.urgency,
+            ticket.impact,
+            screenshot_id.is_some()
+        ),
+    );
+
+    // Stills are stored as PNGs and recordings as GIFs, both go up as the screenshot field with
+    // their own content type so the backend stores them as what they are
+    let screenshot = match &screenshot_id {
+        Some(id) => {
+            let (bytes, mime_type) = get_screenshot_path(id)
+                .and_then(|path| Ok((std::fs::read(&path)?, get_screenshot_mime_type(&path))))
+                .map_err(|e| {
+                    let err_msg = format!("Failed to read screenshot {}: {}", id, e);
+                    log_to_file(String::from("ERROR"), err_msg.clone());
+                    err_msg
+                })?;
+            let file_name = if mime_type == "image/gif" {
+                "recording.gif"
+            } else {
+                "screenshot.png"
+            };
+            log_to_file(String::from("INFO"), format!("Attaching {} {} ({} bytes)", file_name, id, bytes.len()));
+            Some(TicketAttachment {
+                file_name: String::from(file_name),
+                mime_type: String::from(mime_type),
+                data: general_purpose::STANDARD.encode(bytes),
+            })
+        }
+        None => None,
+    };
+
+    let submission = TicketSubmission { ticket, screenshot };
+    let outcome = match request(IpcCommand::SubmitTicket(Box::new(submission.clone()))).await {
+        Err(IpcError::Unavailable(_)) => ticket::submit_ticket(submission, current_peer_info().owner())
+            .await
+            .map_err(|e| e.to_string()),
+        // The service may have created it before going quiet, submitting again would make a second one
+        Err(IpcError::NoResponse(e)) => Err(format!("{}, the ticket may still have been created", e)),
+        result => result.map_err(|e| e.to_string()),
+    }
+    .map_err(|e| {
+        let err_msg = format!("Failed to submit ticket: {}", e);
+        log_to_file(String::from("ERROR"), err_msg.clone());
+        err_msg
+    })?;
+
+    // The screenshot now lives in the ticket or the outbox, don't leave copies behind
+    if let Err(e) = clear_screenshots(&[]) {
+        log_to_file(String::from("WARN"), format!("Failed to clean up screenshots: {}", e));
+    }
+
+    match &outcome {
+        TicketOutcome::Created { ticket_id } => {
+            log_to_file(String::from("INFO"), format!("Ticket created successfully! Ticket ID: {}", ticket_id));
+        }
+        TicketOutcome::Queued { outbox_id } => {
+            log_to_file(String::from("WARN"), format!("Ticket queued for retry as outbox message {}", outbox_id));
+            notify(
+                &app,
+                "Support request saved",
+                "We couldn't reach the support server. Your request will be sent automatically once the connection is back.",
+            );
+        }
+    }
+
+    Ok(outcome)
+}
+
+#[tauri::command]
+async fn get_scheduled_reboot() -> Option<ScheduledReboot> {
+    get_agent_state().await.scheduled_reboot
+}
+
+#[tauri::command]
+async fn postpone_reboot() -> Result<ScheduledReboot, String> {
+    log_to_file(String::from("INFO"), String::from("postpone_reboot command invoked"));
+    match request(IpcCommand::DeferReboot).await {
+        // Without a service the reboot job runs in this process
+        Err(IpcError::Unavailable(_)) => defer_reboot(),
+        result => result.map_err(|e| e.to_string()),
+    }
+    .map_err(|e| {
+        let err_msg = format!("Failed to postpone restart: {}", e);
+        log_to_file(String::from("ERROR"), err_msg.clone());
+        err_msg
+    })
+}
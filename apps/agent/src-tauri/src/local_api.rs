@@ -0,0 +1,531 @@
+use crate::device_manager::{get_settings, Settings};
+use crate::heartbeat::HEARTBEAT_INTERVAL_SECS;
+use crate::ipc::{
+    accept_failed, read_frame, write_frame, IpcResponse, PeerInfo, FRAME_IDLE_TIMEOUT,
+    MAX_CONNECTIONS,
+};
+use crate::logger::log_to_file;
+use crate::ticket::{submit_ticket, TicketRequest, TicketSubmission};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite, BufReader};
+use tokio::sync::Semaphore;
+use tokio::time::timeout;
+
+/// Version of the request/response schema in schemas/local-api.schema.json
+pub const LOCAL_API_VERSION: u32 = 1;
+
+#[cfg(target_os = "windows")]
+pub const LOCAL_API_PIPE_NAME: &str = r"\\.\pipe\MSPAgentApi";
+
+// Scripts on Windows run elevated through the RMM, so only SYSTEM and admins may connect
+#[cfg(target_os = "windows")]
+const LOCAL_API_PIPE_SDDL: &str = "D:(A;;GA;;;SY)(A;;GA;;;BA)";
+
+#[derive(Deserialize, Debug)]
+pub struct LocalApiRequest {
+    pub version: u32,
+    pub id: u64,
+    pub command: Value,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", content = "params", rename_all = "snake_case")]
+pub enum LocalApiCommand {
+    GetStatus,
+    GetHealth,
+    CreateTicket(LocalTicket),
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LocalTicket {
+    pub summary: String,
+    pub description: Option<String>,
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub impact: Option<String>,
+    pub urgency: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct LocalApiStatus {
+    pub registered: bool,
+    pub device_id: Option<String>,
+    pub site_id: String,
+    pub guid: Option<String>,
+    pub hostname: Option<String>,
+    pub version: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct LocalApiHealth {
+    pub healthy: bool,
+    pub registered: bool,
+    pub last_heartbeat_at: Option<String>,
+    pub heartbeat_stale: bool,
+}
+
+impl LocalApiCommand {
+    fn is_action(&self) -> bool {
+        matches!(self, LocalApiCommand::CreateTicket(_))
+    }
+}
+
+#[cfg(unix)]
+pub fn get_local_api_socket_path() -> std::path::PathBuf {
+    #[cfg(target_os = "macos")]
+    {
+        std::path::PathBuf::from("/var/run/mspagent-api.sock")
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        std::path::PathBuf::from("/run/mspagent/api.sock")
+    }
+}
+
+// Read-only commands are open to every local user, actions need root or an allowlisted uid.
+// On Windows the pipe ACL already limits callers to administrators.
+fn is_action_allowed(peer: &PeerInfo, settings: &Settings) -> bool {
+    #[cfg(unix)]
+    {
+        match peer.uid {
+            Some(0) => true,
+            Some(uid) => settings
+                .local_api_action_uids
+                .as_ref()
+                .map(|uids| uids.contains(&uid))
+                .unwrap_or(false),
+            None => false,
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = (peer, settings);
+        true
+    }
+}
+
+fn heartbeat_is_stale(last_heartbeat_at: &Option<String>) -> bool {
+    let last = match last_heartbeat_at
+        .as_ref()
+        .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+    {
+        Some(last) => last,
+        None => return true,
+    };
+
+    // Allow one missed heartbeat before reporting the agent as stale
+    let age = chrono::Utc::now().signed_duration_since(last);
+    age.num_seconds() > (HEARTBEAT_INTERVAL_SECS * 2) as i64
+}
+
+async fn handle_command(command: LocalApiCommand, peer: &PeerInfo) -> Result<Value, String> {
+    let settings = get_settings()
+        .await
+        .map_err(|e| format!("Failed to get settings: {}", e))?;
+
+    if command.is_action() && !is_action_allowed(peer, &settings) {
+        log_to_file(
+            String::from("WARN"),
+            format!("Local API denied {:?} for peer {:?}", command, peer),
+        );
+        return Err(String::from("Permission denied"));
+    }
+
+    match command {
+        LocalApiCommand::GetStatus => {
+            let status = LocalApiStatus {
+                registered: settings.registered_at.is_some(),
+                device_id: settings.device_id,
+                site_id: settings.site_id,
+                guid: settings.guid,
+                hostname: settings.hostname,
+                version: env!("CARGO_PKG_VERSION").to_string(),
+            };
+            serde_json::to_value(status).map_err(|e| e.to_string())
+        }
+        LocalApiCommand::GetHealth => {
+            let registered = settings.registered_at.is_some();
+            let heartbeat_stale = heartbeat_is_stale(&settings.last_heartbeat_at);
+            let health = LocalApiHealth {
+                healthy: registered && !heartbeat_stale,
+                registered,
+                last_heartbeat_at: settings.last_heartbeat_at,
+                heartbeat_stale,
+            };
+            serde_json::to_value(health).map_err(|e| e.to_string())
+        }
+        LocalApiCommand::CreateTicket(ticket) => {
+            log_to_file(
+                String::from("INFO"),
+                format!("Local API ticket requested by peer {:?}: {}", peer, ticket.summary),
+            );
+
+            let request = TicketRequest {
+                summary: ticket.summary,
+                description: ticket.description,
+                name: ticket.name.unwrap_or_else(|| String::from("MSPAgent Local API")),
+                email: ticket.email.unwrap_or_default(),
+                phone: ticket.phone.unwrap_or_default(),
+                impact: ticket.impact.unwrap_or_else(|| String::from("3")),
+                urgency: ticket.urgency.unwrap_or_else(|| String::from("3")),
+                rmm_id: None,
+            };
+
+            let submission = TicketSubmission {
+                ticket: request,
+                screenshot: None,
+            };
+            let outcome = submit_ticket(submission, peer.owner()).await.map_err(|e| {
+                let err_msg = format!("Failed to create ticket: {}", e);
+                log_to_file(String::from("ERROR"), err_msg.clone());
+                err_msg
+            })?;
+            serde_json::to_value(outcome).map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// Serves local API requests on one connection until the client disconnects
+pub async fn handle_connection<S>(stream: S, peer: PeerInfo)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (read_half, mut write_half) = tokio::io::split(stream);
+    let mut reader = BufReader::new(read_half);
+
+    loop {
+        let request: LocalApiRequest = match timeout(FRAME_IDLE_TIMEOUT, read_frame(&mut reader)).await {
+            Ok(Ok(Some(request))) => request,
+            Ok(Ok(None)) | Err(_) => break,
+            Ok(Err(e)) => {
+                let response = IpcResponse::err(0, e.to_string()).with_version(LOCAL_API_VERSION);
+                let _ = write_frame(&mut write_half, &response).await;
+                break;
+            }
+        };
+
+        let response = if request.version != LOCAL_API_VERSION {
+            IpcResponse::err(
+                request.id,
+                format!(
+                    "Unsupported local API version {} (agent speaks {})",
+                    request.version, LOCAL_API_VERSION
+                ),
+            )
+        } else {
+            match serde_json::from_value::<LocalApiCommand>(request.command) {
+                Ok(command) => match handle_command(command, &peer).await {
+                    Ok(data) => IpcResponse::ok(request.id, data),
+                    Err(e) => IpcResponse::err(request.id, e),
+                },
+                Err(e) => IpcResponse::err(request.id, format!("Invalid command: {}", e)),
+            }
+        }
+        .with_version(LOCAL_API_VERSION);
+
+        if write_frame(&mut write_half, &response).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Listens for third-party scripts on the local API socket
+#[cfg(unix)]
+pub async fn serve() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::ipc::bind_unix_socket;
+
+    let socket_path = get_local_api_socket_path();
+    let listener = bind_unix_socket(&socket_path).await?;
+
+    log_to_file(
+        String::from("INFO"),
+        format!("Local API listening on {}", socket_path.display()),
+    );
+
+    accept_connections(listener).await
+}
+
+#[cfg(unix)]
+async fn accept_connections(
+    listener: tokio::net::UnixListener,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use crate::ipc::unix_peer_info;
+
+    let limit = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+    loop {
+        let permit = limit.clone().acquire_owned().await?;
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                accept_failed("Local API", e.to_string()).await;
+                continue;
+            }
+        };
+        let peer = unix_peer_info(&stream);
+        tauri::async_runtime::spawn(async move {
+            handle_connection(stream, peer).await;
+            drop(permit);
+        });
+    }
+}
+
+/// Listens for third-party scripts on the local API named pipe
+#[cfg(target_os = "windows")]
+pub async fn serve() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::ipc::{create_pipe_instance, next_pipe_instance, pipe_peer_info};
+
+    let mut server = create_pipe_instance(LOCAL_API_PIPE_NAME, LOCAL_API_PIPE_SDDL, true)?;
+
+    log_to_file(
+        String::from("INFO"),
+        format!("Local API listening on {}", LOCAL_API_PIPE_NAME),
+    );
+
+    let limit = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+    loop {
+        let permit = limit.clone().acquire_owned().await?;
+        let connected = server.connect().await.map_err(|e| e.to_string());
+        if let Err(e) = connected {
+            accept_failed("Local API", e).await;
+            server = next_pipe_instance(LOCAL_API_PIPE_NAME, LOCAL_API_PIPE_SDDL).await;
+            continue;
+        }
+        let connected = server;
+        server = next_pipe_instance(LOCAL_API_PIPE_NAME, LOCAL_API_PIPE_SDDL).await;
+        let peer = pipe_peer_info(&connected);
+        tauri::async_runtime::spawn(async move {
+            handle_connection(connected, peer).await;
+            drop(permit);
+        });
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::device_manager::test_support::use_settings;
+    use crate::ipc::bind_unix_socket;
+    use serde_json::json;
+    use std::os::unix::fs::MetadataExt;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, UnixStream};
+
+    fn schema() -> jsonschema::Validator {
+        let schema: Value =
+            serde_json::from_str(include_str!("../schemas/local-api.schema.json")).unwrap();
+        jsonschema::validator_for(&schema).unwrap()
+    }
+
+    fn assert_matches_schema(schema: &jsonschema::Validator, frame: &Value) {
+        let errors: Vec<String> = schema.iter_errors(frame).map(|e| e.to_string()).collect();
+        assert!(errors.is_empty(), "{} does not match the schema: {:?}", frame, errors);
+    }
+
+    /// Sends one request over `stream` and returns the response, checking both against the schema
+    async fn exchange<S>(schema: &jsonschema::Validator, stream: &mut BufReader<S>, request: Value) -> Value
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        assert_matches_schema(schema, &request);
+        send_unchecked(schema, stream, request).await
+    }
+
+    /// Like `exchange` for requests the schema doesn't allow, only the response is checked
+    async fn send_unchecked<S>(schema: &jsonschema::Validator, stream: &mut BufReader<S>, request: Value) -> Value
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut frame = serde_json::to_vec(&request).unwrap();
+        frame.push(b'\n');
+        stream.get_mut().write_all(&frame).await.unwrap();
+
+        let mut line = String::new();
+        stream.read_line(&mut line).await.unwrap();
+        let response: Value = serde_json::from_str(&line).unwrap();
+        assert_matches_schema(schema, &response);
+        response
+    }
+
+    /// Binds the local API in a temp dir, as `serve` does at the real path
+    async fn connect_to_server(dir: &tempfile::TempDir) -> BufReader<UnixStream> {
+        let socket_path = dir.path().join("api.sock");
+        let listener = bind_unix_socket(&socket_path).await.unwrap();
+        tokio::spawn(async move {
+            let _ = accept_connections(listener).await.map_err(|e| e.to_string());
+        });
+        BufReader::new(UnixStream::connect(&socket_path).await.unwrap())
+    }
+
+    /// Answers one ticket POST with the given status line and body, returning the raw request
+    async fn fake_ticket_backend(listener: TcpListener, status: &'static str, body: &'static str) -> String {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buffer = [0u8; 8192];
+        loop {
+            let read = stream.read(&mut buffer).await.unwrap();
+            request.extend_from_slice(&buffer[..read]);
+            let text = String::from_utf8_lossy(&request).to_string();
+            if let Some(end) = text.find("\r\n\r\n") {
+                let length = text[..end]
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())
+                            .flatten()
+                    })
+                    .unwrap_or(0);
+                if request.len() >= end + 4 + length || read == 0 {
+                    break;
+                }
+            }
+        }
+
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+        String::from_utf8_lossy(&request).to_string()
+    }
+
+    #[tokio::test]
+    async fn read_commands_match_the_schema() {
+        let _settings = use_settings(json!({})).await;
+        let schema = schema();
+        let dir = tempfile::tempdir().unwrap();
+        let mut stream = connect_to_server(&dir).await;
+
+        let status = exchange(&schema, &mut stream, json!({ "version": 1, "id": 1, "command": { "type": "get_status" } })).await;
+        assert_eq!(status["id"], 1);
+        assert_eq!(status["data"]["device_id"], "device-test");
+        assert_eq!(status["data"]["registered"], true);
+
+        let health = exchange(&schema, &mut stream, json!({ "version": 1, "id": 2, "command": { "type": "get_health" } })).await;
+        assert_eq!(health["data"]["heartbeat_stale"], true);
+        assert_eq!(health["data"]["healthy"], false);
+
+        // Outside the schema, but the agent still has to answer with a well-formed error
+        let unknown = send_unchecked(&schema, &mut stream, json!({ "version": 1, "id": 3, "command": { "type": "get_secrets" } }))
+            .await
+            .to_string();
+        assert!(unknown.contains("Invalid command"), "{}", unknown);
+    }
+
+    #[tokio::test]
+    async fn old_protocol_versions_are_refused() {
+        let _settings = use_settings(json!({})).await;
+        let schema = schema();
+        let dir = tempfile::tempdir().unwrap();
+        let mut stream = connect_to_server(&dir).await;
+
+        let response = send_unchecked(&schema, &mut stream, json!({ "version": 0, "id": 4, "command": { "type": "get_status" } })).await;
+        assert_eq!(response["version"], LOCAL_API_VERSION);
+        assert!(response["error"].as_str().unwrap().contains("Unsupported local API version"));
+    }
+
+    #[tokio::test]
+    async fn create_ticket_posts_to_the_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        // Whoever runs the tests is who the kernel reports on the socket, so allow them
+        let uid = std::fs::metadata(dir.path()).unwrap().uid();
+
+        let backend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api_host = format!("http://{}", backend.local_addr().unwrap());
+        let _settings = use_settings(json!({ "api_host": api_host, "local_api_action_uids": [uid] })).await;
+        let backend = tokio::spawn(fake_ticket_backend(backend, "200 OK", r#"{"data":"T-100"}"#));
+
+        let schema = schema();
+        let mut stream = connect_to_server(&dir).await;
+        let response = exchange(
+            &schema,
+            &mut stream,
+            json!({
+                "version": 1,
+                "id": 5,
+                "command": {
+                    "type": "create_ticket",
+                    "params": { "summary": "Printer offline", "description": "Since this morning", "urgency": "2" }
+                }
+            }),
+        )
+        .await;
+        assert_eq!(response["data"]["status"], "created", "{}", response);
+        assert_eq!(response["data"]["ticket_id"], "T-100", "{}", response);
+
+        let request = backend.await.unwrap();
+        assert!(request.starts_with("POST /v1.0/ticket/create "), "{}", request);
+        assert!(request.to_lowercase().contains("x-device-id: device-test"));
+        assert!(request.contains("Printer offline"));
+    }
+
+    #[tokio::test]
+    async fn create_ticket_is_queued_when_the_backend_is_down() {
+        let dir = tempfile::tempdir().unwrap();
+        let uid = std::fs::metadata(dir.path()).unwrap().uid();
+
+        let backend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api_host = format!("http://{}", backend.local_addr().unwrap());
+        let _settings = use_settings(json!({ "api_host": api_host, "local_api_action_uids": [uid] })).await;
+        let backend = tokio::spawn(fake_ticket_backend(
+            backend,
+            "503 Service Unavailable",
+            r#"{"error":{"message":"Down for maintenance"}}"#,
+        ));
+
+        let schema = schema();
+        let mut stream = connect_to_server(&dir).await;
+        let response = exchange(
+            &schema,
+            &mut stream,
+            json!({
+                "version": 1,
+                "id": 8,
+                "command": { "type": "create_ticket", "params": { "summary": "VPN drops every hour" } }
+            }),
+        )
+        .await;
+        assert_eq!(response["data"]["status"], "queued", "{}", response);
+        assert!(response["data"]["outbox_id"].as_str().is_some_and(|id| !id.is_empty()));
+        assert!(response["data"].get("ticket_id").is_none());
+
+        backend.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn actions_are_refused_for_peers_not_on_the_allowlist() {
+        let _settings = use_settings(json!({ "local_api_action_uids": [1500] })).await;
+        let schema = schema();
+
+        // The kernel reports the test's own uid on a real socket, so drive a connection as someone else
+        let (client, server) = UnixStream::pair().unwrap();
+        let peer = PeerInfo {
+            uid: Some(1501),
+            pid: None,
+            sid: None,
+            admin: false,
+            interactive: true,
+        };
+        tokio::spawn(handle_connection(server, peer));
+        let mut stream = BufReader::new(client);
+
+        let refused = exchange(
+            &schema,
+            &mut stream,
+            json!({ "version": 1, "id": 6, "command": { "type": "create_ticket", "params": { "summary": "Not mine to file" } } }),
+        )
+        .await;
+        assert_eq!(refused["error"], "Permission denied");
+
+        // Reads stay open to everyone
+        let status = exchange(&schema, &mut stream, json!({ "version": 1, "id": 7, "command": { "type": "get_status" } })).await;
+        assert!(status.get("error").is_none(), "{}", status);
+    }
+}
+
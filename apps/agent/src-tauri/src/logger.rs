@@ -2,7 +2,7 @@ use chrono::Local;
 use std::fs::{self, OpenOptions};
 use std::io::Write;
 use std::path::PathBuf;
-use std::sync::Mutex;
+use std::sync::{Mutex, OnceLock};
 
 use crate::device_manager::get_config_dir;
 
@@ -13,6 +13,9 @@ const MAX_ROTATED_FILES: usize = 5;
 // Global mutex to ensure thread-safe log rotation
 static LOG_MUTEX: Mutex<()> = Mutex::new(());
 
+// Set by the tray, which runs as the signed-in user and can't write the service's log
+static USER_LOGS_DIR: OnceLock<PathBuf> = OnceLock::new();
+
 #[derive(Debug, Clone)]
 pub enum LogLevel {
     Info,
@@ -40,17 +43,29 @@ impl From<String> for LogLevel {
     }
 }
 
-fn get_logs_dir() -> PathBuf {
+/// The service's logs, shared by the whole machine
+pub fn get_logs_dir() -> PathBuf {
     let config_dir = get_config_dir();
     config_dir.join("logs")
 }
 
+/// Sends this process's log to a directory of its own instead of the service's
+pub fn use_user_logs_dir(dir: PathBuf) {
+    let _ = USER_LOGS_DIR.set(dir);
+}
+
+// Where this process writes, its own directory when it has one
+fn current_logs_dir() -> PathBuf {
+    USER_LOGS_DIR.get().cloned().unwrap_or_else(get_logs_dir)
+}
+
 fn get_log_filename() -> String {
     format!("runtime_{}.log", VERSION)
 }
 
-fn get_log_path() -> PathBuf {
-    get_logs_dir().join(get_log_filename())
+/// The log this process writes to
+pub fn get_log_path() -> PathBuf {
+    current_logs_dir().join(get_log_filename())
 }
 
 // Rotate log file if it exceeds size limit
@@ -61,8 +76,8 @@ fn check_and_rotate_log() -> Result<(), Box<dyn std::error::Error>> {
         if metadata.len() > MAX_LOG_SIZE_BYTES {
             // Rotate existing logs (shift .4 -> .5, .3 -> .4, etc.)
             for i in (1..MAX_ROTATED_FILES).rev() {
-                let old_file = get_logs_dir().join(format!("{}.{}", get_log_filename(), i));
-                let new_file = get_logs_dir().join(format!("{}.{}", get_log_filename(), i + 1));
+                let old_file = current_logs_dir().join(format!("{}.{}", get_log_filename(), i));
+                let new_file = current_logs_dir().join(format!("{}.{}", get_log_filename(), i + 1));
 
                 if old_file.exists() {
                     let _ = fs::rename(old_file, new_file);
@@ -70,7 +85,7 @@ fn check_and_rotate_log() -> Result<(), Box<dyn std::error::Error>> {
             }
 
             // Move current log to .1
-            let rotated = get_logs_dir().join(format!("{}.1", get_log_filename()));
+            let rotated = current_logs_dir().join(format!("{}.1", get_log_filename()));
             fs::rename(&log_path, rotated)?;
         }
     }
@@ -80,7 +95,7 @@ fn check_and_rotate_log() -> Result<(), Box<dyn std::error::Error>> {
 
 pub fn log_message(level: LogLevel, message: &str) -> Result<(), Box<dyn std::error::Error>> {
     // Acquire mutex to ensure thread-safe rotation and writing
-    let _lock = LOG_MUTEX.lock().unwrap();
+    let _lock = LOG_MUTEX.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
 
     let log_path = get_log_path();
 
@@ -107,8 +122,11 @@ pub fn log_message(level: LogLevel, message: &str) -> Result<(), Box<dyn std::er
     Ok(())
 }
 
+/// Appends to the log, a line that can't be written is dropped rather than taking the agent down
 #[tauri::command]
 pub fn log_to_file(level: String, message: String) {
     let log_level = LogLevel::from(level);
-    log_message(log_level, &message).expect("File could not be written to")
+    if let Err(e) = log_message(log_level, &message) {
+        eprintln!("Failed to write log line: {}", e);
+    }
 }
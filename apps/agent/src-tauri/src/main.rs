@@ -2,5 +2,10 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if agent_lib::cli::is_cli_invocation(&args) {
+        std::process::exit(agent_lib::cli::run(args));
+    }
+
     agent_lib::run()
 }
@@ -0,0 +1,700 @@
+use crate::alerts::{process_sample, AlertEngine};
+use crate::device_manager::{get_config_dir, get_settings};
+use crate::logger::log_to_file;
+use crate::system_context::get_disks;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tokio::time::{interval, interval_at, Duration, Instant};
+
+pub const DEFAULT_METRICS_INTERVAL_SECS: u64 = 60;
+pub const DEFAULT_METRICS_RETENTION_HOURS: u64 = 24;
+
+// Sampling faster than this costs more than it tells us
+const MIN_METRICS_INTERVAL_SECS: u64 = 10;
+
+// Old samples are only dropped every so often, rewriting the history on every append is wasteful
+const PRUNE_EVERY_SAMPLES: u32 = 60;
+
+static METRICS_LOCK: Mutex<()> = Mutex::const_new(());
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DiskUsage {
+    pub mount: String,
+    pub used_percent: f64,
+}
+
+/// One reading of the machine, fields are None where the platform doesn't expose them
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MetricSample {
+    pub at: i64,
+    pub cpu_percent: Option<f64>,
+    pub memory_percent: Option<f64>,
+    pub memory_used_bytes: Option<u64>,
+    pub memory_total_bytes: Option<u64>,
+    pub swap_percent: Option<f64>,
+    pub load_1: Option<f64>,
+    pub load_5: Option<f64>,
+    pub load_15: Option<f64>,
+    pub disks: Vec<DiskUsage>,
+    pub disk_read_bps: Option<f64>,
+    pub disk_write_bps: Option<f64>,
+    pub net_rx_bps: Option<f64>,
+    pub net_tx_bps: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Aggregate {
+    pub min: f64,
+    pub avg: f64,
+    pub max: f64,
+    pub p95: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DiskSummary {
+    pub mount: String,
+    pub used_percent: Aggregate,
+}
+
+/// Samples from one window boiled down for the heartbeat
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MetricsSummary {
+    pub from: i64,
+    pub to: i64,
+    pub samples: usize,
+    pub cpu_percent: Option<Aggregate>,
+    pub memory_percent: Option<Aggregate>,
+    pub swap_percent: Option<Aggregate>,
+    pub load_1: Option<Aggregate>,
+    pub disk_read_bps: Option<Aggregate>,
+    pub disk_write_bps: Option<Aggregate>,
+    pub net_rx_bps: Option<Aggregate>,
+    pub net_tx_bps: Option<Aggregate>,
+    pub disks: Vec<DiskSummary>,
+}
+
+/// Cumulative kernel counters, rates come from the difference between two readings
+#[derive(Debug, Clone)]
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+struct Counters {
+    cpu_total: u64,
+    cpu_idle: u64,
+    disk_read_bytes: u64,
+    disk_write_bytes: u64,
+    net_rx_bytes: u64,
+    net_tx_bytes: u64,
+}
+
+pub fn get_metrics_history_path() -> PathBuf {
+    get_config_dir().join("metrics.jsonl")
+}
+
+/// The first line of /proc/stat as total and idle jiffies
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_cpu_times(stat: &str) -> Option<(u64, u64)> {
+    // cpu  user nice system idle iowait irq softirq steal guest guest_nice
+    let values: Vec<u64> = stat
+        .lines()
+        .next()?
+        .split_whitespace()
+        .skip(1)
+        .filter_map(|v| v.parse().ok())
+        .collect();
+    if values.len() < 5 {
+        return None;
+    }
+
+    // Guest time is already counted in user time
+    let total = values.iter().take(8).sum();
+    let idle = values[3] + values[4];
+    Some((total, idle))
+}
+
+/// Bytes read and written according to /proc/diskstats, counting only devices `is_physical` accepts
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_disk_io_bytes(diskstats: &str, is_physical: impl Fn(&str) -> bool) -> (u64, u64) {
+    let mut read = 0;
+    let mut written = 0;
+
+    for line in diskstats.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 10 {
+            continue;
+        }
+
+        // Device mapper and software RAID pass their I/O on to the disks underneath, which are
+        // counted already
+        let name = fields[2];
+        if ["loop", "ram", "zram", "dm-", "md"]
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+            || !is_physical(name)
+        {
+            continue;
+        }
+
+        // Sectors are always 512 bytes here regardless of the device
+        read += fields[5].parse::<u64>().unwrap_or(0) * 512;
+        written += fields[9].parse::<u64>().unwrap_or(0) * 512;
+    }
+
+    (read, written)
+}
+
+// Only whole disks, partitions would count the same I/O twice, and nothing stacked on other
+// devices (a device with slaves) whatever it's called
+#[cfg(target_os = "linux")]
+fn is_physical_disk(name: &str) -> bool {
+    let device = std::path::Path::new("/sys/block").join(name);
+    device.exists()
+        && std::fs::read_dir(device.join("slaves"))
+            .map(|mut slaves| slaves.next().is_none())
+            .unwrap_or(true)
+}
+
+/// Bytes received and sent on every interface but loopback according to /proc/net/dev
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_network_bytes(dev: &str) -> (u64, u64) {
+    let mut received = 0;
+    let mut sent = 0;
+
+    for line in dev.lines().skip(2) {
+        let (name, counters) = match line.split_once(':') {
+            Some(parts) => parts,
+            None => continue,
+        };
+        if name.trim() == "lo" {
+            continue;
+        }
+
+        let fields: Vec<u64> = counters
+            .split_whitespace()
+            .filter_map(|v| v.parse().ok())
+            .collect();
+        if fields.len() >= 9 {
+            received += fields[0];
+            sent += fields[8];
+        }
+    }
+
+    (received, sent)
+}
+
+/// Memory and swap use from /proc/meminfo
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_memory(meminfo: &str, sample: &mut MetricSample) {
+    // Values are in KiB
+    let value = |key: &str| -> Option<u64> {
+        meminfo
+            .lines()
+            .find(|line| line.starts_with(key))?
+            .split_whitespace()
+            .nth(1)?
+            .parse::<u64>()
+            .ok()
+            .map(|kib| kib * 1024)
+    };
+
+    if let (Some(total), Some(available)) = (value("MemTotal:"), value("MemAvailable:")) {
+        if total > 0 {
+            let used = total.saturating_sub(available);
+            sample.memory_total_bytes = Some(total);
+            sample.memory_used_bytes = Some(used);
+            sample.memory_percent = Some(used as f64 * 100.0 / total as f64);
+        }
+    }
+
+    if let (Some(total), Some(free)) = (value("SwapTotal:"), value("SwapFree:")) {
+        sample.swap_percent = Some(if total == 0 {
+            0.0
+        } else {
+            total.saturating_sub(free) as f64 * 100.0 / total as f64
+        });
+    }
+}
+
+/// The 1, 5 and 15 minute load averages from /proc/loadavg
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_load(loadavg: &str, sample: &mut MetricSample) {
+    let mut values = loadavg.split_whitespace().map(|v| v.parse::<f64>().ok());
+    sample.load_1 = values.next().flatten();
+    sample.load_5 = values.next().flatten();
+    sample.load_15 = values.next().flatten();
+}
+
+#[cfg(target_os = "linux")]
+fn read_proc(name: &str) -> Option<String> {
+    std::fs::read_to_string(std::path::Path::new("/proc").join(name)).ok()
+}
+
+#[cfg(target_os = "linux")]
+fn read_counters() -> Option<Counters> {
+    let (cpu_total, cpu_idle) = parse_cpu_times(&read_proc("stat")?)?;
+    let (disk_read_bytes, disk_write_bytes) = read_proc("diskstats")
+        .map(|diskstats| parse_disk_io_bytes(&diskstats, is_physical_disk))
+        .unwrap_or_default();
+    let (net_rx_bytes, net_tx_bytes) = read_proc("net/dev")
+        .map(|dev| parse_network_bytes(&dev))
+        .unwrap_or_default();
+    Some(Counters {
+        cpu_total,
+        cpu_idle,
+        disk_read_bytes,
+        disk_write_bytes,
+        net_rx_bytes,
+        net_tx_bytes,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_counters() -> Option<Counters> {
+    None
+}
+
+/// Takes readings and turns counter deltas into rates between calls
+#[derive(Default)]
+pub struct Sampler {
+    previous: Option<(Instant, Counters)>,
+}
+
+impl Sampler {
+    pub fn sample(&mut self) -> MetricSample {
+        let mut sample = MetricSample {
+            at: chrono::Utc::now().timestamp(),
+            ..Default::default()
+        };
+
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(meminfo) = read_proc("meminfo") {
+                parse_memory(&meminfo, &mut sample);
+            }
+            if let Some(loadavg) = read_proc("loadavg") {
+                parse_load(&loadavg, &mut sample);
+            }
+        }
+
+        sample.disks = get_disks()
+            .into_iter()
+            .filter(|disk| disk.total_bytes > 0)
+            .map(|disk| DiskUsage {
+                used_percent: disk.total_bytes.saturating_sub(disk.free_bytes) as f64 * 100.0
+                    / disk.total_bytes as f64,
+                mount: disk.mount,
+            })
+            .collect();
+
+        let now = Instant::now();
+        if let Some(current) = read_counters() {
+            // The first reading only sets the baseline
+            if let Some((then, previous)) = &self.previous {
+                let secs = now.duration_since(*then).as_secs_f64();
+                if secs > 0.0 {
+                    let rate = |now: u64, before: u64| now.saturating_sub(before) as f64 / secs;
+
+                    let total = current.cpu_total.saturating_sub(previous.cpu_total);
+                    let idle = current.cpu_idle.saturating_sub(previous.cpu_idle);
+                    if total > 0 {
+                        sample.cpu_percent =
+                            Some(total.saturating_sub(idle) as f64 * 100.0 / total as f64);
+                    }
+                    sample.disk_read_bps = Some(rate(current.disk_read_bytes, previous.disk_read_bytes));
+                    sample.disk_write_bps = Some(rate(current.disk_write_bytes, previous.disk_write_bytes));
+                    sample.net_rx_bps = Some(rate(current.net_rx_bytes, previous.net_rx_bytes));
+                    sample.net_tx_bps = Some(rate(current.net_tx_bytes, previous.net_tx_bytes));
+                }
+            }
+            self.previous = Some((now, current));
+        }
+
+        sample
+    }
+}
+
+/// Min, average, max and 95th percentile, None when there are no values
+pub fn aggregate(values: &[f64]) -> Option<Aggregate> {
+    if values.is_empty() {
+        return None;
+    }
+
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let p95_index = ((sorted.len() as f64 * 0.95).ceil() as usize).clamp(1, sorted.len()) - 1;
+
+    Some(Aggregate {
+        min: sorted[0],
+        avg: sorted.iter().sum::<f64>() / sorted.len() as f64,
+        max: sorted[sorted.len() - 1],
+        p95: sorted[p95_index],
+    })
+}
+
+/// Boils a run of samples down to aggregates, None when there are no samples
+pub fn summarize(samples: &[MetricSample]) -> Option<MetricsSummary> {
+    let first = samples.first()?;
+    let last = samples.last()?;
+    let series = |value: fn(&MetricSample) -> Option<f64>| -> Option<Aggregate> {
+        aggregate(&samples.iter().filter_map(value).collect::<Vec<f64>>())
+    };
+
+    let mut mounts: Vec<String> = Vec::new();
+    for sample in samples {
+        for disk in &sample.disks {
+            if !mounts.contains(&disk.mount) {
+                mounts.push(disk.mount.clone());
+            }
+        }
+    }
+    let disks = mounts
+        .into_iter()
+        .filter_map(|mount| {
+            let values: Vec<f64> = samples
+                .iter()
+                .filter_map(|s| s.disks.iter().find(|d| d.mount == mount))
+                .map(|d| d.used_percent)
+                .collect();
+            Some(DiskSummary {
+                used_percent: aggregate(&values)?,
+                mount,
+            })
+        })
+        .collect();
+
+    Some(MetricsSummary {
+        from: first.at,
+        to: last.at,
+        samples: samples.len(),
+        cpu_percent: series(|s| s.cpu_percent),
+        memory_percent: series(|s| s.memory_percent),
+        swap_percent: series(|s| s.swap_percent),
+        load_1: series(|s| s.load_1),
+        disk_read_bps: series(|s| s.disk_read_bps),
+        disk_write_bps: series(|s| s.disk_write_bps),
+        net_rx_bps: series(|s| s.net_rx_bps),
+        net_tx_bps: series(|s| s.net_tx_bps),
+        disks,
+    })
+}
+
+async fn append_sample(sample: &MetricSample) -> Result<(), Box<dyn std::error::Error>> {
+    let _lock = METRICS_LOCK.lock().await;
+    let path = get_metrics_history_path();
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let mut line = serde_json::to_vec(sample)?;
+    line.push(b'\n');
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .await?;
+    file.write_all(&line).await?;
+    Ok(())
+}
+
+async fn read_samples() -> Result<Vec<MetricSample>, Box<dyn std::error::Error>> {
+    let path = get_metrics_history_path();
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    // A line cut short by a crash is skipped rather than failing the whole history
+    let content = tokio::fs::read_to_string(&path).await?;
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Samples taken at or after `since`, oldest first
+pub async fn read_history(since: i64) -> Result<Vec<MetricSample>, Box<dyn std::error::Error>> {
+    let _lock = METRICS_LOCK.lock().await;
+    Ok(read_samples()
+        .await?
+        .into_iter()
+        .filter(|sample| sample.at >= since)
+        .collect())
+}
+
+/// Drops samples older than the retention window, returning how many were removed
+pub async fn prune_history(retention_hours: u64) -> Result<usize, Box<dyn std::error::Error>> {
+    let _lock = METRICS_LOCK.lock().await;
+    let samples = read_samples().await?;
+    let cutoff = chrono::Utc::now().timestamp() - (retention_hours * 3600) as i64;
+
+    let kept: Vec<&MetricSample> = samples.iter().filter(|s| s.at >= cutoff).collect();
+    let removed = samples.len() - kept.len();
+    if removed == 0 {
+        return Ok(0);
+    }
+
+    let mut content = Vec::new();
+    for sample in kept {
+        content.extend(serde_json::to_vec(sample)?);
+        content.push(b'\n');
+    }
+
+    let path = get_metrics_history_path();
+    let tmp_path = path.with_extension("tmp");
+    tokio::fs::write(&tmp_path, content).await?;
+    tokio::fs::rename(&tmp_path, &path).await?;
+    Ok(removed)
+}
+
+/// Aggregates of the samples taken in the last `window_secs`
+pub async fn summarize_recent(window_secs: u64) -> Result<Option<MetricsSummary>, Box<dyn std::error::Error>> {
+    let since = chrono::Utc::now().timestamp() - window_secs as i64;
+    Ok(summarize(&read_history(since).await?))
+}
+
+async fn get_metrics_settings() -> (u64, u64) {
+    match get_settings().await {
+        Ok(settings) => (
+            settings
+                .metrics_interval_secs
+                .unwrap_or(DEFAULT_METRICS_INTERVAL_SECS)
+                .max(MIN_METRICS_INTERVAL_SECS),
+            settings
+                .metrics_retention_hours
+                .unwrap_or(DEFAULT_METRICS_RETENTION_HOURS)
+                .max(1),
+        ),
+        Err(_) => (DEFAULT_METRICS_INTERVAL_SECS, DEFAULT_METRICS_RETENTION_HOURS),
+    }
+}
+
+/// Starts the background task that samples metrics into the local history
+pub fn start_metrics_task(running: Arc<AtomicBool>) {
+    tauri::async_runtime::spawn(async move {
+        log_to_file(
+            "INFO".to_string(),
+            "Starting metrics background task".to_string(),
+        );
+
+        let (mut interval_secs, _) = get_metrics_settings().await;
+        let mut sample_interval = interval(Duration::from_secs(interval_secs));
+        let mut sampler = Sampler::default();
+        let mut alert_engine = AlertEngine::default();
+        let mut samples_since_prune = 0;
+
+        while running.load(Ordering::Relaxed) {
+            sample_interval.tick().await;
+
+            // df and /proc reads block, keep them off the async workers
+            let sample = match tauri::async_runtime::spawn_blocking(move || {
+                let sample = sampler.sample();
+                (sampler, sample)
+            })
+            .await
+            {
+                Ok((returned, sample)) => {
+                    sampler = returned;
+                    sample
+                }
+                Err(e) => {
+                    // Rates start over from a fresh baseline on the next tick
+                    log_to_file("ERROR".to_string(), format!("Metrics sampler failed: {}", e));
+                    sampler = Sampler::default();
+                    continue;
+                }
+            };
+
+            if let Err(e) = append_sample(&sample).await.map_err(|e| e.to_string()) {
+                log_to_file(
+                    "WARN".to_string(),
+                    format!("Failed to record metrics sample: {}", e),
+                );
+            }
+
+            if let Err(e) = process_sample(&mut alert_engine, &sample).await.map_err(|e| e.to_string()) {
+                log_to_file(
+                    "WARN".to_string(),
+                    format!("Failed to evaluate alert rules: {}", e),
+                );
+            }
+
+            samples_since_prune += 1;
+            if samples_since_prune >= PRUNE_EVERY_SAMPLES {
+                samples_since_prune = 0;
+                let (new_interval_secs, retention_hours) = get_metrics_settings().await;
+
+                // Settings can change with any heartbeat, pick up a new sampling rate without a restart
+                if new_interval_secs != interval_secs {
+                    log_to_file(
+                        "INFO".to_string(),
+                        format!(
+                            "Metrics interval changed from {}s to {}s",
+                            interval_secs, new_interval_secs
+                        ),
+                    );
+                    interval_secs = new_interval_secs;
+                    let period = Duration::from_secs(interval_secs);
+                    sample_interval = interval_at(Instant::now() + period, period);
+                }
+
+                if let Err(e) = prune_history(retention_hours).await.map_err(|e| e.to_string()) {
+                    log_to_file(
+                        "WARN".to_string(),
+                        format!("Failed to prune metrics history: {}", e),
+                    );
+                }
+            }
+        }
+
+        log_to_file(
+            "INFO".to_string(),
+            "Metrics background task stopped".to_string(),
+        );
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DISKSTATS: &str = "\
+   7       0 loop0 52 0 2148 12 0 0 0 0 0 20 12 0 0 0 0 0 0
+   8       0 sda 1000 10 8000 500 2000 20 16000 900 0 1200 1400 0 0 0 0 0 0
+   8       1 sda1 900 10 7000 400 1900 20 15000 800 0 1100 1200 0 0 0 0 0 0
+ 259       0 nvme0n1 400 0 4000 100 100 0 1000 50 0 150 150 0 0 0 0 0 0
+ 259       1 nvme0n1p1 400 0 4000 100 100 0 1000 50 0 150 150 0 0 0 0 0 0
+ 253       0 dm-0 800 0 6400 300 1800 0 14400 700 0 1000 1000 0 0 0 0 0 0
+   9       0 md0 300 0 2400 0 500 0 4000 0 0 0 0 0 0 0 0 0 0
+ 252       0 vdstack 100 0 800 0 100 0 800 0 0 0 0 0 0 0 0 0 0
+";
+
+    #[test]
+    fn cpu_times_count_iowait_as_idle_and_skip_guest_time() {
+        let stat = "cpu  100 5 50 800 20 3 2 10 40 0\ncpu0 50 2 25 400 10 1 1 5 20 0\n";
+
+        assert_eq!(parse_cpu_times(stat), Some((990, 820)));
+        assert_eq!(parse_cpu_times("cpu  1 2 3\n"), None);
+        assert_eq!(parse_cpu_times(""), None);
+    }
+
+    #[test]
+    fn disk_io_counts_each_physical_disk_once() {
+        // Partitions aren't under /sys/block and vdstack has slaves, so the sysfs check turns them down
+        let is_physical = |name: &str| ["sda", "nvme0n1", "dm-0", "md0", "loop0"].contains(&name);
+
+        let (read, written) = parse_disk_io_bytes(DISKSTATS, is_physical);
+
+        assert_eq!(read, (8000 + 4000) * 512);
+        assert_eq!(written, (16000 + 1000) * 512);
+    }
+
+    #[test]
+    fn disk_io_skips_short_lines() {
+        assert_eq!(parse_disk_io_bytes("   8 0 sda 1 2 3\n", |_| true), (0, 0));
+    }
+
+    #[test]
+    fn network_bytes_skip_loopback() {
+        let dev = "\
+Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo: 5000      50    0    0    0     0          0         0     5000      50    0    0    0     0       0          0
+  eth0: 123456    100    0    0    0     0          0         0    65432      80    0    0    0     0       0          0
+wlan0:1000 10 0 0 0 0 0 0 2000 20 0 0 0 0 0 0
+";
+
+        assert_eq!(parse_network_bytes(dev), (124456, 67432));
+    }
+
+    #[test]
+    fn memory_use_comes_from_available_memory_and_swap() {
+        let meminfo = "\
+MemTotal:        8000000 kB
+MemFree:          500000 kB
+MemAvailable:    2000000 kB
+SwapCached:            0 kB
+SwapTotal:       1000000 kB
+SwapFree:         750000 kB
+";
+        let mut sample = MetricSample::default();
+
+        parse_memory(meminfo, &mut sample);
+
+        assert_eq!(sample.memory_total_bytes, Some(8_000_000 * 1024));
+        assert_eq!(sample.memory_used_bytes, Some(6_000_000 * 1024));
+        assert_eq!(sample.memory_percent, Some(75.0));
+        assert_eq!(sample.swap_percent, Some(25.0));
+    }
+
+    #[test]
+    fn no_swap_reads_as_none_used() {
+        let mut sample = MetricSample::default();
+
+        parse_memory("MemTotal: 0 kB\nMemAvailable: 0 kB\nSwapTotal: 0 kB\nSwapFree: 0 kB\n", &mut sample);
+
+        assert_eq!(sample.memory_percent, None);
+        assert_eq!(sample.swap_percent, Some(0.0));
+    }
+
+    #[test]
+    fn load_averages_are_the_first_three_fields() {
+        let mut sample = MetricSample::default();
+
+        parse_load("0.52 1.05 2.50 3/812 12345\n", &mut sample);
+
+        assert_eq!((sample.load_1, sample.load_5, sample.load_15), (Some(0.52), Some(1.05), Some(2.5)));
+    }
+
+    #[test]
+    fn aggregate_takes_the_nearest_rank_p95() {
+        let values: Vec<f64> = (1..=20).rev().map(f64::from).collect();
+
+        assert_eq!(
+            aggregate(&values),
+            Some(Aggregate { min: 1.0, avg: 10.5, max: 20.0, p95: 19.0 })
+        );
+        assert_eq!(aggregate(&[7.0]).map(|a| a.p95), Some(7.0));
+        assert_eq!(aggregate(&[]), None);
+    }
+
+    #[test]
+    fn summarize_aggregates_each_series_and_disk() {
+        let samples = vec![
+            MetricSample {
+                at: 100,
+                cpu_percent: Some(10.0),
+                disks: vec![DiskUsage { mount: String::from("/"), used_percent: 50.0 }],
+                ..MetricSample::default()
+            },
+            MetricSample {
+                at: 160,
+                cpu_percent: Some(30.0),
+                memory_percent: Some(40.0),
+                disks: vec![
+                    DiskUsage { mount: String::from("/"), used_percent: 60.0 },
+                    DiskUsage { mount: String::from("/data"), used_percent: 10.0 },
+                ],
+                ..MetricSample::default()
+            },
+        ];
+
+        let summary = summarize(&samples).unwrap();
+
+        assert_eq!((summary.from, summary.to, summary.samples), (100, 160, 2));
+        assert_eq!(
+            summary.cpu_percent,
+            Some(Aggregate { min: 10.0, avg: 20.0, max: 30.0, p95: 30.0 })
+        );
+        assert_eq!(summary.memory_percent.map(|a| a.avg), Some(40.0));
+        assert_eq!(summary.swap_percent, None);
+        let disks: Vec<(&str, f64)> = summary
+            .disks
+            .iter()
+            .map(|disk| (disk.mount.as_str(), disk.used_percent.avg))
+            .collect();
+        assert_eq!(disks, vec![("/", 55.0), ("/data", 10.0)]);
+        assert!(summarize(&[]).is_none());
+    }
+}
@@ -0,0 +1,832 @@
+use crate::agent_state::set_pending_tickets;
+use crate::device_manager::{get_config_dir, get_settings};
+use crate::logger::log_to_file;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::{interval, Duration};
+
+#[cfg(not(test))]
+const MAX_OUTBOX_MESSAGES: usize = 500;
+#[cfg(not(test))]
+const MAX_OUTBOX_BYTES: u64 = 50 * 1024 * 1024; // 50MB
+
+// Small enough for tests to fill without writing megabytes
+#[cfg(test)]
+const MAX_OUTBOX_MESSAGES: usize = 5;
+#[cfg(test)]
+const MAX_OUTBOX_BYTES: u64 = 64 * 1024;
+const BASE_RETRY_SECS: i64 = 30;
+const MAX_RETRY_SECS: i64 = 60 * 60;
+const FLUSH_INTERVAL_SECS: u64 = 60;
+const RECEIPT_RETENTION_DAYS: i64 = 7;
+
+// Serializes file operations so eviction never races a retry update
+static OUTBOX_LOCK: Mutex<()> = Mutex::const_new(());
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OutboxPayload {
+    Json {
+        body: Value,
+    },
+    Multipart {
+        fields: Vec<(String, String)>,
+        files: Vec<OutboxFile>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OutboxFile {
+    pub field: String,
+    pub file_name: String,
+    pub mime_type: String,
+    pub data: String, // base64, so the message stays a single self-contained file
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OutboxMessage {
+    pub id: String,
+    pub kind: String,
+    pub path: String,
+    pub payload: OutboxPayload,
+    pub created_at: String,
+    pub attempts: u32,
+    pub next_attempt_at: String,
+    pub last_error: Option<String>,
+    /// Account that queued it, for messages that belong to one user like tickets
+    #[serde(default)]
+    pub owner: Option<String>,
+}
+
+/// Summary of a queued message, without its payload
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OutboxEntry {
+    pub id: String,
+    pub kind: String,
+    pub path: String,
+    pub created_at: String,
+    pub attempts: u32,
+    pub next_attempt_at: String,
+    pub last_error: Option<String>,
+    pub size_bytes: u64,
+}
+
+/// Final result of a message that left the outbox, kept for a while so callers can look it up
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OutboxReceipt {
+    pub id: String,
+    pub kind: String,
+    pub delivered: bool,
+    pub response: Option<String>,
+    pub error: Option<String>,
+    pub completed_at: String,
+}
+
+#[derive(Debug)]
+pub struct DeliveredMessage {
+    pub message: OutboxMessage,
+    pub response: String,
+}
+
+#[derive(Debug, Default)]
+pub struct FlushSummary {
+    pub delivered: Vec<DeliveredMessage>,
+    pub retrying: usize,
+    pub dropped: usize,
+}
+
+pub fn get_outbox_dir() -> PathBuf {
+    get_config_dir().join("outbox")
+}
+
+fn get_receipts_dir() -> PathBuf {
+    get_outbox_dir().join("receipts")
+}
+
+/// Returns true when a request failed because of the network, it may or may not have reached the server
+pub fn is_network_error(e: &reqwest::Error) -> bool {
+    e.is_connect() || e.is_timeout() || e.is_request()
+}
+
+// Queued tickets carry screenshots, so only the agent's own account may read the outbox
+async fn create_outbox_dir() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = get_outbox_dir();
+    tokio::fs::create_dir_all(&dir).await?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700)).await?;
+    }
+
+    #[cfg(target_os = "windows")]
+    tauri::async_runtime::spawn_blocking(move || crate::file_access::restrict_to_system(&dir)).await??;
+
+    Ok(())
+}
+
+/// A fresh message id, sent as x-idempotency-key so the server can drop a resent copy
+pub fn generate_idempotency_key() -> String {
+    use rand::RngCore;
+
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn message_path(message: &OutboxMessage) -> PathBuf {
+    // Prefixing with the creation time keeps a directory listing in oldest-first order
+    let created_nanos = chrono::DateTime::parse_from_rfc3339(&message.created_at)
+        .ok()
+        .and_then(|t| t.timestamp_nanos_opt())
+        .unwrap_or(0);
+    get_outbox_dir().join(format!("{:020}-{}.json", created_nanos, message.id))
+}
+
+fn retry_delay_secs(attempts: u32) -> i64 {
+    let exponential = BASE_RETRY_SECS.saturating_mul(1i64 << attempts.min(16));
+    let jitter = rand::random::<u8>() as i64 % BASE_RETRY_SECS;
+    exponential.min(MAX_RETRY_SECS) + jitter
+}
+
+async fn write_message(message: &OutboxMessage) -> Result<(), Box<dyn std::error::Error>> {
+    write_encoded(message, &serde_json::to_vec(message)?).await
+}
+
+async fn write_encoded(message: &OutboxMessage, content: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    let path = message_path(message);
+    let tmp_path = path.with_extension("tmp");
+
+    create_outbox_dir().await?;
+    tokio::fs::write(&tmp_path, content).await?;
+    tokio::fs::rename(&tmp_path, &path).await?;
+    Ok(())
+}
+
+// Returns queued messages oldest first, with their on-disk size
+async fn read_messages() -> Result<Vec<(OutboxMessage, u64)>, Box<dyn std::error::Error>> {
+    let dir = get_outbox_dir();
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut paths = Vec::new();
+    let mut entries = tokio::fs::read_dir(&dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().map(|e| e == "json").unwrap_or(false) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut messages = Vec::new();
+    for path in paths {
+        let content = match tokio::fs::read(&path).await {
+            Ok(content) => content,
+            Err(_) => continue,
+        };
+        match serde_json::from_slice::<OutboxMessage>(&content) {
+            Ok(message) => messages.push((message, content.len() as u64)),
+            Err(e) => {
+                log_to_file(
+                    String::from("WARN"),
+                    format!("Removing unreadable outbox file {}: {}", path.display(), e),
+                );
+                let _ = tokio::fs::remove_file(&path).await;
+            }
+        }
+    }
+
+    Ok(messages)
+}
+
+// Evicts the oldest messages until the outbox fits, never the one just queued
+async fn enforce_limits(keep_id: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let messages = read_messages().await?;
+    let mut count = messages.len();
+    let mut total_bytes: u64 = messages.iter().map(|(_, size)| size).sum();
+
+    for (message, size) in messages {
+        if count <= MAX_OUTBOX_MESSAGES && total_bytes <= MAX_OUTBOX_BYTES {
+            break;
+        }
+        if message.id == keep_id {
+            continue;
+        }
+
+        log_to_file(
+            String::from("WARN"),
+            format!(
+                "Outbox full, evicting oldest {} message {} created at {}",
+                message.kind, message.id, message.created_at
+            ),
+        );
+        let _ = tokio::fs::remove_file(message_path(&message)).await;
+        count -= 1;
+        total_bytes = total_bytes.saturating_sub(size);
+    }
+
+    Ok(())
+}
+
+/// Attaches a queued payload to a request, as JSON or multipart form data
+pub fn apply_payload(
+    request: reqwest::RequestBuilder,
+    payload: &OutboxPayload,
+) -> Result<reqwest::RequestBuilder, Box<dyn std::error::Error>> {
+    use base64::engine::general_purpose;
+    use base64::Engine;
+
+    match payload {
+        OutboxPayload::Json { body } => Ok(request.json(body)),
+        OutboxPayload::Multipart { fields, files } => {
+            let mut form = reqwest::multipart::Form::new();
+            for (name, value) in fields {
+                form = form.text(name.clone(), value.clone());
+            }
+            for file in files {
+                let bytes = general_purpose::STANDARD.decode(&file.data)?;
+                let part = reqwest::multipart::Part::bytes(bytes)
+                    .file_name(file.file_name.clone())
+                    .mime_str(&file.mime_type)?;
+                form = form.part(file.field.clone(), part);
+            }
+            Ok(request.multipart(form))
+        }
+    }
+}
+
+async fn write_receipt(receipt: &OutboxReceipt) {
+    let dir = get_receipts_dir();
+    let result = async {
+        create_outbox_dir().await?;
+        tokio::fs::create_dir_all(&dir).await?;
+        tokio::fs::write(dir.join(format!("{}.json", receipt.id)), serde_json::to_vec(receipt)?).await?;
+        Ok::<(), Box<dyn std::error::Error>>(())
+    }
+    .await;
+
+    if let Err(e) = result {
+        log_to_file(
+            String::from("WARN"),
+            format!("Failed to write outbox receipt {}: {}", receipt.id, e),
+        );
+    }
+}
+
+/// Looks up the final result of a message that is no longer queued
+pub async fn get_outbox_receipt(
+    id: &str,
+) -> Result<Option<OutboxReceipt>, Box<dyn std::error::Error>> {
+    // Ids are generated hex strings, anything else can't name a receipt
+    if !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Ok(None);
+    }
+
+    match tokio::fs::read(get_receipts_dir().join(format!("{}.json", id))).await {
+        Ok(content) => Ok(Some(serde_json::from_slice(&content)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn prune_receipts() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = get_receipts_dir();
+    if !dir.exists() {
+        return Ok(());
+    }
+
+    let cutoff = std::time::SystemTime::now()
+        - std::time::Duration::from_secs((RECEIPT_RETENTION_DAYS * 24 * 60 * 60) as u64);
+    let mut entries = tokio::fs::read_dir(&dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let expired = entry
+            .metadata()
+            .await
+            .and_then(|m| m.modified())
+            .map(|modified| modified < cutoff)
+            .unwrap_or(false);
+        if expired {
+            let _ = tokio::fs::remove_file(entry.path()).await;
+        }
+    }
+
+    Ok(())
+}
+
+// Callers hold OUTBOX_LOCK so the count matches what is on disk
+async fn publish_pending_tickets() {
+    let pending = match read_messages().await {
+        Ok(messages) => messages
+            .iter()
+            .filter(|(message, _)| message.kind == "ticket")
+            .count(),
+        Err(_) => return,
+    };
+    set_pending_tickets(pending);
+}
+
+async fn enqueue_message(
+    id: String,
+    kind: &str,
+    path: &str,
+    payload: OutboxPayload,
+    owner: Option<String>,
+    replace_queued: bool,
+) -> Result<OutboxMessage, Box<dyn std::error::Error>> {
+    let now = chrono::Utc::now().to_rfc3339();
+    let message = OutboxMessage {
+        id,
+        kind: kind.to_string(),
+        path: path.to_string(),
+        payload,
+        created_at: now.clone(),
+        attempts: 0,
+        next_attempt_at: now,
+        last_error: None,
+        owner,
+    };
+
+    // Evicting everything else still wouldn't make room, refuse it rather than lose it later
+    let content = serde_json::to_vec(&message)?;
+    if content.len() as u64 > MAX_OUTBOX_BYTES {
+        return Err(format!(
+            "{} message is {} bytes, over the {} byte outbox limit",
+            message.kind,
+            content.len(),
+            MAX_OUTBOX_BYTES
+        )
+        .into());
+    }
+
+    let _lock = OUTBOX_LOCK.lock().await;
+
+    if replace_queued {
+        let queued_messages = read_messages().await?;
+        for (queued, _) in queued_messages {
+            if queued.kind == kind {
+                let _ = tokio::fs::remove_file(message_path(&queued)).await;
+            }
+        }
+    }
+
+    write_encoded(&message, &content).await?;
+    enforce_limits(&message.id).await?;
+    publish_pending_tickets().await;
+
+    log_to_file(
+        String::from("INFO"),
+        format!("Queued {} message {} for {}", message.kind, message.id, message.path),
+    );
+
+    Ok(message)
+}
+
+/// Queues an arbitrary payload for delivery to the given API path, on behalf of `owner`.
+/// `id` is the idempotency key, the one already sent when a first attempt may have arrived.
+pub async fn enqueue_payload(
+    id: String,
+    kind: &str,
+    path: &str,
+    payload: OutboxPayload,
+    owner: Option<String>,
+) -> Result<OutboxMessage, Box<dyn std::error::Error>> {
+    enqueue_message(id, kind, path, payload, owner, false).await
+}
+
+/// Queues a JSON message for delivery to the given API path
+pub async fn enqueue(
+    kind: &str,
+    path: &str,
+    body: Value,
+) -> Result<OutboxMessage, Box<dyn std::error::Error>> {
+    enqueue_message(generate_idempotency_key(), kind, path, OutboxPayload::Json { body }, None, false).await
+}
+
+/// Queues a JSON message, dropping any older queued message of the same kind
+pub async fn enqueue_latest(
+    kind: &str,
+    path: &str,
+    body: Value,
+) -> Result<OutboxMessage, Box<dyn std::error::Error>> {
+    enqueue_message(generate_idempotency_key(), kind, path, OutboxPayload::Json { body }, None, true).await
+}
+
+/// Lists queued messages oldest first
+pub async fn list_outbox() -> Result<Vec<OutboxEntry>, Box<dyn std::error::Error>> {
+    let _lock = OUTBOX_LOCK.lock().await;
+
+    Ok(read_messages()
+        .await?
+        .into_iter()
+        .map(|(message, size_bytes)| OutboxEntry {
+            id: message.id,
+            kind: message.kind,
+            path: message.path,
+            created_at: message.created_at,
+            attempts: message.attempts,
+            next_attempt_at: message.next_attempt_at,
+            last_error: message.last_error,
+            size_bytes,
+        })
+        .collect())
+}
+
+async fn remove_message(message: &OutboxMessage) {
+    let _lock = OUTBOX_LOCK.lock().await;
+    let _ = tokio::fs::remove_file(message_path(message)).await;
+}
+
+async fn reschedule_message(message: &mut OutboxMessage, error: String) {
+    let _lock = OUTBOX_LOCK.lock().await;
+
+    // The message may have been evicted while it was being sent
+    if !message_path(message).exists() {
+        return;
+    }
+
+    message.attempts += 1;
+    message.last_error = Some(error);
+    message.next_attempt_at = (chrono::Utc::now()
+        + chrono::Duration::seconds(retry_delay_secs(message.attempts)))
+    .to_rfc3339();
+
+    if let Err(e) = write_message(message).await {
+        log_to_file(
+            String::from("ERROR"),
+            format!("Failed to update outbox message {}: {}", message.id, e),
+        );
+    }
+}
+
+/// Sends every due message oldest first, stopping early if the network is down
+pub async fn flush_outbox() -> Result<FlushSummary, Box<dyn std::error::Error>> {
+    let mut summary = FlushSummary::default();
+
+    if let Err(e) = prune_receipts().await {
+        log_to_file(
+            String::from("WARN"),
+            format!("Failed to prune outbox receipts: {}", e),
+        );
+    }
+
+    let messages = {
+        let _lock = OUTBOX_LOCK.lock().await;
+        read_messages().await?
+    };
+    if messages.is_empty() {
+        return Ok(summary);
+    }
+
+    let settings = get_settings().await?;
+    let device_id = match settings.device_id.clone() {
+        Some(device_id) => device_id,
+        None => return Ok(summary),
+    };
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(60))
+        .build()?;
+    let now = chrono::Utc::now();
+
+    for (mut message, _) in messages {
+        let due = chrono::DateTime::parse_from_rfc3339(&message.next_attempt_at)
+            .map(|t| t <= now)
+            .unwrap_or(true);
+        if !due {
+            continue;
+        }
+
+        let request = client
+            .post(format!("{}{}", settings.api_host, message.path))
+            .header("x-device-id", &device_id)
+            .header("x-site-id", &settings.site_id)
+            .header("x-idempotency-key", &message.id);
+        let request = match apply_payload(request, &message.payload).map_err(|e| e.to_string()) {
+            Ok(request) => request,
+            Err(e) => {
+                log_to_file(
+                    String::from("ERROR"),
+                    format!("Dropping malformed {} message {}: {}", message.kind, message.id, e),
+                );
+                remove_message(&message).await;
+                summary.dropped += 1;
+                continue;
+            }
+        };
+
+        match request.send().await {
+            Ok(response) if response.status().is_success() => {
+                let body = response.text().await.unwrap_or_default();
+                remove_message(&message).await;
+                write_receipt(&OutboxReceipt {
+                    id: message.id.clone(),
+                    kind: message.kind.clone(),
+                    delivered: true,
+                    response: Some(body.clone()),
+                    error: None,
+                    completed_at: chrono::Utc::now().to_rfc3339(),
+                })
+                .await;
+                summary.delivered.push(DeliveredMessage {
+                    message,
+                    response: body,
+                });
+            }
+            Ok(response) => {
+                let status = response.status();
+                let error_text = response.text().await.unwrap_or_default();
+
+                // Client errors other than throttling will never succeed on retry
+                if status.is_client_error()
+                    && status != reqwest::StatusCode::TOO_MANY_REQUESTS
+                    && status != reqwest::StatusCode::REQUEST_TIMEOUT
+                {
+                    log_to_file(
+                        String::from("ERROR"),
+                        format!(
+                            "Dropping {} message {} rejected by server ({}): {}",
+                            message.kind, message.id, status, error_text
+                        ),
+                    );
+                    remove_message(&message).await;
+                    write_receipt(&OutboxReceipt {
+                        id: message.id.clone(),
+                        kind: message.kind.clone(),
+                        delivered: false,
+                        response: None,
+                        error: Some(format!("{}: {}", status, error_text)),
+                        completed_at: chrono::Utc::now().to_rfc3339(),
+                    })
+                    .await;
+                    summary.dropped += 1;
+                } else {
+                    reschedule_message(&mut message, format!("{}: {}", status, error_text)).await;
+                    summary.retrying += 1;
+                }
+            }
+            Err(e) => {
+                let network_down = is_network_error(&e);
+                reschedule_message(&mut message, e.to_string()).await;
+                summary.retrying += 1;
+                if network_down {
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Starts the background task that retries queued messages
+pub fn start_outbox_task<F>(running: Arc<AtomicBool>, on_delivered: F)
+where
+    F: Fn(&DeliveredMessage) + Send + 'static,
+{
+    tauri::async_runtime::spawn(async move {
+        log_to_file(
+            "INFO".to_string(),
+            "Starting outbox background task".to_string(),
+        );
+
+        let mut flush_interval = interval(Duration::from_secs(FLUSH_INTERVAL_SECS));
+
+        while running.load(Ordering::Relaxed) {
+            flush_interval.tick().await;
+
+            match flush_outbox().await {
+                Ok(summary) => {
+                    if !summary.delivered.is_empty() || summary.dropped > 0 {
+                        log_to_file(
+                            "INFO".to_string(),
+                            format!(
+                                "Outbox flush: {} delivered, {} retrying, {} dropped",
+                                summary.delivered.len(),
+                                summary.retrying,
+                                summary.dropped
+                            ),
+                        );
+                    }
+                    for delivered in &summary.delivered {
+                        on_delivered(delivered);
+                    }
+                }
+                Err(e) => {
+                    log_to_file(
+                        "WARN".to_string(),
+                        format!("Failed to flush outbox: {}", e),
+                    );
+                }
+            }
+
+            let _lock = OUTBOX_LOCK.lock().await;
+            publish_pending_tickets().await;
+        }
+
+        log_to_file(
+            "INFO".to_string(),
+            "Outbox background task stopped".to_string(),
+        );
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_manager::test_support::use_settings;
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // The outbox lives in the shared test config dir, callers hold the settings guard
+    async fn empty_outbox() {
+        let _ = tokio::fs::remove_dir_all(get_outbox_dir()).await;
+    }
+
+    /// Answers one request per (status line, body) in order, returning the raw requests
+    async fn fake_backend(listener: TcpListener, responses: Vec<(&'static str, &'static str)>) -> Vec<String> {
+        let mut requests = Vec::new();
+        for (status, body) in responses {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 8192];
+            loop {
+                let read = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some(end) = text.find("\r\n\r\n") {
+                    let length = text[..end]
+                        .lines()
+                        .find_map(|line| {
+                            let (name, value) = line.split_once(':')?;
+                            name.eq_ignore_ascii_case("content-length")
+                                .then(|| value.trim().parse::<usize>().ok())
+                                .flatten()
+                        })
+                        .unwrap_or(0);
+                    if request.len() >= end + 4 + length || read == 0 {
+                        break;
+                    }
+                }
+            }
+
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+            requests.push(String::from_utf8_lossy(&request).to_string());
+        }
+        requests
+    }
+
+    async fn use_backend() -> (TcpListener, tokio::sync::MutexGuard<'static, ()>) {
+        let backend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api_host = format!("http://{}", backend.local_addr().unwrap());
+        let settings = use_settings(json!({ "api_host": api_host })).await;
+        empty_outbox().await;
+        (backend, settings)
+    }
+
+    #[test]
+    fn retry_delay_doubles_up_to_the_cap() {
+        for attempts in 0..20 {
+            let delay = retry_delay_secs(attempts);
+            let expected = (BASE_RETRY_SECS << attempts.min(16)).min(MAX_RETRY_SECS);
+            assert!(delay >= expected && delay < expected + BASE_RETRY_SECS, "{} attempts waited {}s", attempts, delay);
+        }
+    }
+
+    #[tokio::test]
+    async fn messages_are_sent_oldest_first_and_leave_receipts() {
+        let (backend, _settings) = use_backend().await;
+        let first = enqueue("alert", "/v1.0/alerts", json!({ "n": 1 })).await.unwrap();
+        let second = enqueue("alert", "/v1.0/alerts", json!({ "n": 2 })).await.unwrap();
+        let backend = tokio::spawn(fake_backend(
+            backend,
+            vec![("200 OK", r#"{"data":"a"}"#), ("200 OK", r#"{"data":"b"}"#)],
+        ));
+
+        let summary = flush_outbox().await.unwrap();
+
+        let requests = backend.await.unwrap();
+        assert!(requests[0].starts_with("POST /v1.0/alerts "));
+        assert!(requests[0].contains(&format!("x-idempotency-key: {}", first.id)));
+        assert!(requests[0].ends_with(r#"{"n":1}"#));
+        assert!(requests[1].contains(&format!("x-idempotency-key: {}", second.id)));
+        assert!(requests[1].ends_with(r#"{"n":2}"#));
+
+        let delivered: Vec<&str> = summary.delivered.iter().map(|d| d.response.as_str()).collect();
+        assert_eq!(delivered, vec![r#"{"data":"a"}"#, r#"{"data":"b"}"#]);
+        assert!(list_outbox().await.unwrap().is_empty());
+
+        let receipt = get_outbox_receipt(&first.id).await.unwrap().unwrap();
+        assert!(receipt.delivered);
+        assert_eq!(receipt.response.as_deref(), Some(r#"{"data":"a"}"#));
+        assert!(get_outbox_receipt("../settings").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn rejected_messages_are_dropped_and_server_errors_retried_later() {
+        let (backend, _settings) = use_backend().await;
+        let rejected = enqueue("alert", "/v1.0/alerts", json!({ "n": 1 })).await.unwrap();
+        let failed = enqueue("alert", "/v1.0/alerts", json!({ "n": 2 })).await.unwrap();
+        let backend = tokio::spawn(fake_backend(
+            backend,
+            vec![
+                ("400 Bad Request", r#"{"error":"bad"}"#),
+                ("503 Service Unavailable", r#"{"error":"busy"}"#),
+            ],
+        ));
+
+        let summary = flush_outbox().await.unwrap();
+        backend.await.unwrap();
+        assert_eq!((summary.delivered.len(), summary.retrying, summary.dropped), (0, 1, 1));
+
+        let receipt = get_outbox_receipt(&rejected.id).await.unwrap().unwrap();
+        assert!(!receipt.delivered);
+        assert!(receipt.error.unwrap().starts_with("400"));
+        assert!(get_outbox_receipt(&failed.id).await.unwrap().is_none());
+
+        let queued = list_outbox().await.unwrap();
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].id, failed.id);
+        assert_eq!(queued[0].attempts, 1);
+        assert!(queued[0].last_error.as_deref().unwrap().starts_with("503"));
+        let next_attempt = chrono::DateTime::parse_from_rfc3339(&queued[0].next_attempt_at).unwrap();
+        assert!(next_attempt > chrono::Utc::now() + chrono::Duration::seconds(BASE_RETRY_SECS));
+
+        // Not due yet, so nothing is sent
+        let summary = flush_outbox().await.unwrap();
+        assert_eq!((summary.delivered.len(), summary.retrying, summary.dropped), (0, 0, 0));
+    }
+
+    #[tokio::test]
+    async fn an_unreachable_server_stops_the_flush() {
+        let _settings = use_settings(json!({})).await;
+        empty_outbox().await;
+        enqueue("alert", "/v1.0/alerts", json!({ "n": 1 })).await.unwrap();
+        enqueue("alert", "/v1.0/alerts", json!({ "n": 2 })).await.unwrap();
+
+        let summary = flush_outbox().await.unwrap();
+
+        assert_eq!(summary.retrying, 1);
+        let attempts: Vec<u32> = list_outbox().await.unwrap().iter().map(|m| m.attempts).collect();
+        assert_eq!(attempts, vec![1, 0]);
+    }
+
+    #[tokio::test]
+    async fn enqueue_latest_replaces_only_its_own_kind() {
+        let _settings = use_settings(json!({})).await;
+        empty_outbox().await;
+        let alert = enqueue("alert", "/v1.0/alerts", json!({})).await.unwrap();
+        enqueue_latest("heartbeat", "/v1.0/heartbeat", json!({ "n": 1 })).await.unwrap();
+        let latest = enqueue_latest("heartbeat", "/v1.0/heartbeat", json!({ "n": 2 })).await.unwrap();
+
+        let ids: Vec<String> = list_outbox().await.unwrap().into_iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![alert.id, latest.id]);
+    }
+
+    #[tokio::test]
+    async fn a_full_outbox_evicts_the_oldest_messages() {
+        let _settings = use_settings(json!({})).await;
+        empty_outbox().await;
+        let mut ids = Vec::new();
+        for n in 0..MAX_OUTBOX_MESSAGES + 2 {
+            ids.push(enqueue("alert", "/v1.0/alerts", json!({ "n": n })).await.unwrap().id);
+        }
+
+        let queued: Vec<String> = list_outbox().await.unwrap().into_iter().map(|m| m.id).collect();
+        assert_eq!(queued, ids[2..]);
+
+        // Two of these don't fit together, the older one makes way
+        let padding = "x".repeat(MAX_OUTBOX_BYTES as usize / 2);
+        enqueue("ticket", "/v1.0/tickets", json!({ "padding": padding })).await.unwrap();
+        let newest = enqueue("ticket", "/v1.0/tickets", json!({ "padding": padding })).await.unwrap();
+        let queued = list_outbox().await.unwrap();
+        assert_eq!(queued.last().unwrap().id, newest.id);
+        assert_eq!(queued.iter().filter(|m| m.kind == "ticket").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_message_over_the_limit_is_refused_without_evicting_anything() {
+        let _settings = use_settings(json!({})).await;
+        empty_outbox().await;
+        let queued = enqueue("alert", "/v1.0/alerts", json!({})).await.unwrap();
+
+        let padding = "x".repeat(MAX_OUTBOX_BYTES as usize);
+        let error = enqueue("ticket", "/v1.0/tickets", json!({ "padding": padding })).await.unwrap_err();
+
+        assert!(error.to_string().contains("over the"));
+        let ids: Vec<String> = list_outbox().await.unwrap().into_iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![queued.id]);
+    }
+}
@@ -0,0 +1,739 @@
+use crate::agent_state::set_scheduled_reboot;
+use crate::device_manager::get_settings;
+use crate::logger::log_to_file;
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::process::Command;
+use std::sync::Mutex;
+use tokio::time::Duration;
+
+#[cfg(target_os = "windows")]
+use std::os::windows::process::CommandExt;
+
+#[cfg(target_os = "windows")]
+const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+pub const DEFAULT_REBOOT_COUNTDOWN_SECS: u64 = 15 * 60;
+const MAX_REBOOT_COUNTDOWN_SECS: u64 = 24 * 60 * 60;
+
+pub const DEFAULT_REBOOT_DEFERRAL_SECS: u64 = 60 * 60;
+const MAX_REBOOT_DEFERRAL_SECS: u64 = 24 * 60 * 60;
+
+// How often a waiting reboot looks at the clock, postponements and the maintenance window
+const REBOOT_CHECK_SECS: u64 = 15;
+
+// The reboot currently counting down or waiting, only one is scheduled at a time
+static SCHEDULE: Mutex<Option<Schedule>> = Mutex::new(None);
+
+/// Reboots the server schedules as a job
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RebootJob {
+    /// Earliest time to reboot, right away when not set
+    #[serde(default)]
+    pub at: Option<String>,
+    /// How long the user is warned before the reboot, defaults to 15 minutes
+    #[serde(default)]
+    pub countdown_secs: Option<u64>,
+    /// How many times the user may postpone it
+    #[serde(default)]
+    pub max_deferrals: u32,
+    /// How long each postponement lasts, defaults to an hour
+    #[serde(default)]
+    pub deferral_secs: Option<u64>,
+    /// Only reboot while the device's maintenance window is open
+    #[serde(default)]
+    pub maintenance_window: bool,
+    /// Skip the reboot when the OS doesn't need one
+    #[serde(default)]
+    pub only_if_pending: bool,
+    /// Shown to the user with the warning
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// When server-scheduled reboots may happen, in the device's local time
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MaintenanceWindow {
+    /// Days it opens on, e.g. ["sat", "sun"], every day when empty
+    #[serde(default)]
+    pub days: Vec<String>,
+    /// Time it opens, "HH:MM"
+    pub start: String,
+    pub duration_minutes: u32,
+}
+
+/// A reboot the user is being counted down to
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ScheduledReboot {
+    pub run_id: String,
+    pub reboot_at: String,
+    pub deferrals_left: u32,
+    pub message: Option<String>,
+}
+
+/// Whether the OS wants a reboot and why, reported with every heartbeat
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RebootStatus {
+    /// None when the OS gives no way to tell
+    pub pending: Option<bool>,
+    pub reasons: Vec<String>,
+    pub scheduled: Option<ScheduledReboot>,
+}
+
+struct Schedule {
+    run_id: String,
+    reboot_at: chrono::DateTime<chrono::Utc>,
+    deferrals_left: u32,
+    deferral_secs: u64,
+    message: Option<String>,
+    /// Whether the user has been told, the countdown only starts inside the window
+    counting_down: bool,
+}
+
+impl Schedule {
+    fn published(&self) -> Option<ScheduledReboot> {
+        self.counting_down.then(|| ScheduledReboot {
+            run_id: self.run_id.clone(),
+            reboot_at: self.reboot_at.to_rfc3339(),
+            deferrals_left: self.deferrals_left,
+            message: self.message.clone(),
+        })
+    }
+}
+
+/// Clears the schedule however the job ends, including when it's cancelled
+struct ScheduleGuard;
+
+impl Drop for ScheduleGuard {
+    fn drop(&mut self) {
+        if let Ok(mut schedule) = SCHEDULE.lock() {
+            *schedule = None;
+        }
+        set_scheduled_reboot(None);
+    }
+}
+
+/// Changes the schedule, publishing it when what the user sees changed
+fn update_schedule<T>(update: impl FnOnce(&mut Schedule) -> T) -> Option<T> {
+    let mut schedule = SCHEDULE.lock().ok()?;
+    let schedule = schedule.as_mut()?;
+    let before = schedule.published();
+    let result = update(schedule);
+    let after = schedule.published();
+    if after != before {
+        set_scheduled_reboot(after);
+    }
+    Some(result)
+}
+
+/// Per-OS checks for a pending reboot and the means to do one
+pub trait RebootBackend {
+    /// Why the OS wants a reboot, empty when it doesn't, None when it can't tell
+    fn pending_reasons(&self) -> Option<Vec<String>>;
+    /// Asks the OS to reboot shortly, returning once it has agreed to
+    fn reboot(&self, message: &str) -> Result<(), String>;
+}
+
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    let mut command = Command::new(program);
+    command.args(args);
+
+    #[cfg(target_os = "windows")]
+    command.creation_flags(CREATE_NO_WINDOW);
+
+    let output = command.output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Splits a version into its number and text runs so they compare like `sort -V`
+fn version_segments(version: &str) -> Vec<Result<u64, String>> {
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    for c in version.chars() {
+        let in_number = current.chars().next().is_some_and(|first| first.is_ascii_digit());
+        if !current.is_empty() && in_number != c.is_ascii_digit() {
+            segments.push(std::mem::take(&mut current));
+        }
+        current.push(c);
+    }
+    if !current.is_empty() {
+        segments.push(current);
+    }
+    segments
+        .into_iter()
+        .map(|segment| segment.parse::<u64>().map_err(|_| segment))
+        .collect()
+}
+
+/// Orders kernel releases, so 6.8.0-45-generic comes after 6.8.0-9-generic
+fn compare_kernel_versions(a: &str, b: &str) -> Ordering {
+    version_segments(a).cmp(&version_segments(b))
+}
+
+/// Why the running kernel needs a reboot to be replaced, if it does
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn kernel_mismatch(running: &str, installed: &[String]) -> Option<String> {
+    // Without any installed kernels to go on there's nothing to compare
+    let newest = installed
+        .iter()
+        .max_by(|a, b| compare_kernel_versions(a, b))?;
+    if !installed.iter().any(|version| version == running) {
+        return Some(format!("Running kernel {} is no longer installed", running));
+    }
+    if compare_kernel_versions(newest, running) == Ordering::Greater {
+        return Some(format!("Kernel {} is installed, {} is running", newest, running));
+    }
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn installed_kernels() -> Vec<String> {
+    let entries = match std::fs::read_dir("/lib/modules") {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        // Removed kernels can leave their module directory behind, only count ones with an image
+        .filter(|version| {
+            std::path::Path::new(&format!("/boot/vmlinuz-{}", version)).exists()
+                || std::path::Path::new(&format!("/lib/modules/{}/vmlinuz", version)).exists()
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy)]
+pub struct SystemReboot;
+
+impl RebootBackend for SystemReboot {
+    fn pending_reasons(&self) -> Option<Vec<String>> {
+        #[cfg(target_os = "linux")]
+        {
+            let mut reasons = Vec::new();
+
+            // Debian and Ubuntu drop this marker, listing the packages behind it alongside
+            if std::path::Path::new("/var/run/reboot-required").exists() {
+                let packages: Vec<String> = std::fs::read_to_string("/var/run/reboot-required.pkgs")
+                    .unwrap_or_default()
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(String::from)
+                    .collect();
+                if packages.is_empty() {
+                    reasons.push(String::from("Updates require a reboot"));
+                } else {
+                    reasons.push(format!("Updates require a reboot: {}", packages.join(", ")));
+                }
+            }
+
+            if let Ok(running) = std::fs::read_to_string("/proc/sys/kernel/osrelease") {
+                reasons.extend(kernel_mismatch(running.trim(), &installed_kernels()));
+            }
+
+            Some(reasons)
+        }
+
+        #[cfg(target_os = "macos")]
+        {
+            None
+        }
+
+        #[cfg(target_os = "windows")]
+        {
+            let key_exists = |key: &str| run("reg", &["query", key]).is_some();
+            let mut reasons = Vec::new();
+            if key_exists("HKLM\\SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Component Based Servicing\\RebootPending") {
+                reasons.push(String::from("Component servicing requires a reboot"));
+            }
+            if key_exists("HKLM\\SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\WindowsUpdate\\Auto Update\\RebootRequired") {
+                reasons.push(String::from("Windows Update requires a reboot"));
+            }
+            let pending_renames = run(
+                "reg",
+                &[
+                    "query",
+                    "HKLM\\SYSTEM\\CurrentControlSet\\Control\\Session Manager",
+                    "/v",
+                    "PendingFileRenameOperations",
+                ],
+            )
+            .is_some();
+            if pending_renames {
+                reasons.push(String::from("Files are waiting to be replaced on reboot"));
+            }
+            Some(reasons)
+        }
+    }
+
+    fn reboot(&self, message: &str) -> Result<(), String> {
+        // A minute's notice lets the job result reach the outbox and shutdown warn anyone logged in
+        #[cfg(unix)]
+        let output = Command::new("shutdown").args(["-r", "+1", message]).output();
+
+        #[cfg(target_os = "windows")]
+        let output = Command::new("shutdown")
+            .args(["/r", "/t", "60", "/c", message])
+            .creation_flags(CREATE_NO_WINDOW)
+            .output();
+
+        match output {
+            Ok(output) if output.status.success() => Ok(()),
+            Ok(output) => Err(format!(
+                "shutdown failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )),
+            Err(e) => Err(format!("Failed to run shutdown: {}", e)),
+        }
+    }
+}
+
+/// Whether the OS is waiting on a reboot to finish installing updates, None if unknown
+pub fn is_reboot_pending() -> Option<bool> {
+    SystemReboot.pending_reasons().map(|reasons| !reasons.is_empty())
+}
+
+/// Pending reboot details and the schedule, for the heartbeat
+pub fn reboot_status() -> RebootStatus {
+    let reasons = SystemReboot.pending_reasons();
+    RebootStatus {
+        pending: reasons.as_ref().map(|reasons| !reasons.is_empty()),
+        reasons: reasons.unwrap_or_default(),
+        scheduled: SCHEDULE
+            .lock()
+            .ok()
+            .and_then(|schedule| schedule.as_ref().and_then(Schedule::published)),
+    }
+}
+
+async fn reboot_pending<B>(backend: &B) -> Option<bool>
+where
+    B: RebootBackend + Clone + Send + 'static,
+{
+    let backend = backend.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        backend.pending_reasons().map(|reasons| !reasons.is_empty())
+    })
+        .await
+        .ok()
+        .flatten()
+}
+
+fn parse_weekday(day: &str) -> Result<Weekday, String> {
+    day.trim()
+        .parse::<Weekday>()
+        .map_err(|_| format!("Unknown day in maintenance window: {}", day))
+}
+
+/// Checks a maintenance window definition, returning why it can't be used
+pub fn validate_window(window: &MaintenanceWindow) -> Result<(), String> {
+    NaiveTime::parse_from_str(&window.start, "%H:%M")
+        .map_err(|_| format!("Maintenance window start must be HH:MM, got {}", window.start))?;
+    for day in &window.days {
+        parse_weekday(day)?;
+    }
+    if window.duration_minutes == 0 || window.duration_minutes > 7 * 24 * 60 {
+        return Err(String::from("Maintenance window must last between a minute and a week"));
+    }
+    Ok(())
+}
+
+impl MaintenanceWindow {
+    /// Whether the window is open at a local time
+    pub fn contains(&self, now: NaiveDateTime) -> bool {
+        let start = match NaiveTime::parse_from_str(&self.start, "%H:%M") {
+            Ok(start) => start,
+            Err(_) => return false,
+        };
+        let days: Vec<Weekday> = self.days.iter().filter_map(|day| parse_weekday(day).ok()).collect();
+        let duration = chrono::Duration::minutes(self.duration_minutes as i64);
+
+        // A window can run past midnight, so it may have opened on one of the days before
+        let lookback = self.duration_minutes as i64 / (24 * 60) + 1;
+        (0..=lookback).any(|back| {
+            let date = now.date() - chrono::Duration::days(back);
+            let opened = date.and_time(start);
+            (days.is_empty() || days.contains(&date.weekday())) && opened <= now && now < opened + duration
+        })
+    }
+}
+
+/// Whether reboots may happen now, always when no window is configured and never when it is unusable
+async fn maintenance_window_open() -> bool {
+    let window = match get_settings().await.ok().and_then(|settings| settings.maintenance_window) {
+        Some(window) => window,
+        None => return true,
+    };
+    // A broken window was still meant to restrict reboots, hold them until it is fixed
+    if let Err(e) = validate_window(&window) {
+        log_to_file("ERROR".to_string(), format!("Holding reboots, maintenance window is invalid: {}", e));
+        return false;
+    }
+    window.contains(chrono::Local::now().naive_local())
+}
+
+/// Pushes the scheduled reboot back if the job allows another postponement
+pub fn defer_reboot() -> Result<ScheduledReboot, String> {
+    let deferred = update_schedule(|schedule| {
+        if !schedule.counting_down {
+            return Err(String::from("The restart hasn't been announced yet"));
+        }
+        if schedule.deferrals_left == 0 {
+            return Err(String::from("The restart can't be postponed any further"));
+        }
+        schedule.deferrals_left -= 1;
+        schedule.reboot_at += chrono::Duration::seconds(schedule.deferral_secs as i64);
+        schedule
+            .published()
+            .ok_or_else(|| String::from("No restart is scheduled"))
+    });
+
+    match deferred {
+        Some(Ok(scheduled)) => {
+            log_to_file(
+                "INFO".to_string(),
+                format!(
+                    "Restart postponed to {}, {} postponement(s) left",
+                    scheduled.reboot_at, scheduled.deferrals_left
+                ),
+            );
+            Ok(scheduled)
+        }
+        Some(Err(e)) => Err(e),
+        None => Err(String::from("No restart is scheduled")),
+    }
+}
+
+/// Waits out a reboot job's start time, maintenance window and countdown, then reboots.
+/// Returns what happened for the job result, the reboot itself follows a minute later.
+pub async fn run_reboot<B>(run_id: &str, job: &RebootJob, backend: B) -> Result<String, String>
+where
+    B: RebootBackend + Clone + Send + 'static,
+{
+    let reboot_at = match &job.at {
+        Some(at) => chrono::DateTime::parse_from_rfc3339(at)
+            .map_err(|_| format!("Invalid reboot time: {}", at))?
+            .with_timezone(&chrono::Utc),
+        None => chrono::Utc::now(),
+    };
+    let countdown = chrono::Duration::seconds(
+        job.countdown_secs
+            .unwrap_or(DEFAULT_REBOOT_COUNTDOWN_SECS)
+            .min(MAX_REBOOT_COUNTDOWN_SECS) as i64,
+    );
+
+    if job.only_if_pending && reboot_pending(&backend).await == Some(false) {
+        return Ok(String::from("No reboot was pending, skipped"));
+    }
+
+    let _guard = {
+        let mut schedule = SCHEDULE
+            .lock()
+            .map_err(|_| String::from("Reboot schedule is unavailable"))?;
+        if let Some(other) = schedule.as_ref() {
+            return Err(format!("Reboot {} is already scheduled", other.run_id));
+        }
+        *schedule = Some(Schedule {
+            run_id: run_id.to_string(),
+            reboot_at,
+            deferrals_left: job.max_deferrals,
+            deferral_secs: job
+                .deferral_secs
+                .unwrap_or(DEFAULT_REBOOT_DEFERRAL_SECS)
+                .clamp(60, MAX_REBOOT_DEFERRAL_SECS),
+            message: job.message.clone(),
+            counting_down: false,
+        });
+        ScheduleGuard
+    };
+
+    loop {
+        let now = chrono::Utc::now();
+        let window_open = !job.maintenance_window || maintenance_window_open().await;
+
+        let due = update_schedule(|schedule| {
+            if !schedule.counting_down {
+                if window_open && now + countdown >= schedule.reboot_at {
+                    // Whenever the countdown starts, the user gets all of it
+                    schedule.reboot_at = schedule.reboot_at.max(now + countdown);
+                    schedule.counting_down = true;
+                    log_to_file(
+                        "INFO".to_string(),
+                        format!("Reboot {} announced for {}", schedule.run_id, schedule.reboot_at.to_rfc3339()),
+                    );
+                }
+                // With no countdown there's nothing to wait out
+                schedule.counting_down && now >= schedule.reboot_at
+            } else if !window_open {
+                // A postponement can push the reboot past the window, start over in the next one
+                schedule.counting_down = false;
+                log_to_file(
+                    "INFO".to_string(),
+                    format!("Reboot {} waits for the next maintenance window", schedule.run_id),
+                );
+                false
+            } else {
+                now >= schedule.reboot_at
+            }
+        });
+        if due.unwrap_or(false) {
+            break;
+        }
+
+        tokio::time::sleep(Duration::from_secs(REBOOT_CHECK_SECS)).await;
+    }
+
+    // Updates can finish on their own while the user postponed
+    if job.only_if_pending && reboot_pending(&backend).await == Some(false) {
+        return Ok(String::from("Reboot was no longer pending, skipped"));
+    }
+
+    let message = job
+        .message
+        .clone()
+        .unwrap_or_else(|| String::from("Restarting for maintenance"));
+    tauri::async_runtime::spawn_blocking(move || backend.reboot(&message))
+        .await
+        .map_err(|e| e.to_string())??;
+    log_to_file("INFO".to_string(), format!("Reboot {} started", run_id));
+    Ok(String::from("Reboot started"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use std::sync::Arc;
+
+    // Tests that touch the one global schedule take turns
+    static SCHEDULE_TEST_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    #[derive(Clone, Default)]
+    struct FakeReboot {
+        pending: Option<Vec<String>>,
+        fail: bool,
+        reboots: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl RebootBackend for FakeReboot {
+        fn pending_reasons(&self) -> Option<Vec<String>> {
+            self.pending.clone()
+        }
+
+        fn reboot(&self, message: &str) -> Result<(), String> {
+            self.reboots.lock().unwrap().push(message.to_string());
+            if self.fail {
+                return Err(String::from("shutdown failed: access denied"));
+            }
+            Ok(())
+        }
+    }
+
+    // 2026-10-16 is a Friday
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 10, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn window(days: &[&str], start: &str, duration_minutes: u32) -> MaintenanceWindow {
+        MaintenanceWindow {
+            days: days.iter().map(|day| day.to_string()).collect(),
+            start: start.to_string(),
+            duration_minutes,
+        }
+    }
+
+    fn job() -> RebootJob {
+        RebootJob {
+            at: None,
+            countdown_secs: Some(0),
+            max_deferrals: 0,
+            deferral_secs: None,
+            maintenance_window: false,
+            only_if_pending: false,
+            message: None,
+        }
+    }
+
+    fn schedule(counting_down: bool, deferrals_left: u32) -> ScheduleGuard {
+        *SCHEDULE.lock().unwrap() = Some(Schedule {
+            run_id: String::from("run-1"),
+            reboot_at: chrono::Utc::now(),
+            deferrals_left,
+            deferral_secs: 600,
+            message: None,
+            counting_down,
+        });
+        ScheduleGuard
+    }
+
+    #[test]
+    fn window_runs_past_midnight() {
+        let window = window(&[], "23:00", 120);
+        assert!(!window.contains(at(16, 22, 59)));
+        assert!(window.contains(at(16, 23, 0)));
+        assert!(window.contains(at(17, 0, 30)));
+        assert!(!window.contains(at(17, 1, 0)));
+    }
+
+    #[test]
+    fn window_days_are_the_days_it_opens() {
+        let friday_night = window(&["fri"], "23:00", 120);
+        // Still open after midnight into Saturday because it opened on Friday
+        assert!(friday_night.contains(at(17, 0, 30)));
+        assert!(!friday_night.contains(at(17, 23, 30)));
+        assert!(!friday_night.contains(at(16, 0, 30)));
+
+        let weekend = window(&["sat", "sun"], "02:00", 60);
+        assert!(weekend.contains(at(17, 2, 30)));
+        assert!(weekend.contains(at(18, 2, 0)));
+        assert!(!weekend.contains(at(18, 3, 0)));
+        assert!(!weekend.contains(at(19, 2, 30)));
+    }
+
+    #[test]
+    fn window_can_last_several_days() {
+        let weekend = window(&["fri"], "18:00", 60 * 60);
+        assert!(weekend.contains(at(18, 12, 0)));
+        assert!(weekend.contains(at(19, 5, 59)));
+        assert!(!weekend.contains(at(19, 6, 0)));
+    }
+
+    #[test]
+    fn unusable_windows_are_rejected() {
+        assert!(validate_window(&window(&["sat"], "02:00", 60)).is_ok());
+        assert!(validate_window(&window(&[], "2am", 60)).is_err());
+        assert!(validate_window(&window(&["someday"], "02:00", 60)).is_err());
+        assert!(validate_window(&window(&[], "02:00", 0)).is_err());
+        assert!(!window(&[], "2am", 60).contains(at(16, 2, 30)));
+    }
+
+    #[test]
+    fn kernel_versions_compare_numerically() {
+        assert_eq!(compare_kernel_versions("6.8.0-45-generic", "6.8.0-9-generic"), Ordering::Greater);
+        assert_eq!(compare_kernel_versions("6.9.0-1-generic", "6.10.0-1-generic"), Ordering::Less);
+        assert_eq!(compare_kernel_versions("6.8.0-45-generic", "6.8.0-45-generic"), Ordering::Equal);
+    }
+
+    #[test]
+    fn kernel_mismatch_needs_a_newer_or_missing_kernel() {
+        let installed = |versions: &[&str]| versions.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(kernel_mismatch("6.8.0-45-generic", &[]), None);
+        assert_eq!(kernel_mismatch("6.8.0-45-generic", &installed(&["6.8.0-45-generic"])), None);
+        assert_eq!(
+            kernel_mismatch("6.8.0-45-generic", &installed(&["6.8.0-9-generic", "6.8.0-45-generic"])),
+            None
+        );
+        assert_eq!(
+            kernel_mismatch("6.8.0-9-generic", &installed(&["6.8.0-9-generic", "6.8.0-45-generic"])),
+            Some(String::from("Kernel 6.8.0-45-generic is installed, 6.8.0-9-generic is running"))
+        );
+        assert_eq!(
+            kernel_mismatch("6.8.0-9-generic", &installed(&["6.8.0-45-generic"])),
+            Some(String::from("Running kernel 6.8.0-9-generic is no longer installed"))
+        );
+    }
+
+    #[test]
+    fn deferring_needs_an_announced_reboot() {
+        let _lock = SCHEDULE_TEST_LOCK.blocking_lock();
+        assert_eq!(defer_reboot().unwrap_err(), "No restart is scheduled");
+
+        let _schedule = schedule(false, 2);
+        assert_eq!(defer_reboot().unwrap_err(), "The restart hasn't been announced yet");
+    }
+
+    #[test]
+    fn deferrals_run_out() {
+        let _lock = SCHEDULE_TEST_LOCK.blocking_lock();
+        let _schedule = schedule(true, 2);
+        let reboot_at =
+            |scheduled: &ScheduledReboot| chrono::DateTime::parse_from_rfc3339(&scheduled.reboot_at).unwrap();
+
+        let first = defer_reboot().unwrap();
+        assert_eq!(first.deferrals_left, 1);
+        let second = defer_reboot().unwrap();
+        assert_eq!(second.deferrals_left, 0);
+        assert_eq!(reboot_at(&second) - reboot_at(&first), chrono::Duration::seconds(600));
+
+        assert_eq!(defer_reboot().unwrap_err(), "The restart can't be postponed any further");
+        let unchanged = SCHEDULE.lock().unwrap().as_ref().and_then(Schedule::published).unwrap();
+        assert_eq!(unchanged, second);
+    }
+
+    #[tokio::test]
+    async fn reboot_is_skipped_when_nothing_is_pending() {
+        let _lock = SCHEDULE_TEST_LOCK.lock().await;
+        let backend = FakeReboot {
+            pending: Some(Vec::new()),
+            ..Default::default()
+        };
+        let job = RebootJob {
+            only_if_pending: true,
+            ..job()
+        };
+
+        let result = run_reboot("run-1", &job, backend.clone()).await;
+        assert_eq!(result.unwrap(), "No reboot was pending, skipped");
+        assert!(backend.reboots.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reboot_goes_ahead_when_pending_is_unknown() {
+        let _lock = SCHEDULE_TEST_LOCK.lock().await;
+        let backend = FakeReboot::default();
+        let job = RebootJob {
+            only_if_pending: true,
+            message: Some(String::from("Patch night")),
+            ..job()
+        };
+
+        let result = run_reboot("run-1", &job, backend.clone()).await;
+        assert_eq!(result.unwrap(), "Reboot started");
+        assert_eq!(*backend.reboots.lock().unwrap(), vec![String::from("Patch night")]);
+        assert!(SCHEDULE.lock().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn reboot_failures_fail_the_job() {
+        let _lock = SCHEDULE_TEST_LOCK.lock().await;
+        let backend = FakeReboot {
+            fail: true,
+            ..Default::default()
+        };
+
+        let result = run_reboot("run-1", &job(), backend.clone()).await;
+        assert_eq!(result.unwrap_err(), "shutdown failed: access denied");
+        assert_eq!(*backend.reboots.lock().unwrap(), vec![String::from("Restarting for maintenance")]);
+        assert!(SCHEDULE.lock().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn only_one_reboot_is_scheduled_at_a_time() {
+        let _lock = SCHEDULE_TEST_LOCK.lock().await;
+        let _schedule = schedule(false, 0);
+        let backend = FakeReboot::default();
+
+        let result = run_reboot("run-2", &job(), backend.clone()).await;
+        assert_eq!(result.unwrap_err(), "Reboot run-1 is already scheduled");
+        assert!(backend.reboots.lock().unwrap().is_empty());
+
+        let job = RebootJob {
+            at: Some(String::from("tomorrow")),
+            ..job()
+        };
+        assert_eq!(
+            run_reboot("run-2", &job, backend).await.unwrap_err(),
+            "Invalid reboot time: tomorrow"
+        );
+    }
+}
@@ -0,0 +1,442 @@
+use crate::logger::log_to_file;
+use crate::screenshot::new_screenshot_file;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::codecs::png::{CompressionType, FilterType as PngFilter, PngEncoder};
+use image::imageops::{self, FilterType};
+use image::{Delay, ExtendedColorType, Frame, ImageEncoder, ImageFormat, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use xcap::Monitor;
+
+pub const DEFAULT_RECORDING_SECS: u32 = 10;
+pub const MAX_RECORDING_SECS: u32 = 30;
+pub const DEFAULT_RECORDING_FPS: u32 = 2;
+pub const MAX_RECORDING_FPS: u32 = 5;
+pub const DEFAULT_RECORDING_MAX_BYTES: usize = 8 * 1024 * 1024;
+pub const DEFAULT_RECORDING_MAX_DIMENSION: u32 = 1280;
+
+// Shrinking stops here, a clip that still doesn't fit is rejected rather than sent unreadable
+const MIN_RECORDING_DIMENSION: u32 = 480;
+
+// The replay buffer stops itself after this long, so the screen isn't recorded indefinitely when
+// nobody saves the clip
+const MAX_REPLAY_BUFFER_SECS: u64 = 10 * 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RecordingOptions {
+    pub monitor_id: Option<u32>,
+    pub duration_secs: Option<u32>,
+    pub fps: Option<u32>,
+    pub max_bytes: Option<usize>,
+    pub max_dimension: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScreenRecording {
+    pub id: String,
+    pub width: u32,
+    pub height: u32,
+    pub frames: usize,
+    pub duration_ms: u64,
+    pub size_bytes: usize,
+}
+
+/// Limits for one recording, with the defaults and caps applied
+#[derive(Debug, Clone, Copy)]
+struct ClipLimits {
+    duration_secs: u32,
+    fps: u32,
+    max_dimension: u32,
+    max_bytes: usize,
+}
+
+impl ClipLimits {
+    fn from_options(options: &RecordingOptions) -> Self {
+        ClipLimits {
+            duration_secs: options
+                .duration_secs
+                .unwrap_or(DEFAULT_RECORDING_SECS)
+                .clamp(1, MAX_RECORDING_SECS),
+            fps: options
+                .fps
+                .unwrap_or(DEFAULT_RECORDING_FPS)
+                .clamp(1, MAX_RECORDING_FPS),
+            max_dimension: options
+                .max_dimension
+                .unwrap_or(DEFAULT_RECORDING_MAX_DIMENSION)
+                .max(MIN_RECORDING_DIMENSION),
+            max_bytes: options.max_bytes.unwrap_or(DEFAULT_RECORDING_MAX_BYTES),
+        }
+    }
+
+    fn frame_interval(&self) -> Duration {
+        Duration::from_millis(1000 / self.fps as u64)
+    }
+
+    fn frame_count(&self) -> usize {
+        (self.duration_secs * self.fps) as usize
+    }
+}
+
+/// Frames captured in the background for "record the last N seconds", oldest first
+struct ReplayBuffer {
+    limits: ClipLimits,
+    // PNG-compressed, a minute of raw frames would be hundreds of megabytes
+    frames: Arc<Mutex<VecDeque<Vec<u8>>>>,
+    stop: Arc<AtomicBool>,
+    capture: JoinHandle<()>,
+}
+
+static REPLAY_BUFFER: Mutex<Option<ReplayBuffer>> = Mutex::new(None);
+
+pub struct EncodedClip {
+    pub bytes: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub frames: usize,
+}
+
+fn pick_monitor(monitor_id: Option<u32>) -> Result<Monitor, Box<dyn std::error::Error>> {
+    let monitors = Monitor::all()?;
+    let monitor = match monitor_id {
+        Some(id) => monitors.into_iter().find(|m| m.id() == id),
+        None => {
+            let primary = monitors.iter().position(|m| m.is_primary()).unwrap_or(0);
+            monitors.into_iter().nth(primary)
+        }
+    };
+
+    monitor.ok_or_else(|| match monitor_id {
+        Some(id) => format!("Monitor {} not found", id).into(),
+        None => "No screenshotable monitors found".into(),
+    })
+}
+
+fn fit_to(image: &RgbaImage, max_dimension: u32) -> RgbaImage {
+    let longest = image.width().max(image.height());
+    if longest <= max_dimension {
+        return image.clone();
+    }
+
+    let scale = max_dimension as f64 / longest as f64;
+    imageops::resize(
+        image,
+        ((image.width() as f64 * scale).round() as u32).max(1),
+        ((image.height() as f64 * scale).round() as u32).max(1),
+        FilterType::Triangle,
+    )
+}
+
+fn encode_gif(frames: &[RgbaImage], frame_ms: u32) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut bytes = Vec::new();
+    {
+        // Quantizing is the slow part, trade a little color accuracy for speed
+        let mut encoder = GifEncoder::new_with_speed(&mut bytes, 20);
+        encoder.set_repeat(Repeat::Infinite)?;
+        for frame in frames {
+            encoder.encode_frame(Frame::from_parts(
+                frame.clone(),
+                0,
+                0,
+                Delay::from_numer_denom_ms(frame_ms, 1),
+            ))?;
+        }
+    }
+    Ok(bytes)
+}
+
+/// Encodes frames as a looping GIF, dropping frames and then resolution until it fits `max_bytes`
+pub fn encode_clip(
+    frames: Vec<RgbaImage>,
+    frame_ms: u32,
+    max_dimension: u32,
+    max_bytes: usize,
+) -> Result<EncodedClip, Box<dyn std::error::Error>> {
+    let mut frames: Vec<RgbaImage> = frames.iter().map(|f| fit_to(f, max_dimension)).collect();
+    let mut frame_ms = frame_ms;
+    let mut dimension = max_dimension;
+
+    loop {
+        let (width, height) = frames
+            .first()
+            .map(|f| f.dimensions())
+            .ok_or("No frames were captured")?;
+        let bytes = encode_gif(&frames, frame_ms)?;
+        if bytes.len() <= max_bytes {
+            return Ok(EncodedClip {
+                bytes,
+                width,
+                height,
+                frames: frames.len(),
+            });
+        }
+
+        if frames.len() > 4 {
+            // Keep every other frame and hold each one twice as long, the clip length stays the same
+            frames = frames.into_iter().step_by(2).collect();
+            frame_ms *= 2;
+        } else if dimension > MIN_RECORDING_DIMENSION {
+            dimension = (dimension * 3 / 4).max(MIN_RECORDING_DIMENSION);
+            frames = frames.iter().map(|f| fit_to(f, dimension)).collect();
+        } else {
+            return Err(format!(
+                "Recording is {} bytes, over the {} byte limit even at the lowest quality",
+                bytes.len(),
+                max_bytes
+            )
+            .into());
+        }
+    }
+}
+
+fn save_clip(
+    frames: Vec<RgbaImage>,
+    limits: &ClipLimits,
+    duration_ms: u64,
+) -> Result<ScreenRecording, Box<dyn std::error::Error>> {
+    let clip = encode_clip(
+        frames,
+        limits.frame_interval().as_millis() as u32,
+        limits.max_dimension,
+        limits.max_bytes,
+    )?;
+
+    let (id, path) = new_screenshot_file("gif")?;
+    std::fs::write(path, &clip.bytes)?;
+
+    Ok(ScreenRecording {
+        id,
+        width: clip.width,
+        height: clip.height,
+        frames: clip.frames,
+        duration_ms,
+        size_bytes: clip.bytes.len(),
+    })
+}
+
+/// Records one monitor for a few seconds at a low frame rate and stores it as a GIF
+pub fn record_screen(options: &RecordingOptions) -> Result<ScreenRecording, Box<dyn std::error::Error>> {
+    let limits = ClipLimits::from_options(options);
+    let monitor = pick_monitor(options.monitor_id)?;
+    let frame_interval = limits.frame_interval();
+
+    let started = Instant::now();
+    let mut frames = Vec::with_capacity(limits.frame_count());
+    for i in 0..limits.frame_count() {
+        // Downscale as we go so a 4K monitor doesn't hold seconds of raw frames in memory
+        frames.push(fit_to(&monitor.capture_image()?, limits.max_dimension));
+
+        let next_frame = frame_interval * (i as u32 + 1);
+        if let Some(wait) = next_frame.checked_sub(started.elapsed()) {
+            std::thread::sleep(wait);
+        }
+    }
+    let duration_ms = started.elapsed().as_millis() as u64;
+
+    save_clip(frames, &limits, duration_ms)
+}
+
+fn compress_frame(frame: &RgbaImage) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut bytes = Vec::new();
+    PngEncoder::new_with_quality(&mut bytes, CompressionType::Fast, PngFilter::NoFilter).write_image(
+        frame.as_raw(),
+        frame.width(),
+        frame.height(),
+        ExtendedColorType::Rgba8,
+    )?;
+    Ok(bytes)
+}
+
+/// Keeps capturing into the buffer, dropping the oldest frame once it holds a full clip
+fn run_replay_capture(
+    monitor_id: Option<u32>,
+    limits: ClipLimits,
+    frames: Arc<Mutex<VecDeque<Vec<u8>>>>,
+    stop: Arc<AtomicBool>,
+) {
+    // Monitor handles aren't Send on every platform, so this thread looks up its own
+    let monitor = match pick_monitor(monitor_id) {
+        Ok(monitor) => monitor,
+        Err(e) => {
+            log_to_file(String::from("ERROR"), format!("Replay recording failed to start: {}", e));
+            return;
+        }
+    };
+
+    let frame_interval = limits.frame_interval();
+    let started = Instant::now();
+    let mut captured: u32 = 0;
+    while !stop.load(Ordering::Relaxed) {
+        if started.elapsed() >= Duration::from_secs(MAX_REPLAY_BUFFER_SECS) {
+            log_to_file(
+                String::from("INFO"),
+                String::from("Replay recording stopped itself, it was never saved"),
+            );
+            frames.lock().unwrap().clear();
+            return;
+        }
+
+        let frame = monitor
+            .capture_image()
+            .map_err(|e| e.to_string())
+            .and_then(|image| compress_frame(&fit_to(&image, limits.max_dimension)).map_err(|e| e.to_string()));
+        match frame {
+            Ok(frame) => {
+                let mut frames = frames.lock().unwrap();
+                frames.push_back(frame);
+                while frames.len() > limits.frame_count() {
+                    frames.pop_front();
+                }
+            }
+            Err(e) => {
+                log_to_file(String::from("ERROR"), format!("Replay recording stopped: {}", e));
+                return;
+            }
+        }
+
+        captured += 1;
+        if let Some(wait) = (frame_interval * captured).checked_sub(started.elapsed()) {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+/// Starts capturing in the background so the last `duration_secs` can be saved once the problem
+/// has happened, instead of having to reproduce it while recording
+pub fn start_replay_buffer(options: &RecordingOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let mut buffer = REPLAY_BUFFER.lock().map_err(|_| "Replay buffer lock poisoned")?;
+    if buffer.as_ref().map(|b| !b.capture.is_finished()).unwrap_or(false) {
+        return Err("A replay recording is already running".into());
+    }
+
+    // Fail here rather than in the capture thread when the monitor doesn't exist
+    pick_monitor(options.monitor_id)?;
+
+    let limits = ClipLimits::from_options(options);
+    let frames = Arc::new(Mutex::new(VecDeque::with_capacity(limits.frame_count())));
+    let stop = Arc::new(AtomicBool::new(false));
+    let capture = {
+        let (monitor_id, frames, stop) = (options.monitor_id, frames.clone(), stop.clone());
+        std::thread::spawn(move || run_replay_capture(monitor_id, limits, frames, stop))
+    };
+
+    *buffer = Some(ReplayBuffer {
+        limits,
+        frames,
+        stop,
+        capture,
+    });
+    Ok(())
+}
+
+// Takes the buffer out and waits for its capture thread, so no frame lands after this returns
+fn take_replay_buffer() -> Option<(ClipLimits, VecDeque<Vec<u8>>)> {
+    let buffer = REPLAY_BUFFER.lock().ok()?.take()?;
+    buffer.stop.store(true, Ordering::Relaxed);
+    let _ = buffer.capture.join();
+
+    let frames = std::mem::take(&mut *buffer.frames.lock().ok()?);
+    Some((buffer.limits, frames))
+}
+
+/// Whether the replay buffer is capturing
+pub fn replay_buffer_running() -> bool {
+    REPLAY_BUFFER
+        .lock()
+        .map(|buffer| buffer.as_ref().map(|b| !b.capture.is_finished()).unwrap_or(false))
+        .unwrap_or(false)
+}
+
+/// Stops the replay buffer and throws its frames away, returning whether one was running
+pub fn stop_replay_buffer() -> bool {
+    take_replay_buffer().is_some()
+}
+
+/// Stops the replay buffer and stores the frames it holds as a GIF
+pub fn save_replay_buffer() -> Result<ScreenRecording, Box<dyn std::error::Error>> {
+    let (limits, frames) = take_replay_buffer().ok_or("No replay recording is running")?;
+    if frames.is_empty() {
+        return Err("The replay recording has no frames yet".into());
+    }
+
+    let frames: Vec<RgbaImage> = frames
+        .iter()
+        .map(|frame| image::load_from_memory_with_format(frame, ImageFormat::Png).map(|image| image.into_rgba8()))
+        .collect::<Result<_, _>>()?;
+    let duration_ms = frames.len() as u64 * limits.frame_interval().as_millis() as u64;
+    save_clip(frames, &limits, duration_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pixels that don't compress, so each frame costs about the same and size tracks frame count and area
+    fn noise_frames(count: usize, width: u32, height: u32) -> Vec<RgbaImage> {
+        let mut seed: u32 = 0x2545_f491;
+        (0..count)
+            .map(|_| {
+                RgbaImage::from_fn(width, height, |_, _| {
+                    seed ^= seed << 13;
+                    seed ^= seed >> 17;
+                    seed ^= seed << 5;
+                    let [r, g, b, _] = seed.to_le_bytes();
+                    image::Rgba([r, g, b, 255])
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_clip_that_fits_keeps_every_frame_and_its_size() {
+        let clip = encode_clip(noise_frames(6, 64, 48), 500, 480, usize::MAX).unwrap();
+
+        assert_eq!(clip.frames, 6);
+        assert_eq!((clip.width, clip.height), (64, 48));
+    }
+
+    #[test]
+    fn frames_are_dropped_before_the_resolution() {
+        let frames = noise_frames(8, 64, 48);
+        let all = encode_gif(&frames, 500).unwrap().len();
+        let half: Vec<RgbaImage> = frames.iter().step_by(2).cloned().collect();
+        let max_bytes = encode_gif(&half, 1000).unwrap().len();
+        assert!(max_bytes < all);
+
+        let clip = encode_clip(frames, 500, 480, max_bytes).unwrap();
+
+        assert_eq!(clip.frames, 4);
+        assert_eq!((clip.width, clip.height), (64, 48));
+        assert!(clip.bytes.len() <= max_bytes);
+    }
+
+    #[test]
+    fn the_resolution_shrinks_once_few_frames_are_left() {
+        let frames = noise_frames(2, 640, 360);
+        let full = encode_gif(&frames, 500).unwrap().len();
+
+        let clip = encode_clip(frames, 500, 640, full - 1).unwrap();
+
+        assert_eq!(clip.frames, 2);
+        assert_eq!((clip.width, clip.height), (MIN_RECORDING_DIMENSION, 270));
+        assert!(clip.bytes.len() < full);
+    }
+
+    #[test]
+    fn a_clip_over_the_limit_at_the_lowest_quality_is_rejected() {
+        let error = encode_clip(noise_frames(2, 640, 360), 500, 640, 1024)
+            .err()
+            .expect("A 1KB limit can't fit 480px of noise");
+
+        assert!(error.to_string().contains("over the 1024 byte limit"));
+    }
+
+    #[test]
+    fn encoding_nothing_is_an_error() {
+        assert!(encode_clip(Vec::new(), 500, 640, usize::MAX).is_err());
+    }
+}
@@ -0,0 +1,335 @@
+use image::{imageops, RgbaImage};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use xcap::Monitor;
+
+pub const DEFAULT_SCREENSHOT_TTL_MINUTES: u64 = 60;
+
+// Stored files are named `{id}.{extension}`, stills are PNGs and recordings GIFs
+const SCREENSHOT_EXTENSIONS: [&str; 2] = ["png", "gif"];
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MonitorInfo {
+    pub id: u32,
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub scale_factor: f32,
+    pub is_primary: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScreenCapture {
+    /// None for the stitched image of every captured monitor
+    pub monitor: Option<MonitorInfo>,
+    pub id: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScreenshotResult {
+    pub captures: Vec<ScreenCapture>,
+    pub stitched: Option<ScreenCapture>,
+}
+
+/// Per-user directory owned by the agent that holds every screenshot until it's sent or expires
+pub fn get_screenshot_dir() -> PathBuf {
+    // Prefer the per-user runtime dir on Linux, it's private and cleared on logout
+    let base = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_dir())
+        .unwrap_or_else(std::env::temp_dir);
+    base.join(format!("mspagent-screenshots-{}", whoami::username()))
+}
+
+fn ensure_screenshot_dir() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let dir = get_screenshot_dir();
+    std::fs::create_dir_all(&dir)?;
+
+    // The temp dir is shared, refuse a symlink someone else planted at our path
+    if !std::fs::symlink_metadata(&dir)?.file_type().is_dir() {
+        return Err(format!("Screenshot dir {} is not a directory", dir.display()).into());
+    }
+
+    // Screenshots can show anything on screen, keep them away from other users.
+    // chmod also fails if another user created the directory first.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700))?;
+    }
+
+    Ok(dir)
+}
+
+fn new_screenshot_id() -> String {
+    let bytes: [u8; 16] = rand::thread_rng().gen();
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Resolves a screenshot id to its file, rejecting anything that isn't an id we handed out
+pub fn get_screenshot_path(id: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    if id.len() != 32 || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid screenshot id: {}", id).into());
+    }
+
+    let dir = get_screenshot_dir();
+    SCREENSHOT_EXTENSIONS
+        .iter()
+        .map(|extension| dir.join(format!("{}.{}", id, extension)))
+        .find(|path| path.exists())
+        .ok_or_else(|| format!("Screenshot {} not found", id).into())
+}
+
+/// Mime type of a stored screenshot, based on the extension it was saved with
+pub fn get_screenshot_mime_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("gif") => "image/gif",
+        _ => "image/png",
+    }
+}
+
+/// Reserves a new id in the screenshot store and returns it with the path to write to
+pub fn new_screenshot_file(extension: &str) -> Result<(String, PathBuf), Box<dyn std::error::Error>> {
+    let id = new_screenshot_id();
+    let path = ensure_screenshot_dir()?.join(format!("{}.{}", id, extension));
+    Ok((id, path))
+}
+
+/// Saves an image into the screenshot store under a new id
+pub fn store_screenshot(image: &RgbaImage) -> Result<String, Box<dyn std::error::Error>> {
+    let (id, path) = new_screenshot_file("png")?;
+    image.save(&path)?;
+    Ok(id)
+}
+
+pub fn delete_screenshot(id: &str) -> Result<(), Box<dyn std::error::Error>> {
+    std::fs::remove_file(get_screenshot_path(id)?)?;
+    Ok(())
+}
+
+/// Deletes screenshots older than `ttl`, returning how many were removed
+pub fn sweep_screenshots(ttl: Duration) -> Result<usize, Box<dyn std::error::Error>> {
+    let dir = get_screenshot_dir();
+    if !dir.exists() {
+        return Ok(0);
+    }
+
+    let now = SystemTime::now();
+    let mut removed = 0;
+    for entry in std::fs::read_dir(&dir)? {
+        let entry = entry?;
+        let modified = entry.metadata()?.modified()?;
+        let age = now.duration_since(modified).unwrap_or_default();
+        if age >= ttl && std::fs::remove_file(entry.path()).is_ok() {
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Removes every stored screenshot, including ones written by older versions to the app data dir
+pub fn clear_screenshots(legacy_dirs: &[PathBuf]) -> Result<usize, Box<dyn std::error::Error>> {
+    for dir in legacy_dirs {
+        if dir.exists() {
+            std::fs::remove_dir_all(dir)?;
+        }
+    }
+    sweep_screenshots(Duration::ZERO)
+}
+
+/// Copies a user chosen image into the store, re-encoding it so it's always a PNG
+pub fn import_screenshot(source: &Path) -> Result<ScreenCapture, Box<dyn std::error::Error>> {
+    let image = image::open(source)?.into_rgba8();
+    save_capture(&image, None)
+}
+
+/// Reads a stored screenshot as a data URL for previews
+pub fn read_screenshot_data_url(id: &str) -> Result<String, Box<dyn std::error::Error>> {
+    use base64::Engine;
+
+    let path = get_screenshot_path(id)?;
+    let bytes = std::fs::read(&path)?;
+    Ok(format!(
+        "data:{};base64,{}",
+        get_screenshot_mime_type(&path),
+        base64::engine::general_purpose::STANDARD.encode(bytes)
+    ))
+}
+
+fn monitor_info(monitor: &Monitor) -> MonitorInfo {
+    MonitorInfo {
+        id: monitor.id(),
+        name: monitor.name().to_string(),
+        x: monitor.x(),
+        y: monitor.y(),
+        width: monitor.width(),
+        height: monitor.height(),
+        scale_factor: monitor.scale_factor(),
+        is_primary: monitor.is_primary(),
+    }
+}
+
+/// Lists the monitors that can be captured, primary first
+pub fn list_monitors() -> Result<Vec<MonitorInfo>, Box<dyn std::error::Error>> {
+    let mut monitors: Vec<MonitorInfo> = Monitor::all()?.iter().map(monitor_info).collect();
+    monitors.sort_by_key(|m| (!m.is_primary, m.x, m.y));
+    Ok(monitors)
+}
+
+// Captured pixels per unit of the monitor's reported geometry. That's its scale factor where the
+// OS reports logical geometry (macOS) and 1 where it reports physical pixels (Windows, X11).
+fn capture_scale(monitor: &MonitorInfo, image: &RgbaImage) -> f64 {
+    if monitor.width == 0 {
+        return monitor.scale_factor.max(1.0) as f64;
+    }
+    image.width() as f64 / monitor.width as f64
+}
+
+/// Places every capture on one canvas at its monitor's position in the virtual desktop. With
+/// mixed scale factors the canvas uses the densest monitor's scale and the rest are resized to
+/// match, so monitors neither overlap nor leave gaps.
+pub fn stitch_captures(captures: &[(MonitorInfo, RgbaImage)]) -> Option<RgbaImage> {
+    let min_x = captures.iter().map(|(m, _)| m.x).min()?;
+    let min_y = captures.iter().map(|(m, _)| m.y).min()?;
+    let canvas_scale = captures
+        .iter()
+        .map(|(m, image)| capture_scale(m, image))
+        .fold(1.0, f64::max);
+
+    // Position and size of each capture on the canvas, in canvas pixels
+    let placements: Vec<(i64, i64, u32, u32)> = captures
+        .iter()
+        .map(|(monitor, image)| {
+            let resize = canvas_scale / capture_scale(monitor, image);
+            (
+                ((monitor.x - min_x) as f64 * canvas_scale).round() as i64,
+                ((monitor.y - min_y) as f64 * canvas_scale).round() as i64,
+                (image.width() as f64 * resize).round() as u32,
+                (image.height() as f64 * resize).round() as u32,
+            )
+        })
+        .collect();
+
+    let max_x = placements.iter().map(|(x, _, width, _)| x + *width as i64).max()?;
+    let max_y = placements.iter().map(|(_, y, _, height)| y + *height as i64).max()?;
+
+    let mut canvas = RgbaImage::new(max_x as u32, max_y as u32);
+    for ((_, image), (x, y, width, height)) in captures.iter().zip(placements) {
+        if (width, height) == image.dimensions() {
+            imageops::overlay(&mut canvas, image, x, y);
+        } else {
+            let resized = imageops::resize(image, width, height, imageops::FilterType::Triangle);
+            imageops::overlay(&mut canvas, &resized, x, y);
+        }
+    }
+
+    Some(canvas)
+}
+
+fn save_capture(
+    image: &RgbaImage,
+    monitor: Option<MonitorInfo>,
+) -> Result<ScreenCapture, Box<dyn std::error::Error>> {
+    Ok(ScreenCapture {
+        id: store_screenshot(image)?,
+        monitor,
+        width: image.width(),
+        height: image.height(),
+    })
+}
+
+/// Captures one monitor or all of them into the store, optionally stitching them into a single image
+pub fn capture_screens(
+    monitor_id: Option<u32>,
+    stitch: bool,
+) -> Result<ScreenshotResult, Box<dyn std::error::Error>> {
+    let mut monitors = Monitor::all()?;
+    if let Some(id) = monitor_id {
+        monitors.retain(|m| m.id() == id);
+        if monitors.is_empty() {
+            return Err(format!("Monitor {} not found", id).into());
+        }
+    }
+    if monitors.is_empty() {
+        return Err("No screenshotable monitors found".into());
+    }
+
+    let mut images = Vec::new();
+    for monitor in &monitors {
+        images.push((monitor_info(monitor), monitor.capture_image()?));
+    }
+    images.sort_by_key(|(m, _)| (!m.is_primary, m.x, m.y));
+
+    let mut captures = Vec::new();
+    for (info, image) in &images {
+        captures.push(save_capture(image, Some(info.clone()))?);
+    }
+
+    let stitched = if stitch && images.len() > 1 {
+        match stitch_captures(&images) {
+            Some(canvas) => Some(save_capture(&canvas, None)?),
+            None => None,
+        }
+    } else {
+        None
+    };
+
+    Ok(ScreenshotResult { captures, stitched })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn monitor(id: u32, x: i32, y: i32, width: u32, height: u32, scale_factor: f32) -> MonitorInfo {
+        MonitorInfo {
+            id,
+            name: format!("Monitor {}", id),
+            x,
+            y,
+            width,
+            height,
+            scale_factor,
+            is_primary: id == 1,
+        }
+    }
+
+    fn capture(monitor: &MonitorInfo, color: u8) -> RgbaImage {
+        let scale = monitor.scale_factor as u32;
+        RgbaImage::from_pixel(monitor.width * scale, monitor.height * scale, Rgba([color, 0, 0, 255]))
+    }
+
+    #[test]
+    fn physical_geometry_is_placed_as_is() {
+        let left = monitor(1, -100, 0, 100, 50, 1.0);
+        let right = monitor(2, 0, 10, 80, 40, 1.0);
+        let stitched = stitch_captures(&[(left.clone(), capture(&left, 10)), (right.clone(), capture(&right, 20))]).unwrap();
+
+        assert_eq!(stitched.dimensions(), (180, 50));
+        assert_eq!(stitched.get_pixel(99, 0)[0], 10);
+        assert_eq!(stitched.get_pixel(100, 10)[0], 20);
+        assert_eq!(stitched.get_pixel(100, 9)[3], 0);
+    }
+
+    #[test]
+    fn mixed_scale_factors_neither_overlap_nor_leave_gaps() {
+        // A Retina laptop with a standard external display to its right, geometry in points
+        let laptop = monitor(1, 0, 0, 100, 60, 2.0);
+        let external = monitor(2, 100, 0, 120, 80, 1.0);
+        let stitched = stitch_captures(&[(laptop.clone(), capture(&laptop, 10)), (external.clone(), capture(&external, 20))]).unwrap();
+
+        assert_eq!(stitched.dimensions(), (440, 160));
+        assert_eq!(stitched.get_pixel(199, 0)[0], 10);
+        assert_eq!(stitched.get_pixel(200, 0)[0], 20);
+        assert_eq!(stitched.get_pixel(439, 159)[0], 20);
+        assert_eq!(stitched.get_pixel(199, 120)[3], 0);
+    }
+}
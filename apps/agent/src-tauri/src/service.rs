@@ -0,0 +1,292 @@
+use crate::agent_state::{current_state, record_heartbeat_result, set_registered};
+use crate::branding::sync_logo;
+use crate::device_manager::{get_rmm_device_id, get_settings, is_device_registered, UiSettings};
+use crate::device_registration::register_device_with_server;
+use crate::heartbeat::{
+    apply_delivered_heartbeat, gather_system_info, send_heartbeat, start_heartbeat_task,
+};
+use crate::ipc::{serve, IpcCommand, PeerInfo};
+use crate::jobs::{resume_jobs, run_on_demand_job};
+use crate::local_api;
+use crate::logger::log_to_file;
+use crate::metrics::start_metrics_task;
+use crate::outbox::{
+    get_outbox_receipt, list_outbox, start_outbox_task, DeliveredMessage, OutboxPayload,
+};
+use crate::reboot::defer_reboot;
+use crate::service_monitor::start_service_monitor_task;
+use crate::ticket::{parse_ticket_id, submit_ticket};
+use crate::ticket_store::{list_owned_tickets, record_ticket};
+use crate::tray::menu_offers_job;
+use crate::updater::start_update_task;
+use serde_json::{json, Value};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+/// Logs and starts tracking queued tickets once the outbox delivers them, and applies the
+/// reply to a queued heartbeat like a live one
+pub fn on_outbox_delivered(delivered: &DeliveredMessage) {
+    if delivered.message.kind == "ticket" {
+        match parse_ticket_id(&delivered.response) {
+            Ok(ticket_id) => {
+                log_to_file(
+                    String::from("INFO"),
+                    format!("Queued ticket {} created as {}", delivered.message.id, ticket_id),
+                );
+
+                let summary = match &delivered.message.payload {
+                    OutboxPayload::Multipart { fields, .. } => fields
+                        .iter()
+                        .find(|(name, _)| name == "summary")
+                        .map(|(_, value)| value.clone())
+                        .unwrap_or_default(),
+                    OutboxPayload::Json { .. } => String::new(),
+                };
+                let owner = delivered.message.owner.clone();
+                tauri::async_runtime::spawn(async move {
+                    if let Err(e) = record_ticket(&ticket_id, &summary, owner.as_deref()).await {
+                        log_to_file(
+                            String::from("WARN"),
+                            format!("Failed to track ticket {}: {}", ticket_id, e),
+                        );
+                    }
+                });
+            }
+            Err(e) => log_to_file(
+                String::from("WARN"),
+                format!("Queued ticket {} delivered with unreadable response: {}", delivered.message.id, e),
+            ),
+        }
+    } else if delivered.message.kind == "heartbeat" {
+        let response = delivered.response.clone();
+        tauri::async_runtime::spawn(async move {
+            apply_delivered_heartbeat(&response).await;
+        });
+    }
+}
+
+/// Registers the device with the server unless it already has an identity
+pub async fn ensure_registered() {
+    if !is_device_registered().await {
+        log_to_file(
+            "INFO".to_string(),
+            "First launch detected, registering device...".to_string(),
+        );
+
+        match register_device_with_server().await {
+            Ok(response) => {
+                log_to_file(
+                    String::from("INFO"),
+                    String::from("Device registered successfully"),
+                );
+                log_to_file(
+                    String::from("INFO"),
+                    format!("Device ID: {}", response.data.device_id),
+                );
+                log_to_file(
+                    String::from("INFO"),
+                    format!("GUID: {}", response.data.guid),
+                );
+            }
+            Err(e) => {
+                log_to_file(
+                    String::from("ERROR"),
+                    format!("Failed to regiter device: {}", e),
+                );
+                log_to_file(
+                    String::from("ERROR"),
+                    String::from("Will retry with the next heartbeat"),
+                );
+            }
+        }
+    } else {
+        log_to_file(
+            String::from("INFO"),
+            "Device already registered".to_string(),
+        );
+    }
+
+    set_registered(is_device_registered().await);
+}
+
+/// Fetches the branding logo in the background, settings may point at a new one since the last run
+pub fn start_logo_sync() {
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = sync_logo().await.map_err(|e| e.to_string()) {
+            log_to_file(
+                String::from("WARN"),
+                format!("Failed to update branding logo: {}", e),
+            );
+        }
+    });
+}
+
+/// Runs the privileged agent service: registration, heartbeat and the local IPC servers
+pub async fn run_service() -> Result<(), Box<dyn std::error::Error>> {
+    log_to_file(
+        String::from("INFO"),
+        format!("Starting agent service v{}", env!("CARGO_PKG_VERSION")),
+    );
+
+    ensure_registered().await;
+    start_logo_sync();
+
+    let heartbeat_running = Arc::new(AtomicBool::new(true));
+    start_heartbeat_task(heartbeat_running.clone());
+    start_metrics_task(heartbeat_running.clone());
+    start_service_monitor_task(heartbeat_running.clone());
+    start_outbox_task(heartbeat_running.clone(), on_outbox_delivered);
+    resume_jobs().await;
+    start_update_task(heartbeat_running.clone());
+
+    tokio::try_join!(serve(handle_ipc_command), local_api::serve())?;
+    Ok(())
+}
+
+async fn handle_ipc_command(command: IpcCommand, peer: PeerInfo) -> Result<Value, String> {
+    // Daemons and sandboxed service accounts can reach the socket too, they only get liveness
+    if !command.is_open() && !peer.is_trusted() {
+        log_to_file(
+            String::from("WARN"),
+            format!("Refused IPC command from untrusted peer {:?}: {:?}", peer, command),
+        );
+        return Err(String::from("Permission denied"));
+    }
+
+    match command {
+        IpcCommand::Ping => Ok(json!({ "version": env!("CARGO_PKG_VERSION") })),
+        IpcCommand::GetSettings => {
+            let settings = get_settings()
+                .await
+                .map_err(|e| format!("Failed to get settings: {}", e))?;
+            // The device and site ids are what the backend authenticates the device by, only
+            // administrators see them
+            if peer.admin {
+                serde_json::to_value(settings).map_err(|e| e.to_string())
+            } else {
+                serde_json::to_value(UiSettings::from(&settings)).map_err(|e| e.to_string())
+            }
+        }
+        IpcCommand::CheckRegistration => Ok(Value::Bool(is_device_registered().await)),
+        IpcCommand::GetSystemInfo => {
+            let info = gather_system_info()
+                .await
+                .map_err(|e| format!("Failed to get system info: {}", e))?;
+            serde_json::to_value(info).map_err(|e| e.to_string())
+        }
+        IpcCommand::GetOutbox => {
+            let entries = list_outbox()
+                .await
+                .map_err(|e| format!("Failed to read outbox: {}", e))?;
+            serde_json::to_value(entries).map_err(|e| e.to_string())
+        }
+        IpcCommand::GetOutboxReceipt { id } => {
+            let receipt = get_outbox_receipt(&id)
+                .await
+                .map_err(|e| format!("Failed to read outbox receipt: {}", e))?;
+            serde_json::to_value(receipt).map_err(|e| e.to_string())
+        }
+        IpcCommand::GetAgentState => serde_json::to_value(current_state()).map_err(|e| e.to_string()),
+        IpcCommand::GetTickets => {
+            // Replies can carry private details, each account only sees what it submitted
+            let owner = peer.owner().ok_or("Unable to identify the caller")?;
+            let tickets = list_owned_tickets(&owner)
+                .await
+                .map_err(|e| format!("Failed to read tickets: {}", e))?;
+            serde_json::to_value(tickets).map_err(|e| e.to_string())
+        }
+        IpcCommand::RunJob { job_id } => {
+            let settings = get_settings()
+                .await
+                .map_err(|e| format!("Failed to get settings: {}", e))?;
+
+            // Only jobs the server put in this device's tray menu may be started from the tray
+            let menu = settings.tray_menu.unwrap_or_default();
+            if !menu_offers_job(&menu, &job_id) {
+                return Err(format!("Job {} is not offered on this device", job_id));
+            }
+
+            let run_id = run_on_demand_job(&job_id)
+                .await
+                .map_err(|e| format!("Failed to start job: {}", e))?;
+            Ok(Value::String(run_id))
+        }
+        IpcCommand::DeferReboot => {
+            let scheduled = defer_reboot()?;
+            serde_json::to_value(scheduled).map_err(|e| e.to_string())
+        }
+        IpcCommand::SendHeartbeat => {
+            // The CLI asks for this rather than posting itself, so delivered jobs run here
+            let result = send_heartbeat().await.map_err(|e| e.to_string());
+            record_heartbeat_result(result.as_ref().map(|_| ()).map_err(|e| e.clone()));
+            let response = result.map_err(|e| format!("Failed to send heartbeat: {}", e))?;
+            Ok(json!({ "guid": response.data.guid }))
+        }
+        IpcCommand::SubmitTicket(submission) => {
+            let outcome = submit_ticket(*submission, peer.owner())
+                .await
+                .map_err(|e| format!("Failed to submit ticket: {}", e))?;
+            serde_json::to_value(outcome).map_err(|e| e.to_string())
+        }
+        IpcCommand::GetRmmId => match get_rmm_device_id() {
+            Some(id) => Ok(Value::String(id)),
+            None => Err("Failed to get key".into()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_manager::test_support::use_settings;
+
+    fn peer(admin: bool, interactive: bool) -> PeerInfo {
+        PeerInfo {
+            uid: Some(1000),
+            pid: Some(4242),
+            sid: None,
+            admin,
+            interactive,
+        }
+    }
+
+    #[tokio::test]
+    async fn settings_are_redacted_for_signed_in_users() {
+        let _settings = use_settings(json!({ "show_tray": true, "support_hotkey": "Ctrl+Shift+H" })).await;
+
+        let user = handle_ipc_command(IpcCommand::GetSettings, peer(false, true)).await.unwrap();
+        assert_eq!(user["show_tray"], json!(true));
+        assert_eq!(user["support_hotkey"], json!("Ctrl+Shift+H"));
+        for credential in ["site_id", "device_id", "guid", "api_host", "job_signing_keys"] {
+            assert!(user.get(credential).is_none(), "{} was handed out", credential);
+        }
+
+        let admin = handle_ipc_command(IpcCommand::GetSettings, peer(true, false)).await.unwrap();
+        assert_eq!(admin["site_id"], json!("site-test"));
+        assert_eq!(admin["device_id"], json!("device-test"));
+
+        assert!(handle_ipc_command(IpcCommand::GetSettings, peer(false, false)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn untrusted_peers_only_get_the_open_commands() {
+        let _settings = use_settings(json!({})).await;
+        let daemon = peer(false, false);
+
+        for command in [IpcCommand::Ping, IpcCommand::CheckRegistration, IpcCommand::GetAgentState] {
+            assert!(command.is_open());
+            assert!(handle_ipc_command(command, daemon.clone()).await.is_ok());
+        }
+        for command in [
+            IpcCommand::GetSettings,
+            IpcCommand::GetOutbox,
+            IpcCommand::GetTickets,
+            IpcCommand::SendHeartbeat,
+            IpcCommand::DeferReboot,
+            IpcCommand::RunJob { job_id: String::from("job-1") },
+        ] {
+            assert!(!command.is_open());
+            assert_eq!(handle_ipc_command(command, daemon.clone()).await.unwrap_err(), "Permission denied");
+        }
+    }
+}
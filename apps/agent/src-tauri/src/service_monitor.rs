@@ -0,0 +1,810 @@
+use crate::device_manager::get_settings;
+use crate::logger::log_to_file;
+use crate::outbox::enqueue;
+use crate::system_context::{get_processes, ProcessInfo};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::time::{interval, Duration};
+
+#[cfg(target_os = "windows")]
+use std::os::windows::process::CommandExt;
+
+#[cfg(target_os = "windows")]
+const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+pub const SERVICE_CHECK_INTERVAL_SECS: u64 = 30;
+
+// A service that keeps dying needs a human, restarting it forever only hides that
+pub const DEFAULT_MAX_RESTARTS_PER_HOUR: u32 = 3;
+
+const RESTART_WINDOW_SECS: i64 = 3600;
+
+const SERVICE_STATE_PATH: &str = "/v1.0/services/state";
+
+static SERVICE_STATES: Mutex<Vec<ServiceState>> = Mutex::new(Vec::new());
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MonitorKind {
+    /// A systemd unit, Windows service or launchd job
+    #[default]
+    Service,
+    /// Any running process with this name
+    Process,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MonitoredService {
+    pub name: String,
+    #[serde(default)]
+    pub kind: MonitorKind,
+    /// Restart the service when it's found stopped or failed, processes can't be restarted
+    #[serde(default)]
+    pub restart: bool,
+    #[serde(default)]
+    pub max_restarts_per_hour: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceStatus {
+    Running,
+    /// Starting, stopping or reloading
+    Changing,
+    Stopped,
+    Failed,
+    NotFound,
+    Unknown,
+}
+
+impl ServiceStatus {
+    pub fn is_down(&self) -> bool {
+        matches!(
+            self,
+            ServiceStatus::Stopped | ServiceStatus::Failed | ServiceStatus::NotFound
+        )
+    }
+}
+
+/// What the platform reports for one service
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnitStatus {
+    pub status: ServiceStatus,
+    pub detail: Option<String>,
+    pub pid: Option<u32>,
+}
+
+/// The last known state of a monitored service, as sent with the heartbeat
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ServiceState {
+    pub name: String,
+    pub kind: MonitorKind,
+    pub status: ServiceStatus,
+    pub detail: Option<String>,
+    pub pid: Option<u32>,
+    pub previous_status: Option<ServiceStatus>,
+    /// Unix seconds of the last status change
+    pub changed_at: i64,
+    /// Restarts done by the agent in the last hour
+    pub restarts: u32,
+}
+
+/// The platform side of monitoring, kept behind a trait so the watchdog logic can run against a fake
+pub trait ServiceBackend {
+    fn service_status(&self, name: &str) -> Result<UnitStatus, String>;
+    fn restart_service(&self, name: &str) -> Result<(), String>;
+    fn processes(&self) -> Vec<ProcessInfo>;
+}
+
+fn run_command(program: &str, args: &[&str]) -> Result<String, String> {
+    let mut command = Command::new(program);
+    command.args(args);
+
+    #[cfg(target_os = "windows")]
+    command.creation_flags(CREATE_NO_WINDOW);
+
+    let output = command
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    if output.status.success() {
+        Ok(stdout)
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(format!("{} failed: {}{}", program, stdout.trim(), stderr.trim()))
+    }
+}
+
+/// Parses `systemctl show --property=LoadState,ActiveState,SubState,MainPID` output
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub fn parse_systemctl_show(output: &str) -> UnitStatus {
+    let properties: HashMap<&str, &str> = output
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .collect();
+
+    let status = if properties.get("LoadState") == Some(&"not-found") {
+        ServiceStatus::NotFound
+    } else {
+        match properties.get("ActiveState").copied() {
+            Some("active") => ServiceStatus::Running,
+            Some("activating") | Some("deactivating") | Some("reloading") => ServiceStatus::Changing,
+            Some("inactive") => ServiceStatus::Stopped,
+            Some("failed") => ServiceStatus::Failed,
+            _ => ServiceStatus::Unknown,
+        }
+    };
+
+    UnitStatus {
+        status,
+        detail: properties
+            .get("SubState")
+            .filter(|sub| !sub.is_empty())
+            .map(|sub| sub.to_string()),
+        pid: properties
+            .get("MainPID")
+            .and_then(|pid| pid.parse().ok())
+            .filter(|pid| *pid > 0),
+    }
+}
+
+/// Parses `sc query` output, e.g. "STATE : 4  RUNNING"
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+pub fn parse_sc_query(output: &str) -> UnitStatus {
+    // 1060 is ERROR_SERVICE_DOES_NOT_EXIST
+    if output.contains("1060") {
+        return UnitStatus {
+            status: ServiceStatus::NotFound,
+            detail: None,
+            pid: None,
+        };
+    }
+
+    let state = output
+        .lines()
+        .find(|line| line.trim_start().starts_with("STATE"))
+        .and_then(|line| line.split(':').nth(1))
+        .and_then(|value| value.split_whitespace().nth(1))
+        .unwrap_or_default()
+        .to_string();
+
+    // 1077 is ERROR_SERVICE_NEVER_STARTED, anything else but 0 means it stopped on an error
+    let exit_code = output
+        .lines()
+        .find(|line| line.trim_start().starts_with("WIN32_EXIT_CODE"))
+        .and_then(|line| line.split(':').nth(1))
+        .and_then(|value| value.split_whitespace().next())
+        .and_then(|code| code.parse::<u32>().ok())
+        .unwrap_or(0);
+
+    let status = match state.as_str() {
+        "RUNNING" => ServiceStatus::Running,
+        "STOPPED" if exit_code != 0 && exit_code != 1077 => ServiceStatus::Failed,
+        "STOPPED" => ServiceStatus::Stopped,
+        "START_PENDING" | "STOP_PENDING" | "CONTINUE_PENDING" | "PAUSE_PENDING" => ServiceStatus::Changing,
+        "PAUSED" => ServiceStatus::Stopped,
+        _ => ServiceStatus::Unknown,
+    };
+
+    UnitStatus {
+        status,
+        detail: Some(state).filter(|state| !state.is_empty()),
+        pid: None,
+    }
+}
+
+/// Parses `launchctl print` output, which has "state = running" and "pid = 123" lines
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+pub fn parse_launchctl_print(output: &str) -> UnitStatus {
+    let value = |key: &str| {
+        output
+            .lines()
+            .filter_map(|line| line.trim().split_once(" = "))
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.trim().to_string())
+    };
+
+    // "last exit code = 78: EX_CONFIG", or "(never exited)" when it hasn't run
+    let exit_code = value("last exit code")
+        .and_then(|code| code.split(':').next().and_then(|code| code.trim().parse::<i32>().ok()))
+        .unwrap_or(0);
+
+    let state = value("state");
+    let status = match state.as_deref() {
+        Some("running") => ServiceStatus::Running,
+        Some("not running") if exit_code != 0 => ServiceStatus::Failed,
+        Some("not running") => ServiceStatus::Stopped,
+        Some(_) => ServiceStatus::Changing,
+        None => ServiceStatus::Unknown,
+    };
+
+    UnitStatus {
+        status,
+        detail: state,
+        pid: value("pid").and_then(|pid| pid.parse().ok()),
+    }
+}
+
+/// Turns a `launchctl print` run into a status, launchctl fails for services it doesn't know
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn launchctl_status(result: Result<String, String>) -> Result<UnitStatus, String> {
+    match result {
+        Ok(output) => Ok(parse_launchctl_print(&output)),
+        Err(e) if e.contains("Could not find service") => Ok(UnitStatus {
+            status: ServiceStatus::NotFound,
+            detail: None,
+            pid: None,
+        }),
+        Err(e) => Err(e),
+    }
+}
+
+/// Asks the OS service manager, systemd on Linux, the SCM on Windows and launchd on macOS
+pub struct SystemServices;
+
+impl ServiceBackend for SystemServices {
+    fn service_status(&self, name: &str) -> Result<UnitStatus, String> {
+        #[cfg(target_os = "linux")]
+        {
+            let output = run_command(
+                "systemctl",
+                &[
+                    "show",
+                    name,
+                    "--no-pager",
+                    "--property=LoadState,ActiveState,SubState,MainPID",
+                ],
+            )?;
+            Ok(parse_systemctl_show(&output))
+        }
+
+        #[cfg(target_os = "windows")]
+        {
+            // sc exits non-zero for unknown services but still explains why on stdout
+            let output = run_command("sc", &["query", name]).unwrap_or_else(|e| e);
+            Ok(parse_sc_query(&output))
+        }
+
+        #[cfg(target_os = "macos")]
+        {
+            launchctl_status(run_command("launchctl", &["print", &format!("system/{}", name)]))
+        }
+    }
+
+    fn restart_service(&self, name: &str) -> Result<(), String> {
+        #[cfg(target_os = "linux")]
+        {
+            run_command("systemctl", &["restart", name]).map(|_| ())
+        }
+
+        #[cfg(target_os = "windows")]
+        {
+            // Stopping fails when the service already died, starting is what matters
+            let _ = run_command("net", &["stop", name]);
+            run_command("net", &["start", name]).map(|_| ())
+        }
+
+        #[cfg(target_os = "macos")]
+        {
+            run_command("launchctl", &["kickstart", "-k", &format!("system/{}", name)]).map(|_| ())
+        }
+    }
+
+    fn processes(&self) -> Vec<ProcessInfo> {
+        get_processes()
+    }
+}
+
+/// Whether a process is the one configured, Windows lists names without ".exe"
+fn process_matches(process: &str, wanted: &str) -> bool {
+    let wanted = wanted.strip_suffix(".exe").unwrap_or(wanted);
+    let process = process.rsplit('/').next().unwrap_or(process);
+    process.eq_ignore_ascii_case(wanted)
+        || process
+            .strip_suffix(".exe")
+            .is_some_and(|process| process.eq_ignore_ascii_case(wanted))
+}
+
+/// Checks monitored services, keeps their last state and restarts them within the rate limit
+pub struct ServiceMonitor<B: ServiceBackend> {
+    backend: B,
+    states: HashMap<String, ServiceState>,
+    restarts: HashMap<String, Vec<i64>>,
+    given_up: HashSet<String>,
+}
+
+impl<B: ServiceBackend> ServiceMonitor<B> {
+    pub fn new(backend: B) -> Self {
+        ServiceMonitor {
+            backend,
+            states: HashMap::new(),
+            restarts: HashMap::new(),
+            given_up: HashSet::new(),
+        }
+    }
+
+    fn status_of(&self, service: &MonitoredService, processes: &mut Option<Vec<ProcessInfo>>) -> UnitStatus {
+        match service.kind {
+            MonitorKind::Service => self.backend.service_status(&service.name).unwrap_or_else(|e| UnitStatus {
+                status: ServiceStatus::Unknown,
+                detail: Some(e),
+                pid: None,
+            }),
+            MonitorKind::Process => {
+                // Listing processes is the slow part, do it once per check
+                let processes = processes.get_or_insert_with(|| self.backend.processes());
+                let pid = processes
+                    .iter()
+                    .find(|process| process_matches(&process.name, &service.name))
+                    .map(|process| process.pid);
+                UnitStatus {
+                    status: if pid.is_some() {
+                        ServiceStatus::Running
+                    } else {
+                        ServiceStatus::Stopped
+                    },
+                    detail: None,
+                    pid,
+                }
+            }
+        }
+    }
+
+    fn recent_restarts(&self, name: &str, now: i64) -> usize {
+        self.restarts
+            .get(name)
+            .map(|restarts| restarts.iter().filter(|at| now - **at < RESTART_WINDOW_SECS).count())
+            .unwrap_or(0)
+    }
+
+    /// Restarts the service unless it's been restarted too often lately
+    fn try_restart(&mut self, service: &MonitoredService, now: i64) {
+        let limit = service.max_restarts_per_hour.unwrap_or(DEFAULT_MAX_RESTARTS_PER_HOUR) as usize;
+        if self.recent_restarts(&service.name, now) >= limit {
+            // Only say so once per outage rather than on every check
+            if self.given_up.insert(service.name.clone()) {
+                log_to_file(
+                    "WARN".to_string(),
+                    format!(
+                        "Not restarting {}, it was restarted {} times in the last hour",
+                        service.name, limit
+                    ),
+                );
+            }
+            return;
+        }
+
+        let restarts = self.restarts.entry(service.name.clone()).or_default();
+        restarts.retain(|at| now - at < RESTART_WINDOW_SECS);
+        restarts.push(now);
+        match self.backend.restart_service(&service.name) {
+            Ok(()) => log_to_file("INFO".to_string(), format!("Restarted {}", service.name)),
+            Err(e) => log_to_file(
+                "ERROR".to_string(),
+                format!("Failed to restart {}: {}", service.name, e),
+            ),
+        }
+    }
+
+    /// Checks every monitored service, returning the states that changed since the last check
+    pub fn check(&mut self, services: &[MonitoredService], now: i64) -> Vec<ServiceState> {
+        self.states
+            .retain(|name, _| services.iter().any(|service| &service.name == name));
+        self.restarts
+            .retain(|name, _| services.iter().any(|service| &service.name == name));
+        self.given_up
+            .retain(|name| services.iter().any(|service| &service.name == name));
+
+        let mut processes = None;
+        let mut changes = Vec::new();
+
+        // Names go straight to the service manager's command line, don't let one pass as an option
+        for service in services.iter().filter(|service| {
+            !service.name.trim().is_empty() && !service.name.starts_with('-')
+        }) {
+            let unit = self.status_of(service, &mut processes);
+            let previous = self.states.get(&service.name);
+
+            let changed = previous.map(|state| state.status) != Some(unit.status);
+            let state = ServiceState {
+                name: service.name.clone(),
+                kind: service.kind,
+                status: unit.status,
+                detail: unit.detail,
+                pid: unit.pid,
+                previous_status: if changed {
+                    previous.map(|state| state.status)
+                } else {
+                    previous.and_then(|state| state.previous_status)
+                },
+                changed_at: if changed {
+                    now
+                } else {
+                    previous.map(|state| state.changed_at).unwrap_or(now)
+                },
+                restarts: 0,
+            };
+
+            if changed {
+                log_to_file(
+                    "INFO".to_string(),
+                    format!("{} is {:?}", service.name, state.status),
+                );
+            }
+
+            if service.restart
+                && service.kind == MonitorKind::Service
+                && matches!(state.status, ServiceStatus::Stopped | ServiceStatus::Failed)
+            {
+                self.try_restart(service, now);
+            }
+
+            if state.status == ServiceStatus::Running {
+                self.given_up.remove(&service.name);
+            }
+
+            let state = ServiceState {
+                restarts: self.recent_restarts(&service.name, now) as u32,
+                ..state
+            };
+
+            if changed {
+                changes.push(state.clone());
+            }
+            self.states.insert(service.name.clone(), state);
+        }
+
+        changes
+    }
+
+    /// The last known state of every monitored service, in configured order
+    pub fn states(&self, services: &[MonitoredService]) -> Vec<ServiceState> {
+        services
+            .iter()
+            .filter_map(|service| self.states.get(&service.name).cloned())
+            .collect()
+    }
+}
+
+/// States from the latest check in this process, empty when nothing is monitored
+pub fn latest_service_states() -> Vec<ServiceState> {
+    match SERVICE_STATES.lock() {
+        Ok(states) => states.clone(),
+        Err(poisoned) => poisoned.into_inner().clone(),
+    }
+}
+
+fn publish_service_states(states: Vec<ServiceState>) {
+    match SERVICE_STATES.lock() {
+        Ok(mut current) => *current = states,
+        Err(poisoned) => *poisoned.into_inner() = states,
+    }
+}
+
+/// Queues status changes for the server, the heartbeat only carries the latest state
+async fn queue_state_changes(guid: Option<String>, changes: Vec<ServiceState>) {
+    let body = json!({ "guid": guid, "changes": changes });
+    if let Err(e) = enqueue("service_state", SERVICE_STATE_PATH, body)
+        .await
+        .map_err(|e| e.to_string())
+    {
+        log_to_file(
+            "WARN".to_string(),
+            format!("Failed to queue service state changes: {}", e),
+        );
+    }
+}
+
+/// Starts the background task that checks monitored services and restarts them when asked to
+pub fn start_service_monitor_task(running: Arc<AtomicBool>) {
+    tauri::async_runtime::spawn(async move {
+        log_to_file(
+            "INFO".to_string(),
+            "Starting service monitor background task".to_string(),
+        );
+
+        let mut check_interval = interval(Duration::from_secs(SERVICE_CHECK_INTERVAL_SECS));
+        let mut monitor = ServiceMonitor::new(SystemServices);
+
+        while running.load(Ordering::Relaxed) {
+            check_interval.tick().await;
+
+            let (services, guid) = match get_settings().await {
+                Ok(settings) => (settings.monitored_services.unwrap_or_default(), settings.guid),
+                Err(_) => continue,
+            };
+            if services.is_empty() {
+                publish_service_states(Vec::new());
+                continue;
+            }
+
+            // Service managers are queried through blocking commands
+            let now = chrono::Utc::now().timestamp();
+            match tauri::async_runtime::spawn_blocking(move || {
+                let changes = monitor.check(&services, now);
+                let states = monitor.states(&services);
+                (monitor, changes, states)
+            })
+            .await
+            {
+                Ok((returned, changes, states)) => {
+                    monitor = returned;
+                    publish_service_states(states);
+                    if !changes.is_empty() {
+                        queue_state_changes(guid, changes).await;
+                    }
+                }
+                Err(e) => {
+                    log_to_file("ERROR".to_string(), format!("Service check failed: {}", e));
+                    monitor = ServiceMonitor::new(SystemServices);
+                }
+            }
+        }
+
+        log_to_file(
+            "INFO".to_string(),
+            "Service monitor background task stopped".to_string(),
+        );
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+
+    #[derive(Default)]
+    struct FakeServices {
+        statuses: RefCell<HashMap<String, ServiceStatus>>,
+        processes: RefCell<Vec<String>>,
+        restarts: Cell<u32>,
+    }
+
+    impl FakeServices {
+        fn set(&self, name: &str, status: ServiceStatus) {
+            self.statuses.borrow_mut().insert(name.to_string(), status);
+        }
+    }
+
+    impl ServiceBackend for &FakeServices {
+        fn service_status(&self, name: &str) -> Result<UnitStatus, String> {
+            let status = self.statuses.borrow().get(name).copied().unwrap_or(ServiceStatus::NotFound);
+            Ok(UnitStatus {
+                status,
+                detail: None,
+                pid: None,
+            })
+        }
+
+        fn restart_service(&self, _name: &str) -> Result<(), String> {
+            self.restarts.set(self.restarts.get() + 1);
+            Ok(())
+        }
+
+        fn processes(&self) -> Vec<ProcessInfo> {
+            self.processes
+                .borrow()
+                .iter()
+                .enumerate()
+                .map(|(pid, name)| ProcessInfo {
+                    pid: pid as u32 + 100,
+                    name: name.clone(),
+                    cpu: 0.0,
+                    memory_bytes: 0,
+                })
+                .collect()
+        }
+    }
+
+    fn service(name: &str, restart: bool, max_restarts_per_hour: Option<u32>) -> MonitoredService {
+        MonitoredService {
+            name: name.to_string(),
+            kind: MonitorKind::Service,
+            restart,
+            max_restarts_per_hour,
+        }
+    }
+
+    #[test]
+    fn only_status_changes_are_reported() {
+        let fake = FakeServices::default();
+        let services = [service("nginx", false, None)];
+        let mut monitor = ServiceMonitor::new(&fake);
+
+        fake.set("nginx", ServiceStatus::Running);
+        let changes = monitor.check(&services, 1000);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].previous_status, None);
+        assert!(monitor.check(&services, 1030).is_empty());
+
+        fake.set("nginx", ServiceStatus::Failed);
+        let changes = monitor.check(&services, 1060);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].status, ServiceStatus::Failed);
+        assert_eq!(changes[0].previous_status, Some(ServiceStatus::Running));
+        assert_eq!(changes[0].changed_at, 1060);
+
+        // Still failed, the change time and previous status stick
+        assert!(monitor.check(&services, 1090).is_empty());
+        let states = monitor.states(&services);
+        assert_eq!(states[0].changed_at, 1060);
+        assert_eq!(states[0].previous_status, Some(ServiceStatus::Running));
+        assert_eq!(fake.restarts.get(), 0);
+    }
+
+    #[test]
+    fn restarts_stop_at_the_hourly_limit() {
+        let fake = FakeServices::default();
+        let services = [service("nginx", true, Some(2))];
+        let mut monitor = ServiceMonitor::new(&fake);
+        fake.set("nginx", ServiceStatus::Stopped);
+
+        for i in 0..5 {
+            monitor.check(&services, 1000 + i * SERVICE_CHECK_INTERVAL_SECS as i64);
+        }
+        assert_eq!(fake.restarts.get(), 2);
+        assert!(monitor.given_up.contains("nginx"));
+        assert_eq!(monitor.states(&services)[0].restarts, 2);
+
+        // Once the first restarts age out of the window it is tried again
+        monitor.check(&services, 1000 + RESTART_WINDOW_SECS);
+        assert_eq!(fake.restarts.get(), 3);
+    }
+
+    #[test]
+    fn giving_up_is_forgotten_once_the_service_runs() {
+        let fake = FakeServices::default();
+        let services = [service("nginx", true, Some(1))];
+        let mut monitor = ServiceMonitor::new(&fake);
+
+        fake.set("nginx", ServiceStatus::Failed);
+        monitor.check(&services, 1000);
+        monitor.check(&services, 1030);
+        assert_eq!(fake.restarts.get(), 1);
+        assert!(monitor.given_up.contains("nginx"));
+
+        fake.set("nginx", ServiceStatus::Running);
+        monitor.check(&services, 1060);
+        assert!(!monitor.given_up.contains("nginx"));
+
+        // Dropping a service from the settings forgets everything about it
+        fake.set("nginx", ServiceStatus::Failed);
+        monitor.check(&services, 1090);
+        assert!(monitor.given_up.contains("nginx"));
+        monitor.check(&[], 1120);
+        assert!(monitor.given_up.is_empty());
+        assert!(monitor.states(&services).is_empty());
+    }
+
+    #[test]
+    fn processes_and_option_like_names_are_never_restarted() {
+        let fake = FakeServices::default();
+        *fake.processes.borrow_mut() = vec![String::from("/usr/sbin/sshd")];
+        let services = [
+            MonitoredService {
+                name: String::from("sshd"),
+                kind: MonitorKind::Process,
+                restart: true,
+                max_restarts_per_hour: None,
+            },
+            MonitoredService {
+                name: String::from("cron"),
+                kind: MonitorKind::Process,
+                restart: true,
+                max_restarts_per_hour: None,
+            },
+            service("--all", true, None),
+        ];
+        let mut monitor = ServiceMonitor::new(&fake);
+
+        let changes = monitor.check(&services, 1000);
+        assert_eq!(changes.len(), 2);
+        assert_eq!((changes[0].status, changes[0].pid), (ServiceStatus::Running, Some(100)));
+        assert_eq!(changes[1].status, ServiceStatus::Stopped);
+        assert_eq!(fake.restarts.get(), 0);
+    }
+
+    fn unit(status: ServiceStatus, detail: Option<&str>, pid: Option<u32>) -> UnitStatus {
+        UnitStatus {
+            status,
+            detail: detail.map(String::from),
+            pid,
+        }
+    }
+
+    #[test]
+    fn systemctl_show_output_is_parsed() {
+        let running = "MainPID=812\nLoadState=loaded\nActiveState=active\nSubState=running\n";
+        assert_eq!(parse_systemctl_show(running), unit(ServiceStatus::Running, Some("running"), Some(812)));
+
+        let not_found = "MainPID=0\nLoadState=not-found\nActiveState=inactive\nSubState=dead\n";
+        assert_eq!(parse_systemctl_show(not_found), unit(ServiceStatus::NotFound, Some("dead"), None));
+
+        let failed = "MainPID=0\nLoadState=loaded\nActiveState=failed\nSubState=failed\n";
+        assert_eq!(parse_systemctl_show(failed), unit(ServiceStatus::Failed, Some("failed"), None));
+
+        let activating = "MainPID=0\nLoadState=loaded\nActiveState=activating\nSubState=auto-restart\n";
+        assert_eq!(
+            parse_systemctl_show(activating),
+            unit(ServiceStatus::Changing, Some("auto-restart"), None)
+        );
+
+        assert_eq!(parse_systemctl_show(""), unit(ServiceStatus::Unknown, None, None));
+    }
+
+    #[test]
+    fn sc_query_output_is_parsed() {
+        let sc = |state: &str, exit_code: &str| {
+            format!(
+                "\r\nSERVICE_NAME: Spooler \r\n        TYPE               : 110  WIN32_OWN_PROCESS  (interactive)\r\n        STATE              : {}\r\n                                (STOPPABLE, NOT_PAUSABLE, ACCEPTS_SHUTDOWN)\r\n        WIN32_EXIT_CODE    : {}\r\n        SERVICE_EXIT_CODE  : 0  (0x0)\r\n        CHECKPOINT         : 0x0\r\n        WAIT_HINT          : 0x0\r\n",
+                state, exit_code
+            )
+        };
+
+        assert_eq!(
+            parse_sc_query(&sc("4  RUNNING", "0  (0x0)")),
+            unit(ServiceStatus::Running, Some("RUNNING"), None)
+        );
+        assert_eq!(
+            parse_sc_query(&sc("1  STOPPED", "1077  (0x435)")),
+            unit(ServiceStatus::Stopped, Some("STOPPED"), None)
+        );
+        assert_eq!(
+            parse_sc_query(&sc("1  STOPPED", "1067  (0x42b)")),
+            unit(ServiceStatus::Failed, Some("STOPPED"), None)
+        );
+        assert_eq!(
+            parse_sc_query(&sc("2  START_PENDING", "0  (0x0)")),
+            unit(ServiceStatus::Changing, Some("START_PENDING"), None)
+        );
+
+        // sc exits non-zero here, the monitor passes on the error text
+        let missing = "sc failed: [SC] EnumQueryServicesStatus:OpenService FAILED 1060:\r\n\r\nThe specified service does not exist as an installed service.";
+        assert_eq!(parse_sc_query(missing), unit(ServiceStatus::NotFound, None, None));
+    }
+
+    #[test]
+    fn launchctl_print_output_is_parsed() {
+        let print = |state: &str, extra: &str| {
+            format!(
+                "system/com.example.agent = {{\n\tactive count = 1\n\tpath = /Library/LaunchDaemons/com.example.agent.plist\n\tstate = {}\n\n\tprogram = /usr/local/bin/agent\n{}\tspawn type = daemon (3)\n}}\n",
+                state, extra
+            )
+        };
+
+        let running = print("running", "\tpid = 412\n\tlast exit code = (never exited)\n");
+        assert_eq!(
+            launchctl_status(Ok(running)),
+            Ok(unit(ServiceStatus::Running, Some("running"), Some(412)))
+        );
+
+        let stopped = print("not running", "\tlast exit code = 0\n");
+        assert_eq!(
+            launchctl_status(Ok(stopped)),
+            Ok(unit(ServiceStatus::Stopped, Some("not running"), None))
+        );
+
+        let failed = print("not running", "\tlast exit code = 78: EX_CONFIG\n");
+        assert_eq!(
+            launchctl_status(Ok(failed)),
+            Ok(unit(ServiceStatus::Failed, Some("not running"), None))
+        );
+
+        let respawning = print("spawn scheduled", "\tlast exit code = 1\n");
+        assert_eq!(
+            launchctl_status(Ok(respawning)),
+            Ok(unit(ServiceStatus::Changing, Some("spawn scheduled"), None))
+        );
+
+        let missing = String::from("launchctl failed: Could not find service \"com.example.missing\" in domain for system");
+        assert_eq!(launchctl_status(Err(missing)), Ok(unit(ServiceStatus::NotFound, None, None)));
+
+        let denied = String::from("launchctl failed: Operation not permitted");
+        assert!(launchctl_status(Err(denied)).is_err());
+    }
+}
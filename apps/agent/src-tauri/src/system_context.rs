@@ -0,0 +1,496 @@
+use crate::heartbeat::{gather_system_info, HeartbeatRequest};
+use crate::logger::get_log_path;
+use crate::reboot::is_reboot_pending;
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+
+#[cfg(target_os = "windows")]
+use std::os::windows::process::CommandExt;
+
+#[cfg(target_os = "windows")]
+const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+const TOP_PROCESS_COUNT: usize = 5;
+const RECENT_ERROR_COUNT: usize = 10;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DiskInfo {
+    pub mount: String,
+    pub total_bytes: u64,
+    pub free_bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub name: String,
+    /// Percent of one core on unix, total CPU seconds on Windows
+    pub cpu: f64,
+    pub memory_bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SystemContext {
+    pub collected_at: String,
+    pub system: HeartbeatRequest,
+    pub os: String,
+    pub uptime_secs: Option<u64>,
+    pub disks: Vec<DiskInfo>,
+    pub top_cpu: Vec<ProcessInfo>,
+    pub top_memory: Vec<ProcessInfo>,
+    pub recent_errors: Vec<String>,
+    pub pending_reboot: Option<bool>,
+}
+
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    let mut command = Command::new(program);
+    command.args(args);
+
+    #[cfg(target_os = "windows")]
+    command.creation_flags(CREATE_NO_WINDOW);
+
+    let output = command.output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Seconds since the machine booted
+pub fn get_uptime_secs() -> Option<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        let uptime = std::fs::read_to_string("/proc/uptime").ok()?;
+        let secs: f64 = uptime.split_whitespace().next()?.parse().ok()?;
+        Some(secs as u64)
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        // Prints "{ sec = 1700000000, usec = 0 } Tue Nov 14 22:13:20 2023"
+        let output = run("sysctl", &["-n", "kern.boottime"])?;
+        let boot: i64 = output
+            .split("sec =")
+            .nth(1)?
+            .split(',')
+            .next()?
+            .trim()
+            .parse()
+            .ok()?;
+        Some((chrono::Utc::now().timestamp() - boot).max(0) as u64)
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        let output = run(
+            "powershell",
+            &[
+                "-NoProfile",
+                "-Command",
+                "[int64]((Get-Date) - (Get-CimInstance Win32_OperatingSystem).LastBootUpTime).TotalSeconds",
+            ],
+        )?;
+        output.trim().parse().ok()
+    }
+}
+
+/// Free and total space of each fixed disk
+pub fn get_disks() -> Vec<DiskInfo> {
+    #[cfg(unix)]
+    {
+        // POSIX output keeps each filesystem on one line: source, 1K-blocks, used, available, capacity, mount
+        let output = match run("df", &["-kP"]) {
+            Some(output) => output,
+            None => return Vec::new(),
+        };
+
+        output
+            .lines()
+            .skip(1)
+            .filter_map(|line| {
+                let parts: Vec<&str> = line.split_whitespace().collect();
+                if parts.len() < 6 || !parts[0].starts_with('/') {
+                    return None;
+                }
+                Some(DiskInfo {
+                    mount: parts[5..].join(" "),
+                    total_bytes: parts[1].parse::<u64>().ok()? * 1024,
+                    free_bytes: parts[3].parse::<u64>().ok()? * 1024,
+                })
+            })
+            .collect()
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        let output = match run(
+            "powershell",
+            &[
+                "-NoProfile",
+                "-Command",
+                "Get-CimInstance Win32_LogicalDisk -Filter 'DriveType=3' | ForEach-Object { \"$($_.DeviceID),$($_.Size),$($_.FreeSpace)\" }",
+            ],
+        ) {
+            Some(output) => output,
+            None => return Vec::new(),
+        };
+
+        output
+            .lines()
+            .filter_map(|line| {
+                let parts: Vec<&str> = line.trim().split(',').collect();
+                if parts.len() != 3 {
+                    return None;
+                }
+                Some(DiskInfo {
+                    mount: parts[0].to_string(),
+                    total_bytes: parts[1].parse().ok()?,
+                    free_bytes: parts[2].parse().ok()?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Snapshot of running processes
+pub fn get_processes() -> Vec<ProcessInfo> {
+    #[cfg(unix)]
+    {
+        // `comm=` last so names with spaces stay intact, rss is in KiB
+        let output = match run("ps", &["-Ao", "pid=,pcpu=,rss=,comm="]) {
+            Some(output) => output,
+            None => return Vec::new(),
+        };
+
+        output
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                let pid = parts.next()?.parse().ok()?;
+                let cpu = parts.next()?.parse().ok()?;
+                let rss: u64 = parts.next()?.parse().ok()?;
+                let name = parts.collect::<Vec<&str>>().join(" ");
+                Some(ProcessInfo {
+                    pid,
+                    name,
+                    cpu,
+                    memory_bytes: rss * 1024,
+                })
+            })
+            .collect()
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        let output = match run(
+            "powershell",
+            &[
+                "-NoProfile",
+                "-Command",
+                "Get-Process | ForEach-Object { \"$($_.Id),$([math]::Round($_.CPU, 2)),$($_.WorkingSet64),$($_.ProcessName)\" }",
+            ],
+        ) {
+            Some(output) => output,
+            None => return Vec::new(),
+        };
+
+        output
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.trim().splitn(4, ',');
+                let pid = parts.next()?.parse().ok()?;
+                let cpu = parts.next()?.parse().unwrap_or(0.0);
+                let memory_bytes = parts.next()?.parse().ok()?;
+                let name = parts.next()?.to_string();
+                Some(ProcessInfo {
+                    pid,
+                    name,
+                    cpu,
+                    memory_bytes,
+                })
+            })
+            .collect()
+    }
+}
+
+fn top_processes(processes: &[ProcessInfo], by_cpu: bool) -> Vec<ProcessInfo> {
+    let mut sorted = processes.to_vec();
+    if by_cpu {
+        sorted.sort_by(|a, b| b.cpu.total_cmp(&a.cpu));
+    } else {
+        sorted.sort_by_key(|p| std::cmp::Reverse(p.memory_bytes));
+    }
+    sorted.truncate(TOP_PROCESS_COUNT);
+    sorted
+}
+
+/// The last few ERROR lines from the agent's own log
+pub fn get_recent_errors() -> Vec<String> {
+    let contents = match std::fs::read_to_string(get_log_path()) {
+        Ok(contents) => contents,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut errors: Vec<String> = contents
+        .lines()
+        .rev()
+        .filter(|line| line.contains("][ERROR]"))
+        .take(RECENT_ERROR_COUNT)
+        .map(String::from)
+        .collect();
+    errors.reverse();
+    errors
+}
+
+/// Collects a snapshot of the machine for techs working the ticket
+pub async fn gather_system_context() -> Result<SystemContext, Box<dyn std::error::Error>> {
+    let system = gather_system_info().await?;
+
+    // Everything below shells out, keep it off the async workers
+    let context = tauri::async_runtime::spawn_blocking(move || {
+        let processes = get_processes();
+        SystemContext {
+            collected_at: chrono::Utc::now().to_rfc3339(),
+            system,
+            os: format!("{} {}", std::env::consts::OS, std::env::consts::ARCH),
+            uptime_secs: get_uptime_secs(),
+            disks: get_disks(),
+            top_cpu: top_processes(&processes, true),
+            top_memory: top_processes(&processes, false),
+            recent_errors: get_recent_errors(),
+            pending_reboot: is_reboot_pending(),
+        }
+    })
+    .await?;
+
+    Ok(context)
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const GB: f64 = 1024.0 * 1024.0 * 1024.0;
+    const MB: f64 = 1024.0 * 1024.0;
+
+    if bytes as f64 >= GB {
+        format!("{:.1} GB", bytes as f64 / GB)
+    } else {
+        format!("{:.0} MB", bytes as f64 / MB)
+    }
+}
+
+// ps reports a share of one core, Get-Process only the CPU time used since the process started
+fn format_cpu(cpu: f64) -> String {
+    #[cfg(unix)]
+    {
+        format!("{:.1}% CPU", cpu)
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        format!("{:.1}s CPU time", cpu)
+    }
+}
+
+fn format_uptime(secs: u64) -> String {
+    let days = secs / 86_400;
+    let hours = (secs % 86_400) / 3_600;
+    let minutes = (secs % 3_600) / 60;
+
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, minutes)
+    } else {
+        format!("{}h {}m", hours, minutes)
+    }
+}
+
+/// Renders the snapshot as a plain text note for the ticket body
+pub fn render_context_note(context: &SystemContext) -> String {
+    let unknown = || String::from("Unknown");
+    let mut lines = vec![String::from("[System Context]")];
+
+    lines.push(format!("Hostname: {}", context.system.hostname));
+    lines.push(format!(
+        "User: {}",
+        context.system.username.clone().unwrap_or_else(unknown)
+    ));
+    lines.push(format!("OS: {}", context.os));
+    lines.push(format!("Agent Version: {}", context.system.version));
+    lines.push(format!(
+        "Local IP: {}",
+        context.system.ip_address.clone().unwrap_or_else(unknown)
+    ));
+    lines.push(format!(
+        "External IP: {}",
+        context.system.ext_address.clone().unwrap_or_else(unknown)
+    ));
+    lines.push(format!(
+        "Uptime: {}",
+        context.uptime_secs.map(format_uptime).unwrap_or_else(unknown)
+    ));
+    lines.push(format!(
+        "Pending Reboot: {}",
+        match context.pending_reboot {
+            Some(true) => "Yes",
+            Some(false) => "No",
+            None => "Unknown",
+        }
+    ));
+
+    if !context.disks.is_empty() {
+        lines.push(String::from("Disks:"));
+        for disk in &context.disks {
+            lines.push(format!(
+                "  {} - {} free of {}",
+                disk.mount,
+                format_bytes(disk.free_bytes),
+                format_bytes(disk.total_bytes)
+            ));
+        }
+    }
+
+    if !context.top_cpu.is_empty() {
+        lines.push(String::from("Top CPU:"));
+        for process in &context.top_cpu {
+            lines.push(format!("  {} ({}) - {}", process.name, process.pid, format_cpu(process.cpu)));
+        }
+    }
+
+    if !context.top_memory.is_empty() {
+        lines.push(String::from("Top Memory:"));
+        for process in &context.top_memory {
+            lines.push(format!(
+                "  {} ({}) - {}",
+                process.name,
+                process.pid,
+                format_bytes(process.memory_bytes)
+            ));
+        }
+    }
+
+    if !context.recent_errors.is_empty() {
+        lines.push(String::from("Recent Agent Errors:"));
+        for error in &context.recent_errors {
+            lines.push(format!("  {}", error));
+        }
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(pid: u32, cpu: f64, memory_bytes: u64) -> ProcessInfo {
+        ProcessInfo {
+            pid,
+            name: format!("proc{}", pid),
+            cpu,
+            memory_bytes,
+        }
+    }
+
+    fn context() -> SystemContext {
+        SystemContext {
+            collected_at: String::from("2026-10-16T09:00:00Z"),
+            system: serde_json::from_value(serde_json::json!({
+                "hostname": "front-desk",
+                "ip_address": "10.0.0.5",
+                "ext_address": null,
+                "version": "1.2.3",
+                "mac_address": null,
+                "guid": null,
+                "username": "alice",
+            }))
+            .unwrap(),
+            os: String::from("linux x86_64"),
+            uptime_secs: Some(90_061),
+            disks: vec![DiskInfo {
+                mount: String::from("/"),
+                total_bytes: 512 * 1024 * 1024 * 1024,
+                free_bytes: 300 * 1024 * 1024,
+            }],
+            top_cpu: vec![process(42, 87.5, 0)],
+            top_memory: vec![process(7, 0.0, 3 * 1024 * 1024 * 1024 / 2)],
+            recent_errors: vec![String::from("[2026-10-16 08:59:00][ERROR] Heartbeat failed")],
+            pending_reboot: None,
+        }
+    }
+
+    #[test]
+    fn bytes_show_as_megabytes_below_a_gigabyte() {
+        assert_eq!(format_bytes(0), "0 MB");
+        assert_eq!(format_bytes(300 * 1024 * 1024), "300 MB");
+        assert_eq!(format_bytes(1024 * 1024 * 1024 - 1), "1024 MB");
+        assert_eq!(format_bytes(1024 * 1024 * 1024), "1.0 GB");
+        assert_eq!(format_bytes(5 * 1024 * 1024 * 1024 / 2), "2.5 GB");
+    }
+
+    #[test]
+    fn uptime_shows_days_only_when_there_are_any() {
+        assert_eq!(format_uptime(59), "0h 0m");
+        assert_eq!(format_uptime(3_660), "1h 1m");
+        assert_eq!(format_uptime(86_399), "23h 59m");
+        assert_eq!(format_uptime(90_061), "1d 1h 1m");
+    }
+
+    #[test]
+    fn cpu_is_labelled_with_what_the_platform_measures() {
+        #[cfg(unix)]
+        assert_eq!(format_cpu(12.345), "12.3% CPU");
+        #[cfg(target_os = "windows")]
+        assert_eq!(format_cpu(12.345), "12.3s CPU time");
+    }
+
+    #[test]
+    fn top_processes_are_the_busiest_few() {
+        let processes: Vec<ProcessInfo> = (1..=8).map(|pid| process(pid, pid as f64, (9 - pid) as u64)).collect();
+
+        let pids = |top: Vec<ProcessInfo>| top.iter().map(|p| p.pid).collect::<Vec<_>>();
+        assert_eq!(pids(top_processes(&processes, true)), vec![8, 7, 6, 5, 4]);
+        assert_eq!(pids(top_processes(&processes, false)), vec![1, 2, 3, 4, 5]);
+        assert!(top_processes(&[], true).is_empty());
+    }
+
+    #[test]
+    fn context_note_lists_everything_collected() {
+        let note = render_context_note(&context());
+
+        let cpu_line = format!("  proc42 (42) - {}", format_cpu(87.5));
+        let expected = [
+            "[System Context]",
+            "Hostname: front-desk",
+            "User: alice",
+            "OS: linux x86_64",
+            "Agent Version: 1.2.3",
+            "Local IP: 10.0.0.5",
+            "External IP: Unknown",
+            "Uptime: 1d 1h 1m",
+            "Pending Reboot: Unknown",
+            "Disks:",
+            "  / - 300 MB free of 512.0 GB",
+            "Top CPU:",
+            cpu_line.as_str(),
+            "Top Memory:",
+            "  proc7 (7) - 1.5 GB",
+            "Recent Agent Errors:",
+            "  [2026-10-16 08:59:00][ERROR] Heartbeat failed",
+        ];
+        assert_eq!(note.lines().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn context_note_leaves_out_empty_sections() {
+        let mut context = context();
+        context.disks.clear();
+        context.top_cpu.clear();
+        context.top_memory.clear();
+        context.recent_errors.clear();
+        context.pending_reboot = Some(true);
+
+        let note = render_context_note(&context);
+        assert!(note.ends_with("Pending Reboot: Yes"));
+        for section in ["Disks:", "Top CPU:", "Top Memory:", "Recent Agent Errors:"] {
+            assert!(!note.contains(section));
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use crate::device_manager::{get_api_endpoint, get_rmm_device_id, get_settings};
+use crate::logger::log_to_file;
+use crate::outbox::{
+    apply_payload, enqueue_payload, generate_idempotency_key, is_network_error, OutboxFile,
+    OutboxPayload,
+};
+use crate::system_context::{gather_system_context, render_context_note, SystemContext};
+use crate::ticket_store::record_ticket;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const TICKET_PATH: &str = "/v1.0/ticket/create";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TicketRequest {
+    pub summary: String,
+    pub description: Option<String>,
+    pub name: String,
+    pub email: String,
+    pub phone: String,
+    pub impact: String,
+    pub urgency: String,
+    pub rmm_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TicketAttachment {
+    pub file_name: String,
+    pub mime_type: String,
+    pub data: String, // base64
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TicketSubmission {
+    pub ticket: TicketRequest,
+    pub screenshot: Option<TicketAttachment>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TicketResponse {
+    pub data: Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TicketOutcome {
+    Created { ticket_id: String },
+    Queued { outbox_id: String },
+}
+
+/// Extracts the ticket id from a ticket endpoint response body
+pub fn parse_ticket_id(response_text: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let result: TicketResponse = serde_json::from_str(response_text)?;
+
+    Ok(match result.data {
+        Value::String(id) => id,
+        other => other.to_string(),
+    })
+}
+
+fn build_payload(submission: TicketSubmission, context: Option<SystemContext>) -> OutboxPayload {
+    let ticket = submission.ticket;
+
+    let mut description = ticket.description.unwrap_or_default();
+    if let Some(context) = &context {
+        if !description.is_empty() {
+            description.push_str("\n\n");
+        }
+        description.push_str(&render_context_note(context));
+    }
+
+    let mut fields = vec![
+        (String::from("summary"), ticket.summary),
+        (String::from("description"), description),
+        (String::from("impact"), ticket.impact),
+        (String::from("urgency"), ticket.urgency),
+        (String::from("name"), ticket.name),
+        (String::from("email"), ticket.email),
+        (String::from("phone"), ticket.phone),
+    ];
+    if let Some(rmm_id) = ticket.rmm_id {
+        fields.push((String::from("rmm_id"), rmm_id));
+    }
+    if let Some(context) = context.and_then(|c| serde_json::to_string(&c).ok()) {
+        fields.push((String::from("system_context"), context));
+    }
+
+    let files = submission
+        .screenshot
+        .map(|screenshot| OutboxFile {
+            field: String::from("screenshot"),
+            file_name: screenshot.file_name,
+            mime_type: screenshot.mime_type,
+            data: screenshot.data,
+        })
+        .into_iter()
+        .collect();
+
+    OutboxPayload::Multipart { fields, files }
+}
+
+/// Submits a ticket as multipart form data, queueing it in the outbox if it can't be delivered now
+///
+/// `owner` is the account the ticket is tracked for, only it sees the ticket's replies
+pub async fn submit_ticket(
+    mut submission: TicketSubmission,
+    owner: Option<String>,
+) -> Result<TicketOutcome, Box<dyn std::error::Error>> {
+    let settings = get_settings().await?;
+    let device_id = settings
+        .device_id
+        .ok_or("Device not registered, cannot create ticket")?;
+
+    if submission.ticket.rmm_id.is_none() {
+        submission.ticket.rmm_id = get_rmm_device_id();
+    }
+
+    // Context is a nice-to-have, never hold up the ticket because part of it failed
+    let context = match gather_system_context().await {
+        Ok(context) => Some(context),
+        Err(e) => {
+            log_to_file(
+                String::from("WARN"),
+                format!("Failed to gather system context for ticket: {}", e),
+            );
+            None
+        }
+    };
+
+    let summary = submission.ticket.summary.clone();
+    let api_url = get_api_endpoint(TICKET_PATH).await?;
+    let payload = build_payload(submission, context);
+
+    // Bounded so the service answers the UI before its IPC request gives up
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(120))
+        .build()?;
+    // The outbox resends with the same key, so a ticket that did arrive isn't created twice
+    let idempotency_key = generate_idempotency_key();
+    let request = client
+        .post(&api_url)
+        .header("x-device-id", device_id)
+        .header("x-site-id", settings.site_id)
+        .header("x-idempotency-key", &idempotency_key);
+    let request = apply_payload(request, &payload)?;
+
+    let response = match request.send().await {
+        Ok(response) => response,
+        Err(e) if is_network_error(&e) => {
+            let message =
+                enqueue_payload(idempotency_key, "ticket", TICKET_PATH, payload, owner).await?;
+            return Ok(TicketOutcome::Queued {
+                outbox_id: message.id,
+            });
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let status = response.status();
+
+    if status.is_success() {
+        let response_text = response.text().await?;
+        let ticket_id = parse_ticket_id(&response_text)?;
+
+        if let Err(e) = record_ticket(&ticket_id, &summary, owner.as_deref()).await {
+            log_to_file(
+                String::from("WARN"),
+                format!("Failed to track ticket {}: {}", ticket_id, e),
+            );
+        }
+
+        Ok(TicketOutcome::Created { ticket_id })
+    } else if status.is_server_error() {
+        // The server may recover before the user gives up, keep the ticket for a retry
+        let message =
+            enqueue_payload(idempotency_key, "ticket", TICKET_PATH, payload, owner).await?;
+        Ok(TicketOutcome::Queued {
+            outbox_id: message.id,
+        })
+    } else {
+        let error_text = response
+            .text()
+            .await
+            .unwrap_or_else(|_| "Unknown error".to_string());
+        Err(format!("Ticket creation failed ({}): {}", status, error_text).into())
+    }
+}
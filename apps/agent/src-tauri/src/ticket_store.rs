@@ -0,0 +1,217 @@
+use crate::device_manager::get_config_dir;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::sync::Mutex;
+
+// Older tickets fall off the list so the store never grows without bound
+const MAX_TRACKED_TICKETS: usize = 50;
+const CLOSED_TICKET_RETENTION_DAYS: i64 = 30;
+
+static TICKET_STORE_LOCK: Mutex<()> = Mutex::const_new(());
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TicketChange {
+    Replied,
+    Closed,
+    StatusChanged,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrackedTicket {
+    pub id: String,
+    pub summary: String,
+    pub created_at: String,
+    pub status: Option<String>,
+    pub closed: bool,
+    pub last_reply_at: Option<String>,
+    pub last_reply: Option<String>,
+    pub updated_at: Option<String>,
+    /// Bumped on every change so watchers can tell what they've already shown
+    pub revision: u32,
+    pub last_change: Option<TicketChange>,
+    /// Account that submitted it, a uid on unix and a SID on Windows
+    #[serde(default)]
+    pub owner: Option<String>,
+}
+
+/// Ticket state reported by the server in the heartbeat response
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TicketStatusUpdate {
+    pub id: String,
+    pub status: Option<String>,
+    pub closed: Option<bool>,
+    pub last_reply_at: Option<String>,
+    pub last_reply: Option<String>,
+}
+
+pub fn get_ticket_store_path() -> PathBuf {
+    get_config_dir().join("tickets.json")
+}
+
+async fn read_tickets() -> Result<Vec<TrackedTicket>, Box<dyn std::error::Error>> {
+    let path = get_ticket_store_path();
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let content = tokio::fs::read(&path).await?;
+    Ok(serde_json::from_slice(&content)?)
+}
+
+async fn write_tickets(tickets: &[TrackedTicket]) -> Result<(), Box<dyn std::error::Error>> {
+    let path = get_ticket_store_path();
+    let tmp_path = path.with_extension("tmp");
+
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(&tmp_path, serde_json::to_vec_pretty(tickets)?).await?;
+    tokio::fs::rename(&tmp_path, &path).await?;
+    Ok(())
+}
+
+fn prune(tickets: &mut Vec<TrackedTicket>) {
+    let cutoff = chrono::Utc::now() - chrono::Duration::days(CLOSED_TICKET_RETENTION_DAYS);
+    tickets.retain(|ticket| {
+        let last_activity = ticket.updated_at.as_ref().unwrap_or(&ticket.created_at);
+        let stale = chrono::DateTime::parse_from_rfc3339(last_activity)
+            .map(|time| time < cutoff)
+            .unwrap_or(false);
+        !(ticket.closed && stale)
+    });
+
+    // Newest first, then drop the oldest past the limit
+    tickets.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    tickets.truncate(MAX_TRACKED_TICKETS);
+}
+
+/// Lists tickets this device created, newest first
+pub async fn list_tickets() -> Result<Vec<TrackedTicket>, Box<dyn std::error::Error>> {
+    let _lock = TICKET_STORE_LOCK.lock().await;
+    let mut tickets = read_tickets().await?;
+    tickets.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    Ok(tickets)
+}
+
+/// Lists tickets the given account submitted, newest first
+pub async fn list_owned_tickets(owner: &str) -> Result<Vec<TrackedTicket>, Box<dyn std::error::Error>> {
+    // Tickets from before owners were recorded belong to nobody, replies can hold private details
+    Ok(list_tickets()
+        .await?
+        .into_iter()
+        .filter(|ticket| ticket.owner.as_deref() == Some(owner))
+        .collect())
+}
+
+/// Ids of tickets the server should report status for
+pub async fn open_ticket_ids() -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    Ok(list_tickets()
+        .await?
+        .into_iter()
+        .filter(|ticket| !ticket.closed)
+        .map(|ticket| ticket.id)
+        .collect())
+}
+
+/// Remembers a ticket the server just created for `owner`
+pub async fn record_ticket(
+    id: &str,
+    summary: &str,
+    owner: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let _lock = TICKET_STORE_LOCK.lock().await;
+    let mut tickets = read_tickets().await?;
+
+    if tickets.iter().any(|ticket| ticket.id == id) {
+        return Ok(());
+    }
+
+    tickets.push(TrackedTicket {
+        id: id.to_string(),
+        summary: summary.to_string(),
+        created_at: chrono::Utc::now().to_rfc3339(),
+        status: None,
+        closed: false,
+        last_reply_at: None,
+        last_reply: None,
+        updated_at: None,
+        revision: 0,
+        last_change: None,
+        owner: owner.map(str::to_string),
+    });
+    prune(&mut tickets);
+    write_tickets(&tickets).await
+}
+
+/// Applies server status updates, returning how many tickets changed
+pub async fn apply_ticket_updates(
+    updates: &[TicketStatusUpdate],
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let _lock = TICKET_STORE_LOCK.lock().await;
+    let mut tickets = read_tickets().await?;
+    let mut changed = 0;
+
+    for update in updates {
+        let ticket = match tickets.iter_mut().find(|ticket| ticket.id == update.id) {
+            Some(ticket) => ticket,
+            None => continue,
+        };
+
+        // A close outranks a reply that came with it, a reply outranks a plain status change
+        let mut change = None;
+        if update.status.is_some() && update.status != ticket.status {
+            ticket.status = update.status.clone();
+            change = Some(TicketChange::StatusChanged);
+        }
+        if update.last_reply_at.is_some() && update.last_reply_at != ticket.last_reply_at {
+            ticket.last_reply_at = update.last_reply_at.clone();
+            ticket.last_reply = update.last_reply.clone();
+            change = Some(TicketChange::Replied);
+        }
+        if let Some(closed) = update.closed {
+            if closed != ticket.closed {
+                ticket.closed = closed;
+                if closed {
+                    change = Some(TicketChange::Closed);
+                } else {
+                    change = change.or(Some(TicketChange::StatusChanged));
+                }
+            }
+        }
+
+        if change.is_some() {
+            ticket.revision += 1;
+            ticket.last_change = change;
+            ticket.updated_at = Some(chrono::Utc::now().to_rfc3339());
+            changed += 1;
+        }
+    }
+
+    if changed > 0 {
+        prune(&mut tickets);
+        write_tickets(&tickets).await?;
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn tickets_are_only_listed_for_their_owner() {
+        record_ticket("owned-1", "Printer jammed", Some("1001")).await.unwrap();
+        record_ticket("owned-2", "VPN down", Some("1002")).await.unwrap();
+        record_ticket("unowned", "Old ticket", None).await.unwrap();
+
+        let ids = |tickets: Vec<TrackedTicket>| tickets.into_iter().map(|t| t.id).collect::<Vec<_>>();
+        assert_eq!(ids(list_owned_tickets("1001").await.unwrap()), vec!["owned-1"]);
+        assert_eq!(ids(list_owned_tickets("1002").await.unwrap()), vec!["owned-2"]);
+        assert!(list_owned_tickets("1003").await.unwrap().is_empty());
+
+        // Every ticket is still polled for, whoever it belongs to
+        let open = open_ticket_ids().await.unwrap();
+        assert!(["owned-1", "owned-2", "unowned"].iter().all(|id| open.contains(&id.to_string())));
+    }
+}
@@ -0,0 +1,385 @@
+use crate::agent_state::{AgentHealth, AgentState};
+use crate::branding::{get_logo, load_branding};
+use crate::logger::log_to_file;
+use crate::recording::replay_buffer_running;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tauri::menu::{Menu, MenuItem, MenuItemKind, PredefinedMenuItem, Submenu};
+use tauri::image::Image;
+use tauri::{AppHandle, Manager, Wry};
+
+pub const TRAY_ID: &str = "main";
+
+// Deeper nesting is unusable from a tray, anything below this is dropped
+const MAX_MENU_DEPTH: usize = 3;
+
+// Links leave the agent, keep them to schemes that open a browser, mail client or dialer
+const ALLOWED_URL_SCHEMES: [&str; 4] = ["https://", "http://", "mailto:", "tel:"];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TrayWindow {
+    Support,
+    SupportScreenshot,
+    About,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TrayAction {
+    OpenUrl { url: String },
+    OpenWindow { window: TrayWindow },
+    RunJob { job_id: String },
+    ShowInfo { title: String, message: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TrayMenuEntry {
+    /// An item without an action is shown as plain text, e.g. a phone number
+    Item {
+        label: String,
+        #[serde(default)]
+        action: Option<TrayAction>,
+    },
+    Separator,
+    Submenu {
+        label: String,
+        items: Vec<TrayMenuEntry>,
+    },
+}
+
+/// Menu item id to the action it runs
+pub type TrayActionMap = HashMap<String, TrayAction>;
+
+/// Actions behind the menu item ids of the current tray menu
+pub struct TrayActions(Mutex<TrayActionMap>);
+
+impl TrayActions {
+    pub fn new(actions: TrayActionMap) -> Self {
+        TrayActions(Mutex::new(actions))
+    }
+
+    pub fn replace(&self, actions: TrayActionMap) {
+        if let Ok(mut current) = self.0.lock() {
+            *current = actions;
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<TrayAction> {
+        self.0.lock().ok()?.get(id).cloned()
+    }
+}
+
+/// The menu shown until the server sends one
+pub fn default_tray_menu() -> Vec<TrayMenuEntry> {
+    vec![
+        TrayMenuEntry::Item {
+            label: String::from("Take Screenshot and Request Support"),
+            action: Some(TrayAction::OpenWindow {
+                window: TrayWindow::SupportScreenshot,
+            }),
+        },
+        TrayMenuEntry::Item {
+            label: String::from("Request Support"),
+            action: Some(TrayAction::OpenWindow {
+                window: TrayWindow::Support,
+            }),
+        },
+        TrayMenuEntry::Item {
+            label: String::from("About"),
+            action: Some(TrayAction::OpenWindow {
+                window: TrayWindow::About,
+            }),
+        },
+    ]
+}
+
+/// Checks an action from the menu definition, returning why it can't be used
+pub fn validate_action(action: &TrayAction) -> Result<(), String> {
+    match action {
+        TrayAction::OpenUrl { url } => {
+            let lower = url.to_lowercase();
+            if ALLOWED_URL_SCHEMES.iter().any(|scheme| lower.starts_with(scheme)) {
+                Ok(())
+            } else {
+                Err(format!("URL scheme not allowed: {}", url))
+            }
+        }
+        TrayAction::RunJob { job_id } if job_id.trim().is_empty() => {
+            Err(String::from("Job id is empty"))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Whether the menu definition offers the given job, users may only run jobs the server put in their menu
+pub fn menu_offers_job(entries: &[TrayMenuEntry], job_id: &str) -> bool {
+    offers_job(entries, job_id, 0)
+}
+
+// Follows build_items, a job in a submenu too deep to be shown isn't offered
+fn offers_job(entries: &[TrayMenuEntry], job_id: &str, depth: usize) -> bool {
+    entries.iter().any(|entry| match entry {
+        TrayMenuEntry::Item {
+            action: Some(TrayAction::RunJob { job_id: id }),
+            ..
+        } => id == job_id,
+        TrayMenuEntry::Submenu { items, .. } => depth < MAX_MENU_DEPTH && offers_job(items, job_id, depth + 1),
+        _ => false,
+    })
+}
+
+fn build_items(
+    app: &AppHandle,
+    entries: &[TrayMenuEntry],
+    path: &str,
+    depth: usize,
+    actions: &mut TrayActionMap,
+) -> Result<Vec<MenuItemKind<Wry>>, Box<dyn std::error::Error>> {
+    let mut items = Vec::new();
+
+    for (index, entry) in entries.iter().enumerate() {
+        let id = format!("{}_{}", path, index);
+        match entry {
+            TrayMenuEntry::Item { label, action } => {
+                let action = match action {
+                    Some(action) => match validate_action(action) {
+                        Ok(()) => Some(action.clone()),
+                        Err(e) => {
+                            log_to_file(
+                                String::from("WARN"),
+                                format!("Skipping tray menu item '{}': {}", label, e),
+                            );
+                            continue;
+                        }
+                    },
+                    None => None,
+                };
+
+                let enabled = action.is_some();
+                if let Some(action) = action {
+                    actions.insert(id.clone(), action);
+                }
+                items.push(MenuItemKind::MenuItem(MenuItem::with_id(
+                    app,
+                    id,
+                    label,
+                    enabled,
+                    None::<&str>,
+                )?));
+            }
+            TrayMenuEntry::Separator => {
+                items.push(MenuItemKind::Predefined(PredefinedMenuItem::separator(app)?));
+            }
+            TrayMenuEntry::Submenu { label, items: children } => {
+                if depth >= MAX_MENU_DEPTH {
+                    log_to_file(
+                        String::from("WARN"),
+                        format!("Skipping tray submenu '{}': nested too deep", label),
+                    );
+                    continue;
+                }
+
+                let submenu = Submenu::with_id(app, id.clone(), label, true)?;
+                for child in build_items(app, children, &id, depth + 1, actions)? {
+                    submenu.append(&child)?;
+                }
+                items.push(MenuItemKind::Submenu(submenu));
+            }
+        }
+    }
+
+    Ok(items)
+}
+
+/// Builds a tray menu from its definition along with the action behind each item id
+pub fn build_tray_menu(
+    app: &AppHandle,
+    entries: &[TrayMenuEntry],
+) -> Result<(Menu<Wry>, TrayActionMap), Box<dyn std::error::Error>> {
+    let mut actions = HashMap::new();
+    let menu = Menu::new(app)?;
+    for item in build_items(app, entries, "tray", 0, &mut actions)? {
+        menu.append(&item)?;
+    }
+    Ok((menu, actions))
+}
+
+/// Replaces the menu of the running tray icon
+pub fn apply_tray_menu(app: &AppHandle, entries: &[TrayMenuEntry]) -> Result<(), Box<dyn std::error::Error>> {
+    let tray = app.tray_by_id(TRAY_ID).ok_or("Tray icon not found")?;
+    let (menu, actions) = build_tray_menu(app, entries)?;
+
+    // Item ids are positional, swap the actions right before the menu so the two stay in step
+    if let Some(state) = app.try_state::<TrayActions>() {
+        state.replace(actions);
+    }
+    tray.set_menu(Some(menu))?;
+    Ok(())
+}
+
+fn health_color(health: AgentHealth) -> [u8; 4] {
+    match health {
+        AgentHealth::Healthy => [46, 160, 67, 255],
+        AgentHealth::Attention => [219, 154, 4, 255],
+        AgentHealth::Error => [207, 34, 46, 255],
+    }
+}
+
+// A replay recording runs while the support window is hidden, the badge is how the user can
+// tell the screen is still being captured
+const RECORDING_COLOR: [u8; 4] = [130, 80, 223, 255];
+
+/// Draws a status dot with a white ring in the bottom right corner of the icon
+fn badge_icon(icon: &Image<'_>, color: [u8; 4]) -> Image<'static> {
+    let (width, height) = (icon.width(), icon.height());
+    let mut rgba = icon.rgba().to_vec();
+
+    let radius = width.min(height) as f64 * 0.22;
+    let ring = (radius * 0.3).max(1.0);
+    let center_x = width as f64 - radius - ring;
+    let center_y = height as f64 - radius - ring;
+
+    for y in 0..height {
+        for x in 0..width {
+            let distance = ((x as f64 + 0.5 - center_x).powi(2) + (y as f64 + 0.5 - center_y).powi(2)).sqrt();
+            let pixel = if distance <= radius {
+                color
+            } else if distance <= radius + ring {
+                [255, 255, 255, 255]
+            } else {
+                continue;
+            };
+            let offset = ((y * width + x) * 4) as usize;
+            rgba[offset..offset + 4].copy_from_slice(&pixel);
+        }
+    }
+
+    Image::new_owned(rgba, width, height)
+}
+
+/// The tenant's logo when one is cached, otherwise the app icon
+pub fn tray_base_icon(app: &AppHandle) -> Option<Image<'static>> {
+    if let Some(logo) = get_logo().and_then(|path| image::open(path).ok()) {
+        let logo = logo.into_rgba8();
+        let (width, height) = logo.dimensions();
+        return Some(Image::new_owned(logo.into_raw(), width, height));
+    }
+    app.default_window_icon().map(|icon| icon.clone().to_owned())
+}
+
+/// Shows the agent's health on the tray icon and its details in the tooltip, or that the
+/// screen is being recorded while a replay recording runs
+pub fn apply_tray_health(app: &AppHandle, state: &AgentState) -> Result<(), Box<dyn std::error::Error>> {
+    let tray = app.tray_by_id(TRAY_ID).ok_or("Tray icon not found")?;
+    let recording = replay_buffer_running();
+    let color = if recording { RECORDING_COLOR } else { health_color(state.health()) };
+    if let Some(icon) = tray_base_icon(app) {
+        tray.set_icon(Some(badge_icon(&icon, color)))?;
+    }
+
+    let summary = state.summary(load_branding().display_name());
+    tray.set_tooltip(Some(if recording {
+        format!("Recording the screen for a support request\n{}", summary)
+    } else {
+        summary
+    }))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(label: &str, job_id: &str) -> TrayMenuEntry {
+        TrayMenuEntry::Item {
+            label: label.to_string(),
+            action: Some(TrayAction::RunJob {
+                job_id: job_id.to_string(),
+            }),
+        }
+    }
+
+    fn submenu(label: &str, items: Vec<TrayMenuEntry>) -> TrayMenuEntry {
+        TrayMenuEntry::Submenu {
+            label: label.to_string(),
+            items,
+        }
+    }
+
+    fn open_url(url: &str) -> TrayAction {
+        TrayAction::OpenUrl { url: url.to_string() }
+    }
+
+    #[test]
+    fn jobs_are_found_in_nested_submenus() {
+        let menu = vec![
+            job("Flush DNS", "flush-dns"),
+            TrayMenuEntry::Separator,
+            submenu(
+                "Fixes",
+                vec![
+                    job("Clear print queue", "clear-print-queue"),
+                    submenu("Network", vec![job("Reset Wi-Fi", "reset-wifi")]),
+                ],
+            ),
+        ];
+
+        assert!(menu_offers_job(&menu, "flush-dns"));
+        assert!(menu_offers_job(&menu, "clear-print-queue"));
+        assert!(menu_offers_job(&menu, "reset-wifi"));
+        assert!(!menu_offers_job(&menu, "reimage-disk"));
+        assert!(!menu_offers_job(&menu, ""));
+        assert!(!menu_offers_job(&[], "flush-dns"));
+        assert!(!menu_offers_job(&default_tray_menu(), "flush-dns"));
+    }
+
+    #[test]
+    fn jobs_too_deep_to_show_are_not_offered() {
+        let nested = |entry: TrayMenuEntry, levels: usize| {
+            (0..levels).fold(entry, |entry, level| submenu(&format!("Level {}", level), vec![entry]))
+        };
+
+        assert!(menu_offers_job(&[nested(job("Deepest shown", "shown"), MAX_MENU_DEPTH)], "shown"));
+        assert!(!menu_offers_job(&[nested(job("Hidden", "hidden"), MAX_MENU_DEPTH + 1)], "hidden"));
+    }
+
+    #[test]
+    fn only_browser_mail_and_phone_links_are_allowed() {
+        for url in [
+            "https://support.example.com",
+            "HTTP://intranet.example.com",
+            "mailto:help@example.com",
+            "tel:+15551234567",
+        ] {
+            assert!(validate_action(&open_url(url)).is_ok(), "{} should be allowed", url);
+        }
+
+        for url in [
+            "javascript:alert(1)",
+            "JavaScript:alert(1)",
+            "file:///C:/Windows/System32/cmd.exe",
+            "file:///etc/passwd",
+            "\\\\server\\share\\tool.exe",
+            "",
+        ] {
+            assert!(validate_action(&open_url(url)).is_err(), "{} should be rejected", url);
+        }
+    }
+
+    #[test]
+    fn run_job_actions_need_a_job_id() {
+        let run = |job_id: &str| TrayAction::RunJob {
+            job_id: job_id.to_string(),
+        };
+        assert!(validate_action(&run("flush-dns")).is_ok());
+        assert!(validate_action(&run("")).is_err());
+        assert!(validate_action(&run("  ")).is_err());
+        let about = TrayAction::OpenWindow {
+            window: TrayWindow::About,
+        };
+        assert!(validate_action(&about).is_ok());
+    }
+}
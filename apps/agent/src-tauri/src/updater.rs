@@ -0,0 +1,1451 @@
+use crate::agent_state::{current_state, set_update_available};
+use crate::device_manager::{get_config_dir, get_settings};
+use crate::file_transfer::hash_file;
+use crate::job_signing::{verify_payload, PinnedKey};
+use crate::jobs::running_job_count;
+use crate::logger::log_to_file;
+use crate::outbox::enqueue;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::time::{interval, Duration, Instant};
+
+#[cfg(target_os = "windows")]
+use std::os::windows::process::CommandExt;
+
+#[cfg(target_os = "windows")]
+const CREATE_NO_WINDOW: u32 = 0x08000000;
+#[cfg(target_os = "windows")]
+const DETACHED_PROCESS: u32 = 0x00000008;
+
+// Manifests are signed over this prefix so a job or key rotation can never pass as one
+const UPDATE_MANIFEST_CONTEXT: &[u8] = b"mspagent-update:";
+
+// The vendor's offline release key, baked in by the release build. Every tenant gets the same
+// manifest, and a tenant's job key must never be enough to install a binary that runs as root.
+const RELEASE_SIGNING_KEY: Option<&str> = option_env!("MSPAGENT_RELEASE_KEY");
+// Set for at least one release before manifests are signed with it, so the fleet already trusts
+// it when the rotation happens. The build after that moves it to MSPAGENT_RELEASE_KEY.
+const NEXT_RELEASE_SIGNING_KEY: Option<&str> = option_env!("MSPAGENT_RELEASE_KEY_NEXT");
+
+const UPDATE_CHECK_INTERVAL_SECS: i64 = 6 * 60 * 60;
+// How often the task looks for an idle moment to apply a staged update
+const UPDATE_POLL_SECS: u64 = 60;
+// A staged update that was held back or failed to apply is tried again after this long
+const ROLLOUT_RECHECK_SECS: u64 = 15 * 60;
+
+const MAX_PACKAGE_BYTES: u64 = 512 * 1024 * 1024;
+const DOWNLOAD_TIMEOUT_SECS: u64 = 30 * 60;
+const PREFLIGHT_TIMEOUT_SECS: u64 = 30;
+
+// A new version that hasn't checked in by then is rolled back
+const VERIFY_WINDOW_SECS: i64 = 30 * 60;
+// Starts of the new version before it's treated as crashing, the service manager restarts it in between
+const MAX_START_ATTEMPTS: u32 = 3;
+
+// The scheduled task the service runs as, it isn't restarted when it stops
+#[cfg(target_os = "windows")]
+const SERVICE_TASK: &str = "MSPAgentService";
+// How often the Windows guard looks in on the new version, and how long it waits before starting it again
+#[cfg(target_os = "windows")]
+const GUARD_POLL_SECS: u64 = 10;
+
+// Non-zero so systemd's Restart=on-failure starts the new binary
+#[cfg(unix)]
+const RESTART_EXIT_CODE: i32 = 75;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateChannel {
+    #[default]
+    Stable,
+    Beta,
+}
+
+impl UpdateChannel {
+    fn as_str(&self) -> &'static str {
+        match self {
+            UpdateChannel::Stable => "stable",
+            UpdateChannel::Beta => "beta",
+        }
+    }
+}
+
+/// A manifest as served, the signature covers the exact payload bytes like a job's
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UpdateEnvelope {
+    /// Base64 JSON of an `UpdateManifest`
+    pub payload: String,
+    /// Base64 Ed25519 signature of the update context and the decoded payload
+    pub signature: String,
+}
+
+/// The newest release on a channel
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UpdateManifest {
+    pub channel: UpdateChannel,
+    pub version: String,
+    #[serde(default)]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub released_at: Option<String>,
+    pub packages: Vec<UpdatePackage>,
+    /// Everyone takes the release when not set
+    #[serde(default)]
+    pub rollout: Option<Rollout>,
+}
+
+/// How far a release has rolled out
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Rollout {
+    /// Devices whose bucket is below this take the release, 0 to 100
+    pub percentage: u8,
+    /// Holds the rollout where it is, devices that haven't applied the release wait until it's resumed
+    #[serde(default)]
+    pub paused: bool,
+}
+
+/// Update settings and progress, sent with the heartbeat
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UpdateStatus {
+    pub channel: UpdateChannel,
+    pub rollout_bucket: Option<u8>,
+    pub pinned_version: Option<String>,
+    pub staged_version: Option<String>,
+}
+
+/// The agent executable for one platform
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UpdatePackage {
+    /// "{os}-{arch}" as Rust names them, e.g. "linux-x86_64" or "windows-aarch64"
+    pub target: String,
+    /// Absolute, or a path on the API host
+    pub url: String,
+    pub sha256: String,
+    pub size: u64,
+}
+
+/// A verified package waiting for the agent to be idle
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct StagedUpdate {
+    version: String,
+    path: PathBuf,
+    sha256: String,
+    staged_at: String,
+}
+
+/// An update swapped in, waiting for the new version to prove itself
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct AppliedUpdate {
+    from_version: String,
+    to_version: String,
+    applied_at: String,
+    #[serde(default)]
+    start_attempts: u32,
+    /// The executable that was replaced, the guard runs from the backup and can't ask for its own path
+    #[serde(default)]
+    exe: Option<PathBuf>,
+}
+
+/// What the guard does about one start of the service while an update is unconfirmed
+#[derive(Debug, PartialEq)]
+enum GuardAction {
+    Start,
+    RollBack(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct UpdateState {
+    #[serde(default)]
+    staged: Option<StagedUpdate>,
+    #[serde(default)]
+    applying: Option<AppliedUpdate>,
+    /// Versions that were rolled back, never offered again
+    #[serde(default)]
+    failed_versions: Vec<String>,
+    #[serde(default)]
+    last_checked_at: Option<String>,
+}
+
+// Serializes access to the state file
+static UPDATE_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+fn get_updates_dir() -> PathBuf {
+    get_config_dir().join("updates")
+}
+
+fn get_state_path() -> PathBuf {
+    get_updates_dir().join("state.json")
+}
+
+fn get_rollback_path() -> PathBuf {
+    get_updates_dir().join("rollback").join(exe_name())
+}
+
+fn exe_name() -> String {
+    format!("MSPAgent{}", std::env::consts::EXE_SUFFIX)
+}
+
+fn current_version() -> &'static str {
+    env!("CARGO_PKG_VERSION")
+}
+
+/// The package target this build installs
+pub fn current_target() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+/// A version part read the way JavaScript's `Number()` reads it, NaN when it isn't a number
+fn version_part(part: &str) -> f64 {
+    let part = part.trim();
+    if part.is_empty() {
+        return 0.0;
+    }
+
+    let radix = match part.get(..2).map(|prefix| prefix.to_ascii_lowercase()) {
+        Some(prefix) if prefix == "0x" => Some(16),
+        Some(prefix) if prefix == "0o" => Some(8),
+        Some(prefix) if prefix == "0b" => Some(2),
+        _ => None,
+    };
+    if let Some(radix) = radix {
+        let digits = &part[2..];
+        if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+            return f64::NAN;
+        }
+        return u64::from_str_radix(digits, radix)
+            .map(|value| value as f64)
+            .unwrap_or(f64::INFINITY);
+    }
+
+    let unsigned = part.strip_prefix(['+', '-']).unwrap_or(part);
+    if unsigned == "Infinity" {
+        return if part.starts_with('-') {
+            f64::NEG_INFINITY
+        } else {
+            f64::INFINITY
+        };
+    }
+    // Rust also reads "inf" and "nan", JavaScript doesn't
+    if !unsigned
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-'))
+    {
+        return f64::NAN;
+    }
+    part.parse().unwrap_or(f64::NAN)
+}
+
+/// Whether `value` is at least `target`, the same comparison as the backend's `isVersionGte`.
+/// Parts compare numerically, missing parts count as 0 and parts that aren't numbers count as equal.
+pub fn is_version_gte(value: &str, target: &str) -> bool {
+    let value: Vec<f64> = value.split('.').map(version_part).collect();
+    let target: Vec<f64> = target.split('.').map(version_part).collect();
+
+    for i in 0..value.len().max(target.len()) {
+        let a = value.get(i).copied().unwrap_or(0.0);
+        let b = target.get(i).copied().unwrap_or(0.0);
+        if a > b {
+            return true;
+        }
+        if a < b {
+            return false;
+        }
+    }
+    true
+}
+
+/// Strictly newer, by the same rules
+pub fn is_newer_version(value: &str, current: &str) -> bool {
+    !is_version_gte(current, value)
+}
+
+/// The device's place in staged rollouts, 0 to 99. The server works it out the same way:
+/// the first 8 bytes of the SHA-256 of the GUID, big-endian, modulo 100.
+pub fn rollout_bucket(guid: &str) -> u8 {
+    let digest = Sha256::digest(guid.trim().as_bytes());
+    let mut prefix = [0u8; 8];
+    prefix.copy_from_slice(&digest[..8]);
+    (u64::from_be_bytes(prefix) % 100) as u8
+}
+
+/// Whether a release's rollout includes this device, and why not when it doesn't
+pub fn rollout_admits(manifest: &UpdateManifest, bucket: Option<u8>, pinned: Option<&str>) -> Result<(), String> {
+    // A pinned version is an explicit choice for this device, rollouts don't hold it back
+    if let Some(pinned) = pinned {
+        if manifest.version != pinned {
+            return Err(format!("Agent is pinned to {}", pinned));
+        }
+        return Ok(());
+    }
+
+    let rollout = match &manifest.rollout {
+        Some(rollout) => rollout,
+        None => return Ok(()),
+    };
+    if rollout.paused {
+        return Err(format!("Rollout of {} is paused", manifest.version));
+    }
+    let percentage = rollout.percentage.min(100);
+    // Without a GUID there's no bucket, such devices wait for the full rollout
+    match bucket {
+        _ if percentage == 100 => Ok(()),
+        Some(bucket) if bucket < percentage => Ok(()),
+        _ => Err(format!(
+            "Device is outside the {}% rollout of {}",
+            percentage, manifest.version
+        )),
+    }
+}
+
+async fn load_state() -> UpdateState {
+    match tokio::fs::read_to_string(get_state_path()).await {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+            log_to_file(
+                "WARN".to_string(),
+                format!("Update state is unreadable, starting over: {}", e),
+            );
+            UpdateState::default()
+        }),
+        Err(_) => UpdateState::default(),
+    }
+}
+
+async fn save_state(state: &UpdateState) -> Result<(), String> {
+    let path = get_state_path();
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    let content = serde_json::to_string_pretty(state).map_err(|e| e.to_string())?;
+    tokio::fs::write(&path, content)
+        .await
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+async fn update_state<T>(update: impl FnOnce(&mut UpdateState) -> T) -> Result<T, String> {
+    let _lock = UPDATE_LOCK.lock().await;
+    let mut state = load_state().await;
+    let value = update(&mut state);
+    save_state(&state).await?;
+    Ok(value)
+}
+
+/// The release keys compiled into this build, empty when it was built without one
+pub fn release_keys() -> Vec<PinnedKey> {
+    [RELEASE_SIGNING_KEY, NEXT_RELEASE_SIGNING_KEY]
+        .into_iter()
+        .flatten()
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .map(|key| PinnedKey {
+            key: key.to_string(),
+            pinned_at: String::new(),
+            retires_at: None,
+        })
+        .collect()
+}
+
+/// Checks a manifest's signature against the release keys and that it's for the channel asked for
+pub fn verify_manifest(
+    envelope: &UpdateEnvelope,
+    keys: &[PinnedKey],
+    channel: UpdateChannel,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<UpdateManifest, String> {
+    let payload = STANDARD
+        .decode(envelope.payload.trim())
+        .map_err(|_| String::from("Manifest payload is not valid base64"))?;
+    verify_payload(UPDATE_MANIFEST_CONTEXT, &payload, &envelope.signature, keys, now)
+        .map_err(|e| format!("Manifest rejected: {}", e))?;
+    let manifest: UpdateManifest = serde_json::from_slice(&payload)
+        .map_err(|e| format!("Payload is not an update manifest: {}", e))?;
+    if manifest.channel != channel {
+        return Err(format!(
+            "Manifest is for the {} channel, expected {}",
+            manifest.channel.as_str(),
+            channel.as_str()
+        ));
+    }
+    Ok(manifest)
+}
+
+/// The server side of update checks
+struct UpdateClient {
+    client: reqwest::Client,
+    api_host: String,
+    device_id: String,
+    site_id: String,
+    channel: UpdateChannel,
+    bucket: Option<u8>,
+    pinned: Option<String>,
+    keys: Vec<PinnedKey>,
+}
+
+impl UpdateClient {
+    async fn new() -> Result<Self, String> {
+        let settings = get_settings()
+            .await
+            .map_err(|e| format!("Failed to get settings: {}", e))?;
+        let device_id = settings
+            .device_id
+            .clone()
+            .ok_or_else(|| String::from("Device is not registered"))?;
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(DOWNLOAD_TIMEOUT_SECS))
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(UpdateClient {
+            client,
+            api_host: settings.api_host,
+            device_id,
+            site_id: settings.site_id,
+            channel: settings.update_channel.unwrap_or_default(),
+            bucket: settings.guid.as_deref().map(rollout_bucket),
+            pinned: pinned_version(settings.pinned_update_version),
+            keys: release_keys(),
+        })
+    }
+
+    fn get(&self, url: &str) -> reqwest::RequestBuilder {
+        // Device headers only go to our own server, packages may sit on a CDN
+        if url.starts_with(&self.api_host) {
+            self.client
+                .get(url)
+                .header("x-device-id", &self.device_id)
+                .header("x-site-id", &self.site_id)
+        } else {
+            self.client.get(url)
+        }
+    }
+
+    /// The channel's release, or the pinned one, checked against the release signing keys
+    async fn fetch_manifest(&self) -> Result<UpdateManifest, String> {
+        if self.keys.is_empty() {
+            return Err(String::from("This build has no release signing key, updates are disabled"));
+        }
+        let url = format!("{}/v1.0/updates/{}/manifest", self.api_host, self.channel.as_str());
+        let mut request = self
+            .get(&url)
+            .header("x-agent-version", current_version());
+        if let Some(bucket) = self.bucket {
+            request = request.header("x-rollout-bucket", bucket.to_string());
+        }
+        if let Some(pinned) = &self.pinned {
+            request = request.query(&[("version", pinned)]);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("Failed to fetch update manifest: {}", e))?;
+        if !response.status().is_success() {
+            return Err(format!(
+                "Failed to fetch update manifest: {}: {}",
+                response.status(),
+                response.text().await.unwrap_or_default()
+            ));
+        }
+        let envelope: UpdateEnvelope = response
+            .json()
+            .await
+            .map_err(|e| format!("Unreadable update manifest: {}", e))?;
+        verify_manifest(&envelope, &self.keys, self.channel, chrono::Utc::now())
+    }
+
+    /// Whether the release is one to move to, a pinned version may be older than this one
+    fn wants(&self, version: &str) -> bool {
+        match &self.pinned {
+            Some(pinned) => version == pinned && version != current_version(),
+            None => is_newer_version(version, current_version()),
+        }
+    }
+
+    fn admits(&self, manifest: &UpdateManifest) -> Result<(), String> {
+        rollout_admits(manifest, self.bucket, self.pinned.as_deref())
+    }
+
+    fn package_url(&self, package: &UpdatePackage) -> String {
+        if package.url.starts_with('/') {
+            format!("{}{}", self.api_host, package.url)
+        } else {
+            package.url.clone()
+        }
+    }
+
+    /// Streams a package to disk, refusing anything bigger than the manifest said
+    async fn download(&self, package: &UpdatePackage, path: &Path) -> Result<(), String> {
+        let mut response = self
+            .get(&self.package_url(package))
+            .send()
+            .await
+            .map_err(|e| format!("Failed to download update: {}", e))?;
+        if !response.status().is_success() {
+            return Err(format!("Failed to download update: {}", response.status()));
+        }
+
+        let mut file = tokio::fs::File::create(path)
+            .await
+            .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        let mut written: u64 = 0;
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| format!("Failed to download update: {}", e))?
+        {
+            written += chunk.len() as u64;
+            if written > package.size {
+                return Err(format!("Update package is larger than the {} bytes expected", package.size));
+            }
+            file.write_all(&chunk)
+                .await
+                .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        }
+        file.flush()
+            .await
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+
+        if written != package.size {
+            return Err(format!(
+                "Update package is {} bytes, expected {}",
+                written, package.size
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Runs the staged binary's `--version` so a package that can't start on this OS is never swapped in
+async fn preflight(path: &Path, version: &str) -> Result<(), String> {
+    let mut command = tokio::process::Command::new(path);
+    command.arg("--version").kill_on_drop(true);
+
+    #[cfg(target_os = "windows")]
+    command.creation_flags(CREATE_NO_WINDOW);
+
+    let output = tokio::time::timeout(Duration::from_secs(PREFLIGHT_TIMEOUT_SECS), command.output())
+        .await
+        .map_err(|_| String::from("Staged update did not answer --version"))?
+        .map_err(|e| format!("Failed to run staged update: {}", e))?;
+
+    let reported = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let expected = format!("MSPAgent {}", version);
+    if !output.status.success() || reported != expected {
+        return Err(format!(
+            "Staged update reported \"{}\", expected \"{}\"",
+            reported, expected
+        ));
+    }
+    Ok(())
+}
+
+/// Downloads, verifies and tries out a package, leaving it staged for the next idle moment
+async fn stage_update(client: &UpdateClient, manifest: &UpdateManifest, package: &UpdatePackage) -> Result<(), String> {
+    if package.size == 0 || package.size > MAX_PACKAGE_BYTES {
+        return Err(format!("Update package size {} is out of range", package.size));
+    }
+    let sha256 = package.sha256.trim().to_ascii_lowercase();
+    if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(String::from("Update package has an invalid SHA-256"));
+    }
+
+    let staged_dir = get_updates_dir().join("staged");
+    // Only one package is kept staged, an older one is superseded
+    let _ = tokio::fs::remove_dir_all(&staged_dir).await;
+    tokio::fs::create_dir_all(&staged_dir)
+        .await
+        .map_err(|e| format!("Failed to create {}: {}", staged_dir.display(), e))?;
+
+    let path = staged_dir.join(exe_name());
+    let result = async {
+        client.download(package, &path).await?;
+
+        let actual = hash_file(&path).await?;
+        if actual != sha256 {
+            return Err(format!("Update package SHA-256 is {}, expected {}", actual, sha256));
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            tokio::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+                .await
+                .map_err(|e| format!("Failed to mark update executable: {}", e))?;
+        }
+
+        preflight(&path, &manifest.version).await
+    }
+    .await;
+
+    if let Err(e) = result {
+        let _ = tokio::fs::remove_dir_all(&staged_dir).await;
+        return Err(e);
+    }
+
+    update_state(|state| {
+        state.staged = Some(StagedUpdate {
+            version: manifest.version.clone(),
+            path: path.clone(),
+            sha256,
+            staged_at: chrono::Utc::now().to_rfc3339(),
+        })
+    })
+    .await?;
+    set_update_available(Some(manifest.version.clone()));
+
+    log_to_file(
+        "INFO".to_string(),
+        format!("Update {} staged, it will be applied when the agent is idle", manifest.version),
+    );
+    Ok(())
+}
+
+fn pinned_version(pinned: Option<String>) -> Option<String> {
+    pinned
+        .map(|version| version.trim().to_string())
+        .filter(|version| !version.is_empty())
+}
+
+/// Channel, bucket and any staged release, for the server to follow rollouts with
+pub async fn update_status() -> Option<UpdateStatus> {
+    let settings = get_settings().await.ok()?;
+    let staged = {
+        let _lock = UPDATE_LOCK.lock().await;
+        load_state().await.staged
+    };
+    Some(UpdateStatus {
+        channel: settings.update_channel.unwrap_or_default(),
+        rollout_bucket: settings.guid.as_deref().map(rollout_bucket),
+        pinned_version: pinned_version(settings.pinned_update_version),
+        staged_version: staged.map(|staged| staged.version),
+    })
+}
+
+async fn discard_staged_update() {
+    let _ = update_state(|state| state.staged = None).await;
+    let _ = tokio::fs::remove_dir_all(get_updates_dir().join("staged")).await;
+    set_update_available(None);
+}
+
+/// Looks for a release to move to on the configured channel and stages it, returning the staged version
+pub async fn check_for_update() -> Result<Option<String>, String> {
+    let client = UpdateClient::new().await?;
+    let manifest = client.fetch_manifest().await;
+    update_state(|state| state.last_checked_at = Some(chrono::Utc::now().to_rfc3339())).await?;
+    let manifest = manifest?;
+
+    if !client.wants(&manifest.version) {
+        return Ok(None);
+    }
+    if let Err(reason) = client.admits(&manifest) {
+        log_to_file(
+            "INFO".to_string(),
+            format!("Not taking update {}: {}", manifest.version, reason),
+        );
+        return Ok(None);
+    }
+
+    let state = {
+        let _lock = UPDATE_LOCK.lock().await;
+        load_state().await
+    };
+    if state.failed_versions.contains(&manifest.version) {
+        return Ok(None);
+    }
+    if state.staged.as_ref().map(|staged| staged.version.as_str()) == Some(manifest.version.as_str()) {
+        return Ok(None);
+    }
+
+    let target = current_target();
+    let package = manifest
+        .packages
+        .iter()
+        .find(|package| package.target == target)
+        .ok_or_else(|| format!("Update {} has no package for {}", manifest.version, target))?;
+
+    stage_update(&client, &manifest, package).await?;
+    Ok(Some(manifest.version))
+}
+
+/// Asks the server again right before applying, so pausing a rollout stops devices that already staged it
+async fn confirm_rollout(staged: &StagedUpdate) -> Result<(), String> {
+    let client = UpdateClient::new().await?;
+    let manifest = client.fetch_manifest().await?;
+    if manifest.version != staged.version || !client.wants(&manifest.version) {
+        // Superseded or no longer wanted, the next check stages whatever is current
+        discard_staged_update().await;
+        let _ = update_state(|state| state.last_checked_at = None).await;
+        return Err(format!(
+            "Staged update {} is no longer the release for this device",
+            staged.version
+        ));
+    }
+    client.admits(&manifest)
+}
+
+/// Replaces the running executable with `source`, which stays where it is
+fn swap_executable(source: &Path, exe: &Path) -> Result<(), String> {
+    #[cfg(unix)]
+    {
+        // Renamed into place so a crash halfway never leaves a truncated binary, running processes keep the old inode
+        let incoming = exe.with_extension("new");
+        std::fs::copy(source, &incoming)
+            .map_err(|e| format!("Failed to copy {}: {}", source.display(), e))?;
+        std::fs::rename(&incoming, exe).map_err(|e| {
+            let _ = std::fs::remove_file(&incoming);
+            format!("Failed to replace {}: {}", exe.display(), e)
+        })
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        // A running executable can be renamed but not overwritten, the old one is deleted on the next start
+        let old = exe.with_extension("old");
+        let _ = std::fs::remove_file(&old);
+        std::fs::rename(exe, &old).map_err(|e| format!("Failed to move {} aside: {}", exe.display(), e))?;
+        if let Err(e) = std::fs::copy(source, exe) {
+            let _ = std::fs::rename(&old, exe);
+            return Err(format!("Failed to copy {}: {}", source.display(), e));
+        }
+        Ok(())
+    }
+}
+
+/// Ends this process so the service manager starts whatever executable is now installed
+fn restart_service() -> ! {
+    log_to_file("INFO".to_string(), "Restarting agent service".to_string());
+
+    // The scheduled task doesn't restart on exit, a detached helper starts it again once we're gone.
+    // While an update is unconfirmed that's the guard in the previous version's backup, which also
+    // stands in for the restarts systemd would do.
+    #[cfg(target_os = "windows")]
+    {
+        let rollback = get_rollback_path();
+        let started = if rollback.exists() {
+            std::process::Command::new(&rollback)
+                .args(["update-guard", "--watch"])
+                .creation_flags(CREATE_NO_WINDOW | DETACHED_PROCESS)
+                .spawn()
+        } else {
+            std::process::Command::new("cmd")
+                .raw_arg(format!("/C \"ping -n 6 127.0.0.1 >nul & schtasks /Run /TN {}\"", SERVICE_TASK))
+                .creation_flags(CREATE_NO_WINDOW | DETACHED_PROCESS)
+                .spawn()
+        };
+        if let Err(e) = started {
+            log_to_file(
+                "ERROR".to_string(),
+                format!("Failed to schedule service restart, it starts again at boot: {}", e),
+            );
+        }
+        std::process::exit(0);
+    }
+
+    #[cfg(unix)]
+    std::process::exit(RESTART_EXIT_CODE);
+}
+
+async fn report_result(from_version: &str, to_version: &str, status: &str, error: Option<&str>) {
+    let body = json!({
+        "from_version": from_version,
+        "to_version": to_version,
+        "status": status,
+        "error": error,
+        "target": current_target(),
+        "at": chrono::Utc::now().to_rfc3339(),
+    });
+    if let Err(e) = enqueue("update_result", "/v1.0/updates/result", body)
+        .await
+        .map_err(|e| e.to_string())
+    {
+        log_to_file(
+            "WARN".to_string(),
+            format!("Failed to queue update result: {}", e),
+        );
+    }
+}
+
+/// Swaps in the staged update and restarts, only returning if it couldn't
+async fn apply_staged_update(staged: StagedUpdate) -> Result<(), String> {
+    // The staged file sat on disk since it was verified
+    if hash_file(&staged.path).await? != staged.sha256 {
+        update_state(|state| state.staged = None).await?;
+        set_update_available(None);
+        return Err(format!("Staged update {} changed on disk, discarding it", staged.version));
+    }
+
+    let exe = std::env::current_exe().map_err(|e| format!("Failed to find the agent executable: {}", e))?;
+    let rollback = get_rollback_path();
+    if let Some(parent) = rollback.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    tokio::fs::copy(&exe, &rollback)
+        .await
+        .map_err(|e| format!("Failed to back up {}: {}", exe.display(), e))?;
+
+    // Recorded before the swap, a crash in between is caught by the version check on the next start
+    update_state(|state| {
+        state.applying = Some(AppliedUpdate {
+            from_version: current_version().to_string(),
+            to_version: staged.version.clone(),
+            applied_at: chrono::Utc::now().to_rfc3339(),
+            start_attempts: 0,
+            exe: Some(exe.clone()),
+        })
+    })
+    .await?;
+
+    let source = staged.path.clone();
+    let target = exe.clone();
+    let swapped = tauri::async_runtime::spawn_blocking(move || swap_executable(&source, &target))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|result| result);
+    if let Err(e) = swapped {
+        update_state(|state| {
+            state.applying = None;
+            state.staged = None;
+            state.failed_versions.push(staged.version.clone());
+        })
+        .await?;
+        set_update_available(None);
+        report_result(current_version(), &staged.version, "failed", Some(&e)).await;
+        return Err(e);
+    }
+
+    log_to_file(
+        "INFO".to_string(),
+        format!("Updated {} from {} to {}", exe.display(), current_version(), staged.version),
+    );
+    restart_service();
+}
+
+/// Puts the previous executable back over an update that failed, the service starts it next
+async fn roll_back(applied: &AppliedUpdate, reason: &str) -> Result<(), String> {
+    log_to_file(
+        "ERROR".to_string(),
+        format!("Rolling back update {}: {}", applied.to_version, reason),
+    );
+
+    let exe = applied
+        .exe
+        .clone()
+        .ok_or_else(|| format!("Update {} didn't record the executable it replaced", applied.to_version))?;
+    let rollback = get_rollback_path();
+    let restored = tauri::async_runtime::spawn_blocking(move || swap_executable(&rollback, &exe))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|result| result);
+
+    update_state(|state| {
+        state.applying = None;
+        state.staged = None;
+        state.failed_versions.push(applied.to_version.clone());
+    })
+    .await?;
+
+    match restored {
+        Ok(()) => {
+            report_result(&applied.from_version, &applied.to_version, "rolled_back", Some(reason)).await;
+        }
+        Err(e) => {
+            // Nothing better to run, carry on with the new version and let the server know
+            log_to_file("ERROR".to_string(), format!("Rollback failed: {}", e));
+            report_result(
+                &applied.from_version,
+                &applied.to_version,
+                "rollback_failed",
+                Some(&format!("{}; rollback failed: {}", reason, e)),
+            )
+            .await;
+        }
+    }
+    Ok(())
+}
+
+/// When an update has to have checked in by, an unreadable time has already passed
+fn verify_deadline(applied: &AppliedUpdate) -> chrono::DateTime<chrono::Utc> {
+    chrono::DateTime::parse_from_rfc3339(&applied.applied_at)
+        .map(|at| at.with_timezone(&chrono::Utc) + chrono::Duration::seconds(VERIFY_WINDOW_SECS))
+        .unwrap_or(chrono::DateTime::<chrono::Utc>::MIN_UTC)
+}
+
+/// Counts a start of the new version and decides whether it gets to run
+fn guard_start(applied: &mut AppliedUpdate, now: chrono::DateTime<chrono::Utc>) -> GuardAction {
+    applied.start_attempts += 1;
+    if applied.start_attempts > MAX_START_ATTEMPTS {
+        return GuardAction::RollBack(format!(
+            "Stopped {} times before checking in",
+            applied.start_attempts - 1
+        ));
+    }
+    if now > verify_deadline(applied) {
+        return GuardAction::RollBack(format!(
+            "Did not check in within {} minutes",
+            VERIFY_WINDOW_SECS / 60
+        ));
+    }
+    GuardAction::Start
+}
+
+/// Runs from the previous version's backup before each start of the service, so a new version
+/// that crashes before it gets anywhere is still rolled back. Returns whether an update is
+/// still waiting to check in.
+pub async fn guard_update() -> Result<bool, String> {
+    let now = chrono::Utc::now();
+    let guarded = {
+        let _lock = UPDATE_LOCK.lock().await;
+        let mut state = load_state().await;
+        let guarded = state
+            .applying
+            .as_mut()
+            .map(|applied| (guard_start(applied, now), applied.clone()));
+        if guarded.is_some() {
+            save_state(&state).await?;
+        }
+        guarded
+    };
+
+    match guarded {
+        None => Ok(false),
+        Some((GuardAction::Start, applied)) => {
+            log_to_file(
+                "INFO".to_string(),
+                format!(
+                    "Starting update {}, attempt {} of {}",
+                    applied.to_version, applied.start_attempts, MAX_START_ATTEMPTS
+                ),
+            );
+            Ok(true)
+        }
+        Some((GuardAction::RollBack(reason), applied)) => {
+            roll_back(&applied, &reason).await?;
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn run_service_task(action: &str) {
+    let result = std::process::Command::new("schtasks")
+        .args([action, "/TN", SERVICE_TASK])
+        .creation_flags(CREATE_NO_WINDOW)
+        .output();
+    if let Err(e) = result {
+        log_to_file("ERROR".to_string(), format!("Failed to run schtasks {}: {}", action, e));
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn service_task_running() -> bool {
+    std::process::Command::new("schtasks")
+        .args(["/Query", "/TN", SERVICE_TASK, "/FO", "CSV", "/NH"])
+        .creation_flags(CREATE_NO_WINDOW)
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).contains("\"Running\""))
+        .unwrap_or(false)
+}
+
+/// The scheduled task isn't restarted when it stops, so on Windows the guard stays up and
+/// does the restarting itself until the update checks in or is rolled back
+#[cfg(target_os = "windows")]
+pub async fn watch_applied_update() {
+    let poll = Duration::from_secs(GUARD_POLL_SECS);
+    // The version that started us is still on its way out
+    tokio::time::sleep(poll).await;
+
+    loop {
+        let pending = guard_update().await.unwrap_or_else(|e| {
+            log_to_file("ERROR".to_string(), format!("Update guard failed: {}", e));
+            false
+        });
+        run_service_task("/Run");
+        if !pending {
+            return;
+        }
+
+        loop {
+            tokio::time::sleep(poll).await;
+            let applied = {
+                let _lock = UPDATE_LOCK.lock().await;
+                load_state().await.applying
+            };
+            let Some(applied) = applied else {
+                return;
+            };
+            if chrono::Utc::now() > verify_deadline(&applied) {
+                // Hung without checking in, the next guard start rolls it back
+                run_service_task("/End");
+                break;
+            }
+            if !service_task_running() {
+                break;
+            }
+        }
+        tokio::time::sleep(poll).await;
+    }
+}
+
+/// Whether this process has checked in since it started
+fn checked_in_since(started: chrono::DateTime<chrono::Utc>) -> bool {
+    current_state()
+        .last_heartbeat_at
+        .and_then(|at| chrono::DateTime::parse_from_rfc3339(&at).ok())
+        .map(|at| at >= started)
+        .unwrap_or(false)
+}
+
+/// Follows up on an update applied before the last restart. Counting starts and rolling back
+/// are left to the guard in the previous version, this only confirms the update or gives up.
+async fn confirm_applied_update(running: &AtomicBool) {
+    let started = chrono::Utc::now();
+    let applied = {
+        let _lock = UPDATE_LOCK.lock().await;
+        load_state().await.applying
+    };
+    let applied = match applied {
+        Some(applied) => applied,
+        None => {
+            // Left behind by a rollback, the guard ran from it
+            let _ = tokio::fs::remove_dir_all(get_updates_dir().join("rollback")).await;
+            return;
+        }
+    };
+
+    if applied.to_version != current_version() {
+        // The swap never took, or something else put a different version in place
+        log_to_file(
+            "WARN".to_string(),
+            format!(
+                "Update to {} did not take, running {}",
+                applied.to_version,
+                current_version()
+            ),
+        );
+        let _ = update_state(|state| {
+            state.applying = None;
+            state.staged = None;
+            state.failed_versions.push(applied.to_version.clone());
+        })
+        .await;
+        report_result(
+            &applied.from_version,
+            &applied.to_version,
+            "failed",
+            Some(&format!("Agent restarted as {}", current_version())),
+        )
+        .await;
+        return;
+    }
+
+    let deadline = verify_deadline(&applied);
+    let mut check = interval(Duration::from_secs(10));
+    while running.load(Ordering::Relaxed) {
+        check.tick().await;
+        if checked_in_since(started) {
+            let _ = update_state(|state| {
+                state.applying = None;
+                state.staged = None;
+            })
+            .await;
+            let _ = tokio::fs::remove_dir_all(get_updates_dir().join("rollback")).await;
+            let _ = tokio::fs::remove_dir_all(get_updates_dir().join("staged")).await;
+            log_to_file(
+                "INFO".to_string(),
+                format!("Update to {} confirmed", applied.to_version),
+            );
+            report_result(&applied.from_version, &applied.to_version, "applied", None).await;
+            return;
+        }
+        if chrono::Utc::now() > deadline {
+            // The guard rolls back on the next start, on Windows restarting is what brings it up
+            log_to_file(
+                "ERROR".to_string(),
+                format!(
+                    "Update to {} did not check in within {} minutes, stopping",
+                    applied.to_version,
+                    VERIFY_WINDOW_SECS / 60
+                ),
+            );
+            restart_service();
+        }
+    }
+}
+
+fn check_due(last_checked_at: Option<&str>) -> bool {
+    match last_checked_at.and_then(|at| chrono::DateTime::parse_from_rfc3339(at).ok()) {
+        Some(at) => chrono::Utc::now() - at.with_timezone(&chrono::Utc) > chrono::Duration::seconds(UPDATE_CHECK_INTERVAL_SECS),
+        None => true,
+    }
+}
+
+/// Confirms a just-applied update, then checks for new releases and applies them once no jobs are running
+pub fn start_update_task(running: Arc<AtomicBool>) {
+    tauri::async_runtime::spawn(async move {
+        log_to_file(
+            "INFO".to_string(),
+            "Starting update background task".to_string(),
+        );
+
+        #[cfg(target_os = "windows")]
+        if let Ok(exe) = std::env::current_exe() {
+            let _ = tokio::fs::remove_file(exe.with_extension("old")).await;
+        }
+
+        confirm_applied_update(&running).await;
+
+        // A package staged by an earlier run is still waiting to be applied
+        let staged = {
+            let _lock = UPDATE_LOCK.lock().await;
+            load_state().await.staged
+        };
+        if let Some(staged) = staged {
+            if staged.version != current_version() {
+                set_update_available(Some(staged.version));
+            } else {
+                let _ = update_state(|state| state.staged = None).await;
+            }
+        }
+
+        // The first tick fires at once, jobs resumed at startup get a moment to register first
+        let mut poll = interval(Duration::from_secs(UPDATE_POLL_SECS));
+        poll.tick().await;
+        let mut recheck_at: Option<Instant> = None;
+        let mut held_for: Option<String> = None;
+        while running.load(Ordering::Relaxed) {
+            poll.tick().await;
+
+            let state = {
+                let _lock = UPDATE_LOCK.lock().await;
+                load_state().await
+            };
+
+            if check_due(state.last_checked_at.as_deref()) {
+                match check_for_update().await {
+                    Ok(Some(version)) => log_to_file(
+                        "INFO".to_string(),
+                        format!("Found update {}", version),
+                    ),
+                    Ok(None) => {}
+                    Err(e) => log_to_file(
+                        "WARN".to_string(),
+                        format!("Update check failed: {}", e),
+                    ),
+                }
+                continue;
+            }
+
+            // Jobs and countdowns would be cut off by the restart
+            let staged = match state.staged {
+                Some(staged) if running_job_count() == 0 => staged,
+                _ => continue,
+            };
+            if recheck_at.is_some_and(|at| Instant::now() < at) {
+                continue;
+            }
+
+            match confirm_rollout(&staged).await {
+                Ok(()) => {
+                    if let Err(e) = apply_staged_update(staged).await {
+                        log_to_file("ERROR".to_string(), format!("Failed to apply update: {}", e));
+                        recheck_at = Some(Instant::now() + Duration::from_secs(ROLLOUT_RECHECK_SECS));
+                    }
+                }
+                Err(reason) => {
+                    // Logged once per reason, a paused rollout can hold for days
+                    if held_for.as_ref() != Some(&reason) {
+                        log_to_file(
+                            "INFO".to_string(),
+                            format!("Holding update {}: {}", staged.version, reason),
+                        );
+                        held_for = Some(reason);
+                    }
+                    recheck_at = Some(Instant::now() + Duration::from_secs(ROLLOUT_RECHECK_SECS));
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn manifest(channel: UpdateChannel, rollout: Option<Rollout>) -> UpdateManifest {
+        UpdateManifest {
+            channel,
+            version: String::from("1.1.0"),
+            notes: None,
+            released_at: None,
+            packages: Vec::new(),
+            rollout,
+        }
+    }
+
+    fn sign_manifest(key: &SigningKey, manifest: &UpdateManifest, context: &[u8]) -> UpdateEnvelope {
+        let payload = serde_json::to_vec(manifest).unwrap();
+        let mut message = context.to_vec();
+        message.extend_from_slice(&payload);
+        UpdateEnvelope {
+            payload: STANDARD.encode(&payload),
+            signature: STANDARD.encode(key.sign(&message).to_bytes()),
+        }
+    }
+
+    fn release_key(key: &SigningKey) -> Vec<PinnedKey> {
+        vec![PinnedKey {
+            key: STANDARD.encode(key.verifying_key().to_bytes()),
+            pinned_at: String::new(),
+            retires_at: None,
+        }]
+    }
+
+    #[test]
+    fn versions_compare_like_the_backend() {
+        // Expected values are what the backend's isVersionGte returns under Node
+        let cases = [
+            ("0.1.15", "0.1.9", true),
+            ("0.1.9", "0.1.15", false),
+            ("1.0", "1.0.0", true),
+            ("1.0.0", "1.0", true),
+            ("1.0.0", "1.0.1", false),
+            ("2", "1.9.9", true),
+            // "0-beta" isn't a number, so that part counts as equal and the next one decides
+            ("1.2.0-beta.1", "1.2.0", true),
+            ("1.2.0", "1.2.0-beta.1", false),
+            ("0x10", "16", true),
+            ("16", "0x10", true),
+            ("0x10", "17", false),
+            ("1e1", "10", true),
+            (" 1 ", "1", true),
+            ("Infinity", "999", true),
+            ("inf", "999", true),
+            ("999", "inf", true),
+            ("", "", true),
+            ("", "0", true),
+            ("", "0.0.1", false),
+        ];
+        for (value, target, expected) in cases {
+            assert_eq!(
+                is_version_gte(value, target),
+                expected,
+                "is_version_gte({:?}, {:?})",
+                value,
+                target
+            );
+        }
+    }
+
+    #[test]
+    fn version_parts_read_like_javascript_numbers() {
+        assert_eq!(version_part("15"), 15.0);
+        assert_eq!(version_part(""), 0.0);
+        assert_eq!(version_part(" 7 "), 7.0);
+        assert_eq!(version_part("0x1F"), 31.0);
+        assert_eq!(version_part("0b101"), 5.0);
+        assert_eq!(version_part("0o17"), 15.0);
+        assert_eq!(version_part(".5"), 0.5);
+        assert_eq!(version_part("-Infinity"), f64::NEG_INFINITY);
+        for not_a_number in ["0x", "-0x10", "1e", "0-beta", "inf", "nan", "1_000"] {
+            assert!(version_part(not_a_number).is_nan(), "{:?}", not_a_number);
+        }
+    }
+
+    #[test]
+    fn only_manifests_signed_for_updates_on_the_right_channel_verify() {
+        let key = SigningKey::from_bytes(&rand::random::<[u8; 32]>());
+        let keys = release_key(&key);
+        let now = chrono::Utc::now();
+        let stable = manifest(UpdateChannel::Stable, None);
+
+        let envelope = sign_manifest(&key, &stable, UPDATE_MANIFEST_CONTEXT);
+        let verified = verify_manifest(&envelope, &keys, UpdateChannel::Stable, now).unwrap();
+        assert_eq!(verified.version, "1.1.0");
+
+        let err = verify_manifest(&envelope, &keys, UpdateChannel::Beta, now).unwrap_err();
+        assert!(err.contains("stable channel"), "{}", err);
+
+        // Signed the way a job is, without the update context
+        let job_signed = sign_manifest(&key, &stable, b"");
+        assert!(verify_manifest(&job_signed, &keys, UpdateChannel::Stable, now).is_err());
+
+        let other = SigningKey::from_bytes(&rand::random::<[u8; 32]>());
+        let foreign = sign_manifest(&other, &stable, UPDATE_MANIFEST_CONTEXT);
+        assert!(verify_manifest(&foreign, &keys, UpdateChannel::Stable, now).is_err());
+        assert!(verify_manifest(&envelope, &[], UpdateChannel::Stable, now).is_err());
+    }
+
+    fn applied(applied_at: chrono::DateTime<chrono::Utc>, exe: Option<PathBuf>) -> AppliedUpdate {
+        AppliedUpdate {
+            from_version: String::from("1.0.0"),
+            to_version: String::from("1.1.0"),
+            applied_at: applied_at.to_rfc3339(),
+            start_attempts: 0,
+            exe,
+        }
+    }
+
+    #[test]
+    fn rollout_buckets_match_the_server() {
+        // From Node: createHash("sha256").update(guid).digest().readBigUInt64BE(0) % 100n
+        assert_eq!(rollout_bucket("3f2a9c1e-8b4d-4e6f-a1b2-c3d4e5f60718"), 32);
+        assert_eq!(rollout_bucket("device-guid"), 6);
+        assert_eq!(rollout_bucket("0000"), 53);
+        assert_eq!(rollout_bucket(" device-guid\n"), 6);
+    }
+
+    #[test]
+    fn releases_without_a_rollout_go_to_everyone() {
+        let release = manifest(UpdateChannel::Stable, None);
+        assert!(rollout_admits(&release, Some(99), None).is_ok());
+        assert!(rollout_admits(&release, None, None).is_ok());
+    }
+
+    #[test]
+    fn paused_rollouts_hold_every_device() {
+        let release = manifest(
+            UpdateChannel::Stable,
+            Some(Rollout {
+                percentage: 100,
+                paused: true,
+            }),
+        );
+        let err = rollout_admits(&release, Some(0), None).unwrap_err();
+        assert!(err.contains("paused"), "{}", err);
+    }
+
+    #[test]
+    fn rollouts_admit_buckets_below_the_percentage() {
+        let release = manifest(
+            UpdateChannel::Stable,
+            Some(Rollout {
+                percentage: 25,
+                paused: false,
+            }),
+        );
+        assert!(rollout_admits(&release, Some(0), None).is_ok());
+        assert!(rollout_admits(&release, Some(24), None).is_ok());
+        assert!(rollout_admits(&release, Some(25), None).is_err());
+        assert!(rollout_admits(&release, Some(99), None).is_err());
+
+        let none = manifest(
+            UpdateChannel::Stable,
+            Some(Rollout {
+                percentage: 0,
+                paused: false,
+            }),
+        );
+        assert!(rollout_admits(&none, Some(0), None).is_err());
+    }
+
+    #[test]
+    fn devices_without_a_guid_wait_for_the_full_rollout() {
+        let partial = manifest(
+            UpdateChannel::Stable,
+            Some(Rollout {
+                percentage: 99,
+                paused: false,
+            }),
+        );
+        assert!(rollout_admits(&partial, None, None).is_err());
+
+        let full = manifest(
+            UpdateChannel::Stable,
+            Some(Rollout {
+                percentage: 100,
+                paused: false,
+            }),
+        );
+        assert!(rollout_admits(&full, None, None).is_ok());
+    }
+
+    #[test]
+    fn pinned_devices_skip_the_rollout_but_only_take_their_version() {
+        let release = manifest(
+            UpdateChannel::Stable,
+            Some(Rollout {
+                percentage: 0,
+                paused: true,
+            }),
+        );
+        assert!(rollout_admits(&release, Some(99), Some("1.1.0")).is_ok());
+        assert!(rollout_admits(&release, None, Some("1.1.0")).is_ok());
+
+        let err = rollout_admits(&release, Some(0), Some("1.0.0")).unwrap_err();
+        assert!(err.contains("pinned to 1.0.0"), "{}", err);
+    }
+
+    #[test]
+    fn guard_rolls_back_after_too_many_starts() {
+        let now = chrono::Utc::now();
+        let mut update = applied(now, None);
+
+        for attempt in 1..=MAX_START_ATTEMPTS {
+            assert_eq!(guard_start(&mut update, now), GuardAction::Start);
+            assert_eq!(update.start_attempts, attempt);
+        }
+        assert_eq!(
+            guard_start(&mut update, now),
+            GuardAction::RollBack(format!("Stopped {} times before checking in", MAX_START_ATTEMPTS))
+        );
+    }
+
+    #[test]
+    fn guard_rolls_back_once_the_window_has_passed() {
+        let now = chrono::Utc::now();
+        let window = chrono::Duration::seconds(VERIFY_WINDOW_SECS);
+
+        let mut update = applied(now - window + chrono::Duration::minutes(1), None);
+        assert_eq!(guard_start(&mut update, now), GuardAction::Start);
+
+        let mut update = applied(now - window - chrono::Duration::minutes(1), None);
+        assert!(matches!(guard_start(&mut update, now), GuardAction::RollBack(_)));
+
+        // A time that can't be read can't be trusted to still be inside the window
+        let mut update = applied(now, None);
+        update.applied_at = String::from("soon");
+        assert!(matches!(guard_start(&mut update, now), GuardAction::RollBack(_)));
+    }
+
+    #[tokio::test]
+    async fn guard_restores_the_backup_and_remembers_the_failed_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let exe = dir.path().join(exe_name());
+        std::fs::write(&exe, b"new").unwrap();
+        let rollback = get_rollback_path();
+        std::fs::create_dir_all(rollback.parent().unwrap()).unwrap();
+        std::fs::write(&rollback, b"old").unwrap();
+
+        let mut update = applied(chrono::Utc::now(), Some(exe.clone()));
+        update.start_attempts = MAX_START_ATTEMPTS - 1;
+        save_state(&UpdateState {
+            applying: Some(update),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        // The last start the new version gets
+        assert!(guard_update().await.unwrap());
+        assert_eq!(std::fs::read(&exe).unwrap(), b"new");
+
+        assert!(!guard_update().await.unwrap());
+        assert_eq!(std::fs::read(&exe).unwrap(), b"old");
+        let state = load_state().await;
+        assert!(state.applying.is_none());
+        assert_eq!(state.failed_versions, ["1.1.0"]);
+
+        // With nothing applying, starts pass straight through
+        assert!(!guard_update().await.unwrap());
+    }
+}
@@ -1,14 +1,19 @@
-import { getSystemInfo, SystemInfo } from "@/lib/agent.ts";
+import { Branding, getBranding, getSystemInfo, SystemInfo } from "@/lib/agent.ts";
 import Loader from "@workspace/ui/components/Loader.tsx";
 import { useEffect, useState } from "react";
 
 export default function About() {
   const [settings, setSettings] = useState<SystemInfo | undefined>(undefined);
+  const [branding, setBranding] = useState<Branding | undefined>(undefined);
 
   useEffect(() => {
     const load = async () => {
-      const settings = await getSystemInfo();
+      const [settings, branding] = await Promise.all([
+        getSystemInfo(),
+        getBranding(),
+      ]);
       setSettings(settings.data);
+      setBranding(branding.data);
     };
 
     load();
@@ -23,7 +28,15 @@ export default function About() {
 
   return (
     <div className="flex flex-col gap-2 size-full p-2 items-center">
-      <h1 className="text-2xl font-bold">About</h1>
+      {branding?.logo && (
+        <img src={branding.logo} alt={branding.name} className="size-12" />
+      )}
+      <h1
+        className="text-2xl font-bold"
+        style={{ color: branding?.accent_color ?? undefined }}
+      >
+        {branding?.name ?? "About"}
+      </h1>
       <div className="grid gap-1 w-full">
         <div className={itemClass}>
           <span>PC Name</span>
@@ -41,6 +54,22 @@ export default function About() {
           <span>IP (WAN)</span>
           <span>{settings.ext_address || "N/A"}</span>
         </div>
+        {branding?.support_phone && (
+          <div className={itemClass}>
+            <span>Support Phone</span>
+            <a href={`tel:${branding.support_phone}`}>
+              {branding.support_phone}
+            </a>
+          </div>
+        )}
+        {branding?.support_email && (
+          <div className={itemClass}>
+            <span>Support Email</span>
+            <a href={`mailto:${branding.support_email}`}>
+              {branding.support_email}
+            </a>
+          </div>
+        )}
       </div>
       <div className="flex flex-col gap-1 items-center mt-auto text-sm text-muted-foreground">
         <span>App Ver {settings.version || "N/A"}</span>
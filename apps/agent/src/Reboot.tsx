@@ -0,0 +1,118 @@
+import {
+  Branding,
+  getBranding,
+  getScheduledReboot,
+  postponeReboot,
+  ScheduledReboot,
+} from "@/lib/agent.ts";
+import { hideWindow } from "@/lib/window.ts";
+import { Button } from "@workspace/ui/components/button.tsx";
+import Loader from "@workspace/ui/components/Loader.tsx";
+import { useEffect, useState } from "react";
+import { toast } from "sonner";
+
+// The service moves the restart when it is postponed or a maintenance window closes, follow it
+const POLL_MS = 15_000;
+
+function formatRemaining(ms: number) {
+  const total = Math.max(0, Math.floor(ms / 1000));
+  const hours = Math.floor(total / 3600);
+  const minutes = Math.floor((total % 3600) / 60);
+  const seconds = String(total % 60).padStart(2, "0");
+
+  return hours > 0
+    ? `${hours}:${String(minutes).padStart(2, "0")}:${seconds}`
+    : `${minutes}:${seconds}`;
+}
+
+export default function Reboot() {
+  const [reboot, setReboot] = useState<ScheduledReboot | null | undefined>(
+    undefined,
+  );
+  const [branding, setBranding] = useState<Branding | undefined>(undefined);
+  const [now, setNow] = useState(Date.now());
+  const [postponing, setPostponing] = useState(false);
+
+  const refresh = async () => {
+    const result = await getScheduledReboot();
+    if (result.error) {
+      return;
+    }
+
+    setReboot(result.data);
+    if (!result.data) {
+      // Cancelled or done, nothing left to count down to
+      await hideWindow("reboot");
+    }
+  };
+
+  useEffect(() => {
+    getBranding().then((result) => setBranding(result.data));
+    refresh();
+
+    const poll = setInterval(refresh, POLL_MS);
+    const tick = setInterval(() => setNow(Date.now()), 1000);
+    return () => {
+      clearInterval(poll);
+      clearInterval(tick);
+    };
+  }, []);
+
+  const handlePostpone = async () => {
+    setPostponing(true);
+    const result = await postponeReboot();
+    setPostponing(false);
+
+    if (result.error) {
+      toast.error(result.error.message);
+      return;
+    }
+
+    setReboot(result.data);
+    await hideWindow("reboot");
+  };
+
+  if (!reboot) {
+    return <Loader />;
+  }
+
+  const rebootAt = new Date(reboot.reboot_at);
+
+  return (
+    <div className="flex flex-col gap-2 size-full p-4 items-center">
+      <h1
+        className="text-xl font-bold"
+        style={{ color: branding?.accent_color ?? undefined }}
+      >
+        This computer will restart in
+      </h1>
+      <span className="text-4xl font-mono tabular-nums">
+        {formatRemaining(rebootAt.getTime() - now)}
+      </span>
+      <span className="text-sm text-muted-foreground">
+        at{" "}
+        {rebootAt.toLocaleTimeString([], {
+          hour: "2-digit",
+          minute: "2-digit",
+        })}
+        . Save your work before then.
+      </span>
+      {reboot.message && <p className="text-center">{reboot.message}</p>}
+      <div className="flex gap-2 mt-auto">
+        {reboot.deferrals_left > 0 && (
+          <Button
+            type="button"
+            variant="outline"
+            disabled={postponing}
+            onClick={handlePostpone}
+          >
+            Postpone ({reboot.deferrals_left} left)
+          </Button>
+        )}
+        <Button type="button" onClick={() => hideWindow("reboot")}>
+          OK
+        </Button>
+      </div>
+    </div>
+  );
+}
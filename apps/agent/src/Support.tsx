@@ -23,16 +23,24 @@ import {
 } from "@workspace/ui/components/select.tsx";
 import { Button } from "@workspace/ui/components/button.tsx";
 import {
+  annotateScreenshot,
   chooseImageDialog,
-  readFileBase64,
+  discardScreenshots,
+  getReplayRecordingStatus,
+  importScreenshot,
+  readScreenshot,
+  recordScreen,
+  saveReplayRecording,
+  startReplayRecording,
+  stopReplayRecording,
   takeScreenshot,
   logToFile,
-  readFileBinary,
+  ScreenCapture,
+  ScreenRecording,
+  ScreenshotResult,
 } from "@/lib/file.ts";
 import { listen } from "@tauri-apps/api/event";
-import { fetch } from "@tauri-apps/plugin-http";
-import { getSettings, getRmmId } from "@/lib/agent.ts";
-import { APIResponse } from "@workspace/shared/types/api.ts";
+import { Branding, getBranding, submitTicket } from "@/lib/agent.ts";
 import { hideWindow, showWindow } from "@/lib/window.ts";
 
 const phoneSchema = z
@@ -60,13 +68,21 @@ const formSchema = z.object({
   phone: phoneSchema,
 
   screenshot: z.string().optional(),
+  screenshot_name: z.string().optional(),
   screenshot_url: z.string().optional(),
   screenshot_blob: z.string().optional(),
 });
 type FormSchema = z.infer<typeof formSchema>;
 
+// How much of the screen a replay keeps, it records until saved or discarded
+const REPLAY_SECS = 30;
+
 export default function Support() {
   const [isSubmitting, setIsSubmitting] = useState(false);
+  const [captures, setCaptures] = useState<ScreenCapture[]>([]);
+  const [recordingId, setRecordingId] = useState<string>();
+  const [replayRunning, setReplayRunning] = useState(false);
+  const [branding, setBranding] = useState<Branding | undefined>(undefined);
 
   const form = useForm<FormSchema>({
     resolver: zodResolver(formSchema),
@@ -81,16 +97,49 @@ export default function Support() {
       phone: "",
 
       screenshot: undefined,
+      screenshot_name: undefined,
       screenshot_url: undefined,
       screenshot_blob: undefined,
     },
   });
   const formValues = form.watch();
 
+  const captureLabel = (capture: ScreenCapture) =>
+    capture.monitor
+      ? `${capture.monitor.name}${capture.monitor.is_primary ? " (Primary)" : ""} - ${capture.width}x${capture.height}`
+      : `All Screens - ${capture.width}x${capture.height}`;
+
+  const selectCapture = (capture?: ScreenCapture) => {
+    form.setValue("screenshot", capture?.id);
+    form.setValue("screenshot_name", capture ? captureLabel(capture) : undefined);
+  };
+
+  // Offer the stitched image first, then each monitor on its own
+  const applyCaptures = (result: ScreenshotResult) => {
+    const options = result.stitched
+      ? [result.stitched, ...result.captures]
+      : result.captures;
+
+    setCaptures(options);
+    selectCapture(options[0]);
+  };
+
+  const clearScreenshots = async () => {
+    form.setValue("screenshot", undefined);
+    form.setValue("screenshot_name", undefined);
+    setCaptures([]);
+    setRecordingId(undefined);
+    await discardScreenshots();
+  };
+
+  useEffect(() => {
+    getBranding().then((result) => setBranding(result.data));
+  }, []);
+
   useEffect(() => {
-    const usePromise = listen("use_screenshot", async (path) => {
+    const usePromise = listen<ScreenshotResult>("use_screenshot", (event) => {
       try {
-        form.setValue("screenshot", path.payload as string);
+        applyCaptures(event.payload);
       } catch (err) {
         toast.error("Failed to get screenshot");
       }
@@ -104,6 +153,7 @@ export default function Support() {
   useEffect(() => {
     const unlistenPromise = listen("on_hide", async () => {
       form.reset();
+      await clearScreenshots();
     });
 
     return () => {
@@ -111,15 +161,27 @@ export default function Support() {
     };
   }, []);
 
-  // Process screenshot changes when path changes
+  // A replay keeps running while the window is hidden and stops itself when left unsaved
+  useEffect(() => {
+    const refresh = async () => {
+      const { data: running } = await getReplayRecordingStatus();
+      setReplayRunning(!!running);
+    };
+
+    refresh();
+    const interval = setInterval(refresh, 5000);
+    return () => clearInterval(interval);
+  }, []);
+
+  // Process screenshot changes when the selected id changes
   useEffect(() => {
     if (formValues.screenshot) {
       (async () => {
-        const { data: base64 } = await readFileBase64(formValues.screenshot!);
+        const { data: dataUrl } = await readScreenshot(formValues.screenshot!);
 
-        if (base64) {
-          form.setValue("screenshot_url", `data:image/png;base64,${base64}`);
-          form.setValue("screenshot_blob", base64);
+        if (dataUrl) {
+          form.setValue("screenshot_url", dataUrl);
+          form.setValue("screenshot_blob", dataUrl.split(",")[1]);
         } else {
           toast.error("Failed to process file");
           form.setValue("screenshot_url", undefined);
@@ -137,17 +199,14 @@ export default function Support() {
   const screenshot = useMemo(() => {
     if (!formValues.screenshot) return undefined;
 
-    const name = formValues.screenshot.includes("/")
-      ? formValues.screenshot.split("/").pop()
-      : formValues.screenshot.split("\\").pop();
-
     return {
-      name,
+      name: formValues.screenshot_name ?? "Screenshot",
       url: formValues.screenshot_url ?? "",
       data: formValues.screenshot_blob ?? null,
     };
   }, [
     formValues.screenshot,
+    formValues.screenshot_name,
     formValues.screenshot_url,
     formValues.screenshot_blob,
   ]);
@@ -157,100 +216,42 @@ export default function Support() {
     await logToFile("INFO", "Starting ticket submission");
 
     try {
-      await logToFile("INFO", "Fetching agent settings");
-      const { data: settings } = await getSettings();
-      if (
-        !settings ||
-        !settings.device_id ||
-        !settings.site_id ||
-        !settings.api_host
-      ) {
-        const errMsg = "Invalid settings. Please restart agent.";
-        await logToFile("ERROR", errMsg);
-        throw errMsg;
-      }
-
-      await logToFile(
-        "INFO",
-        `Settings loaded: device_id=${settings.device_id}, site_id=${settings.site_id}`,
-      );
-
-      await logToFile("INFO", "Fetching RMM ID from registry");
-      const { data: rmmId } = await getRmmId();
-      await logToFile("INFO", `RMM ID: ${rmmId || "Not found"}`);
-
-      const apiUrl = `${settings.api_host}/v1.0/ticket/create`;
-      await logToFile("INFO", `Submitting ticket to: ${apiUrl}`);
-      await logToFile(
-        "INFO",
-        `Ticket data: summary="${formData.summary}", urgency=${formData.urgency}, impact=${formData.impact}, has_screenshot=${!!screenshot}`,
-      );
-
-      // Use FormData for multipart/form-data to handle large screenshots
-      const formDataToSend = new FormData();
-      formDataToSend.append("summary", formData.summary);
-      formDataToSend.append("description", formData.description || "");
-      formDataToSend.append("impact", formData.impact);
-      formDataToSend.append("urgency", formData.urgency);
-      formDataToSend.append("name", formData.name);
-      formDataToSend.append("email", formData.email);
-      formDataToSend.append("phone", formData.phone.replace(/\D/g, ""));
-      if (rmmId) {
-        formDataToSend.append("rmm_id", rmmId);
-      }
-
-      // Add screenshot as a file if present
-      if (screenshot && formData.screenshot) {
-        await logToFile("INFO", `Adding screenshot file: ${screenshot.name}`);
-        // Read the file as a blob
-        const { data: fileContent } = await readFileBinary(formData.screenshot);
-
-        if (fileContent) {
-          const blob = new Blob([new Uint8Array(fileContent)], {
-            type: "image/png",
-          });
-          formDataToSend.append(
-            "screenshot",
-            blob,
-            screenshot.name || "screenshot.png",
-          );
-          await logToFile("INFO", `Screenshot file size: ${blob.size} bytes`);
-        } else {
-          await logToFile(
-            "WARN",
-            `Screenshot failed to be read as binary: ${formData.screenshot}`,
-          );
+      // Downscale and strip metadata before upload, falling back to the original file
+      let screenshotId = screenshot ? formData.screenshot : undefined;
+      if (screenshotId && screenshotId !== recordingId) {
+        const { data: prepared } = await annotateScreenshot(screenshotId, []);
+        if (prepared) {
+          screenshotId = prepared.id;
         }
       }
 
-      const res = await fetch(apiUrl, {
-        method: "POST",
-        headers: {
-          "X-Site-ID": settings.site_id,
-          "X-Device-ID": settings.device_id,
-          // Don't set Content-Type - browser will set it with boundary
+      const { data: outcome, error } = await submitTicket(
+        {
+          summary: formData.summary,
+          description: formData.description || "",
+          impact: formData.impact,
+          urgency: formData.urgency,
+          name: formData.name,
+          email: formData.email,
+          phone: formData.phone.replace(/\D/g, ""),
         },
-        body: formDataToSend,
-      });
+        screenshotId,
+      );
 
-      await logToFile("INFO", `API response status: ${res.status}`);
+      if (!outcome) {
+        throw error?.message ?? "Submission failed";
+      }
 
-      if (!res.ok) {
-        const errorText = await res.text();
-        await logToFile(
-          "ERROR",
-          `API request failed with status ${res.status}: ${errorText}`,
+      if (outcome.status === "created") {
+        alert(
+          `Support ticket created successfully! Ticket ID: ${outcome.ticket_id}`,
+        );
+      } else {
+        alert(
+          "We couldn't reach the support server. Your ticket has been saved and will be sent automatically.",
         );
-        throw "API Fetch Error";
       }
 
-      const ret: APIResponse<string> = await res.json();
-      await logToFile(
-        "INFO",
-        `Ticket created successfully! Ticket ID: ${ret.data}`,
-      );
-
-      alert(`Support ticket created successfully! Ticket ID: ${ret.data}`);
       form.reset();
       await hideWindow("support");
     } catch (err) {
@@ -270,19 +271,92 @@ export default function Support() {
       return;
     }
 
-    form.setValue("screenshot", path);
+    const { data: capture } = await importScreenshot(path);
+    if (!capture) {
+      toast.error("Failed to load the chosen image");
+      return;
+    }
+
+    setCaptures([]);
+    setRecordingId(undefined);
+    form.setValue("screenshot", capture.id);
+    form.setValue(
+      "screenshot_name",
+      path.includes("/") ? path.split("/").pop() : path.split("\\").pop(),
+    );
   };
 
   const handleScreenshot = async () => {
-    const { data: path } = await takeScreenshot();
+    const { data: result } = await takeScreenshot();
     await showWindow("support");
-    form.setValue("screenshot", path);
+    if (result) {
+      setRecordingId(undefined);
+      applyCaptures(result);
+    }
+  };
+
+  const attachRecording = (recording: ScreenRecording, label: string) => {
+    setCaptures([]);
+    setRecordingId(recording.id);
+    form.setValue("screenshot", recording.id);
+    form.setValue(
+      "screenshot_name",
+      `${label} - ${Math.round(recording.duration_ms / 1000)}s`,
+    );
+  };
+
+  const handleRecord = async () => {
+    const { data: recording } = await recordScreen({ duration_secs: 10 });
+    await showWindow("support");
+    if (!recording) {
+      toast.error("Failed to record screen");
+      return;
+    }
+
+    attachRecording(recording, "Recording");
+  };
+
+  const handleStartReplay = async () => {
+    const { error } = await startReplayRecording({
+      duration_secs: REPLAY_SECS,
+    });
+    if (error) {
+      await showWindow("support");
+      toast.error("Failed to start recording");
+      return;
+    }
+
+    setReplayRunning(true);
+  };
+
+  const handleSaveReplay = async () => {
+    const { data: recording } = await saveReplayRecording();
+    setReplayRunning(false);
+    if (!recording) {
+      toast.error("Failed to save recording");
+      return;
+    }
+
+    attachRecording(recording, "Replay");
+  };
+
+  const handleDiscardReplay = async () => {
+    await stopReplayRecording();
+    setReplayRunning(false);
   };
 
   return (
     <main className="flex flex-col size-full p-6 gap-2 items-center">
-      <h1 className="flex text-4xl text-center items-center">
-        <span className="text-6xl text-primary">Centriserve IT</span>
+      <h1 className="flex gap-3 text-4xl text-center items-center">
+        {branding?.logo && (
+          <img src={branding.logo} alt={branding.name} className="size-14" />
+        )}
+        <span
+          className="text-6xl text-primary"
+          style={{ color: branding?.accent_color ?? undefined }}
+        >
+          {branding?.name}
+        </span>
       </h1>
 
       <Form {...form}>
@@ -454,7 +528,7 @@ export default function Support() {
               {!!screenshot ? (
                 <Button
                   variant="destructive"
-                  onClick={() => form.setValue("screenshot", undefined)}
+                  onClick={clearScreenshots}
                   disabled={isSubmitting}
                 >
                   Clear Screenshot
@@ -468,8 +542,76 @@ export default function Support() {
                   Take Screenshot
                 </Button>
               )}
+              {!screenshot && (
+                <Button
+                  type="button"
+                  variant="outline"
+                  onClick={handleRecord}
+                  disabled={isSubmitting}
+                >
+                  Record 10s
+                </Button>
+              )}
+              {!screenshot && !replayRunning && (
+                <Button
+                  type="button"
+                  variant="outline"
+                  onClick={handleStartReplay}
+                  disabled={isSubmitting}
+                >
+                  Keep last {REPLAY_SECS}s
+                </Button>
+              )}
             </div>
 
+            {replayRunning && (
+              <div className="flex w-full items-center gap-2 rounded border border-destructive p-2">
+                <span className="size-2 rounded-full bg-destructive animate-pulse" />
+                <span className="flex-1 text-sm">
+                  Recording your screen, save to attach the last {REPLAY_SECS}{" "}
+                  seconds
+                </span>
+                <Button
+                  type="button"
+                  size="sm"
+                  onClick={handleSaveReplay}
+                  disabled={isSubmitting}
+                >
+                  Save Replay
+                </Button>
+                <Button
+                  type="button"
+                  size="sm"
+                  variant="outline"
+                  onClick={handleDiscardReplay}
+                  disabled={isSubmitting}
+                >
+                  Discard
+                </Button>
+              </div>
+            )}
+
+            {captures.length > 1 && (
+              <Select
+                onValueChange={(id) =>
+                  selectCapture(captures.find((capture) => capture.id === id))
+                }
+                value={formValues.screenshot}
+                disabled={isSubmitting}
+              >
+                <SelectTrigger className="w-full">
+                  <SelectValue placeholder="Select screen" />
+                </SelectTrigger>
+                <SelectContent>
+                  {captures.map((capture) => (
+                    <SelectItem key={capture.id} value={capture.id}>
+                      {captureLabel(capture)}
+                    </SelectItem>
+                  ))}
+                </SelectContent>
+              </Select>
+            )}
+
             {screenshot && (
               <div className="border rounded p-2 w-fit overflow-clip">
                 <img
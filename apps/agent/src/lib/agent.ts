@@ -2,15 +2,12 @@ import { invoke } from "@tauri-apps/api/core";
 import Debug from "@workspace/shared/lib/Debug.ts";
 import { APIResponse } from "@workspace/shared/types/api.ts";
 
+// The device's credentials stay with the service, the UI only gets what it displays
 export type AgentSettings = {
-  site_id: string;
-  api_host: string;
-  installed_at: string;
-
-  guid?: string;
-  device_id?: string;
-  hostname?: string;
-  registered_at?: string;
+  hostname?: string | null;
+  show_tray?: boolean | null;
+  screenshot_ttl_minutes?: number | null;
+  support_hotkey?: string | null;
 };
 
 export type SystemInfo = {
@@ -71,3 +68,124 @@ export async function getRmmId(): Promise<APIResponse<string>> {
     });
   }
 }
+
+export type TicketRequest = {
+  summary: string;
+  description?: string;
+  name: string;
+  email: string;
+  phone: string;
+  impact: string;
+  urgency: string;
+  rmm_id?: string;
+};
+
+export type TicketOutcome =
+  | { status: "created"; ticket_id: string }
+  | { status: "queued"; outbox_id: string };
+
+export async function submitTicket(
+  ticket: TicketRequest,
+  screenshotId?: string,
+): Promise<APIResponse<TicketOutcome>> {
+  try {
+    const outcome = await invoke<TicketOutcome>("submit_ticket", {
+      ticket,
+      screenshotId: screenshotId ?? null,
+    });
+
+    return { data: outcome };
+  } catch (err) {
+    return Debug.error({
+      module: "Agent",
+      context: "submitTicket",
+      message: `Failed to submit ticket: ${err}`,
+    });
+  }
+}
+
+export type TrackedTicket = {
+  id: string;
+  summary: string;
+  created_at: string;
+  status: string | null;
+  closed: boolean;
+  last_reply_at: string | null;
+  last_reply: string | null;
+  updated_at: string | null;
+  revision: number;
+  last_change: "replied" | "closed" | "status_changed" | null;
+};
+
+export async function getMyTickets(): Promise<APIResponse<TrackedTicket[]>> {
+  try {
+    const tickets = await invoke<TrackedTicket[]>("get_my_tickets");
+
+    return { data: tickets };
+  } catch (err) {
+    return Debug.error({
+      module: "Agent",
+      context: "getMyTickets",
+      message: `Failed to get tickets: ${err}`,
+    });
+  }
+}
+
+export type Branding = {
+  name: string;
+  accent_color: string | null;
+  support_phone: string | null;
+  support_email: string | null;
+  logo: string | null;
+};
+
+export async function getBranding(): Promise<APIResponse<Branding>> {
+  try {
+    const branding = await invoke<Branding>("get_branding");
+
+    return { data: branding };
+  } catch (err) {
+    return Debug.error({
+      module: "Agent",
+      context: "getBranding",
+      message: `Failed to get branding: ${err}`,
+    });
+  }
+}
+
+export type ScheduledReboot = {
+  run_id: string;
+  reboot_at: string;
+  deferrals_left: number;
+  message: string | null;
+};
+
+export async function getScheduledReboot(): Promise<
+  APIResponse<ScheduledReboot | null>
+> {
+  try {
+    const reboot = await invoke<ScheduledReboot | null>("get_scheduled_reboot");
+
+    return { data: reboot };
+  } catch (err) {
+    return Debug.error({
+      module: "Agent",
+      context: "getScheduledReboot",
+      message: `Failed to get scheduled restart: ${err}`,
+    });
+  }
+}
+
+export async function postponeReboot(): Promise<APIResponse<ScheduledReboot>> {
+  try {
+    const reboot = await invoke<ScheduledReboot>("postpone_reboot");
+
+    return { data: reboot };
+  } catch (err) {
+    return Debug.error({
+      module: "Agent",
+      context: "postponeReboot",
+      message: `Failed to postpone restart: ${err}`,
+    });
+  }
+}
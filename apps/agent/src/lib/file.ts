@@ -3,9 +3,38 @@ import { open } from "@tauri-apps/plugin-dialog";
 import Debug from "@workspace/shared/lib/Debug.ts";
 import { APIResponse } from "@workspace/shared/types/api.ts";
 
-export async function takeScreenshot(): Promise<APIResponse<string>> {
+export type MonitorInfo = {
+  id: number;
+  name: string;
+  x: number;
+  y: number;
+  width: number;
+  height: number;
+  scale_factor: number;
+  is_primary: boolean;
+};
+
+export type ScreenCapture = {
+  monitor: MonitorInfo | null;
+  id: string;
+  width: number;
+  height: number;
+};
+
+export type ScreenshotResult = {
+  captures: ScreenCapture[];
+  stitched: ScreenCapture | null;
+};
+
+export async function takeScreenshot(
+  monitorId?: number,
+  stitch = true,
+): Promise<APIResponse<ScreenshotResult>> {
   try {
-    const result = await invoke<string>("take_screenshot");
+    const result = await invoke<ScreenshotResult>("take_screenshot", {
+      monitorId: monitorId ?? null,
+      stitch,
+    });
 
     return {
       data: result,
@@ -133,3 +162,222 @@ export async function readFileBinary(
     });
   }
 }
+
+export type Rect = { x: number; y: number; width: number; height: number };
+export type Point = { x: number; y: number };
+
+export type AnnotationOp =
+  | { type: "blur"; rect: Rect }
+  | { type: "redact"; rect: Rect; color?: string }
+  | { type: "highlight"; rect: Rect; color?: string; thickness?: number }
+  | {
+      type: "arrow";
+      from: Point;
+      to: Point;
+      color?: string;
+      thickness?: number;
+    }
+  | {
+      type: "text";
+      at: Point;
+      text: string;
+      color?: string;
+      background?: string;
+      scale?: number;
+    };
+
+export type UploadOptions = {
+  max_dimension?: number;
+  max_bytes?: number;
+};
+
+export type AnnotatedImage = {
+  id: string;
+  width: number;
+  height: number;
+  size_bytes: number;
+};
+
+export async function annotateScreenshot(
+  id: string,
+  operations: AnnotationOp[],
+  options?: UploadOptions,
+): Promise<APIResponse<AnnotatedImage>> {
+  try {
+    const result = await invoke<AnnotatedImage>("annotate_screenshot", {
+      id,
+      operations,
+      options: options ?? null,
+    });
+
+    return {
+      data: result,
+    };
+  } catch (err) {
+    return Debug.error({
+      module: "File",
+      context: "annotateScreenshot",
+      message: `Failed to annotate screenshot: ${err}`,
+    });
+  }
+}
+
+export async function importScreenshot(
+  path: string,
+): Promise<APIResponse<ScreenCapture>> {
+  try {
+    const result = await invoke<ScreenCapture>("import_screenshot_file", {
+      path,
+    });
+
+    return {
+      data: result,
+    };
+  } catch (err) {
+    return Debug.error({
+      module: "File",
+      context: "importScreenshot",
+      message: `Failed to import screenshot: ${err}`,
+    });
+  }
+}
+
+export type RecordingOptions = {
+  monitor_id?: number;
+  duration_secs?: number;
+  fps?: number;
+  max_bytes?: number;
+  max_dimension?: number;
+};
+
+export type ScreenRecording = {
+  id: string;
+  width: number;
+  height: number;
+  frames: number;
+  duration_ms: number;
+  size_bytes: number;
+};
+
+export async function recordScreen(
+  options?: RecordingOptions,
+): Promise<APIResponse<ScreenRecording>> {
+  try {
+    const result = await invoke<ScreenRecording>("record_screen_clip", {
+      options: options ?? null,
+    });
+
+    return {
+      data: result,
+    };
+  } catch (err) {
+    return Debug.error({
+      module: "File",
+      context: "recordScreen",
+      message: `Failed to record screen: ${err}`,
+    });
+  }
+}
+
+// Keeps the last `duration_secs` of the screen until saved or stopped
+export async function startReplayRecording(
+  options?: RecordingOptions,
+): Promise<APIResponse<undefined>> {
+  try {
+    await invoke("start_replay_recording", { options: options ?? null });
+
+    return {
+      data: undefined,
+    };
+  } catch (err) {
+    return Debug.error({
+      module: "File",
+      context: "startReplayRecording",
+      message: `Failed to start replay recording: ${err}`,
+    });
+  }
+}
+
+export async function saveReplayRecording(): Promise<
+  APIResponse<ScreenRecording>
+> {
+  try {
+    const result = await invoke<ScreenRecording>("save_replay_recording");
+
+    return {
+      data: result,
+    };
+  } catch (err) {
+    return Debug.error({
+      module: "File",
+      context: "saveReplayRecording",
+      message: `Failed to save replay recording: ${err}`,
+    });
+  }
+}
+
+export async function stopReplayRecording(): Promise<APIResponse<boolean>> {
+  try {
+    const result = await invoke<boolean>("stop_replay_recording");
+
+    return {
+      data: result,
+    };
+  } catch (err) {
+    return Debug.error({
+      module: "File",
+      context: "stopReplayRecording",
+      message: `Failed to stop replay recording: ${err}`,
+    });
+  }
+}
+
+// The replay stops itself when it's left unsaved for too long
+export async function getReplayRecordingStatus(): Promise<APIResponse<boolean>> {
+  try {
+    const result = await invoke<boolean>("get_replay_recording_status");
+
+    return {
+      data: result,
+    };
+  } catch (err) {
+    return Debug.error({
+      module: "File",
+      context: "getReplayRecordingStatus",
+      message: `Failed to get replay recording status: ${err}`,
+    });
+  }
+}
+
+// Resolves to a data URL, stills are PNGs and recordings GIFs
+export async function readScreenshot(id: string): Promise<APIResponse<string>> {
+  try {
+    const result = await invoke<string>("read_screenshot", { id });
+
+    return {
+      data: result,
+    };
+  } catch (err) {
+    return Debug.error({
+      module: "File",
+      context: "readScreenshot",
+      message: `Failed to read screenshot: ${err}`,
+    });
+  }
+}
+
+export async function discardScreenshots(): Promise<APIResponse<boolean>> {
+  try {
+    await invoke("discard_screenshots");
+
+    return {
+      data: true,
+    };
+  } catch (err) {
+    return Debug.error({
+      module: "File",
+      context: "discardScreenshots",
+      message: `Failed to discard screenshots: ${err}`,
+    });
+  }
+}
@@ -0,0 +1,12 @@
+import React from "react";
+import ReactDOM from "react-dom/client";
+import Reboot from "./Reboot.tsx";
+import "@workspace/ui/styles/agent.css";
+import { Toaster } from "@workspace/ui/components/sonner.tsx";
+
+ReactDOM.createRoot(document.getElementById("root") as HTMLElement).render(
+  <React.StrictMode>
+    <Reboot />
+    <Toaster position="bottom-right" />
+  </React.StrictMode>,
+);
@@ -34,6 +34,7 @@ CONFIG_DIR="/Library/Application Support/${CONFIG_DIR_NAME}"
 LOG_DIR="${CONFIG_DIR}/logs"
 SETTINGS_FILE="${CONFIG_DIR}/settings.json"
 LAUNCH_AGENT_PLIST="/Library/LaunchAgents/com.mspbyte.agent.plist"
+LAUNCH_DAEMON_PLIST="/Library/LaunchDaemons/com.mspbyte.agent.service.plist"
 
 APP_PATH="/Applications/${APP_NAME}.app"
 MOUNT_POINT="/Volumes/${APP_NAME}"
@@ -197,6 +198,12 @@ else
     log "Application is not currently running"
 fi
 
+# Stop the agent service before its binary is replaced
+if [ -f "${LAUNCH_DAEMON_PLIST}" ]; then
+    log "Stopping existing agent service"
+    launchctl bootout system "${LAUNCH_DAEMON_PLIST}" 2>/dev/null || true
+fi
+
 # Unload launch agent if it exists
 if [ -f "${LAUNCH_AGENT_PLIST}" ]; then
     log "Unloading existing launch agent"
@@ -277,17 +284,12 @@ log "Target directory: ${CONFIG_DIR}"
 mkdir -p "${CONFIG_DIR}"
 mkdir -p "${LOG_DIR}"
 
-# Set permissions - readable by all, writable by admin
-chmod 1777 "${CONFIG_DIR}"
-chmod 1777 "${LOG_DIR}"
+# The agent service owns the configuration, the tray reads it over the service's socket
+chown root:wheel "${CONFIG_DIR}"
+chmod 755 "${CONFIG_DIR}"
 
 log "Directory created successfully"
 
-# Set extended permissions to ensure all users can read
-# This makes the config globally accessible
-log "Setting global read permissions"
-chmod -R a+rw "${CONFIG_DIR}"
-
 # Ensure logs are writable by all (for runtime logging)
 # Use sticky bit (1777) so users can write but not delete others' logs
 chmod 1777 "${LOG_DIR}"
@@ -340,21 +342,15 @@ if [ "${SHOULD_CREATE_SETTINGS}" = true ]; then
 }
 EOF
 
-    # Set permissions - readable by all users
-    chmod 666 "${SETTINGS_FILE}"
-    
     log "settings.json created successfully"
-    
-    # Verify file is readable
-    if [ -r "${SETTINGS_FILE}" ]; then
-        log "Verified settings.json is readable by all users"
-    else
-        log "WARNING: Could not verify settings.json is readable"
-    fi
 else
     log "Keeping existing settings.json"
 fi
 
+# Only the agent service reads and writes the settings, they hold the device's credentials
+chown root:wheel "${SETTINGS_FILE}"
+chmod 600 "${SETTINGS_FILE}"
+
 # Create runtime log file with global write permissions
 RUNTIME_LOG="${LOG_DIR}/runtime_${APP_VERSION}.log"
 touch "${RUNTIME_LOG}"
@@ -362,9 +358,26 @@ chmod 666 "${RUNTIME_LOG}"
 log "Runtime log created with global write access: ${RUNTIME_LOG}"
 
 # =============================================================================
-# STEP 7: Configure Auto-Start for All Users (System-wide Launch Agent)
+# STEP 7: Install the Agent Service (Launch Daemon)
 # =============================================================================
-log "=== STEP 7: Configuring Auto-Start for All Users ==="
+log "=== STEP 7: Installing Agent Service ==="
+log "Installing launch daemon at: ${LAUNCH_DAEMON_PLIST}"
+
+mkdir -p /Library/LaunchDaemons
+cp "${APP_PATH}/Contents/Library/LaunchDaemons/com.mspbyte.agent.service.plist" "${LAUNCH_DAEMON_PLIST}"
+chown root:wheel "${LAUNCH_DAEMON_PLIST}"
+chmod 644 "${LAUNCH_DAEMON_PLIST}"
+
+launchctl bootstrap system "${LAUNCH_DAEMON_PLIST}" 2>&1 | tee -a "${LOG_FILE}" || {
+    log "WARNING: Could not start the agent service, it starts at next boot"
+}
+
+log "✓ Agent service installed"
+
+# =============================================================================
+# STEP 8: Configure Auto-Start for All Users (System-wide Launch Agent)
+# =============================================================================
+log "=== STEP 8: Configuring Auto-Start for All Users ==="
 log "Creating system-wide launch agent at: ${LAUNCH_AGENT_PLIST}"
 log "This will run for ALL users (current and future) when they log in"
 
@@ -433,9 +446,9 @@ fi
 log "Auto-start configured for all users (system-wide)"
 
 # =============================================================================
-# STEP 8: Verify Installation
+# STEP 9: Verify Installation
 # =============================================================================
-log "=== STEP 8: Verifying Installation ==="
+log "=== STEP 9: Verifying Installation ==="
 
 # Check if app exists
 if [ -d "${APP_PATH}" ]; then
@@ -459,6 +472,13 @@ fi
 CONFIG_PERMS=$(ls -ld "${CONFIG_DIR}" | awk '{print $1}')
 log "Configuration directory permissions: ${CONFIG_PERMS}"
 
+# Check the agent service
+if launchctl print system/com.mspbyte.agent.service > /dev/null 2>&1; then
+    log "✓ Agent service loaded: ${LAUNCH_DAEMON_PLIST}"
+else
+    log "✗ Agent service NOT loaded: ${LAUNCH_DAEMON_PLIST}"
+fi
+
 # Check system-wide launch agent
 if [ -f "${LAUNCH_AGENT_PLIST}" ]; then
     AGENT_PERMS=$(ls -l "${LAUNCH_AGENT_PLIST}" | awk '{print $1}')
@@ -479,9 +499,9 @@ else
 fi
 
 # =============================================================================
-# STEP 9: Cleanup
+# STEP 10: Cleanup
 # =============================================================================
-log "=== STEP 9: Cleanup ==="
+log "=== STEP 10: Cleanup ==="
 
 # Remove temporary config file if it exists
 if [ -f "/tmp/mspagent_config.json" ]; then
@@ -503,9 +523,10 @@ echo "✓ MSPAgent installed successfully for ALL USERS!"
 echo ""
 echo "Global Configuration: ${CONFIG_DIR}"
 echo "Logs: ${LOG_DIR}"
+echo "Agent service: ${LAUNCH_DAEMON_PLIST}"
 echo "System-wide launch agent: ${LAUNCH_AGENT_PLIST}"
 echo ""
 echo "The application will start automatically on login for ALL users (current and future)."
-echo "Config is globally accessible at: ${SETTINGS_FILE}"
+echo "Config is managed by the agent service at: ${SETTINGS_FILE}"
 
 exit 0
@@ -75,6 +75,14 @@ else
     log "No launch agents found"
 fi
 
+# Stop and remove the agent service
+if [ -f "/Library/LaunchDaemons/com.mspbyte.agent.service.plist" ]; then
+    log "Found agent service - removing"
+    launchctl bootout system "/Library/LaunchDaemons/com.mspbyte.agent.service.plist" 2>/dev/null || true
+    rm -f "/Library/LaunchDaemons/com.mspbyte.agent.service.plist"
+    log "Agent service removed"
+fi
+
 # Also check for global LaunchDaemon (in case it was used)
 if [ -f "/Library/LaunchDaemons/com.mspbyte.agent.plist" ]; then
     log "Found global launch daemon - removing"
@@ -0,0 +1,244 @@
+import { getAgentContext } from "@/lib/agentContext.js";
+import { api } from "@workspace/database/convex/_generated/api.js";
+import { Doc } from "@workspace/database/convex/_generated/dataModel.js";
+import { client } from "@workspace/shared/lib/convex.js";
+import Debug from "@workspace/shared/lib/Debug.js";
+import { createHash } from "node:crypto";
+import { FastifyInstance } from "fastify";
+
+type AlertEvent = {
+  guid?: string;
+  alertType?: string;
+  severity?: "low" | "medium" | "high" | "critical";
+  message?: string;
+  fingerprint?: string;
+  status?: "active" | "resolved";
+  metadata?: Record<string, unknown>;
+  detectedAt?: string;
+};
+
+const SEVERITIES = ["low", "medium", "high", "critical"];
+
+/**
+ * Threshold and service alerts raised by the agent, stored in entity_alerts
+ * against the agent's endpoint entity so they show next to integration alerts.
+ */
+export default async function (fastify: FastifyInstance) {
+  const CONVEX_API_KEY = process.env.CONVEX_API_KEY!;
+
+  fastify.post("/", async (req) => {
+    try {
+      const context = await getAgentContext(req);
+      if (!context) {
+        return Debug.response(
+          {
+            error: {
+              module: "v1.0/alerts",
+              context: "POST",
+              message: "API headers invalid",
+            },
+          },
+          401,
+        );
+      }
+      const { agent, site } = context;
+
+      const event = req.body as AlertEvent;
+      if (
+        !event?.alertType ||
+        !event.message ||
+        !event.fingerprint ||
+        !SEVERITIES.includes(event.severity as string) ||
+        (event.status !== "active" && event.status !== "resolved")
+      ) {
+        return Debug.response(
+          {
+            error: {
+              module: "v1.0/alerts",
+              context: "POST",
+              message:
+                "alertType, severity, message, fingerprint and status are required",
+            },
+          },
+          400,
+        );
+      }
+
+      // Fingerprints are only unique per agent, scope them so one device can't touch another's alerts
+      const fingerprint = `${agent._id}:${event.fingerprint}`;
+
+      const agentSource = (await client.query(api.helpers.orm.get_s, {
+        tableName: "data_sources",
+        tenantId: site.tenantId,
+        secret: CONVEX_API_KEY,
+        index: {
+          name: "by_integration_primary",
+          params: {
+            integrationId: "msp-agent",
+            isPrimary: true,
+          },
+        },
+      })) as Doc<"data_sources">;
+
+      if (!agentSource) {
+        return Debug.response(
+          {
+            error: {
+              module: "v1.0/alerts",
+              context: "POST",
+              message: "Agent data source not found",
+            },
+          },
+          404,
+        );
+      }
+
+      const existing = (await client.query(api.helpers.orm.get_s, {
+        tableName: "entity_alerts",
+        tenantId: site.tenantId,
+        secret: CONVEX_API_KEY,
+        index: {
+          name: "by_fingerprint",
+          params: { fingerprint },
+        },
+      })) as Doc<"entity_alerts"> | null;
+
+      const now = Date.now();
+      const detectedAt = Date.parse(event.detectedAt ?? "") || now;
+
+      if (event.status === "resolved") {
+        if (existing && existing.status === "active") {
+          await client.mutation(api.helpers.orm.update_s, {
+            tableName: "entity_alerts",
+            data: [
+              {
+                id: existing._id,
+                updates: {
+                  status: "resolved",
+                  resolvedAt: detectedAt,
+                  metadata: event.metadata,
+                },
+              },
+            ],
+            secret: CONVEX_API_KEY,
+          });
+        }
+
+        return Debug.response({ data: { fingerprint } }, 200);
+      }
+
+      if (existing) {
+        // A suppression stands until it expires, only the last seen time moves
+        const suppressed =
+          existing.status === "suppressed" &&
+          (!existing.suppressedUntil || existing.suppressedUntil > now);
+
+        await client.mutation(api.helpers.orm.update_s, {
+          tableName: "entity_alerts",
+          data: [
+            {
+              id: existing._id,
+              updates: {
+                status: suppressed ? "suppressed" : "active",
+                severity: event.severity,
+                message: event.message,
+                metadata: event.metadata,
+                lastSeenAt: detectedAt,
+              },
+            },
+          ],
+          secret: CONVEX_API_KEY,
+        });
+      } else {
+        await client.mutation(api.helpers.orm.insert_s, {
+          tableName: "entity_alerts",
+          data: [
+            {
+              entityId: await getAgentEntity(agent, site, agentSource),
+              dataSourceId: agentSource._id,
+              integrationId: "msp-agent",
+              siteId: site._id,
+              alertType: event.alertType,
+              severity: event.severity,
+              message: event.message,
+              fingerprint,
+              metadata: event.metadata,
+              status: "active",
+              lastSeenAt: detectedAt,
+            },
+          ],
+          tenantId: site.tenantId,
+          secret: CONVEX_API_KEY,
+        });
+      }
+
+      return Debug.response({ data: { fingerprint } }, 200);
+    } catch (err) {
+      return Debug.response(
+        {
+          error: {
+            module: "v1.0/alerts",
+            context: "POST",
+            message: `Failed to record alert: ${err}`,
+          },
+        },
+        500,
+      );
+    }
+  });
+}
+
+/**
+ * The endpoint entity standing in for the agent, created on its first alert
+ */
+async function getAgentEntity(
+  agent: Doc<"agents">,
+  site: Doc<"sites">,
+  agentSource: Doc<"data_sources">,
+) {
+  const CONVEX_API_KEY = process.env.CONVEX_API_KEY!;
+
+  const existing = (await client.query(api.helpers.orm.get_s, {
+    tableName: "entities",
+    tenantId: site.tenantId,
+    secret: CONVEX_API_KEY,
+    index: {
+      name: "by_data_source_type",
+      params: { dataSourceId: agentSource._id, entityType: "endpoints" },
+    },
+    filters: { externalId: agent.guid },
+  })) as Doc<"entities"> | null;
+
+  if (existing) {
+    return existing._id;
+  }
+
+  const rawData = {
+    guid: agent.guid,
+    hostname: agent.hostname,
+    platform: agent.platform,
+    version: agent.version,
+  };
+  const [id] = await client.mutation(api.helpers.orm.insert_s, {
+    tableName: "entities",
+    data: [
+      {
+        integrationId: "msp-agent",
+        dataSourceId: agentSource._id,
+        siteId: site._id,
+        entityType: "endpoints",
+        state: "normal",
+        externalId: agent.guid,
+        dataHash: createHash("sha256")
+          .update(JSON.stringify(rawData))
+          .digest("hex"),
+        rawData,
+        lastSeenAt: Date.now(),
+      },
+    ],
+    tenantId: site.tenantId,
+    secret: CONVEX_API_KEY,
+  });
+
+  return id;
+}
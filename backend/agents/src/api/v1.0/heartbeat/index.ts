@@ -46,10 +46,14 @@ export default async function (fastify: FastifyInstance) {
         siteID,
       );
 
+      // Queuing and signing jobs is out of scope for this endpoint, nothing is delivered yet.
+      // The fields are always sent so agents see an empty queue rather than a missing one.
       return Debug.response(
         {
           data: {
             guid: calculatedGuid,
+            jobs: [],
+            cancel_jobs: [],
           },
         },
         200,
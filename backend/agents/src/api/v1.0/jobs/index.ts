@@ -0,0 +1,282 @@
+import { getAgentContext, recordAgentReport } from "@/lib/agentContext.js";
+import Debug from "@workspace/shared/lib/Debug.js";
+import { FastifyInstance, FastifyRequest } from "fastify";
+import { createHash } from "node:crypto";
+import { createReadStream } from "node:fs";
+import { mkdir, open, rename, stat, truncate, unlink } from "node:fs/promises";
+import { join } from "node:path";
+
+// Files moved by transfer jobs, <dir>/uploads/<agent>/<job>/<run> and <dir>/downloads/<agent>/<job>
+const TRANSFERS_DIR =
+  process.env.AGENT_TRANSFERS_DIR || join(process.cwd(), "transfers");
+
+// Agents send at most 8 MiB per chunk
+const MAX_CHUNK_BYTES = 8 * 1024 * 1024;
+const ID_PATTERN = /^[A-Za-z0-9_-]+$/;
+
+type JobResult = {
+  job_id?: string;
+  run_id?: string;
+  status?: string;
+};
+
+function transferError(context: string, message: string, status: number) {
+  return Debug.response(
+    {
+      error: {
+        module: "v1.0/jobs",
+        context,
+        message,
+      },
+    },
+    status,
+  );
+}
+
+// Size of a file, or null when it doesn't exist
+async function fileSize(path: string): Promise<number | null> {
+  try {
+    return (await stat(path)).size;
+  } catch (err) {
+    if ((err as NodeJS.ErrnoException).code === "ENOENT") {
+      return null;
+    }
+    throw err;
+  }
+}
+
+async function sha256File(path: string): Promise<string> {
+  const hash = createHash("sha256");
+  for await (const chunk of createReadStream(path)) {
+    hash.update(chunk);
+  }
+  return hash.digest("hex");
+}
+
+// Where one run of an upload job is stored, null when the ids can't name a file
+function uploadPaths(req: FastifyRequest, agentId: string) {
+  const { id } = req.params as { id: string };
+  const runId = req.headers["x-run-id"] as string | undefined;
+  if (!ID_PATTERN.test(id) || !runId || !ID_PATTERN.test(runId)) {
+    return null;
+  }
+  const dir = join(TRANSFERS_DIR, "uploads", agentId, id);
+  return { dir, partial: join(dir, `${runId}.part`), complete: join(dir, runId) };
+}
+
+export default async function (fastify: FastifyInstance) {
+  // Upload chunks arrive as raw bytes
+  fastify.addContentTypeParser(
+    "application/octet-stream",
+    { parseAs: "buffer", bodyLimit: MAX_CHUNK_BYTES },
+    (_req, body, done) => done(null, body),
+  );
+
+  /**
+   * How much of an upload the server holds, 404 until the first chunk arrives
+   */
+  fastify.get("/:id/upload", async (req) => {
+    try {
+      const context = await getAgentContext(req);
+      if (!context) {
+        return transferError("GET upload", "API headers invalid", 401);
+      }
+
+      const paths = uploadPaths(req, context.agent._id);
+      if (!paths) {
+        return transferError("GET upload", "Invalid job or run id", 400);
+      }
+
+      const complete = await fileSize(paths.complete);
+      const offset = complete ?? (await fileSize(paths.partial));
+      if (offset === null) {
+        return transferError("GET upload", "No upload started for this run", 404);
+      }
+      return Response.json({ offset }, { status: 200 });
+    } catch (err) {
+      return transferError("GET upload", `Failed to read upload offset: ${err}`, 500);
+    }
+  });
+
+  /**
+   * Appends one chunk at x-upload-offset. A chunk for any other offset gets a 409 with
+   * the offset the server holds, the last one is checked against x-upload-sha256.
+   */
+  fastify.put("/:id/upload", async (req) => {
+    // Set once a chunk is being written, a half written one is cut back off on failure
+    let writing: { path: string; offset: number } | null = null;
+    try {
+      const context = await getAgentContext(req);
+      if (!context) {
+        return transferError("PUT upload", "API headers invalid", 401);
+      }
+
+      const paths = uploadPaths(req, context.agent._id);
+      const offset = Number(req.headers["x-upload-offset"]);
+      const length = Number(req.headers["x-upload-length"]);
+      const sha256 = String(req.headers["x-upload-sha256"] ?? "").toLowerCase();
+      const chunk = req.body as Buffer;
+      if (
+        !paths ||
+        !Number.isSafeInteger(offset) ||
+        !Number.isSafeInteger(length) ||
+        offset < 0 ||
+        !/^[0-9a-f]{64}$/.test(sha256) ||
+        !Buffer.isBuffer(chunk) ||
+        chunk.length === 0 ||
+        offset + chunk.length > length
+      ) {
+        return transferError(
+          "PUT upload",
+          "Job and run ids, an octet-stream chunk and x-upload-offset, x-upload-length and x-upload-sha256 are required",
+          400,
+        );
+      }
+
+      const complete = await fileSize(paths.complete);
+      if (complete !== null) {
+        return Response.json({ offset: complete }, { status: 409 });
+      }
+
+      await mkdir(paths.dir, { recursive: true });
+      const held = (await fileSize(paths.partial)) ?? 0;
+      if (held !== offset) {
+        return Response.json({ offset: held }, { status: 409 });
+      }
+
+      writing = { path: paths.partial, offset };
+      const file = await open(paths.partial, "a");
+      try {
+        await file.write(chunk);
+      } finally {
+        await file.close();
+      }
+
+      const next = offset + chunk.length;
+      if (next === length) {
+        if ((await sha256File(paths.partial)) !== sha256) {
+          await unlink(paths.partial);
+          return transferError("PUT upload", "Uploaded file does not match x-upload-sha256", 422);
+        }
+        await rename(paths.partial, paths.complete);
+      }
+
+      return Response.json({ offset: next }, { status: 200 });
+    } catch (err) {
+      if (writing) {
+        await truncate(writing.path, writing.offset).catch(() => {});
+      }
+      return transferError("PUT upload", `Failed to store upload chunk: ${err}`, 500);
+    }
+  });
+
+  /**
+   * The file a download job fetches, honouring a single bytes=start-end range
+   */
+  fastify.get("/:id/download", async (req) => {
+    try {
+      const context = await getAgentContext(req);
+      if (!context) {
+        return transferError("GET download", "API headers invalid", 401);
+      }
+
+      const { id } = req.params as { id: string };
+      if (!ID_PATTERN.test(id)) {
+        return transferError("GET download", "Invalid job id", 400);
+      }
+
+      const path = join(TRANSFERS_DIR, "downloads", context.agent._id, id);
+      const size = await fileSize(path);
+      if (size === null) {
+        return transferError("GET download", "No file staged for this job", 404);
+      }
+
+      const range = /^bytes=(\d+)-(\d*)$/.exec(String(req.headers.range ?? ""));
+      const start = range ? Number(range[1]) : 0;
+      const end = range && range[2] ? Math.min(Number(range[2]), size - 1) : size - 1;
+      if (start > end || start >= size) {
+        return new Response(null, {
+          status: 416,
+          headers: { "Content-Range": `bytes */${size}` },
+        });
+      }
+      if (end - start + 1 > MAX_CHUNK_BYTES) {
+        return transferError("GET download", `Ranges are limited to ${MAX_CHUNK_BYTES} bytes`, 416);
+      }
+
+      const chunk = Buffer.alloc(end - start + 1);
+      const file = await open(path, "r");
+      try {
+        await file.read(chunk, 0, chunk.length, start);
+      } finally {
+        await file.close();
+      }
+
+      return new Response(chunk, {
+        status: range ? 206 : 200,
+        headers: {
+          "Content-Type": "application/octet-stream",
+          "Accept-Ranges": "bytes",
+          ...(range ? { "Content-Range": `bytes ${start}-${end}/${size}` } : {}),
+        },
+      });
+    } catch (err) {
+      return transferError("GET download", `Failed to read download: ${err}`, 500);
+    }
+  });
+
+  fastify.post("/:id/result", async (req) => {
+    try {
+      const context = await getAgentContext(req);
+      if (!context) {
+        return Debug.response(
+          {
+            error: {
+              module: "v1.0/jobs",
+              context: "POST",
+              message: "API headers invalid",
+            },
+          },
+          401,
+        );
+      }
+
+      const { id } = req.params as { id: string };
+      const result = req.body as JobResult;
+      if (!result?.run_id || !result.status || result.job_id !== id) {
+        return Debug.response(
+          {
+            error: {
+              module: "v1.0/jobs",
+              context: "POST",
+              message: "run_id and status are required and job_id must match the path",
+            },
+          },
+          400,
+        );
+      }
+
+      await recordAgentReport(
+        context,
+        "job_result",
+        id,
+        result.status,
+        result,
+        req.headers["x-idempotency-key"] as string | undefined,
+      );
+
+      return Debug.response({ data: { run_id: result.run_id } }, 200);
+    } catch (err) {
+      return Debug.response(
+        {
+          error: {
+            module: "v1.0/jobs",
+            context: "POST",
+            message: `Failed to record job result: ${err}`,
+          },
+        },
+        500,
+      );
+    }
+  });
+}
@@ -5,6 +5,50 @@ import { client } from "@workspace/shared/lib/convex.js";
 import Debug from "@workspace/shared/lib/Debug.js";
 import { FastifyInstance } from "fastify";
 
+type BuildInfo = {
+  version: string;
+  git_sha: string;
+  built_at?: string | null;
+  target: string;
+  profile: string;
+  features: string[];
+};
+
+type OsRelease = {
+  id: string;
+  id_like?: string | null;
+  name?: string | null;
+  version_id?: string | null;
+  version_codename?: string | null;
+  pretty_name?: string | null;
+  build?: string | null;
+};
+
+// The agent sends snake_case and null for missing fields, the agents table stores camelCase
+// and leaves missing fields out
+const toBuild = (build?: BuildInfo) =>
+  build && {
+    version: build.version,
+    gitSha: build.git_sha,
+    builtAt: build.built_at ?? undefined,
+    target: build.target,
+    profile: build.profile,
+    features: build.features,
+  };
+
+const toOsRelease = (release?: OsRelease | null) =>
+  release
+    ? {
+        id: release.id,
+        idLike: release.id_like ?? undefined,
+        name: release.name ?? undefined,
+        versionId: release.version_id ?? undefined,
+        versionCodename: release.version_codename ?? undefined,
+        prettyName: release.pretty_name ?? undefined,
+        build: release.build ?? undefined,
+      }
+    : undefined;
+
 export default async function (fastify: FastifyInstance) {
   fastify.post("/", async (req) => {
     const {
@@ -17,6 +61,9 @@ export default async function (fastify: FastifyInstance) {
       ip_address,
       ext_address,
       username,
+      capabilities,
+      build,
+      os_release,
     } = req.body as string as {
       site_id?: string;
       hostname?: string;
@@ -27,6 +74,15 @@ export default async function (fastify: FastifyInstance) {
       ip_address?: string;
       ext_address?: string;
       username?: string;
+      capabilities?: string[];
+      build?: BuildInfo;
+      os_release?: OsRelease | null;
+    };
+
+    const agentInfo = {
+      capabilities,
+      build: toBuild(build),
+      osRelease: toOsRelease(os_release),
     };
 
     if (!site_id || !hostname || !version || !platform) {
@@ -87,6 +143,7 @@ export default async function (fastify: FastifyInstance) {
               macAddress: mac,
               ipAddress: ip_address,
               extAddress: ext_address,
+              ...agentInfo,
               status: "online" as const,
               statusChangedAt: now,
             },
@@ -107,6 +164,7 @@ export default async function (fastify: FastifyInstance) {
                 macAddress: mac,
                 ipAddress: ip_address,
                 extAddress: ext_address,
+                ...agentInfo,
                 status: "online" as const,
                 statusChangedAt: now,
               },
@@ -128,11 +186,19 @@ export default async function (fastify: FastifyInstance) {
       );
     }
 
+    // Agents pin this key at every registration and refuse jobs it didn't sign
+    const tenant = (await client.query(api.helpers.orm.get_s, {
+      tableName: "tenants",
+      id: site.tenantId,
+      secret: process.env.CONVEX_API_KEY!,
+    })) as Doc<"tenants"> | null;
+
     return Debug.response(
       {
         data: {
           device_id: result[0],
           guid: calculatedGuid,
+          job_signing_key: tenant?.jobSigningKey ?? null,
         },
       },
       200,
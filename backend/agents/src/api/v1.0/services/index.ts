@@ -0,0 +1,75 @@
+import { getAgentContext, recordAgentReport } from "@/lib/agentContext.js";
+import Debug from "@workspace/shared/lib/Debug.js";
+import { FastifyInstance } from "fastify";
+
+type ServiceState = {
+  name?: string;
+  status?: string;
+};
+
+export default async function (fastify: FastifyInstance) {
+  /**
+   * Status changes of the services an agent monitors, one report per change
+   */
+  fastify.post("/state", async (req) => {
+    try {
+      const context = await getAgentContext(req);
+      if (!context) {
+        return Debug.response(
+          {
+            error: {
+              module: "v1.0/services",
+              context: "POST",
+              message: "API headers invalid",
+            },
+          },
+          401,
+        );
+      }
+
+      const { changes } = req.body as { changes?: ServiceState[] };
+      if (
+        !Array.isArray(changes) ||
+        changes.some((change) => !change?.name || !change.status)
+      ) {
+        return Debug.response(
+          {
+            error: {
+              module: "v1.0/services",
+              context: "POST",
+              message: "changes must list a name and status for each service",
+            },
+          },
+          400,
+        );
+      }
+
+      const idempotencyKey = req.headers["x-idempotency-key"] as
+        | string
+        | undefined;
+      for (const change of changes) {
+        await recordAgentReport(
+          context,
+          "service_state",
+          change.name!,
+          change.status!,
+          change,
+          idempotencyKey && `${idempotencyKey}:${change.name}`,
+        );
+      }
+
+      return Debug.response({ data: { recorded: changes.length } }, 200);
+    } catch (err) {
+      return Debug.response(
+        {
+          error: {
+            module: "v1.0/services",
+            context: "POST",
+            message: `Failed to record service state: ${err}`,
+          },
+        },
+        500,
+      );
+    }
+  });
+}
@@ -126,6 +126,38 @@ export default async function (fastify: FastifyInstance) {
 				);
 			}
 
+			// Agents resend with the same key when they didn't see the first response,
+			// hand back the ticket that request created instead of opening another
+			const idempotencyKey = req.headers["x-idempotency-key"] as
+				| string
+				| undefined;
+			if (idempotencyKey) {
+				const existing = (await client.query(api.helpers.orm.get_s, {
+					tableName: "ticket_usage",
+					tenantId: site.tenantId,
+					secret: CONVEX_API_KEY,
+					index: {
+						name: "by_idempotency_key",
+						params: { idempotencyKey },
+					},
+				})) as Doc<"ticket_usage"> | null;
+
+				if (existing && existing.agentId === agent._id) {
+					Debug.log({
+						module: "v1.0/ticket/create",
+						context: "POST",
+						message: `Ticket already created for agent ${agent.hostname} (TicketID: ${existing.ticketId})`,
+					});
+					statusCode = 200;
+					return Debug.response(
+						{
+							data: existing.ticketId,
+						},
+						200,
+					);
+				}
+			}
+
 			Debug.log({
 				module: "v1.0/ticket/create",
 				context: "POST",
@@ -144,7 +176,11 @@ export default async function (fastify: FastifyInstance) {
 				// Handle multipart/form-data
 				if (contentType.includes("multipart/form-data")) {
 					const formData: Record<string, any> = {};
-					let screenshotFile: { filename: string; data: Buffer } | null = null;
+					let screenshotFile: {
+						filename: string;
+						mimetype: string;
+						data: Buffer;
+					} | null = null;
 
 					// Check if req has multipart method
 					if (!req.isMultipart || !req.isMultipart()) {
@@ -162,6 +198,7 @@ export default async function (fastify: FastifyInstance) {
 								}
 								screenshotFile = {
 									filename: part.filename,
+									mimetype: part.mimetype,
 									data: Buffer.concat(chunks),
 								};
 							}
@@ -175,6 +212,7 @@ export default async function (fastify: FastifyInstance) {
 					if (screenshotFile) {
 						formData.screenshot = {
 							name: screenshotFile.filename,
+							type: screenshotFile.mimetype,
 							data: screenshotFile.data.toString("base64"),
 						};
 					}
@@ -182,6 +220,7 @@ export default async function (fastify: FastifyInstance) {
 					return formData as {
 						screenshot?: {
 							name?: string;
+							type?: string;
 							data?: string;
 						};
 						link?: string;
@@ -200,6 +239,7 @@ export default async function (fastify: FastifyInstance) {
 				return JSON.parse(req.body as string) as {
 					screenshot?: {
 						name?: string;
+						type?: string;
 						data?: string;
 					};
 					link?: string;
@@ -288,8 +328,15 @@ export default async function (fastify: FastifyInstance) {
 						bytes[i] = binary.charCodeAt(i);
 					}
 
-					const blob = new Blob([bytes], { type: "image/png" });
-					const { data } = await connector.uploadImage(blob);
+					// Agents send stills as PNG and screen recordings as GIF
+					const isGif = body.screenshot!.type === "image/gif";
+					const blob = new Blob([bytes], {
+						type: isGif ? "image/gif" : "image/png",
+					});
+					const { data } = await connector.uploadImage(
+						blob,
+						isGif ? "upload.gif" : "upload.png",
+					);
 					if (data) {
 						body.link = data;
 
@@ -367,6 +414,7 @@ export default async function (fastify: FastifyInstance) {
 							impact: body.impact,
 							urgency: body.urgency,
 						},
+						idempotencyKey,
 					});
 				} catch (err) {
 					// Log error but don't fail the request
@@ -0,0 +1,126 @@
+import { getAgentContext, recordAgentReport } from "@/lib/agentContext.js";
+import Debug from "@workspace/shared/lib/Debug.js";
+import { FastifyInstance } from "fastify";
+import { readFile } from "node:fs/promises";
+import { join } from "node:path";
+
+const CHANNELS = ["stable", "beta"];
+const VERSION_PATTERN = /^\d+\.\d+\.\d+(?:-[0-9A-Za-z.]+)?$/;
+
+// Envelopes are signed offline with the release key, the server only hands them out
+const UPDATES_DIR =
+  process.env.AGENT_UPDATES_DIR || join(process.cwd(), "assets", "updates");
+
+type UpdateResult = {
+  from_version?: string;
+  to_version?: string;
+  status?: string;
+};
+
+export default async function (fastify: FastifyInstance) {
+  /**
+   * The signed manifest for a channel, or for one release when ?version= pins it.
+   * Rollout buckets are enforced by the agent against the signed manifest.
+   */
+  fastify.get("/:channel/manifest", async (req) => {
+    const context = await getAgentContext(req);
+    if (!context) {
+      return Debug.response(
+        {
+          error: {
+            module: "v1.0/updates",
+            context: "GET",
+            message: "API headers invalid",
+          },
+        },
+        401,
+      );
+    }
+
+    const { channel } = req.params as { channel: string };
+    const { version } = req.query as { version?: string };
+    if (!CHANNELS.includes(channel) || (version && !VERSION_PATTERN.test(version))) {
+      return Debug.response(
+        {
+          error: {
+            module: "v1.0/updates",
+            context: "GET",
+            message: "Unknown channel or version",
+          },
+        },
+        400,
+      );
+    }
+
+    try {
+      const file = join(UPDATES_DIR, channel, version ? `${version}.json` : "manifest.json");
+      const envelope = JSON.parse(await readFile(file, "utf8"));
+      return Response.json(envelope, { status: 200 });
+    } catch (err) {
+      return Debug.response(
+        {
+          error: {
+            module: "v1.0/updates",
+            context: "GET",
+            message: `No ${channel} release${version ? ` ${version}` : ""} published: ${err}`,
+          },
+        },
+        404,
+      );
+    }
+  });
+
+  fastify.post("/result", async (req) => {
+    try {
+      const context = await getAgentContext(req);
+      if (!context) {
+        return Debug.response(
+          {
+            error: {
+              module: "v1.0/updates",
+              context: "POST",
+              message: "API headers invalid",
+            },
+          },
+          401,
+        );
+      }
+
+      const result = req.body as UpdateResult;
+      if (!result?.to_version || !result.status) {
+        return Debug.response(
+          {
+            error: {
+              module: "v1.0/updates",
+              context: "POST",
+              message: "to_version and status are required",
+            },
+          },
+          400,
+        );
+      }
+
+      await recordAgentReport(
+        context,
+        "update_result",
+        result.to_version,
+        result.status,
+        result,
+        req.headers["x-idempotency-key"] as string | undefined,
+      );
+
+      return Debug.response({ data: { to_version: result.to_version } }, 200);
+    } catch (err) {
+      return Debug.response(
+        {
+          error: {
+            module: "v1.0/updates",
+            context: "POST",
+            message: `Failed to record update result: ${err}`,
+          },
+        },
+        500,
+      );
+    }
+  });
+}
@@ -0,0 +1,87 @@
+import { api } from "@workspace/database/convex/_generated/api.js";
+import { Doc } from "@workspace/database/convex/_generated/dataModel.js";
+import { client } from "@workspace/shared/lib/convex.js";
+import { FastifyRequest } from "fastify";
+
+export interface AgentContext {
+  agent: Doc<"agents">;
+  site: Doc<"sites">;
+}
+
+/**
+ * Resolves the agent and site named by the x-device-id and x-site-id headers.
+ * Returns null when either is missing or the agent doesn't belong to the site.
+ */
+export async function getAgentContext(
+  req: FastifyRequest,
+): Promise<AgentContext | null> {
+  const siteID = req.headers["x-site-id"] as string;
+  const deviceID = req.headers["x-device-id"] as string;
+  if (!siteID || !deviceID) {
+    return null;
+  }
+
+  const [agent, site] = (await Promise.all([
+    client.query(api.helpers.orm.get_s, {
+      tableName: "agents",
+      id: deviceID as any,
+      secret: process.env.CONVEX_API_KEY!,
+    }),
+    client.query(api.helpers.orm.get_s, {
+      tableName: "sites",
+      id: siteID as any,
+      secret: process.env.CONVEX_API_KEY!,
+    }),
+  ])) as [Doc<"agents"> | null, Doc<"sites"> | null];
+
+  if (!agent || !site || agent.siteId !== site._id || agent.deletedAt) {
+    return null;
+  }
+  return { agent, site };
+}
+
+/**
+ * Records a report from the agent once, agents resend with the same
+ * x-idempotency-key when they didn't see the first response.
+ */
+export async function recordAgentReport(
+  context: AgentContext,
+  kind: "job_result" | "update_result" | "service_state",
+  reference: string,
+  status: string,
+  data: unknown,
+  idempotencyKey?: string,
+): Promise<void> {
+  if (idempotencyKey) {
+    const existing = await client.query(api.helpers.orm.get_s, {
+      tableName: "agent_reports",
+      tenantId: context.site.tenantId,
+      secret: process.env.CONVEX_API_KEY!,
+      index: {
+        name: "by_idempotency_key",
+        params: { idempotencyKey },
+      },
+    });
+    if (existing) {
+      return;
+    }
+  }
+
+  await client.mutation(api.helpers.orm.insert_s, {
+    tableName: "agent_reports",
+    data: [
+      {
+        siteId: context.site._id,
+        agentId: context.agent._id,
+        kind,
+        reference,
+        status,
+        idempotencyKey,
+        data,
+        reportedAt: Date.now(),
+      },
+    ],
+    tenantId: context.site.tenantId,
+    secret: process.env.CONVEX_API_KEY!,
+  });
+}
@@ -110,6 +110,7 @@ export const TableName = v.union(
 	v.literal("roles"),
 	v.literal("entities"),
 	v.literal("agents"),
+	v.literal("agent_reports"),
 	v.literal("data_sources"),
 	v.literal("job_history"),
 	v.literal("sites"),
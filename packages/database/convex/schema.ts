@@ -32,6 +32,7 @@ export default defineSchema({
 
 		metadata: v.optional(v.any()),
 		concurrentJobLimit: v.optional(v.number()), // Max concurrent jobs per tenant (default 5)
+		jobSigningKey: v.optional(v.string()), // Base64 Ed25519 public key agents pin to verify this tenant's jobs
 
 		updatedAt: v.number(),
 		deletedAt: v.optional(v.number()),
@@ -142,6 +143,28 @@ export default defineSchema({
 		),
 		statusChangedAt: v.optional(v.number()),
 		registeredAt: v.optional(v.number()),
+		capabilities: v.optional(v.array(v.string())), // Features the agent reports it supports
+		build: v.optional(
+			v.object({
+				version: v.string(),
+				gitSha: v.string(),
+				builtAt: v.optional(v.string()),
+				target: v.string(),
+				profile: v.string(),
+				features: v.array(v.string()),
+			}),
+		),
+		osRelease: v.optional(
+			v.object({
+				id: v.string(),
+				idLike: v.optional(v.string()),
+				name: v.optional(v.string()),
+				versionId: v.optional(v.string()),
+				versionCodename: v.optional(v.string()),
+				prettyName: v.optional(v.string()),
+				build: v.optional(v.string()),
+			}),
+		),
 
 		updatedAt: v.number(),
 		deletedAt: v.optional(v.number()),
@@ -226,6 +249,28 @@ export default defineSchema({
 		.index("by_site", ["siteId"])
 		.index("by_agent", ["agentId"]),
 
+	agent_reports: defineTable({
+		tenantId: v.id("tenants"),
+		siteId: v.id("sites"),
+		agentId: v.id("agents"),
+		kind: v.union(
+			v.literal("job_result"),
+			v.literal("update_result"),
+			v.literal("service_state"),
+		),
+		reference: v.string(), // Job id, version updated to or service name
+		status: v.string(), // As the agent reported it, e.g. "completed", "rolled_back"
+		idempotencyKey: v.optional(v.string()), // For dropping resent reports
+		data: v.any(), // The report as the agent sent it
+		reportedAt: v.number(),
+
+		updatedAt: v.number(),
+	})
+		.index("by_tenant", ["tenantId"])
+		.index("by_agent", ["agentId", "tenantId"])
+		.index("by_agent_kind", ["agentId", "kind", "tenantId"])
+		.index("by_idempotency_key", ["idempotencyKey", "tenantId"]),
+
 	ticket_usage: defineTable({
 		tenantId: v.id("tenants"),
 		siteId: v.id("sites"),
@@ -237,12 +282,14 @@ export default defineSchema({
 		billingPeriod: v.string(), // "YYYY-MM" format
 		createdAt: v.number(),
 		metadata: v.optional(v.any()),
+		idempotencyKey: v.optional(v.string()), // From the agent, a resent ticket returns this one instead of creating another
 	})
 		.index("by_tenant", ["tenantId"])
 		.index("by_site", ["siteId", "tenantId"])
 		.index("by_agent", ["agentId", "tenantId"])
 		.index("by_billing_period", ["billingPeriod", "tenantId"])
-		.index("by_site_billing_period", ["siteId", "billingPeriod", "tenantId"]),
+		.index("by_site_billing_period", ["siteId", "billingPeriod", "tenantId"])
+		.index("by_idempotency_key", ["idempotencyKey", "tenantId"]),
 
 	api_logs: defineTable({
 		url: v.string(),
@@ -17,6 +17,7 @@ export const logTicketUsage = mutation({
     psaType: v.string(),
     endpoint: v.string(),
     metadata: v.optional(v.any()),
+    idempotencyKey: v.optional(v.string()),
   },
   handler: async (ctx, { secret, ...args }) => {
     await isValidSecret(secret);
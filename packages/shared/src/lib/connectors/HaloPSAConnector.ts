@@ -152,17 +152,18 @@ export default class HaloPSAConnector {
 
     const images = ticket.images
       .map((image) => {
-        return `<img src=\"${image}\" class=\"fr-fil fr-dib\" width=\"720\" height=\"374\">`;
+        return `<img src=\"${escapeHtml(image)}\" class=\"fr-fil fr-dib\" width=\"720\" height=\"374\">`;
       })
       .join("<br>");
     const details: string[] = [];
     details.push("[User Submitted Request]");
-    details.push(`Summary: ${ticket.summary}`);
+    // Everything here comes from the end user, escape it before it becomes ticket HTML
+    details.push(`Summary: ${escapeHtml(ticket.summary)}`);
     details.push("");
-    details.push(`Name: ${ticket.user.name}`);
-    details.push(`Email: ${ticket.user.email}`);
-    details.push(`Phone: ${ticket.user.phone}`);
-    details.push(`Details: ${ticket.details}`);
+    details.push(`Name: ${escapeHtml(ticket.user.name)}`);
+    details.push(`Email: ${escapeHtml(ticket.user.email)}`);
+    details.push(`Phone: ${escapeHtml(ticket.user.phone)}`);
+    details.push(`Details: ${escapeHtml(ticket.details).replace(/\n/g, "<br>")}`);
     const details_html = `<p>${details.join("<br>")}<br>${images}</p>`;
 
     const params = new URLSearchParams();
@@ -218,7 +219,10 @@ export default class HaloPSAConnector {
     };
   }
 
-  async uploadImage(file: Blob): Promise<APIResponse<string>> {
+  async uploadImage(
+    file: Blob,
+    fileName = "upload.png",
+  ): Promise<APIResponse<string>> {
     const { data: token, error: tokenError } = await this.getToken();
     if (tokenError) return { error: tokenError };
 
@@ -226,7 +230,7 @@ export default class HaloPSAConnector {
     formData.append("ticket_id", "");
     formData.append("image_upload_id", "0");
     formData.append("image_upload_key", "");
-    formData.append("file", file, "upload.png");
+    formData.append("file", file, fileName);
 
     const response = await fetch(`${this.config.url}/api/attachment/image`, {
       method: "POST",
@@ -286,3 +290,12 @@ export default class HaloPSAConnector {
     }
   }
 }
+
+function escapeHtml(value: string): string {
+  return value
+    .replace(/&/g, "&amp;")
+    .replace(/</g, "&lt;")
+    .replace(/>/g, "&gt;")
+    .replace(/"/g, "&quot;")
+    .replace(/'/g, "&#39;");
+}